@@ -0,0 +1,61 @@
+//! Pair log decoding throughput, run with `cargo bench --bench decode`
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use ethabi::{encode, Token};
+use web3::types::{Bytes, Log, H160, H256, U256, U64};
+use rust_backend::protocol::abi::AbiFiles;
+use rust_backend::protocol::uniswap_v2::UniswapV2;
+use rust_backend::protocol::ProtocolAdapter;
+
+const LOGS: u64 = 10_000;
+
+/// a Swap followed by its Sync in each transaction of a pair
+fn fixture() -> Vec<Log> {
+    let topics = UniswapV2::embedded_topics();
+    let (pair, trader) = (H160::from_low_u64_be(0x1001), H256::from(H160::from_low_u64_be(0xaaa)));
+    let e18 = U256::exp10(18);
+    (0..LOGS).map(|i| {
+        let (topics, data) = if i % 2 == 0 {
+            (vec![topics.topic("swap"), trader, trader],
+             encode(&[Token::Uint(e18), Token::Uint(U256::zero()), Token::Uint(U256::zero()), Token::Uint(e18 / 2)]))
+        } else {
+            (vec![topics.topic("sync")], encode(&[Token::Uint(e18 * 1000), Token::Uint(e18 * 2000)]))
+        };
+        Log {
+            address: pair,
+            topics,
+            data: Bytes(data),
+            block_hash: Some(H256::from_low_u64_be(i / 2 + 1)),
+            block_number: Some(U64::from(i / 2 + 1)),
+            transaction_hash: Some(H256::from_low_u64_be(i / 2 + 1)),
+            transaction_index: Some(0.into()),
+            log_index: Some((i % 2).into()),
+            transaction_log_index: Some((i % 2).into()),
+            log_type: None,
+            removed: Some(false),
+        }
+    }).collect()
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let logs = fixture();
+    let mut group = c.benchmark_group("decode_pool_log");
+    group.throughput(Throughput::Elements(LOGS));
+    let protocol = UniswapV2::new();
+    group.bench_function("adapter", |b| b.iter(|| {
+        for log in &logs {
+            protocol.decode_pool_log(log).unwrap();
+        }
+    }));
+    // what every log cost when the topics were rebuilt from the ABIs for each one
+    let sample = &logs[..100];
+    group.throughput(Throughput::Elements(sample.len() as u64));
+    group.bench_function("parsing the abi per log", |b| b.iter(|| {
+        for log in sample {
+            UniswapV2::with_abi(&AbiFiles::default()).unwrap().decode_pool_log(log).unwrap();
+        }
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
@@ -0,0 +1,4 @@
+pub mod v2;
+pub mod units;
+pub mod quote;
+pub mod router;
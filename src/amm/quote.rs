@@ -0,0 +1,118 @@
+//! Quotes of swaps through V2 pairs, from their reserves.
+//!
+//! A quote follows a path of pairs like `UniswapV2Router02`: the output of each hop is the
+//! input of the next. Its price impact compares the output with the one of the same input at
+//! the spot prices of the pairs after their fees, so the fees aren't counted as impact.
+use anyhow::{ensure, format_err};
+use web3::types::U256;
+use crate::amm::units::{mul_div, PRICE_DECIMALS};
+use crate::amm::v2::{get_amount_in, get_amount_out, BPS};
+
+/// The reserves of a pair in the direction of a swap and its fee.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hop {
+    pub reserve_in: U256,
+    pub reserve_out: U256,
+    pub fee_bps: u64,
+}
+
+/// The amounts in and out of each hop of a swap, `amounts[0]` is sent and the last one received.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quote {
+    pub amounts: Vec<U256>,
+    /// the output short of the one at the spot prices, with `PRICE_DECIMALS` decimals
+    pub price_impact: U256,
+}
+
+impl Quote {
+    pub fn amount_in(&self) -> U256 {
+        self.amounts[0]
+    }
+
+    pub fn amount_out(&self) -> U256 {
+        self.amounts[self.amounts.len() - 1]
+    }
+}
+
+/// `UniswapV2Library.getAmountsOut`, `amount_in` sent through every hop
+pub fn quote_exact_in(amount_in: U256, hops: &[Hop]) -> anyhow::Result<Quote> {
+    ensure!(!hops.is_empty(), "empty path");
+    let mut amounts = vec![amount_in];
+    for hop in hops {
+        let amount = get_amount_out(amounts[amounts.len() - 1], hop.reserve_in, hop.reserve_out, hop.fee_bps)?;
+        ensure!(!amount.is_zero(), "insufficient output amount");
+        amounts.push(amount);
+    }
+    Ok(Quote { price_impact: price_impact(amounts[amounts.len() - 1], spot_amount_out(amounts[0], hops)), amounts })
+}
+
+/// `UniswapV2Library.getAmountsIn`, the input receiving `amount_out` from the last hop
+pub fn quote_exact_out(amount_out: U256, hops: &[Hop]) -> anyhow::Result<Quote> {
+    ensure!(!hops.is_empty(), "empty path");
+    let mut amounts = vec![amount_out];
+    for hop in hops.iter().rev() {
+        amounts.push(get_amount_in(amounts[amounts.len() - 1], hop.reserve_in, hop.reserve_out, hop.fee_bps)?);
+    }
+    amounts.reverse();
+    Ok(Quote { price_impact: price_impact(amounts[amounts.len() - 1], spot_amount_out(amounts[0], hops)), amounts })
+}
+
+/// the output of `amount_in` at the spot prices of the hops after their fees
+pub fn spot_amount_out(amount_in: U256, hops: &[Hop]) -> Option<U256> {
+    hops.iter().try_fold(amount_in, |amount, hop| {
+        let amount = mul_div(amount, (BPS - hop.fee_bps).into(), BPS.into())?;
+        mul_div(amount, hop.reserve_out, hop.reserve_in)
+    })
+}
+
+/// `1 - amount_out / spot_amount_out`, zero without a spot output
+pub fn price_impact(amount_out: U256, spot_amount_out: Option<U256>) -> U256 {
+    match spot_amount_out {
+        Some(spot) if spot > amount_out => {
+            mul_div(spot - amount_out, U256::exp10(PRICE_DECIMALS as usize), spot).unwrap_or_default()
+        }
+        _ => U256::zero(),
+    }
+}
+
+/// the least output of a swap quoted `amount_out` when the price moves by `slippage_bps`
+pub fn minimum_amount_out(amount_out: U256, slippage_bps: u64) -> anyhow::Result<U256> {
+    ensure!(slippage_bps <= BPS, "slippage of {} bps", slippage_bps);
+    mul_div(amount_out, (BPS - slippage_bps).into(), BPS.into()).ok_or_else(|| format_err!("amount overflows"))
+}
+
+/// the most input of a swap quoted `amount_in` when the price moves by `slippage_bps`
+pub fn maximum_amount_in(amount_in: U256, slippage_bps: u64) -> anyhow::Result<U256> {
+    ensure!(slippage_bps <= BPS, "slippage of {} bps", slippage_bps);
+    mul_div(amount_in, (BPS + slippage_bps).into(), BPS.into()).ok_or_else(|| format_err!("amount overflows"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amm::v2::FEE_BPS;
+
+    #[test]
+    fn test_quote() {
+        let e18 = U256::exp10(18);
+        let pair = Hop { reserve_in: e18 * 1000, reserve_out: U256::from(2_000_000_000u64), fee_bps: FEE_BPS };
+        let quote = quote_exact_in(e18, &[pair]).unwrap();
+        assert_eq!(quote.amounts, vec![e18, U256::from(1_992_013)]);
+        // the input after the fee is 1.994 at the spot price of 2
+        assert_eq!(quote.price_impact, U256::from(996_489_468_405_215u64));
+        assert_eq!(minimum_amount_out(quote.amount_out(), 50).unwrap(), U256::from(1_982_052));
+
+        let exact_out = quote_exact_out(U256::from(1_992_013), &[pair]).unwrap();
+        assert_eq!(quote_exact_in(exact_out.amount_in(), &[pair]).unwrap().amount_out(), U256::from(1_992_013));
+        assert_eq!(maximum_amount_in(U256::from(10_000), 50).unwrap(), U256::from(10_050));
+
+        // back through the same reserves, with a cheaper fork
+        let back = Hop { reserve_in: pair.reserve_out, reserve_out: pair.reserve_in, fee_bps: 25 };
+        let round_trip = quote_exact_in(e18, &[pair, back]).unwrap();
+        assert_eq!(round_trip.amounts.len(), 3);
+        assert!(round_trip.amount_out() < e18);
+        assert!(round_trip.price_impact > quote.price_impact);
+        assert!(quote_exact_in(e18, &[]).is_err());
+        assert!(quote_exact_out(pair.reserve_out, &[pair]).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! Best routes of swaps through the graph of V2 pairs.
+//!
+//! A `UniswapV2Router02` only swaps through the pairs of its own factory, so a path stays
+//! within one factory. Paths are extended hop by hop up to `max_hops`, keeping the path that
+//! reaches each token with the most, and never visit a token twice. A split route sends
+//! `SPLIT_PARTS` parts of the input one after the other, each through the path giving the
+//! most for it once the parts before it moved the reserves, through at most `max_routes` paths.
+use std::collections::{BTreeMap, HashMap};
+use web3::types::U256;
+use crate::amm::quote::{self, Hop, Quote};
+use crate::amm::v2::get_amount_out;
+
+/// the parts the input of a split route is sent in
+pub const SPLIT_PARTS: u64 = 20;
+
+/// A V2 pair of the graph, with its stored reserves and the fee of its factory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphPair {
+    pub pair_address: String,
+    pub factory_address: Option<String>,
+    pub token_x: String,
+    pub token_y: String,
+    pub reserve_x: U256,
+    pub reserve_y: U256,
+    pub fee_bps: u64,
+}
+
+impl GraphPair {
+    fn other(&self, token: &str) -> &str {
+        if self.token_x == token { &self.token_y } else { &self.token_x }
+    }
+}
+
+/// The pairs of one factory a swap goes through and the tokens along them, from the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Path {
+    pairs: Vec<usize>,
+    tokens: Vec<String>,
+}
+
+/// the reserves moved by the swaps simulated before, by pair
+type Moved = HashMap<usize, (U256, U256)>;
+
+/// One swap of a route, through the pairs of one factory.
+#[derive(Debug, Clone)]
+pub struct Route {
+    pub pairs: Vec<GraphPair>,
+    /// `pairs.len() + 1` tokens, from the input to the output
+    pub tokens: Vec<String>,
+    pub quote: Quote,
+}
+
+/// Swaps of parts of an input, one after the other.
+#[derive(Debug, Clone)]
+pub struct RouteSplit {
+    pub routes: Vec<Route>,
+    pub amount_out: U256,
+    /// the output short of the one at the spot prices of every route, as in `Quote`
+    pub price_impact: U256,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PairGraph {
+    pairs: Vec<GraphPair>,
+    index: HashMap<String, usize>,
+    /// the pairs of each token, by factory
+    adjacent: BTreeMap<Option<String>, HashMap<String, Vec<usize>>>,
+}
+
+impl PairGraph {
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    pub fn pair(&self, pair_address: &str) -> Option<&GraphPair> {
+        self.index.get(pair_address).map(|&i| &self.pairs[i])
+    }
+
+    /// add a pair, or replace one added before
+    pub fn upsert(&mut self, pair: GraphPair) {
+        let i = match self.index.get(&pair.pair_address) {
+            Some(&i) => {
+                let old = &self.pairs[i];
+                if old.factory_address == pair.factory_address && old.token_x == pair.token_x
+                    && old.token_y == pair.token_y {
+                    self.pairs[i] = pair;
+                    return;
+                }
+                // a factory set after the pair was added
+                if let Some(tokens) = self.adjacent.get_mut(&old.factory_address) {
+                    for token in [&old.token_x, &old.token_y] {
+                        if let Some(pairs) = tokens.get_mut(token) {
+                            pairs.retain(|&p| p != i);
+                        }
+                    }
+                }
+                self.pairs[i] = pair;
+                i
+            }
+            None => {
+                self.index.insert(pair.pair_address.clone(), self.pairs.len());
+                self.pairs.push(pair);
+                self.pairs.len() - 1
+            }
+        };
+        let pair = &self.pairs[i];
+        let tokens = self.adjacent.entry(pair.factory_address.clone()).or_default();
+        for token in [&pair.token_x, &pair.token_y] {
+            tokens.entry(token.clone()).or_default().push(i);
+        }
+    }
+
+    fn hop(&self, moved: &Moved, i: usize, token_in: &str) -> Hop {
+        let pair = &self.pairs[i];
+        let (reserve_x, reserve_y) = moved.get(&i).copied().unwrap_or((pair.reserve_x, pair.reserve_y));
+        let (reserve_in, reserve_out) = if pair.token_x == token_in {
+            (reserve_x, reserve_y)
+        } else {
+            (reserve_y, reserve_x)
+        };
+        Hop { reserve_in, reserve_out, fee_bps: pair.fee_bps }
+    }
+
+    fn hops(&self, moved: &Moved, path: &Path) -> Vec<Hop> {
+        path.pairs.iter().zip(&path.tokens).map(|(&i, token)| self.hop(moved, i, token)).collect()
+    }
+
+    /// the path giving the most for `amount_in`, and that amount
+    fn best_path(&self, moved: &Moved, token_in: &str, token_out: &str, amount_in: U256, max_hops: usize)
+        -> Option<(Path, U256)> {
+        let mut best: Option<(Path, U256)> = None;
+        for tokens in self.adjacent.values() {
+            let start = Path { pairs: vec![], tokens: vec![token_in.to_string()] };
+            let mut frontier = BTreeMap::from([(token_in.to_string(), (start, amount_in))]);
+            for _ in 0..max_hops {
+                let mut next: BTreeMap<String, (Path, U256)> = BTreeMap::new();
+                for (token, (path, amount)) in &frontier {
+                    for &i in tokens.get(token).into_iter().flatten() {
+                        let other = self.pairs[i].other(token);
+                        if path.tokens.iter().any(|t| t == other) {
+                            continue;
+                        }
+                        let hop = self.hop(moved, i, token);
+                        let out = match get_amount_out(*amount, hop.reserve_in, hop.reserve_out, hop.fee_bps) {
+                            Ok(out) if !out.is_zero() => out,
+                            _ => continue,
+                        };
+                        let current = if other == token_out { best.as_ref() } else { next.get(other) };
+                        if current.map(|(_, b)| *b).is_some_and(|current| current >= out) {
+                            continue;
+                        }
+                        let mut path = path.clone();
+                        path.pairs.push(i);
+                        path.tokens.push(other.to_string());
+                        if other == token_out {
+                            best = Some((path, out));
+                        } else {
+                            next.insert(other.to_string(), (path, out));
+                        }
+                    }
+                }
+                frontier = next;
+            }
+        }
+        best
+    }
+
+    /// the reserves of the pairs of `path` after a swap of `quote` through it
+    fn apply(&self, moved: &mut Moved, path: &Path, quote: &Quote) {
+        for (k, &i) in path.pairs.iter().enumerate() {
+            let hop = self.hop(moved, i, &path.tokens[k]);
+            let reserve_in = hop.reserve_in + quote.amounts[k];
+            let reserve_out = hop.reserve_out - quote.amounts[k + 1];
+            let reserves = if self.pairs[i].token_x == path.tokens[k] {
+                (reserve_in, reserve_out)
+            } else {
+                (reserve_out, reserve_in)
+            };
+            moved.insert(i, reserves);
+        }
+    }
+
+    /// the swaps of `parts` one after the other from the stored reserves, `None` when one fails
+    /// or the outputs overflow
+    fn split(&self, parts: Vec<(Path, U256)>) -> Option<RouteSplit> {
+        let mut moved = Moved::new();
+        let mut routes = Vec::new();
+        let (mut amount_out, mut spot_amount_out) = (U256::zero(), U256::zero());
+        for (path, amount_in) in parts {
+            let spot = quote::spot_amount_out(amount_in, &self.hops(&Moved::new(), &path))?;
+            spot_amount_out = spot_amount_out.checked_add(spot)?;
+            let quote = quote::quote_exact_in(amount_in, &self.hops(&moved, &path)).ok()?;
+            self.apply(&mut moved, &path, &quote);
+            amount_out = amount_out.checked_add(quote.amount_out())?;
+            routes.push(Route {
+                pairs: path.pairs.iter().map(|&i| self.pairs[i].clone()).collect(),
+                tokens: path.tokens,
+                quote,
+            });
+        }
+        Some(RouteSplit { routes, amount_out, price_impact: quote::price_impact(amount_out, Some(spot_amount_out)) })
+    }
+
+    /// the route giving the most for `amount_in`, split through up to `max_routes` paths when
+    /// that gives more, `None` when no path of up to `max_hops` pairs can fill it
+    pub fn best_route(&self, token_in: &str, token_out: &str, amount_in: U256, max_hops: usize, max_routes: usize)
+        -> Option<RouteSplit> {
+        let (path, _) = self.best_path(&Moved::new(), token_in, token_out, amount_in, max_hops)?;
+        let single = self.split(vec![(path, amount_in)])?;
+        if max_routes <= 1 || amount_in < U256::from(SPLIT_PARTS) {
+            return Some(single);
+        }
+
+        let part = amount_in / SPLIT_PARTS;
+        let mut moved = Moved::new();
+        let mut parts: Vec<(Path, U256)> = Vec::new();
+        for n in 0..SPLIT_PARTS {
+            let amount = if n == SPLIT_PARTS - 1 { amount_in - part * (SPLIT_PARTS - 1) } else { part };
+            let mut best: Option<(Path, Quote)> = None;
+            let mut candidates: Vec<Path> = parts.iter().map(|(path, _)| path.clone()).collect();
+            if parts.len() < max_routes {
+                candidates.extend(self.best_path(&moved, token_in, token_out, amount, max_hops).map(|(path, _)| path));
+            }
+            for path in candidates {
+                if let Ok(quote) = quote::quote_exact_in(amount, &self.hops(&moved, &path)) {
+                    if best.as_ref().is_none_or(|(_, b)| quote.amount_out() > b.amount_out()) {
+                        best = Some((path, quote));
+                    }
+                }
+            }
+            let Some((path, quote)) = best else {
+                return Some(single);
+            };
+            self.apply(&mut moved, &path, &quote);
+            match parts.iter_mut().find(|(p, _)| *p == path) {
+                Some((_, total)) => *total += amount,
+                None => parts.push((path, amount)),
+            }
+        }
+        match self.split(parts) {
+            Some(split) if split.amount_out > single.amount_out => Some(split),
+            _ => Some(single),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::amm::v2::FEE_BPS;
+
+    fn pair(address: &str, factory: &str, (x, y): (&str, &str), (reserve_x, reserve_y): (u64, u64)) -> GraphPair {
+        GraphPair {
+            pair_address: address.to_string(),
+            factory_address: Some(factory.to_string()),
+            token_x: x.to_string(),
+            token_y: y.to_string(),
+            reserve_x: U256::from(reserve_x) * U256::exp10(18),
+            reserve_y: U256::from(reserve_y) * U256::exp10(18),
+            fee_bps: FEE_BPS,
+        }
+    }
+
+    #[test]
+    fn test_best_route() {
+        let mut graph = PairGraph::default();
+        // a shallow direct pair at a better price, and a deep path through b of the same factory
+        graph.upsert(pair("p1", "f", ("a", "c"), (100, 110)));
+        graph.upsert(pair("p2", "f", ("a", "b"), (10_000, 10_000)));
+        graph.upsert(pair("p3", "f", ("b", "c"), (10_000, 10_000)));
+        // a better price of b in c through another factory, a router can't mix them
+        graph.upsert(pair("p4", "g", ("c", "b"), (10_000, 20_000)));
+
+        let e18 = U256::exp10(18);
+        let small = graph.best_route("a", "c", e18 / 1000, 3, 1).unwrap();
+        assert_eq!(small.routes.len(), 1);
+        assert_eq!(small.routes[0].tokens, vec!["a", "c"]);
+
+        let large = graph.best_route("a", "c", e18 * 50, 3, 1).unwrap();
+        assert_eq!(large.routes[0].tokens, vec!["a", "b", "c"]);
+        assert_eq!(large.routes[0].pairs.iter().map(|p| p.pair_address.as_str()).collect::<Vec<_>>(), vec!["p2", "p3"]);
+        assert_eq!(large.amount_out, large.routes[0].quote.amount_out());
+        assert!(graph.best_route("a", "c", e18 * 50, 1, 1).unwrap().amount_out < large.amount_out);
+
+        let split = graph.best_route("a", "c", e18 * 50, 3, 2).unwrap();
+        assert_eq!(split.routes.len(), 2);
+        assert!(split.amount_out > large.amount_out);
+        let amount_in = split.routes.iter().fold(U256::zero(), |sum, r| sum + r.quote.amount_in());
+        assert_eq!(amount_in, e18 * 50);
+
+        // the reserves of a pair replaced by the watcher
+        graph.upsert(pair("p1", "f", ("a", "c"), (1_000_000, 1_000_000)));
+        assert_eq!(graph.len(), 4);
+        assert_eq!(graph.best_route("a", "c", e18 * 50, 3, 2).unwrap().routes.len(), 1);
+        assert!(graph.best_route("a", "d", e18, 3, 1).is_none());
+
+        let pow2 = |n: usize| U256::one() << n;
+        let pair_of = |address: &str, reserve_a: U256, reserve_c: U256| GraphPair {
+            reserve_x: reserve_a,
+            reserve_y: reserve_c,
+            ..pair(address, "f", ("a", "c"), (0, 0))
+        };
+        let mut graph = PairGraph::default();
+        // reserve ratios of 2^100 and 2^101, the spot output of the whole input through the
+        // deeper pair fits a U256, the sum of the ones of the parts spread over both doesn't
+        graph.upsert(pair_of("p1", pow2(150), pow2(250)));
+        graph.upsert(pair_of("p2", pow2(140), pow2(241)));
+        let amount_in = pow2(156);
+        let single = graph.best_route("a", "c", amount_in, 1, 1).unwrap();
+        assert_eq!(single.routes[0].pairs[0].pair_address, "p1");
+        // the split overflowing falls back to the single route
+        let split = graph.best_route("a", "c", amount_in, 1, 2).unwrap();
+        assert_eq!(split.routes.len(), 1);
+        assert_eq!(split.amount_out, single.amount_out);
+    }
+}
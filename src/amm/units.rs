@@ -0,0 +1,98 @@
+//! Raw token amounts in whole tokens, and prices between tokens of different decimals.
+//!
+//! Amounts are kept as decimal strings, a float can't hold an 18 decimals amount exactly.
+use web3::types::U256;
+
+/// the decimals of a price
+pub const PRICE_DECIMALS: u8 = 18;
+
+/// `a * b / denominator` rounded down, through a 512 bit product. `None` when the
+/// denominator is zero or the result doesn't fit in 256 bits.
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> Option<U256> {
+    if denominator.is_zero() {
+        return None;
+    }
+    U256::try_from(a.full_mul(b) / denominator).ok()
+}
+
+/// `value` in units of `10^decimals`, without trailing zeros, e.g. 1500000 with 6 decimals is `1.5`
+pub fn format_units(value: U256, decimals: u8) -> String {
+    let digits = value.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+/// a decimal amount of whole tokens, e.g. `1.5`, in units of `10^decimals`. `None` when it
+/// isn't a plain decimal, has more fractional digits than `decimals` or overflows.
+pub fn parse_units(amount: &str, decimals: u8) -> Option<U256> {
+    let (integer, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    let decimals = decimals as usize;
+    let digits = format!("{}{}", integer, fraction);
+    if digits.is_empty() || fraction.len() > decimals || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    U256::from_dec_str(&format!("{}{}", digits, "0".repeat(decimals - fraction.len()))).ok()
+}
+
+fn exp10(exponent: u32) -> Option<U256> {
+    U256::from(10).checked_pow(exponent.into())
+}
+
+/// the price of one whole token x in whole tokens y, from amounts of both in raw units
+/// (e.g. the reserves of a pair), with `PRICE_DECIMALS` decimals. `None` without any x.
+pub fn price(amount_x: U256, decimals_x: u8, amount_y: U256, decimals_y: u8) -> Option<String> {
+    let numerator = exp10(decimals_x as u32 + PRICE_DECIMALS as u32)?;
+    let denominator = amount_x.checked_mul(exp10(decimals_y as u32)?)?;
+    Some(format_units(mul_div(amount_y, numerator, denominator)?, PRICE_DECIMALS))
+}
+
+/// the prices of x in y and of y in x of a concentrated liquidity pool at `sqrt_price_x96`,
+/// the square root of the raw price of token0 in token1 as a Q64.96
+pub fn sqrt_price_prices(sqrt_price_x96: U256, decimals_x: u8, decimals_y: u8) -> Option<(String, String)> {
+    // the raw price as a Q128.128, the square of a Q64.96 is a Q128.192
+    let ratio_x128 = mul_div(sqrt_price_x96, sqrt_price_x96, U256::one() << 64)?;
+    let one = U256::one() << 128;
+    Some((price(one, decimals_x, ratio_x128, decimals_y)?, price(ratio_x128, decimals_y, one, decimals_x)?))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        assert_eq!(format_units(U256::from(1_500_000), 6), "1.5");
+        assert_eq!(format_units(U256::from(42), 6), "0.000042");
+        assert_eq!(format_units(U256::exp10(18) * 3, 18), "3");
+        assert_eq!(format_units(U256::zero(), 18), "0");
+        assert_eq!(format_units(U256::from(7), 0), "7");
+        assert_eq!(parse_units("1.5", 6), Some(U256::from(1_500_000)));
+        assert_eq!(parse_units(".5", 1), Some(U256::from(5)));
+        assert_eq!(parse_units("3", 18), Some(U256::exp10(18) * 3));
+        assert_eq!(parse_units("0.0000001", 6), None);
+        assert_eq!(parse_units("-1", 6), None);
+        assert_eq!(parse_units("", 6), None);
+
+        // 1000 tokens of 18 decimals against 2000 of 6 decimals
+        let (x, y) = (U256::exp10(21), U256::exp10(9) * 2);
+        assert_eq!(price(x, 18, y, 6).unwrap(), "2");
+        assert_eq!(price(y, 6, x, 18).unwrap(), "0.5");
+        assert_eq!(price(U256::zero(), 18, y, 6), None);
+        assert_eq!(mul_div(U256::MAX, U256::MAX, U256::MAX), Some(U256::MAX));
+
+        // a raw price of 4 token1 per token0
+        let sqrt_price = U256::from(2) << 96;
+        assert_eq!(sqrt_price_prices(sqrt_price, 18, 18).unwrap(), ("4".to_string(), "0.25".to_string()));
+        assert_eq!(sqrt_price_prices(sqrt_price, 18, 6).unwrap().0, "4000000000000");
+    }
+}
@@ -0,0 +1,277 @@
+//! Reference model of a Uniswap V2 pair, following `UniswapV2Pair.sol`.
+//!
+//! `PairSimulator` replays the decoded `PairEvent` stream of one pair and checks that
+//! every event is consistent with the state the previous events produced: the `Sync`
+//! emitted before each Mint, Burn and Swap must match the reserves the operation implies,
+//! and swaps must keep the fee adjusted constant product.
+use anyhow::{ensure, format_err};
+use web3::types::U256;
+use crate::amm::units::mul_div;
+use crate::watcher::event::PairEvent;
+
+pub const MINIMUM_LIQUIDITY: u64 = 1000;
+/// the swap fee in basis points of `UniswapV2Pair`, 0.3%, forks may charge another one
+pub const FEE_BPS: u64 = 30;
+pub const BPS: u64 = 10000;
+
+/// `UniswapV2Library.getAmountOut` of a pair charging `fee_bps`
+pub fn get_amount_out(amount_in: U256, reserve_in: U256, reserve_out: U256, fee_bps: u64) -> anyhow::Result<U256> {
+    ensure!(!amount_in.is_zero(), "insufficient input amount");
+    ensure!(!reserve_in.is_zero() && !reserve_out.is_zero(), "insufficient liquidity");
+    ensure!(fee_bps < BPS, "fee of {} bps", fee_bps);
+    let amount_in_with_fee = amount_in.checked_mul((BPS - fee_bps).into()).ok_or_else(|| format_err!("input amount overflows"))?;
+    let denominator = reserve_in.checked_mul(BPS.into())
+        .and_then(|r| r.checked_add(amount_in_with_fee))
+        .ok_or_else(|| format_err!("input amount overflows"))?;
+    mul_div(amount_in_with_fee, reserve_out, denominator).ok_or_else(|| format_err!("output amount overflows"))
+}
+
+/// `UniswapV2Library.getAmountIn` of a pair charging `fee_bps`
+pub fn get_amount_in(amount_out: U256, reserve_in: U256, reserve_out: U256, fee_bps: u64) -> anyhow::Result<U256> {
+    ensure!(!amount_out.is_zero(), "insufficient output amount");
+    ensure!(!reserve_in.is_zero() && amount_out < reserve_out, "insufficient liquidity");
+    ensure!(fee_bps < BPS, "fee of {} bps", fee_bps);
+    let numerator = reserve_in.full_mul(amount_out) * BPS;
+    let amount_in = U256::try_from(numerator / (reserve_out - amount_out).full_mul((BPS - fee_bps).into()))
+        .map_err(|_| format_err!("input amount overflows"))?;
+    amount_in.checked_add(U256::one()).ok_or_else(|| format_err!("input amount overflows"))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PairSimulator {
+    pub reserve0: U256,
+    pub reserve1: U256,
+    /// lp supply including the locked `MINIMUM_LIQUIDITY` and protocol fee shares
+    pub total_supply: U256,
+    pub k_last: U256,
+    /// whether the factory `feeTo` is set, i.e. 1/6 of the fee growth is minted to it
+    pub fee_on: bool,
+    pub swap_count: u64,
+    pub mint_count: u64,
+    pub burn_count: u64,
+    /// reserves of a `Sync` whose Mint, Burn or Swap has not been seen yet
+    pending_sync: Option<(U256, U256)>,
+}
+
+impl PairSimulator {
+    pub fn new(fee_on: bool) -> Self {
+        Self { fee_on, ..Default::default() }
+    }
+
+    /// apply the next event of the pair, in (block, log index) order
+    pub fn apply(&mut self, event: &PairEvent) -> anyhow::Result<()> {
+        match event {
+            PairEvent::SyncPairEvent(sync) => {
+                // a Sync directly after another Sync came from `sync()` or `skim()`
+                self.flush();
+                self.pending_sync = Some((sync.reserve0, sync.reserve1));
+            }
+            PairEvent::MintPairEvent(mint) => {
+                let (new0, new1) = self.take_sync("Mint")?;
+                ensure!(new0 == self.reserve0 + mint.amount0 && new1 == self.reserve1 + mint.amount1,
+                    "Mint {:?} amounts ({}, {}) don't match reserves ({}, {}) -> ({}, {})",
+                    mint.meta.tx_hash, mint.amount0, mint.amount1, self.reserve0, self.reserve1, new0, new1);
+                self.mint_fee();
+                let liquidity = if self.total_supply.is_zero() {
+                    let liquidity = (mint.amount0 * mint.amount1).integer_sqrt();
+                    ensure!(liquidity > MINIMUM_LIQUIDITY.into(),
+                        "Mint {:?} first liquidity below the minimum", mint.meta.tx_hash);
+                    self.total_supply = MINIMUM_LIQUIDITY.into();
+                    liquidity - MINIMUM_LIQUIDITY
+                } else {
+                    ensure!(!self.reserve0.is_zero() && !self.reserve1.is_zero(),
+                        "Mint {:?} into a pair with supply but no reserves", mint.meta.tx_hash);
+                    std::cmp::min(mint.amount0 * self.total_supply / self.reserve0,
+                                  mint.amount1 * self.total_supply / self.reserve1)
+                };
+                ensure!(!liquidity.is_zero(), "Mint {:?} mints no liquidity", mint.meta.tx_hash);
+                self.total_supply += liquidity;
+                self.update(new0, new1);
+                self.mint_count += 1;
+            }
+            PairEvent::BurnPairEvent(burn) => {
+                let (new0, new1) = self.take_sync("Burn")?;
+                // the pair pays out a share of its balance, which can exceed the reserves by donations
+                ensure!(new0 + burn.amount0 >= self.reserve0 && new1 + burn.amount1 >= self.reserve1,
+                    "Burn {:?} amounts ({}, {}) don't match reserves ({}, {}) -> ({}, {})",
+                    burn.meta.tx_hash, burn.amount0, burn.amount1, self.reserve0, self.reserve1, new0, new1);
+                ensure!(!self.total_supply.is_zero(), "Burn {:?} with no liquidity", burn.meta.tx_hash);
+                self.mint_fee();
+                let balance0 = new0 + burn.amount0;
+                let liquidity = if balance0.is_zero() {
+                    U256::zero()
+                } else {
+                    burn.amount0 * self.total_supply / balance0
+                };
+                ensure!(liquidity <= self.total_supply, "Burn {:?} burns more than the supply", burn.meta.tx_hash);
+                self.total_supply -= liquidity;
+                self.update(new0, new1);
+                self.burn_count += 1;
+            }
+            PairEvent::SwapPairEvent(swap) => {
+                let (new0, new1) = self.take_sync("Swap")?;
+                ensure!(!swap.amount0_out.is_zero() || !swap.amount1_out.is_zero(),
+                    "Swap {:?} has no output", swap.meta.tx_hash);
+                ensure!(swap.amount0_out < self.reserve0 && swap.amount1_out < self.reserve1,
+                    "Swap {:?} output exceeds the reserves", swap.meta.tx_hash);
+                ensure!(!swap.amount0_in.is_zero() || !swap.amount1_in.is_zero(),
+                    "Swap {:?} has no input", swap.meta.tx_hash);
+                ensure!(new0 == self.reserve0 + swap.amount0_in - swap.amount0_out
+                        && new1 == self.reserve1 + swap.amount1_in - swap.amount1_out,
+                    "Swap {:?} amounts don't match reserves ({}, {}) -> ({}, {})",
+                    swap.meta.tx_hash, self.reserve0, self.reserve1, new0, new1);
+                let adjusted0 = new0 * BPS - swap.amount0_in * FEE_BPS;
+                let adjusted1 = new1 * BPS - swap.amount1_in * FEE_BPS;
+                ensure!(adjusted0 * adjusted1 >= self.reserve0 * self.reserve1 * BPS * BPS,
+                    "Swap {:?} breaks the constant product", swap.meta.tx_hash);
+                self.reserve0 = new0;
+                self.reserve1 = new1;
+                self.swap_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// apply a trailing standalone `Sync`, call after the last event
+    pub fn flush(&mut self) {
+        if let Some((reserve0, reserve1)) = self.pending_sync.take() {
+            self.reserve0 = reserve0;
+            self.reserve1 = reserve1;
+        }
+    }
+
+    fn take_sync(&mut self, event: &str) -> anyhow::Result<(U256, U256)> {
+        self.pending_sync.take().ok_or_else(|| format_err!("{} without a preceding Sync", event))
+    }
+
+    fn update(&mut self, reserve0: U256, reserve1: U256) {
+        self.reserve0 = reserve0;
+        self.reserve1 = reserve1;
+        if self.fee_on {
+            self.k_last = reserve0 * reserve1;
+        }
+    }
+
+    /// `UniswapV2Pair._mintFee`
+    fn mint_fee(&mut self) {
+        if !self.fee_on {
+            self.k_last = U256::zero();
+            return;
+        }
+        if self.k_last.is_zero() {
+            return;
+        }
+        let root_k = (self.reserve0 * self.reserve1).integer_sqrt();
+        let root_k_last = self.k_last.integer_sqrt();
+        if root_k > root_k_last {
+            let liquidity = self.total_supply * (root_k - root_k_last) / (root_k * 5 + root_k_last);
+            self.total_supply += liquidity;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+    use web3::types::H160;
+    use crate::watcher::mock_rpc::MockChain;
+    use crate::protocol::uniswap_v2::UniswapV2;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        /// the first mint uses the amounts as is, later ones add a percentage of the
+        /// reserves plus a donation of token1 so the pair would never revert
+        Mint(u64, u64),
+        Burn(u8),
+        Swap(u64, bool, u64),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            (1_001u64..u64::MAX, 1_001u64..u64::MAX).prop_map(|(a, b)| Op::Mint(a, b)),
+            (1u8..100).prop_map(Op::Burn),
+            (1u64..u64::MAX, any::<bool>(), any::<u64>()).prop_map(|(a, d, to)| Op::Swap(a, d, to)),
+        ]
+    }
+
+    #[test]
+    fn test_amounts() {
+        let out = get_amount_out(U256::exp10(18), U256::exp10(21), U256::exp10(9) * 2, FEE_BPS).unwrap();
+        assert_eq!(out, U256::from(1_992_013u64));
+        let amount_in = get_amount_in(out, U256::exp10(21), U256::exp10(9) * 2, FEE_BPS).unwrap();
+        assert!(amount_in <= U256::exp10(18));
+        assert!(get_amount_out(amount_in, U256::exp10(21), U256::exp10(9) * 2, FEE_BPS).unwrap() >= out);
+    }
+
+    proptest! {
+        /// encode random operations as pair logs, decode them with the adapter
+        /// and check the replayed state and the decoded accounts
+        #[test]
+        fn test_replay_decoded_events(ops in proptest::collection::vec(op(), 1..40)) {
+            let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+            let token0 = chain.deploy_token("A", 18);
+            let token1 = chain.deploy_token("B", 18);
+            let pair = chain.create_pair(token0, token1);
+            let sender = H160::from_low_u64_be(0x5e);
+            let mut recipients = Vec::new();
+            for op in ops {
+                let state = chain.pairs[&pair].clone();
+                match op {
+                    Op::Mint(a, b) if state.reserve0.is_zero() => {
+                        chain.mint(pair, sender, a.into(), b.into());
+                    }
+                    Op::Mint(a, b) => {
+                        let percent = a % 100 + 1;
+                        let amount0 = state.reserve0 * percent / 100;
+                        let amount1 = state.reserve1 * percent / 100 + b % 1000;
+                        // a pair reverts a mint of no liquidity
+                        if state.reserve0.is_zero() || state.reserve1.is_zero()
+                            || (amount0 * state.total_supply / state.reserve0).is_zero()
+                            || (amount1 * state.total_supply / state.reserve1).is_zero() {
+                            continue;
+                        }
+                        chain.mint(pair, sender, amount0, amount1);
+                    }
+                    Op::Burn(percent) if !state.reserve0.is_zero() => {
+                        let amount0 = state.reserve0 * percent / 100;
+                        let amount1 = state.reserve1 * percent / 100;
+                        chain.burn(pair, sender, sender, amount0, amount1);
+                    }
+                    Op::Swap(amount, zero_for_one, to) if !state.reserve0.is_zero() => {
+                        let (reserve_in, reserve_out) = if zero_for_one {
+                            (state.reserve0, state.reserve1)
+                        } else {
+                            (state.reserve1, state.reserve0)
+                        };
+                        if get_amount_out(amount.into(), reserve_in, reserve_out, FEE_BPS).map(|o| o.is_zero()).unwrap_or(true) {
+                            continue;
+                        }
+                        let to = H160::from_low_u64_be(to);
+                        chain.swap(pair, sender, to, amount.into(), zero_for_one);
+                        recipients.push(to);
+                    }
+                    _ => {}
+                }
+            }
+
+            let mut simulator = PairSimulator::new(false);
+            let mut swaps = Vec::new();
+            // the LP token transfers aren't pair events
+            let protocol = UniswapV2::new();
+            let transfer = UniswapV2::embedded_topics().topic("transfer");
+            for log in chain.logs.iter().filter(|l| l.address == pair && l.topics[0] != transfer) {
+                let event = protocol.decode_pair_log(log).unwrap();
+                simulator.apply(&event).unwrap();
+                if let PairEvent::SwapPairEvent(swap) = event {
+                    prop_assert_eq!(swap.sender, sender);
+                    swaps.push(swap.to);
+                }
+            }
+            simulator.flush();
+            prop_assert_eq!(swaps, recipients);
+            prop_assert_eq!(simulator.reserve0, chain.pairs[&pair].reserve0);
+            prop_assert_eq!(simulator.reserve1, chain.pairs[&pair].reserve1);
+        }
+    }
+}
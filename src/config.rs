@@ -1,6 +1,8 @@
 use std::env;
 use serde::Deserialize;
 use web3::types::H160;
+use crate::amm::v2::FEE_BPS;
+use crate::watcher::multicall::MULTICALL3_ADDRESS;
 
 #[derive(Default, Debug, Deserialize, Clone)]
 pub struct BackendConfig {
@@ -8,9 +10,68 @@ pub struct BackendConfig {
     pub database_url: String,
     pub db_pool_size: u16,
     pub remote_web3_url: String,
+    /// seconds before a request to the node times out, `0` for none
+    pub rpc_timeout_secs: u64,
+    /// how many times a request failing on the node is sent again
+    pub rpc_retries: u32,
+    /// the wait before the first retry, doubled before each next one
+    pub rpc_retry_backoff_ms: u64,
+    /// how deep a block must be to be synced, the blocks a reorg can drop are left out
+    pub confirmations: u64,
     pub watch_time_interval: u32,
     pub workers_number: u16,
     pub contract_address: H160,
+    /// the protocol adapter of the factory at `contract_address`
+    pub protocol: String,
+    /// a Uniswap V3 factory indexed next to the one at `contract_address`
+    pub v3_factory_address: Option<H160>,
+    /// ABIs and event names replacing the embedded ones of `protocol`, for forks
+    pub factory_abi_file: Option<String>,
+    pub pool_abi_file: Option<String>,
+    pub event_names_file: Option<String>,
+    pub replay_log_file: Option<String>,
+    pub record_log_file: Option<String>,
+    pub reconcile_interval: u32,
+    pub reconcile_sample_size: u16,
+    pub reconcile_reindex: bool,
+    pub token_cache_size: u32,
+    /// Multicall3 used to batch view calls, json-rpc batches are used without it
+    pub multicall_address: Option<H160>,
+    pub multicall_batch_size: u32,
+    /// stablecoins priced at one usd, every other usd price is routed from them
+    pub usd_anchors: Vec<H160>,
+    /// wrapped native tokens, priced from the anchors only and preferred as quotes after them
+    pub native_tokens: Vec<H160>,
+    /// the usd liquidity a pool needs to set the price of a token
+    pub min_price_liquidity_usd: u64,
+    /// the swap fees in basis points of V2 factories charging another one than `FEE_BPS`
+    pub v2_fees: Vec<(H160, u64)>,
+    /// the bearer token of the `/admin` routes, which are refused without one
+    pub admin_token: Option<String>,
+}
+
+/// comma separated addresses with or without `0x`
+fn addresses_from_env(name: &str) -> Vec<H160> {
+    env::var(name).unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(|a| H160::from_slice(&hex::decode(a.trim_start_matches("0x")).unwrap()))
+        .collect()
+}
+
+/// comma separated `address:bps` pairs, with or without `0x`
+fn fees_from_env(name: &str) -> Vec<(H160, u64)> {
+    env::var(name).unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|f| {
+            let (address, bps) = f.split_once(':').expect("a fee is set as address:bps");
+            (H160::from_slice(&hex::decode(address.trim().trim_start_matches("0x")).unwrap()),
+             bps.trim().parse::<u64>().unwrap())
+        })
+        .collect()
 }
 
 impl BackendConfig {
@@ -19,6 +80,14 @@ impl BackendConfig {
             .parse::<u16>().unwrap_or(8088u16);
         let database_url = env::var("DATABASE_URL").unwrap_or_default();
         let remote_web3_url = env::var("REMOTE_WEB3_URL").unwrap_or_default();
+        let rpc_timeout_secs = env::var("RPC_TIMEOUT_SECS").unwrap_or_default()
+            .parse::<u64>().unwrap_or(30u64);
+        let rpc_retries = env::var("RPC_RETRIES").unwrap_or_default()
+            .parse::<u32>().unwrap_or(3u32);
+        let rpc_retry_backoff_ms = env::var("RPC_RETRY_BACKOFF_MS").unwrap_or_default()
+            .parse::<u64>().unwrap_or(500u64);
+        let confirmations = env::var("CONFIRMATIONS").unwrap_or_default()
+            .parse::<u64>().unwrap_or(12u64);
         let watch_time_interval = env::var("WATCH_TIME_INTERVAL").unwrap_or_default()
             .parse::<u32>().unwrap_or(60u32);
         let workers_number = env::var("WORKERS_NUMBER").unwrap_or_default()
@@ -26,14 +95,75 @@ impl BackendConfig {
         let db_pool_size = env::var("DB_POOL_SIZE").unwrap_or_default()
             .parse::<u16>().unwrap_or(1u16);
         let contract_address = env::var("CONTRACT_ADDRESS").unwrap_or_default();
+        let protocol = env::var("PROTOCOL").unwrap_or_else(|_| String::from("uniswap_v2"));
+        let v3_factory_address = env::var("V3_FACTORY_ADDRESS").ok()
+            .filter(|a| !a.is_empty())
+            .map(|a| H160::from_slice(&hex::decode(a.trim_start_matches("0x")).unwrap()));
+        let factory_abi_file = env::var("FACTORY_ABI_FILE").ok().filter(|f| !f.is_empty());
+        let pool_abi_file = env::var("POOL_ABI_FILE").ok().filter(|f| !f.is_empty());
+        let event_names_file = env::var("EVENT_NAMES_FILE").ok().filter(|f| !f.is_empty());
+        let replay_log_file = env::var("REPLAY_LOG_FILE").ok().filter(|f| !f.is_empty());
+        let record_log_file = env::var("RECORD_LOG_FILE").ok().filter(|f| !f.is_empty());
+        let reconcile_interval = env::var("RECONCILE_INTERVAL").unwrap_or_default()
+            .parse::<u32>().unwrap_or(600u32);
+        let reconcile_sample_size = env::var("RECONCILE_SAMPLE_SIZE").unwrap_or_default()
+            .parse::<u16>().unwrap_or(20u16);
+        let reconcile_reindex = env::var("RECONCILE_REINDEX").unwrap_or_default()
+            .parse::<bool>().unwrap_or(false);
+        let token_cache_size = env::var("TOKEN_CACHE_SIZE").unwrap_or_default()
+            .parse::<u32>().unwrap_or(10000u32);
+        // the canonical Multicall3 unless set, an empty value disables it
+        let multicall_address = match env::var("MULTICALL_ADDRESS") {
+            Err(_) => Some(H160::from_slice(&hex::decode(MULTICALL3_ADDRESS).unwrap())),
+            Ok(address) if address.is_empty() => None,
+            Ok(address) => Some(H160::from_slice(&hex::decode(address.trim_start_matches("0x")).unwrap())),
+        };
+        let multicall_batch_size = env::var("MULTICALL_BATCH_SIZE").unwrap_or_default()
+            .parse::<u32>().unwrap_or(500u32);
+        let usd_anchors = addresses_from_env("USD_ANCHORS");
+        let native_tokens = addresses_from_env("NATIVE_TOKENS");
+        let min_price_liquidity_usd = env::var("MIN_PRICE_LIQUIDITY_USD").unwrap_or_default()
+            .parse::<u64>().unwrap_or(10000u64);
+        let v2_fees = fees_from_env("V2_FEES");
+        let admin_token = env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
         Self {
             server_port,
             database_url,
             remote_web3_url,
+            rpc_timeout_secs,
+            rpc_retries,
+            rpc_retry_backoff_ms,
+            confirmations,
             watch_time_interval,
             workers_number,
             db_pool_size,
-            contract_address: H160::from_slice(&hex::decode(contract_address).unwrap())
+            contract_address: H160::from_slice(&hex::decode(contract_address).unwrap()),
+            protocol,
+            v3_factory_address,
+            factory_abi_file,
+            pool_abi_file,
+            event_names_file,
+            replay_log_file,
+            record_log_file,
+            reconcile_interval,
+            reconcile_sample_size,
+            reconcile_reindex,
+            token_cache_size,
+            multicall_address,
+            multicall_batch_size,
+            usd_anchors,
+            native_tokens,
+            min_price_liquidity_usd,
+            v2_fees,
+            admin_token,
         }
     }
+
+    /// the swap fee in basis points of the V2 pairs of `factory`, a stored factory address
+    pub fn v2_fee_bps(&self, factory: Option<&str>) -> u64 {
+        self.v2_fees.iter()
+            .find(|(address, _)| Some(hex::encode(address).as_str()) == factory)
+            .map(|(_, bps)| *bps)
+            .unwrap_or(FEE_BPS)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,164 @@
+//! The activity of an account across pools: the events it sent, received or sent the
+//! transaction of.
+use std::collections::{BTreeMap, BTreeSet};
+use rbatis::Rbatis;
+use rbatis::rbdc::decimal::Decimal;
+use web3::types::U256;
+
+/// The block number and time of an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activity {
+    pub block_number: i64,
+    pub block_timestamp: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountSummary {
+    pub swap_count: i64,
+    pub add_liq_count: i64,
+    pub rm_liq_count: i64,
+    /// the amounts in and out of the swaps, by token address
+    pub volume: BTreeMap<String, U256>,
+    pub pools: BTreeSet<String>,
+    pub first_activity: Option<Activity>,
+    pub last_activity: Option<Activity>,
+}
+
+pub async fn get_account_summary(rb: &Rbatis, account: &str) -> anyhow::Result<AccountSummary> {
+    #[derive(serde::Deserialize)]
+    struct Row {
+        event_type: i8,
+        pair_address: String,
+        block_number: i64,
+        block_timestamp: Option<i64>,
+        amount0_in: Option<Decimal>,
+        amount1_in: Option<Decimal>,
+        amount0_out: Option<Decimal>,
+        amount1_out: Option<Decimal>,
+        token_x_address: String,
+        token_y_address: String,
+    }
+    // the amounts are summed here, sqlite would sum them as floats
+    let rows: Vec<Row> = rb
+        .query_decode("select e.event_type, e.pair_address, e.block_number, e.block_timestamp, \
+                       e.amount0_in, e.amount1_in, e.amount0_out, e.amount1_out, \
+                       p.token_x_address, p.token_y_address \
+                       from events e join pool_info p on p.pair_address = e.pair_address \
+                       where (e.from_account = ? or e.to_account = ? or e.tx_origin = ?) \
+                       and e.event_type in (1, 2, 3) and e.block_number is not null \
+                       order by e.block_number, e.log_index",
+                      vec![rbs::to_value!(account), rbs::to_value!(account), rbs::to_value!(account)])
+        .await?;
+    let amount = |value: &Option<Decimal>| -> anyhow::Result<U256> {
+        match value {
+            Some(value) => Ok(U256::from_dec_str(&value.0)?),
+            None => Ok(U256::zero()),
+        }
+    };
+    let mut summary = AccountSummary::default();
+    for row in rows {
+        match row.event_type {
+            1 => summary.add_liq_count += 1,
+            2 => summary.rm_liq_count += 1,
+            _ => {
+                summary.swap_count += 1;
+                let volume_x = amount(&row.amount0_in)? + amount(&row.amount0_out)?;
+                let volume_y = amount(&row.amount1_in)? + amount(&row.amount1_out)?;
+                *summary.volume.entry(row.token_x_address).or_default() += volume_x;
+                *summary.volume.entry(row.token_y_address).or_default() += volume_y;
+            }
+        }
+        let activity = Activity { block_number: row.block_number, block_timestamp: row.block_timestamp };
+        summary.first_activity.get_or_insert(activity);
+        summary.last_activity = Some(activity);
+        summary.pools.insert(row.pair_address);
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+    use crate::db::{save_pool, sqlite};
+    use crate::db::tables::{Event, PoolInfo};
+
+    fn pool(address: u8) -> PoolInfo {
+        PoolInfo {
+            pair_address: hex::encode([address; 20]),
+            token_x_symbol: "TKA".to_string(),
+            token_y_symbol: "WETH".to_string(),
+            token_x_address: hex::encode([address + 100; 20]),
+            token_y_address: hex::encode([0xee; 20]),
+            token_x_reserves: Decimal::from_str("0").unwrap(),
+            token_y_reserves: Decimal::from_str("0").unwrap(),
+            total_swap_count: 0,
+            total_add_liq_count: 0,
+            total_rm_liq_count: 0,
+            protocol: "uniswap_v2".to_string(),
+            factory_address: None,
+            lp_total_supply: None,
+            tvl_usd: None,
+            volume_usd: None,
+            created_block: None,
+        }
+    }
+
+    fn event(pool: u8, block: i64, event_type: i8, (from, to, origin): (u8, u8, u8), amount0_in: u64, amount1_out: u64) -> Event {
+        let decimal = |amount: u64| Some(Decimal::from_str(&amount.to_string()).unwrap());
+        Event {
+            tx_hash: hex::encode([block as u8; 32]),
+            event_type,
+            pair_address: hex::encode([pool; 20]),
+            block_number: Some(block),
+            log_index: Some(0),
+            block_timestamp: Some(block * 10),
+            tx_origin: Some(hex::encode([origin; 20])),
+            from_account: hex::encode([from; 20]),
+            to_account: Some(hex::encode([to; 20])),
+            amount_x: None,
+            amount_y: None,
+            lp_amount: None,
+            amount0_in: decimal(amount0_in),
+            amount1_in: decimal(0),
+            amount0_out: decimal(0),
+            amount1_out: decimal(amount1_out),
+            direction: None,
+            flash: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_account_summary() {
+        let mut rb = sqlite::new_test_db("account_summary").await;
+        save_pool(&mut rb, &pool(1)).await.unwrap();
+        save_pool(&mut rb, &pool(2)).await.unwrap();
+        let (router, user) = (0x0a, 0x0b);
+        for event in [
+            // through a router, as the recipient and as the sender of the transaction
+            event(1, 5, 3, (router, user, user), 100, 7),
+            event(2, 3, 3, (router, router, user), 10, 1),
+            event(2, 8, 1, (user, user, 0x0c), 0, 0),
+            // someone else's swap
+            event(1, 9, 3, (router, 0x0c, 0x0c), 1000, 1000),
+        ] {
+            Event::insert(&mut rb, &event).await.unwrap();
+        }
+        let summary = get_account_summary(&rb, &hex::encode([user; 20])).await.unwrap();
+        assert_eq!(summary, AccountSummary {
+            swap_count: 2,
+            add_liq_count: 1,
+            rm_liq_count: 0,
+            volume: BTreeMap::from([
+                (hex::encode([101; 20]), U256::from(100)),
+                (hex::encode([102; 20]), U256::from(10)),
+                (hex::encode([0xee; 20]), U256::from(8)),
+            ]),
+            pools: BTreeSet::from([hex::encode([1; 20]), hex::encode([2; 20])]),
+            first_activity: Some(Activity { block_number: 3, block_timestamp: Some(30) }),
+            last_activity: Some(Activity { block_number: 8, block_timestamp: Some(80) }),
+        });
+        let nobody = get_account_summary(&rb, &hex::encode([0x0d; 20])).await.unwrap();
+        assert_eq!(nobody, AccountSummary::default());
+    }
+}
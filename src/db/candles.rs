@@ -0,0 +1,304 @@
+//! OHLCV candles of pools. The watcher turns the swaps and price changes of every batch of
+//! events it stores into `CandlePoint`s and merges them into the stored candles of each
+//! interval in the same transaction. V2 prices come from the reserves of Sync events, V3
+//! prices from the square root price of swaps.
+use std::collections::{BTreeMap, BTreeSet};
+use rbatis::Rbatis;
+use rbatis::executor::RBatisTxExecutor;
+use rbatis::rbdc::decimal::Decimal;
+use std::str::FromStr;
+use web3::types::U256;
+use crate::amm::units::mul_div;
+use crate::db::tables::PoolCandle;
+use crate::protocol::uniswap_v3::V3PoolEvent;
+use crate::watcher::event::{EventData, PairEvent};
+
+/// the intervals of the candles by their api name, in seconds
+pub const INTERVALS: [(&str, i64); 6] = [
+    ("1m", 60), ("5m", 300), ("1h", 3600), ("4h", 4 * 3600), ("1d", 24 * 3600), ("1w", WEEK),
+];
+const WEEK: i64 = 7 * 24 * 3600;
+/// 1970-01-05, the first monday after the epoch, weekly candles open on mondays
+const FIRST_MONDAY: i64 = 4 * 24 * 3600;
+/// the decimals of a stored price, a raw price of 10^-30 keeps 6 digits
+pub const PRICE_SCALE_DECIMALS: u8 = 36;
+
+pub fn price_scale() -> U256 {
+    U256::exp10(PRICE_SCALE_DECIMALS as usize)
+}
+
+/// the unix time the candle of `interval` seconds containing `timestamp` opens at
+pub fn open_time(timestamp: i64, interval: i64) -> i64 {
+    if interval == WEEK {
+        (timestamp - FIRST_MONDAY).div_euclid(WEEK) * WEEK + FIRST_MONDAY
+    } else {
+        timestamp.div_euclid(interval) * interval
+    }
+}
+
+/// A price, a swap or both at a position in the chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandlePoint {
+    pub block_number: i64,
+    pub log_index: i64,
+    pub timestamp: i64,
+    /// the raw price of token x in token y after the event, with `PRICE_SCALE_DECIMALS`
+    pub price: Option<U256>,
+    pub volume_x: U256,
+    pub volume_y: U256,
+    pub swap: bool,
+}
+
+impl CandlePoint {
+    /// `None` for an event without its block time
+    fn at(meta: &EventData, price: Option<U256>, volume_x: U256, volume_y: U256, swap: bool) -> Option<Self> {
+        Some(Self {
+            block_number: meta.block_number as i64,
+            log_index: meta.log_index as i64,
+            timestamp: meta.block_timestamp? as i64,
+            price,
+            volume_x,
+            volume_y,
+            swap,
+        })
+    }
+
+    /// the price of a Sync or the volume of a swap of a V2 pair
+    pub fn of_pair_event(event: &PairEvent) -> Option<Self> {
+        match event {
+            PairEvent::SyncPairEvent(sync) => {
+                let price = mul_div(sync.reserve1, price_scale(), sync.reserve0)?;
+                Self::at(&sync.meta, Some(price), U256::zero(), U256::zero(), false)
+            }
+            PairEvent::SwapPairEvent(swap) => Self::at(
+                &swap.meta,
+                None,
+                swap.amount0_in.saturating_add(swap.amount0_out),
+                swap.amount1_in.saturating_add(swap.amount1_out),
+                true,
+            ),
+            _ => None,
+        }
+    }
+
+    /// the price after and the volume of a swap of a V3 pool
+    pub fn of_v3_event(event: &V3PoolEvent) -> Option<Self> {
+        match event {
+            V3PoolEvent::Swap { meta, amount0, amount1, sqrt_price_x96, .. } => {
+                // the square of a Q64.96 as a Q128.128, then scaled
+                let ratio_x128 = mul_div(*sqrt_price_x96, *sqrt_price_x96, U256::one() << 64)?;
+                let price = mul_div(ratio_x128, price_scale(), U256::one() << 128)?;
+                Self::at(meta, Some(price), U256::from(amount0.unsigned_abs()),
+                         U256::from(amount1.unsigned_abs()), true)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candle {
+    pub open_time: i64,
+    /// `None` in a candle of swaps whose price changes aren't stored yet
+    pub open: Option<U256>,
+    pub high: Option<U256>,
+    pub low: Option<U256>,
+    pub close: Option<U256>,
+    /// the block number and log index of the open and close prices
+    open_position: Option<(i64, i64)>,
+    close_position: Option<(i64, i64)>,
+    pub volume_x: U256,
+    pub volume_y: U256,
+    pub swap_count: i64,
+}
+
+impl Candle {
+    fn new(open_time: i64) -> Self {
+        Self {
+            open_time,
+            open: None,
+            high: None,
+            low: None,
+            close: None,
+            open_position: None,
+            close_position: None,
+            volume_x: U256::zero(),
+            volume_y: U256::zero(),
+            swap_count: 0,
+        }
+    }
+
+    fn add(&mut self, point: &CandlePoint) {
+        if let Some(price) = point.price {
+            let position = (point.block_number, point.log_index);
+            if self.open_position.is_none_or(|open| position < open) {
+                self.open = Some(price);
+                self.open_position = Some(position);
+            }
+            if self.close_position.is_none_or(|close| position > close) {
+                self.close = Some(price);
+                self.close_position = Some(position);
+            }
+            self.high = Some(self.high.map_or(price, |high| high.max(price)));
+            self.low = Some(self.low.map_or(price, |low| low.min(price)));
+        }
+        self.volume_x = self.volume_x.saturating_add(point.volume_x);
+        self.volume_y = self.volume_y.saturating_add(point.volume_y);
+        if point.swap {
+            self.swap_count += 1;
+        }
+    }
+
+    fn from_row(row: &PoolCandle) -> anyhow::Result<Self> {
+        let price = |value: &Option<Decimal>| -> anyhow::Result<Option<U256>> {
+            match value {
+                Some(value) => Ok(Some(U256::from_dec_str(&value.0)?)),
+                None => Ok(None),
+            }
+        };
+        let position = |block: Option<i64>, log_index: Option<i64>| Some((block?, log_index?));
+        Ok(Self {
+            open_time: row.open_time,
+            open: price(&row.price_open)?,
+            high: price(&row.price_high)?,
+            low: price(&row.price_low)?,
+            close: price(&row.price_close)?,
+            open_position: position(row.open_block, row.open_log_index),
+            close_position: position(row.close_block, row.close_log_index),
+            volume_x: U256::from_dec_str(&row.volume_x.0)?,
+            volume_y: U256::from_dec_str(&row.volume_y.0)?,
+            swap_count: row.swap_count,
+        })
+    }
+
+    fn to_row(&self, pair_address: &str, interval: i64) -> PoolCandle {
+        let decimal = |value: U256| Decimal::from_str(&value.to_string()).unwrap();
+        PoolCandle {
+            pair_address: pair_address.to_string(),
+            interval_secs: interval,
+            open_time: self.open_time,
+            price_open: self.open.map(decimal),
+            price_high: self.high.map(decimal),
+            price_low: self.low.map(decimal),
+            price_close: self.close.map(decimal),
+            open_block: self.open_position.map(|(block, _)| block),
+            open_log_index: self.open_position.map(|(_, log_index)| log_index),
+            close_block: self.close_position.map(|(block, _)| block),
+            close_log_index: self.close_position.map(|(_, log_index)| log_index),
+            volume_x: decimal(self.volume_x),
+            volume_y: decimal(self.volume_y),
+            swap_count: self.swap_count,
+        }
+    }
+}
+
+/// merge the points of a pool into its stored candles of every interval
+pub(crate) async fn update_candles(tx: &mut RBatisTxExecutor, pair_address: &str, points: &[CandlePoint])
+    -> anyhow::Result<()> {
+    let (Some(first), Some(last)) = (points.iter().map(|p| p.timestamp).min(), points.iter().map(|p| p.timestamp).max()) else {
+        return Ok(());
+    };
+    for (_, interval) in INTERVALS {
+        let rows: Vec<PoolCandle> = tx
+            .query_decode("select * from pool_candles where pair_address = ? and interval_secs = ? \
+                           and open_time >= ? and open_time <= ?",
+                          vec![rbs::to_value!(pair_address), rbs::to_value!(interval),
+                               rbs::to_value!(open_time(first, interval)), rbs::to_value!(open_time(last, interval))])
+            .await?;
+        let mut candles = rows.iter()
+            .map(|row| Ok((row.open_time, Candle::from_row(row)?)))
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+        let mut updated = BTreeSet::new();
+        for point in points {
+            let open = open_time(point.timestamp, interval);
+            candles.entry(open).or_insert_with(|| Candle::new(open)).add(point);
+            updated.insert(open);
+        }
+        for open in updated {
+            let row = candles[&open].to_row(pair_address, interval);
+            tx.exec("insert into pool_candles (pair_address, interval_secs, open_time, price_open, price_high, \
+                    price_low, price_close, open_block, open_log_index, close_block, close_log_index, volume_x, \
+                    volume_y, swap_count) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+                    on conflict (pair_address, interval_secs, open_time) do update set \
+                    price_open = excluded.price_open, price_high = excluded.price_high, \
+                    price_low = excluded.price_low, price_close = excluded.price_close, \
+                    open_block = excluded.open_block, open_log_index = excluded.open_log_index, \
+                    close_block = excluded.close_block, close_log_index = excluded.close_log_index, \
+                    volume_x = excluded.volume_x, volume_y = excluded.volume_y, swap_count = excluded.swap_count",
+                    vec![rbs::to_value!(row.pair_address), rbs::to_value!(row.interval_secs),
+                         rbs::to_value!(row.open_time), rbs::to_value!(row.price_open),
+                         rbs::to_value!(row.price_high), rbs::to_value!(row.price_low),
+                         rbs::to_value!(row.price_close), rbs::to_value!(row.open_block),
+                         rbs::to_value!(row.open_log_index), rbs::to_value!(row.close_block),
+                         rbs::to_value!(row.close_log_index), rbs::to_value!(row.volume_x),
+                         rbs::to_value!(row.volume_y), rbs::to_value!(row.swap_count)])
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// the candles of a pool of `interval` seconds opening from `from` to `to`, oldest first.
+/// Intervals without any event have no candle.
+pub async fn get_candles(rb: &Rbatis, pair_address: &str, interval: i64, from: i64, to: i64)
+    -> anyhow::Result<Vec<Candle>> {
+    let rows: Vec<PoolCandle> = rb
+        .query_decode("select * from pool_candles where pair_address = ? and interval_secs = ? \
+                       and open_time >= ? and open_time <= ? order by open_time",
+                      vec![rbs::to_value!(pair_address), rbs::to_value!(interval),
+                           rbs::to_value!(from), rbs::to_value!(to)])
+        .await?;
+    rows.iter().map(Candle::from_row).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{finish, sqlite};
+
+    fn point(block: i64, timestamp: i64, price: Option<u64>, volume_x: u64) -> CandlePoint {
+        CandlePoint {
+            block_number: block,
+            log_index: 0,
+            timestamp,
+            price: price.map(U256::from),
+            volume_x: U256::from(volume_x),
+            volume_y: U256::zero(),
+            swap: volume_x > 0,
+        }
+    }
+
+    #[test]
+    fn test_open_time() {
+        assert_eq!(open_time(119, 60), 60);
+        assert_eq!(open_time(4 * 3600 + 1, 4 * 3600), 4 * 3600);
+        // 2023-11-14 (a tuesday) is in the week of monday 2023-11-13
+        assert_eq!(open_time(1_700_000_000, WEEK), 1_699_833_600);
+        assert_eq!(open_time(FIRST_MONDAY - 1, WEEK), FIRST_MONDAY - WEEK);
+    }
+
+    #[tokio::test]
+    async fn test_update_candles() {
+        let rb = sqlite::new_test_db("update_candles").await;
+        let pair = hex::encode([1; 20]);
+        // swaps of a range stored before its prices, then a later range
+        for points in [
+            vec![point(1, 10, None, 5), point(3, 70, None, 1)],
+            vec![point(1, 10, Some(7), 0), point(2, 30, Some(3), 0), point(3, 70, Some(9), 0)],
+            vec![point(4, 80, Some(4), 2)],
+        ] {
+            let mut tx = rb.acquire_begin().await.unwrap();
+            let result = update_candles(&mut tx, &pair, &points).await;
+            finish(tx, result).await.unwrap();
+        }
+
+        let ohlc = |c: &Candle| [c.open, c.high, c.low, c.close].map(|p| p.unwrap().as_u64());
+        let minutes = get_candles(&rb, &pair, 60, 0, 120).await.unwrap();
+        assert_eq!(minutes.iter().map(|c| (c.open_time, ohlc(c), c.volume_x.as_u64(), c.swap_count)).collect::<Vec<_>>(),
+                   vec![(0, [7, 7, 3, 3], 5, 1), (60, [9, 9, 4, 4], 3, 2)]);
+        let hours = get_candles(&rb, &pair, 3600, 0, 0).await.unwrap();
+        assert_eq!(hours.iter().map(|c| (ohlc(c), c.volume_x.as_u64(), c.swap_count)).collect::<Vec<_>>(),
+                   vec![([7, 9, 3, 4], 8, 3)]);
+        assert!(get_candles(&rb, &pair, 60, 121, 1000).await.unwrap().is_empty());
+    }
+}
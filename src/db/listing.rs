@@ -0,0 +1,540 @@
+//! Paged reads behind the `/v1` api. A page is ordered by a sort column and a unique key,
+//! and the next page starts after the `Cursor` of the last row, so pages stay stable while
+//! the watcher inserts rows.
+use std::str::FromStr;
+use anyhow::format_err;
+use rbatis::Rbatis;
+use rbatis::rbdc::decimal::Decimal;
+use rbs::Value;
+use crate::db::sqlite::is_sqlite;
+use web3::types::U256;
+use crate::db::tables::{Event, PoolInfo, Token, V3Pool};
+
+/// The sort value and the unique key of the last row of a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub value: String,
+    pub key: String,
+}
+
+impl Cursor {
+    /// an opaque string for the client to send back
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}|{}", self.value, self.key))
+    }
+
+    pub fn decode(cursor: &str) -> anyhow::Result<Self> {
+        let cursor = String::from_utf8(hex::decode(cursor)?)?;
+        let (value, key) = cursor.split_once('|').ok_or_else(|| format_err!("invalid cursor"))?;
+        Ok(Self { value: value.to_string(), key: key.to_string() })
+    }
+}
+
+/// How a sort column is compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnKind {
+    /// `numeric` in postgres, text in sqlite
+    Decimal,
+    Integer,
+    Text,
+}
+
+/// the expression a column is ordered by, sqlite keeps decimals as text
+fn sort_expression(rb: &Rbatis, column: &str, kind: ColumnKind) -> String {
+    if kind == ColumnKind::Decimal && is_sqlite(rb) {
+        format!("cast({} as real)", column)
+    } else {
+        column.to_string()
+    }
+}
+
+/// a cursor value as the type of its column
+fn sort_value(rb: &Rbatis, value: &str, kind: ColumnKind) -> anyhow::Result<Value> {
+    Ok(match kind {
+        ColumnKind::Decimal if is_sqlite(rb) => rbs::to_value!(f64::from_str(value)?),
+        ColumnKind::Decimal => rbs::to_value!(Decimal::from_str(value)?),
+        ColumnKind::Integer => rbs::to_value!(i64::from_str(value)?),
+        ColumnKind::Text => rbs::to_value!(value),
+    })
+}
+
+/// what `/v1/pools` can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoolSort {
+    #[default]
+    Address,
+    ReserveX,
+    ReserveY,
+    SwapCount,
+    AddLiqCount,
+    RmLiqCount,
+    Tvl,
+    Volume,
+}
+
+impl PoolSort {
+    pub const NAMES: [&'static str; 8] = ["address", "reserve_x", "reserve_y", "swap_count", "add_liq_count",
+        "rm_liq_count", "tvl", "volume"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "address" => Some(Self::Address),
+            "reserve_x" => Some(Self::ReserveX),
+            "reserve_y" => Some(Self::ReserveY),
+            "swap_count" => Some(Self::SwapCount),
+            "add_liq_count" => Some(Self::AddLiqCount),
+            "rm_liq_count" => Some(Self::RmLiqCount),
+            "tvl" => Some(Self::Tvl),
+            "volume" => Some(Self::Volume),
+            _ => None,
+        }
+    }
+
+    fn column(&self) -> (&'static str, ColumnKind) {
+        match self {
+            Self::Address => ("pair_address", ColumnKind::Text),
+            Self::ReserveX => ("token_x_reserves", ColumnKind::Decimal),
+            Self::ReserveY => ("token_y_reserves", ColumnKind::Decimal),
+            Self::SwapCount => ("total_swap_count", ColumnKind::Integer),
+            Self::AddLiqCount => ("total_add_liq_count", ColumnKind::Integer),
+            Self::RmLiqCount => ("total_rm_liq_count", ColumnKind::Integer),
+            // pools without a usd value sort as zero
+            Self::Tvl => ("coalesce(tvl_usd, 0)", ColumnKind::Decimal),
+            Self::Volume => ("coalesce(volume_usd, 0)", ColumnKind::Decimal),
+        }
+    }
+
+    /// the sort value of a pool, for the cursor after it
+    fn value(&self, pool: &PoolInfo) -> String {
+        match self {
+            Self::Address => pool.pair_address.clone(),
+            Self::ReserveX => pool.token_x_reserves.0.clone(),
+            Self::ReserveY => pool.token_y_reserves.0.clone(),
+            Self::SwapCount => pool.total_swap_count.to_string(),
+            Self::AddLiqCount => pool.total_add_liq_count.to_string(),
+            Self::RmLiqCount => pool.total_rm_liq_count.to_string(),
+            Self::Tvl => pool.tvl_usd.as_ref().map_or("0".to_string(), |v| v.0.clone()),
+            Self::Volume => pool.volume_usd.as_ref().map_or("0".to_string(), |v| v.0.clone()),
+        }
+    }
+}
+
+/// Filters of a pool page, combined with and. Addresses are lower case hex without `0x`.
+#[derive(Debug, Clone, Default)]
+pub struct PoolQuery {
+    /// pools with this token on either side
+    pub token: Option<String>,
+    /// pools with a token of this symbol, in any case
+    pub symbol: Option<String>,
+    pub factory: Option<String>,
+    pub protocol: Option<String>,
+    pub sort: PoolSort,
+    pub descending: bool,
+    pub after: Option<Cursor>,
+    pub limit: u64,
+}
+
+/// a page of pools and the cursor of the next page, `None` after the last page
+pub async fn get_pools(rb: &Rbatis, query: &PoolQuery) -> anyhow::Result<(Vec<PoolInfo>, Option<Cursor>)> {
+    let mut sql = "select * from pool_info where 1 = 1".to_string();
+    let mut args = Vec::new();
+    if let Some(token) = &query.token {
+        sql.push_str(" and (token_x_address = ? or token_y_address = ?)");
+        args.extend([rbs::to_value!(token), rbs::to_value!(token)]);
+    }
+    if let Some(symbol) = &query.symbol {
+        sql.push_str(" and (lower(token_x_symbol) = lower(?) or lower(token_y_symbol) = lower(?))");
+        args.extend([rbs::to_value!(symbol), rbs::to_value!(symbol)]);
+    }
+    if let Some(factory) = &query.factory {
+        sql.push_str(" and factory_address = ?");
+        args.push(rbs::to_value!(factory));
+    }
+    if let Some(protocol) = &query.protocol {
+        sql.push_str(" and protocol = ?");
+        args.push(rbs::to_value!(protocol));
+    }
+    let (column, kind) = query.sort.column();
+    let order = sort_expression(rb, column, kind);
+    let (compare, direction) = if query.descending { ("<", "desc") } else { (">", "asc") };
+    if let Some(after) = &query.after {
+        sql.push_str(&format!(" and ({order} {compare} ? or ({order} = ? and pair_address > ?))"));
+        let value = sort_value(rb, &after.value, kind)?;
+        args.extend([value.clone(), value, rbs::to_value!(after.key.clone())]);
+    }
+    sql.push_str(&format!(" order by {order} {direction}, pair_address asc limit ?"));
+    args.push(rbs::to_value!(query.limit));
+
+    let pools: Vec<PoolInfo> = rb.query_decode(&sql, args).await?;
+    let next = match pools.last() {
+        Some(last) if pools.len() as u64 == query.limit => Some(Cursor {
+            value: query.sort.value(last),
+            key: last.pair_address.clone(),
+        }),
+        _ => None,
+    };
+    Ok((pools, next))
+}
+
+/// the pools of `addresses` that are indexed
+pub async fn get_pools_of(rb: &Rbatis, addresses: &[String]) -> anyhow::Result<Vec<PoolInfo>> {
+    if addresses.is_empty() {
+        return Ok(vec![]);
+    }
+    let placeholders = vec!["?"; addresses.len()].join(", ");
+    let pools: Vec<PoolInfo> = rb
+        .query_decode(&format!("select * from pool_info where pair_address in ({})", placeholders),
+                      addresses.iter().map(|a| rbs::to_value!(a)).collect())
+        .await?;
+    Ok(pools)
+}
+
+/// the concentrated liquidity state of those of `addresses` that are V3 pools
+pub async fn get_v3_pools_of(rb: &Rbatis, addresses: &[String]) -> anyhow::Result<Vec<V3Pool>> {
+    if addresses.is_empty() {
+        return Ok(vec![]);
+    }
+    let placeholders = vec!["?"; addresses.len()].join(", ");
+    let pools: Vec<V3Pool> = rb
+        .query_decode(&format!("select * from v3_pools where pair_address in ({})", placeholders),
+                      addresses.iter().map(|a| rbs::to_value!(a)).collect())
+        .await?;
+    Ok(pools)
+}
+
+/// Filters of a token page, ordered by address.
+#[derive(Debug, Clone, Default)]
+pub struct TokenQuery {
+    /// tokens whose symbol or name contains this text in any case, or with this address
+    pub search: Option<String>,
+    /// the address of the last token of the previous page
+    pub after: Option<Cursor>,
+    pub limit: u64,
+}
+
+/// a page of tokens and the cursor of the next page, `None` after the last page
+pub async fn get_tokens(rb: &Rbatis, query: &TokenQuery) -> anyhow::Result<(Vec<Token>, Option<Cursor>)> {
+    let mut sql = "select * from tokens where 1 = 1".to_string();
+    let mut args = Vec::new();
+    if let Some(search) = &query.search {
+        // `%` and `_` of the search are matched literally
+        let pattern = format!("%{}%", search.to_lowercase()
+            .replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        sql.push_str(" and (lower(symbol) like ? escape '\\' or lower(name) like ? escape '\\' or address = ?)");
+        let address = search.strip_prefix("0x").unwrap_or(search).to_lowercase();
+        args.extend([rbs::to_value!(pattern.clone()), rbs::to_value!(pattern), rbs::to_value!(address)]);
+    }
+    if let Some(after) = &query.after {
+        sql.push_str(" and address > ?");
+        args.push(rbs::to_value!(after.key.clone()));
+    }
+    sql.push_str(" order by address asc limit ?");
+    args.push(rbs::to_value!(query.limit));
+
+    let tokens: Vec<Token> = rb.query_decode(&sql, args).await?;
+    let next = match tokens.last() {
+        Some(last) if tokens.len() as u64 == query.limit => Some(Cursor {
+            value: last.address.clone(),
+            key: last.address.clone(),
+        }),
+        _ => None,
+    };
+    Ok((tokens, next))
+}
+
+/// every pool with `token` on either side
+pub async fn get_pools_of_token(rb: &Rbatis, token: &str) -> anyhow::Result<Vec<PoolInfo>> {
+    let pools: Vec<PoolInfo> = rb
+        .query_decode("select * from pool_info where token_x_address = ? or token_y_address = ?",
+                      vec![rbs::to_value!(token), rbs::to_value!(token)])
+        .await?;
+    Ok(pools)
+}
+
+/// every pool of `protocol` between two tokens, in either order
+pub async fn get_pools_between(rb: &Rbatis, token_a: &str, token_b: &str, protocol: &str) -> anyhow::Result<Vec<PoolInfo>> {
+    let pools: Vec<PoolInfo> = rb
+        .query_decode("select * from pool_info where protocol = ? and \
+                       ((token_x_address = ? and token_y_address = ?) or (token_x_address = ? and token_y_address = ?))",
+                      vec![rbs::to_value!(protocol), rbs::to_value!(token_a), rbs::to_value!(token_b),
+                           rbs::to_value!(token_b), rbs::to_value!(token_a)])
+        .await?;
+    Ok(pools)
+}
+
+/// the event types `/v1/pools/{address}/events` returns, by their api name
+pub const EVENT_TYPES: [(&str, i8); 3] = [("add", 1), ("remove", 2), ("swap", 3)];
+
+/// Filters of a page of events, combined with and. Ranges are inclusive.
+#[derive(Debug, Clone, Default)]
+pub struct EventQuery {
+    /// the events of every pool when `None`
+    pub pair_address: Option<String>,
+    /// every type of `EVENT_TYPES` when empty
+    pub event_types: Vec<i8>,
+    /// events sent by or to the account, or in a transaction it sent
+    pub account: Option<String>,
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    /// unix seconds of the block
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+    /// raw amounts of token x and y
+    pub min_amount_x: Option<U256>,
+    pub min_amount_y: Option<U256>,
+    pub descending: bool,
+    /// the block number and the log index of the last event of the previous page
+    pub after: Option<Cursor>,
+    pub limit: u64,
+}
+
+/// a page of events in chain order, or reversed, and the cursor of the next page
+pub async fn get_events(rb: &Rbatis, query: &EventQuery) -> anyhow::Result<(Vec<Event>, Option<Cursor>)> {
+    let mut sql = "select * from events where block_number is not null".to_string();
+    let mut args = Vec::new();
+    if let Some(pair_address) = &query.pair_address {
+        sql.push_str(" and pair_address = ?");
+        args.push(rbs::to_value!(pair_address));
+    }
+    let event_types = if query.event_types.is_empty() {
+        EVENT_TYPES.iter().map(|(_, t)| *t).collect()
+    } else {
+        query.event_types.clone()
+    };
+    sql.push_str(&format!(" and event_type in ({})", vec!["?"; event_types.len()].join(", ")));
+    args.extend(event_types.into_iter().map(|t| rbs::to_value!(t)));
+    if let Some(account) = &query.account {
+        sql.push_str(" and (from_account = ? or to_account = ? or tx_origin = ?)");
+        args.extend([rbs::to_value!(account), rbs::to_value!(account), rbs::to_value!(account)]);
+    }
+    for (column, compare, value) in [
+        ("block_number", ">=", query.from_block),
+        ("block_number", "<=", query.to_block),
+        ("block_timestamp", ">=", query.from_time),
+        ("block_timestamp", "<=", query.to_time),
+    ] {
+        if let Some(value) = value {
+            sql.push_str(&format!(" and {} {} ?", column, compare));
+            args.push(rbs::to_value!(value));
+        }
+    }
+    for (column, min) in [("amount_x", query.min_amount_x), ("amount_y", query.min_amount_y)] {
+        if let Some(min) = min {
+            sql.push_str(&format!(" and {} >= ?", sort_expression(rb, column, ColumnKind::Decimal)));
+            args.push(sort_value(rb, &min.to_string(), ColumnKind::Decimal)?);
+        }
+    }
+    let (compare, direction) = if query.descending { ("<", "desc") } else { (">", "asc") };
+    if let Some(after) = &query.after {
+        sql.push_str(&format!(" and (block_number {compare} ? or (block_number = ? and log_index {compare} ?))"));
+        let block = i64::from_str(&after.value)?;
+        args.extend([rbs::to_value!(block), rbs::to_value!(block), rbs::to_value!(i64::from_str(&after.key)?)]);
+    }
+    sql.push_str(&format!(" order by block_number {direction}, log_index {direction} limit ?"));
+    args.push(rbs::to_value!(query.limit));
+
+    let events: Vec<Event> = rb.query_decode(&sql, args).await?;
+    let next = match events.last() {
+        Some(last) if events.len() as u64 == query.limit => Some(Cursor {
+            value: last.block_number.unwrap_or_default().to_string(),
+            key: last.log_index.unwrap_or_default().to_string(),
+        }),
+        _ => None,
+    };
+    Ok((events, next))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{save_pool, save_tokens, sqlite};
+
+    fn pool(address: u8, reserve_x: &str, swaps: i64, symbol: &str, protocol: &str) -> PoolInfo {
+        PoolInfo {
+            pair_address: hex::encode([address; 20]),
+            token_x_symbol: symbol.to_string(),
+            token_y_symbol: "WETH".to_string(),
+            token_x_address: hex::encode([address + 100; 20]),
+            token_y_address: hex::encode([0xee; 20]),
+            token_x_reserves: Decimal::from_str(reserve_x).unwrap(),
+            token_y_reserves: Decimal::from_str("0").unwrap(),
+            total_swap_count: swaps,
+            total_add_liq_count: 0,
+            total_rm_liq_count: 0,
+            protocol: protocol.to_string(),
+            factory_address: Some(hex::encode([0xfa; 20])),
+            lp_total_supply: None,
+            tvl_usd: None,
+            volume_usd: None,
+            created_block: None,
+        }
+    }
+
+    /// every page of a query, following the cursors
+    async fn pages(rb: &Rbatis, mut query: PoolQuery) -> Vec<Vec<u8>> {
+        let mut pages = Vec::new();
+        loop {
+            let (pools, next) = get_pools(rb, &query).await.unwrap();
+            pages.push(pools.iter().map(|p| hex::decode(&p.pair_address).unwrap()[0]).collect());
+            match next {
+                Some(cursor) => query.after = Some(Cursor::decode(&cursor.encode()).unwrap()),
+                None => return pages,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_pools() {
+        let mut rb = sqlite::new_test_db("get_pools").await;
+        // reserves beyond the precision of a float and in an order text sorting gets wrong
+        for pool in [
+            pool(1, "9", 5, "USDC", "uniswap_v2"),
+            pool(2, "100000000000000000000000000000", 5, "DAI", "uniswap_v2"),
+            pool(3, "20", 1, "usdc", "uniswap_v3"),
+            pool(4, "20", 7, "WBTC", "uniswap_v2"),
+            pool(5, "0", 0, "UNI", "uniswap_v2"),
+        ] {
+            save_pool(&mut rb, &pool).await.unwrap();
+        }
+
+        let query = PoolQuery { sort: PoolSort::ReserveX, descending: true, limit: 2, ..Default::default() };
+        assert_eq!(pages(&rb, query).await, vec![vec![2, 3], vec![4, 1], vec![5]]);
+        let query = PoolQuery { sort: PoolSort::SwapCount, limit: 2, ..Default::default() };
+        assert_eq!(pages(&rb, query).await, vec![vec![5, 3], vec![1, 2], vec![4]]);
+        // pools without a tvl after the others
+        for (address, tvl) in [(1u8, "5000000000000000000000"), (4, "70000000000000000000000")] {
+            rb.exec("update pool_info set tvl_usd = ? where pair_address = ?",
+                    vec![rbs::to_value!(Decimal::from_str(tvl).unwrap()), rbs::to_value!(hex::encode([address; 20]))])
+                .await.unwrap();
+        }
+        let query = PoolQuery { sort: PoolSort::Tvl, descending: true, limit: 2, ..Default::default() };
+        assert_eq!(pages(&rb, query).await, vec![vec![4, 1], vec![2, 3], vec![5]]);
+        let query = PoolQuery { symbol: Some("UsDc".to_string()), limit: 10, ..Default::default() };
+        assert_eq!(pages(&rb, query).await, vec![vec![1, 3]]);
+        let query = PoolQuery { token: Some(hex::encode([0xee; 20])), protocol: Some("uniswap_v2".to_string()),
+                                limit: 4, ..Default::default() };
+        assert_eq!(pages(&rb, query).await, vec![vec![1, 2, 4, 5], vec![]]);
+        let query = PoolQuery { factory: Some(hex::encode([0xfb; 20])), limit: 4, ..Default::default() };
+        assert_eq!(pages(&rb, query).await, vec![Vec::<u8>::new()]);
+        assert!(Cursor::decode("zz").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_tokens() {
+        let rb = sqlite::new_test_db("get_tokens").await;
+        let token = |address: u8, symbol: &str, name: &str| Token {
+            address: hex::encode([address; 20]),
+            symbol: symbol.to_string(),
+            decimals: 18,
+            name: name.to_string(),
+            total_supply: None,
+            metadata_status: "ok".to_string(),
+            metadata_attempts: 1,
+        };
+        save_tokens(&rb, &[
+            token(1, "USDC", "USD Coin"),
+            token(2, "WETH", "Wrapped Ether"),
+            token(3, "DAI", "Dai Stablecoin"),
+            token(4, "A_B", "Underscore"),
+        ]).await.unwrap();
+
+        let symbols = |tokens: Vec<Token>| tokens.into_iter().map(|t| t.symbol).collect::<Vec<_>>();
+        let query = TokenQuery { limit: 3, ..Default::default() };
+        let (first, next) = get_tokens(&rb, &query).await.unwrap();
+        assert_eq!(symbols(first), ["USDC", "WETH", "DAI"]);
+        let query = TokenQuery { after: Some(Cursor::decode(&next.unwrap().encode()).unwrap()), ..query };
+        let (second, next) = get_tokens(&rb, &query).await.unwrap();
+        assert_eq!((symbols(second), next), (vec!["A_B".to_string()], None));
+
+        for (search, expected) in [
+            ("usd", vec!["USDC"]),
+            ("ETHER", vec!["WETH"]),
+            ("coin", vec!["USDC", "DAI"]),
+            ("_", vec!["A_B"]),
+            (&format!("0x{}", hex::encode([2; 20])), vec!["WETH"]),
+        ] {
+            let query = TokenQuery { search: Some(search.to_string()), limit: 10, ..Default::default() };
+            assert_eq!(symbols(get_tokens(&rb, &query).await.unwrap().0), expected, "{}", search);
+        }
+    }
+
+    fn event(block: i64, log_index: i64, event_type: i8, account: u8, amount_x: u64) -> Event {
+        Event {
+            tx_hash: hex::encode([block as u8; 32]),
+            event_type,
+            pair_address: hex::encode([1; 20]),
+            block_number: Some(block),
+            log_index: Some(log_index),
+            block_timestamp: Some(1000 + block * 12),
+            tx_origin: None,
+            from_account: hex::encode([account; 20]),
+            to_account: Some(hex::encode([0xaa; 20])),
+            amount_x: Some(Decimal::from_str(&amount_x.to_string()).unwrap()),
+            amount_y: Some(Decimal::from_str("1").unwrap()),
+            lp_amount: None,
+            amount0_in: None,
+            amount1_in: None,
+            amount0_out: None,
+            amount1_out: None,
+            direction: None,
+            flash: false,
+        }
+    }
+
+    /// the (block, log index) of every event of every page of a query
+    async fn event_pages(rb: &Rbatis, mut query: EventQuery) -> Vec<Vec<(i64, i64)>> {
+        let mut pages = Vec::new();
+        loop {
+            let (events, next) = get_events(rb, &query).await.unwrap();
+            pages.push(events.iter().map(|e| (e.block_number.unwrap(), e.log_index.unwrap())).collect());
+            match next {
+                Some(cursor) => query.after = Some(Cursor::decode(&cursor.encode()).unwrap()),
+                None => return pages,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_events() {
+        let mut rb = sqlite::new_test_db("get_events").await;
+        // inserted out of chain order
+        for event in [
+            event(10, 0, 3, 2, 5),
+            event(9, 3, 1, 2, 100),
+            event(9, 1, 3, 3, 10_000_000_000_000_000_000),
+            event(11, 0, 2, 3, 7),
+            event(11, 1, 5, 3, 7),
+        ] {
+            Event::insert(&mut rb, &event).await.unwrap();
+        }
+        let pair_address = hex::encode([1; 20]);
+        let query = EventQuery { pair_address: Some(pair_address.clone()), limit: 2, ..Default::default() };
+        assert_eq!(event_pages(&rb, query.clone()).await, vec![vec![(9, 1), (9, 3)], vec![(10, 0), (11, 0)], vec![]]);
+        assert_eq!(event_pages(&rb, EventQuery { descending: true, limit: 3, ..query.clone() }).await,
+                   vec![vec![(11, 0), (10, 0), (9, 3)], vec![(9, 1)]]);
+
+        let query = EventQuery { limit: 10, ..query };
+        let swaps = EventQuery { event_types: vec![3], ..query.clone() };
+        assert_eq!(event_pages(&rb, swaps).await, vec![vec![(9, 1), (10, 0)]]);
+        let account = EventQuery { account: Some(hex::encode([3; 20])), ..query.clone() };
+        assert_eq!(event_pages(&rb, account).await, vec![vec![(9, 1), (11, 0)]]);
+        let receiver = EventQuery { account: Some(hex::encode([0xaa; 20])), ..query.clone() };
+        assert_eq!(event_pages(&rb, receiver).await[0].len(), 4);
+        let blocks = EventQuery { from_block: Some(10), to_block: Some(11), ..query.clone() };
+        assert_eq!(event_pages(&rb, blocks).await, vec![vec![(10, 0), (11, 0)]]);
+        let time = EventQuery { to_time: Some(1000 + 10 * 12), ..query.clone() };
+        assert_eq!(event_pages(&rb, time).await, vec![vec![(9, 1), (9, 3), (10, 0)]]);
+        let amount = EventQuery { min_amount_x: Some(U256::from(7)), ..query.clone() };
+        assert_eq!(event_pages(&rb, amount).await, vec![vec![(9, 1), (9, 3), (11, 0)]]);
+
+        // the account sent the transaction of a swap in another pool
+        let other_pool = Event {
+            pair_address: hex::encode([2; 20]),
+            tx_origin: Some(hex::encode([3; 20])),
+            ..event(10, 5, 3, 4, 1)
+        };
+        Event::insert(&mut rb, &other_pool).await.unwrap();
+        let activity = EventQuery { pair_address: None, account: Some(hex::encode([3; 20])), ..query.clone() };
+        assert_eq!(event_pages(&rb, activity).await, vec![vec![(9, 1), (10, 5), (11, 0)]]);
+    }
+}
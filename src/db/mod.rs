@@ -1,19 +1,39 @@
 use rbatis::Rbatis;
-use crate::db::tables::{Event, PoolInfo, LastSyncBlock, Token};
+use rbatis::executor::{Executor, RBatisTxExecutor};
+use crate::db::tables::{BackfillJob, Event, PoolInfo, LastSyncBlock, Token, ReconciliationIssue, FailedLog, V3Pool, V3Tick};
 use num::ToPrimitive;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use web3::types::{H160, U256};
 use crate::watcher::event::PairEvent;
+use crate::protocol::PoolState;
+use crate::protocol::uniswap_v3::V3PoolEvent;
+use crate::db::candles::{update_candles, CandlePoint};
+use crate::db::rollups::{delete_pool_stats, swap_volume, update_pool_stats, StatPoint};
+use crate::db::prices::{add_volume, update_prices, Pricing};
+use crate::config::BackendConfig;
 use rbatis::rbdc::decimal::Decimal;
 use std::str::FromStr;
 
 pub(crate) mod tables;
+pub mod sqlite;
+pub mod token_cache;
+pub mod pool_graph;
+pub(crate) mod listing;
+pub(crate) mod pool_stats;
+pub(crate) mod accounts;
+pub(crate) mod candles;
+pub(crate) mod rollups;
+pub(crate) mod prices;
 
-pub(crate) async fn upsert_last_sync_block(rb: &mut Rbatis, new_block : LastSyncBlock) -> anyhow::Result<()> {
+pub(crate) async fn upsert_last_sync_block(rb: &mut dyn Executor, new_block : LastSyncBlock) -> anyhow::Result<()> {
     let block = LastSyncBlock::select_all(rb).await?;
     if block.is_empty() {
         LastSyncBlock::insert(rb,&new_block).await?;
     } else {
-        LastSyncBlock::update_by_column(rb, &new_block, "block_number").await?;
+        // update_by_column leaves the filter column out of the set clause, which leaves
+        // nothing to set in a single column table
+        rb.exec("update last_sync_block set block_number = ?", vec![rbs::to_value!(new_block.block_number)])
+            .await?;
     }
     Ok(())
 }
@@ -30,26 +50,15 @@ pub async fn get_last_sync_block(rb:&Rbatis) -> anyhow::Result<u64> {
     Ok(number)
 }
 
-pub(crate) async fn save_events(rb: &Rbatis, events: Vec<Event>) -> anyhow::Result<()> {
-    let mut tx = rb
-        .acquire_begin()
-        .await?;
-
-    for event in events {
-        Event::insert(&mut tx, &event)
-            .await?;
-    }
-    tx.commit().await?;
-    Ok(())
-}
-
-pub(crate) async fn save_pool(rb: &mut Rbatis, pool: &PoolInfo) -> anyhow::Result<()> {
+pub(crate) async fn save_pool(rb: &mut dyn Executor, pool: &PoolInfo) -> anyhow::Result<()> {
     PoolInfo::insert(rb,pool).await?;
     Ok(())
 }
 
-pub(crate) async fn update_pool(rb: &mut Rbatis,new_pool: PoolInfo) -> anyhow::Result<()> {
-    PoolInfo::update_by_column(rb,&new_pool,"pair_id")
+/// set the factory of the pools of `protocol` stored before the factory was recorded
+pub(crate) async fn set_missing_pool_factory(rb: &Rbatis, protocol: &str, factory_address: &str) -> anyhow::Result<()> {
+    rb.exec("update pool_info set factory_address = ? where factory_address is null and protocol = ?",
+            vec![rbs::to_value!(factory_address), rbs::to_value!(protocol)])
         .await?;
     Ok(())
 }
@@ -61,6 +70,13 @@ pub async fn get_all_store_pools(rb:&Rbatis ) -> anyhow::Result<Vec<PoolInfo>> {
     Ok(pools)
 }
 
+pub async fn get_pool(rb: &Rbatis, pair_address: &str) -> anyhow::Result<Option<PoolInfo>> {
+    let pools: Vec<PoolInfo> = rb
+        .query_decode("select * from pool_info where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    Ok(pools.into_iter().next())
+}
+
 pub async fn get_token(rb:&Rbatis,address: String ) -> anyhow::Result<Vec<Token>> {
     let tokens: Vec<Token> = rb
         .query_decode("select * from tokens where address = ?",vec![rbs::to_value!(address)])
@@ -68,10 +84,35 @@ pub async fn get_token(rb:&Rbatis,address: String ) -> anyhow::Result<Vec<Token>
     Ok(tokens)
 }
 
-pub(crate) async fn save_token(rb: &mut Rbatis, token: Token) -> anyhow::Result<()> {
-    Token::insert(rb, &token)
-        .await?;
-    Ok(())
+/// insert or update tokens in one transaction
+pub(crate) async fn save_tokens(rb: &Rbatis, tokens: &[Token]) -> anyhow::Result<()> {
+    let mut tx = rb.acquire_begin().await?;
+    let result = async {
+        for token in tokens {
+            tx.exec("insert into tokens (address, symbol, decimals, name, total_supply, \
+                    metadata_status, metadata_attempts) values (?, ?, ?, ?, ?, ?, ?) \
+                    on conflict (address) do update set symbol = excluded.symbol, \
+                    decimals = excluded.decimals, name = excluded.name, \
+                    total_supply = excluded.total_supply, metadata_status = excluded.metadata_status, \
+                    metadata_attempts = excluded.metadata_attempts",
+                    vec![rbs::to_value!(token.address.clone()), rbs::to_value!(token.symbol.clone()),
+                         rbs::to_value!(token.decimals), rbs::to_value!(token.name.clone()),
+                         rbs::to_value!(token.total_supply.clone()),
+                         rbs::to_value!(token.metadata_status.clone()),
+                         rbs::to_value!(token.metadata_attempts)]).await?;
+        }
+        Ok::<(), rbatis::Error>(())
+    }.await;
+    match result {
+        Ok(()) => {
+            tx.commit().await?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = tx.rollback().await;
+            Err(e.into())
+        }
+    }
 }
 
 pub async fn get_tokens(rb:&Rbatis) -> anyhow::Result<Vec<Token>> {
@@ -81,74 +122,562 @@ pub async fn get_tokens(rb:&Rbatis) -> anyhow::Result<Vec<Token>> {
     Ok(tokens)
 }
 
-pub async fn store_pair_events(rb: &mut Rbatis,events: Vec<PairEvent>) -> anyhow::Result<()> {
-    let mut added_events_count = HashMap::new();
+/// tokens whose metadata fell back to placeholders and may be fetched again
+pub async fn get_tokens_to_retry(rb: &Rbatis, max_attempts: i64) -> anyhow::Result<Vec<Token>> {
+    let tokens: Vec<Token> = rb
+        .query_decode("select * from tokens where metadata_status <> 'ok' and metadata_attempts < ?",
+                      vec![rbs::to_value!(max_attempts)])
+        .await?;
+    Ok(tokens)
+}
+
+/// update the metadata of a stored token and the symbols of its pools
+pub(crate) async fn update_token_metadata(rb: &Rbatis, token: &Token) -> anyhow::Result<()> {
+    let mut tx = rb.acquire_begin().await?;
+    tx.exec("update tokens set symbol = ?, decimals = ?, name = ?, total_supply = ?, \
+            metadata_status = ?, metadata_attempts = ? where address = ?",
+            vec![rbs::to_value!(token.symbol.clone()), rbs::to_value!(token.decimals),
+                 rbs::to_value!(token.name.clone()), rbs::to_value!(token.total_supply.clone()),
+                 rbs::to_value!(token.metadata_status.clone()), rbs::to_value!(token.metadata_attempts),
+                 rbs::to_value!(token.address.clone())]).await?;
+    tx.exec("update pool_info set token_x_symbol = ? where token_x_address = ?",
+            vec![rbs::to_value!(token.symbol.clone()), rbs::to_value!(token.address.clone())]).await?;
+    tx.exec("update pool_info set token_y_symbol = ? where token_y_address = ?",
+            vec![rbs::to_value!(token.symbol.clone()), rbs::to_value!(token.address.clone())]).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// insert the events of V2 pairs and add them to the counts, candles and statistics of their
+/// pairs. An event already stored, of a block range stored again, is left out of all of them.
+pub(crate) async fn store_pair_events(tx: &mut RBatisTxExecutor, events: Vec<PairEvent>, config: &BackendConfig,
+                                      pricing: &Pricing) -> anyhow::Result<()> {
+    let mut added_events_count: HashMap<(H160, &'static str), u32> = HashMap::new();
     let mut last_synced_reserves = HashMap::new();
-    let mut db_events = Vec::new();
-    if events.is_empty() {
-        return Ok(());
-    }
-    let column_name = events[0].get_table_column_name().to_string().clone();
+    let mut candle_points: HashMap<H160, Vec<CandlePoint>> = HashMap::new();
+    let mut stat_points: HashMap<H160, Vec<StatPoint>> = HashMap::new();
+    // the block and time of the last reserves of the batch
+    let mut last_sync = None;
     for event in events {
         let pair_address = event.get_pair_address();
+        let candle_point = CandlePoint::of_pair_event(&event);
+        let stat_point = StatPoint::of_pair_event(&event);
         match event {
             PairEvent::SyncPairEvent(sync_event) => {
                 //Sync event
                 last_synced_reserves.insert(pair_address,(sync_event.reserve0,sync_event.reserve1));
+                last_sync = last_sync.max(Some((sync_event.meta.block_number as i64,
+                                                sync_event.meta.block_timestamp.map(|t| t as i64))));
             }
             _ => {
-                let new_count = if let Some(count) = added_events_count.get(&pair_address) {
-                    count + 1
-                } else {
-                    1u32
-                };
-                added_events_count.insert(pair_address, new_count);
-                let db_event = Event::from(event);
-                db_events.push(db_event);
+                let column_name = event.get_table_column_name();
+                if !insert_event(tx, &Event::from(event)).await? {
+                    continue;
+                }
+                *added_events_count.entry((pair_address, column_name)).or_default() += 1;
             }
         }
+        if let Some(point) = candle_point {
+            candle_points.entry(pair_address).or_default().push(point);
+        }
+        if let Some(point) = stat_point {
+            stat_points.entry(pair_address).or_default().push(point);
+        }
     }
 
+    //add to the total count by event type
+    for ((pair_address, column_name), count) in added_events_count {
+        if !matches!(column_name, "total_add_liq_count" | "total_rm_liq_count" | "total_swap_count") {
+            continue;
+        }
+        tx.exec(&format!("update pool_info set {0} = {0} + ? where pair_address = ?", column_name),
+                vec![rbs::to_value!(count), rbs::to_value!(hex::encode(pair_address.as_bytes()))])
+            .await?;
+    }
+    //update pool reserves
+    let synced_pools: Vec<String> = last_synced_reserves.keys().map(hex::encode).collect();
+    for (pair_address,(reserve_x,reserve_y)) in last_synced_reserves {
+        let reserve_x_decimal = Decimal::from_str(&reserve_x.to_string()).unwrap();
+        let reserve_y_decimal = Decimal::from_str(&reserve_y.to_string()).unwrap();
+        tx.exec("update pool_info set token_x_reserves = ?,token_y_reserves = ? \
+        where pair_address = ?", vec![rbs::to_value!(reserve_x_decimal),
+                                      rbs::to_value!(reserve_y_decimal),
+                                      rbs::to_value!(hex::encode(pair_address))])
+            .await?;
+    }
+    for (pair_address, points) in candle_points {
+        update_candles(tx, &hex::encode(pair_address), &points).await?;
+    }
+    let (block_number, timestamp) = last_sync.unzip();
+    update_prices(tx, pricing, &synced_pools, block_number, timestamp.flatten()).await?;
+    for (pair_address, points) in stat_points {
+        let (volume_x, volume_y) = swap_volume(&points);
+        add_volume(tx, &hex::encode(pair_address), volume_x, volume_y).await?;
+        let fee = v2_fee_bps(tx, config, &hex::encode(pair_address)).await? * 100;
+        update_pool_stats(tx, &hex::encode(pair_address), fee, &points).await?;
+    }
+    Ok(())
+}
+
+/// the swap fee in basis points of a V2 pair, by the factory it is stored with
+async fn v2_fee_bps(tx: &mut RBatisTxExecutor, config: &BackendConfig, pair_address: &str) -> anyhow::Result<u64> {
+    let pools: Vec<PoolInfo> = tx
+        .query_decode("select * from pool_info where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    Ok(config.v2_fee_bps(pools.first().and_then(|p| p.factory_address.as_deref())))
+}
+
+/// insert an event unless one with its transaction and log index is stored, returns whether
+/// it was inserted
+async fn insert_event(tx: &mut RBatisTxExecutor, event: &Event) -> anyhow::Result<bool> {
+    let result = tx
+        .exec("insert into events (tx_hash, event_type, pair_address, block_number, log_index, \
+              block_timestamp, tx_origin, from_account, to_account, amount_x, amount_y, lp_amount, \
+              amount0_in, amount1_in, amount0_out, amount1_out, direction, flash) \
+              values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+              on conflict (tx_hash, log_index) do nothing",
+              vec![rbs::to_value!(event.tx_hash.clone()), rbs::to_value!(event.event_type),
+                   rbs::to_value!(event.pair_address.clone()), rbs::to_value!(event.block_number),
+                   rbs::to_value!(event.log_index), rbs::to_value!(event.block_timestamp),
+                   rbs::to_value!(event.tx_origin.clone()), rbs::to_value!(event.from_account.clone()),
+                   rbs::to_value!(event.to_account.clone()), rbs::to_value!(event.amount_x.clone()),
+                   rbs::to_value!(event.amount_y.clone()), rbs::to_value!(event.lp_amount.clone()),
+                   rbs::to_value!(event.amount0_in.clone()), rbs::to_value!(event.amount1_in.clone()),
+                   rbs::to_value!(event.amount0_out.clone()), rbs::to_value!(event.amount1_out.clone()),
+                   rbs::to_value!(event.direction.clone()), rbs::to_value!(event.flash)])
+        .await?;
+    Ok(result.rows_affected > 0)
+}
+
+/// a random sample of at most `limit` pools of `protocol`
+pub async fn get_sample_pools(rb: &Rbatis, protocol: &str, limit: u64) -> anyhow::Result<Vec<PoolInfo>> {
+    let pools: Vec<PoolInfo> = rb
+        .query_decode("select * from pool_info where protocol = ? order by random() limit ?",
+                      vec![rbs::to_value!(protocol), rbs::to_value!(limit)])
+        .await?;
+    Ok(pools)
+}
+
+/// replace every stored event of a pair and set its counts and reserves from `state`,
+/// the state after all of the events. used to reindex a pair from a full refetch.
+pub async fn replace_pair_events(rb: &Rbatis, pair_address: &str, events: Vec<PairEvent>,
+                                 state: &PoolState, config: &BackendConfig, pricing: &Pricing) -> anyhow::Result<()> {
+    let candle_points: Vec<CandlePoint> = events.iter().filter_map(CandlePoint::of_pair_event).collect();
+    let stat_points: Vec<StatPoint> = events.iter().filter_map(StatPoint::of_pair_event).collect();
+    let db_events: Vec<Event> = events.into_iter()
+        .filter(|e| !matches!(e, PairEvent::SyncPairEvent(_)))
+        .map(Event::from)
+        .collect();
+
     let mut tx = rb
         .acquire_begin()
         .await?;
-    for event in db_events {
-        Event::insert(&mut tx, &event)
+    let result = async {
+        tx.exec("delete from events where pair_address = ?", vec![rbs::to_value!(pair_address)])
+            .await?;
+        tx.exec("delete from pool_candles where pair_address = ?", vec![rbs::to_value!(pair_address)])
+            .await?;
+        update_candles(&mut tx, pair_address, &candle_points).await?;
+        delete_pool_stats(&mut tx, pair_address).await?;
+        let fee = v2_fee_bps(&mut tx, config, pair_address).await? * 100;
+        update_pool_stats(&mut tx, pair_address, fee, &stat_points).await?;
+        for event in db_events {
+            Event::insert(&mut tx, &event)
+                .await?;
+        }
+        tx.exec("update pool_info set total_add_liq_count = ?,total_rm_liq_count = ?,total_swap_count = ?, \
+            token_x_reserves = ?,token_y_reserves = ? where pair_address = ?",
+                vec![rbs::to_value!(state.mint_count),
+                     rbs::to_value!(state.burn_count),
+                     rbs::to_value!(state.swap_count),
+                     rbs::to_value!(Decimal::from_str(&state.reserve0.to_string()).unwrap()),
+                     rbs::to_value!(Decimal::from_str(&state.reserve1.to_string()).unwrap()),
+                     rbs::to_value!(pair_address)])
+            .await?;
+        let last = stat_points.iter().map(|p| (p.block_number, p.timestamp)).max();
+        update_prices(&mut tx, pricing, &[pair_address.to_string()], last.map(|(block, _)| block),
+                      last.map(|(_, timestamp)| timestamp)).await?;
+        reset_volume(&mut tx, pair_address, &stat_points).await
+    }.await;
+    pricing.finish(tx, result).await
+}
+
+/// value the swaps of a pool again from all its events, at the current prices
+async fn reset_volume(tx: &mut RBatisTxExecutor, pair_address: &str, points: &[StatPoint]) -> anyhow::Result<()> {
+    tx.exec("update pool_info set volume_usd = ? where pair_address = ?",
+            vec![rbs::to_value!(Decimal::from_str("0").unwrap()), rbs::to_value!(pair_address)])
+        .await?;
+    let (volume_x, volume_y) = swap_volume(points);
+    add_volume(tx, pair_address, volume_x, volume_y).await
+}
+
+#[derive(serde::Deserialize)]
+struct PairAddressRow {
+    pair_address: String,
+}
+
+/// pools with events stored before the four swap amounts, the block and its time, or the
+/// sender of the transaction were kept
+pub async fn get_pools_to_backfill(rb: &Rbatis) -> anyhow::Result<Vec<String>> {
+    let rows: Vec<PairAddressRow> = rb
+        .query_decode("select distinct pair_address from events \
+                       where (event_type = 3 and amount0_in is null) or block_number is null \
+                       or tx_origin is null",
+                      vec![])
+        .await?;
+    Ok(rows.into_iter().map(|r| r.pair_address).collect())
+}
+
+/// pools with swaps stored before their candles were built
+pub async fn get_pools_missing_candles(rb: &Rbatis) -> anyhow::Result<Vec<String>> {
+    let rows: Vec<PairAddressRow> = rb
+        .query_decode("select pair_address from pool_info p where total_swap_count > 0 and not exists \
+                       (select 1 from pool_candles c where c.pair_address = p.pair_address)",
+                      vec![])
+        .await?;
+    Ok(rows.into_iter().map(|r| r.pair_address).collect())
+}
+
+/// pools with events stored before their hourly and daily statistics were rolled up
+pub async fn get_pools_missing_stats(rb: &Rbatis) -> anyhow::Result<Vec<String>> {
+    let rows: Vec<PairAddressRow> = rb
+        .query_decode("select pair_address from pool_info p \
+                       where total_swap_count + total_add_liq_count + total_rm_liq_count > 0 and not exists \
+                       (select 1 from pool_day_stats s where s.pair_address = p.pair_address)",
+                      vec![])
+        .await?;
+    Ok(rows.into_iter().map(|r| r.pair_address).collect())
+}
+
+/// pools of `protocol` stored before their LP token supply was tracked
+pub async fn get_pools_missing_lp_supply(rb: &Rbatis, protocol: &str) -> anyhow::Result<Vec<String>> {
+    let rows: Vec<PairAddressRow> = rb
+        .query_decode("select pair_address from pool_info where protocol = ? and lp_total_supply is null",
+                      vec![rbs::to_value!(protocol)])
+        .await?;
+    Ok(rows.into_iter().map(|r| r.pair_address).collect())
+}
+
+/// queue a backfill job for each of `pools`, a pool queued before keeps its job
+pub async fn enqueue_backfill_jobs(rb: &Rbatis, pools: &[String], now: i64) -> anyhow::Result<()> {
+    for pool in pools {
+        rb.exec("insert into backfill_jobs (pair_address, status, attempts, updated_at) \
+                values (?, 'pending', 0, ?) on conflict (pair_address) do nothing",
+                vec![rbs::to_value!(pool), rbs::to_value!(now)])
             .await?;
     }
-    //update total count by event type
-    for (pair_address,count) in added_events_count {
-        match &column_name[..] {
-            "total_add_liq_count" => {
-                rb.exec("update pool_info set total_add_liq_count = ? where pair_address = ?",
-                        vec![rbs::to_value!(count), rbs::to_value!(hex::encode(pair_address.as_bytes()))])
-                    .await?;
-            },
-            "total_rmv_liq_count" => {
-                rb.exec("update pool_info set total_rmv_liq_count = ? where pair_address = ?",
-                        vec![rbs::to_value!(count), rbs::to_value!(hex::encode(pair_address.as_bytes()))])
+    Ok(())
+}
+
+/// at most `limit` pending jobs, and failed ones with fewer than `max_attempts`
+pub async fn get_backfill_jobs(rb: &Rbatis, max_attempts: i64, limit: u64) -> anyhow::Result<Vec<BackfillJob>> {
+    let jobs: Vec<BackfillJob> = rb
+        .query_decode("select * from backfill_jobs where status = 'pending' or \
+                       (status = 'failed' and attempts < ?) order by attempts, pair_address limit ?",
+                      vec![rbs::to_value!(max_attempts), rbs::to_value!(limit)])
+        .await?;
+    Ok(jobs)
+}
+
+pub(crate) async fn update_backfill_job(rb: &Rbatis, job: &BackfillJob) -> anyhow::Result<()> {
+    rb.exec("update backfill_jobs set status = ?, attempts = ?, synced_block = ?, error = ?, updated_at = ? \
+            where pair_address = ?",
+            vec![rbs::to_value!(job.status.clone()), rbs::to_value!(job.attempts),
+                 rbs::to_value!(job.synced_block), rbs::to_value!(job.error.clone()),
+                 rbs::to_value!(job.updated_at), rbs::to_value!(job.pair_address.clone())])
+        .await?;
+    Ok(())
+}
+
+pub(crate) async fn set_created_block(rb: &Rbatis, pair_address: &str, block: u64) -> anyhow::Result<()> {
+    rb.exec("update pool_info set created_block = ? where pair_address = ?",
+            vec![rbs::to_value!(block as i64), rbs::to_value!(pair_address)])
+        .await?;
+    Ok(())
+}
+
+/// add the LP tokens minted and subtract those burned, by pool, to the supply of the pools.
+/// Pools without a supply yet are left to the backfill.
+pub(crate) async fn add_lp_supply(tx: &mut RBatisTxExecutor, changes: &HashMap<H160, (U256, U256)>)
+    -> anyhow::Result<()> {
+    #[derive(serde::Deserialize)]
+    struct Row {
+        lp_total_supply: Option<Decimal>,
+    }
+    for (pool, (minted, burned)) in changes {
+        let pair_address = hex::encode(pool);
+        let rows: Vec<Row> = tx
+            .query_decode("select lp_total_supply from pool_info where pair_address = ?",
+                          vec![rbs::to_value!(pair_address.clone())])
+            .await?;
+        let Some(supply) = rows.into_iter().next().and_then(|r| r.lp_total_supply) else {
+            continue;
+        };
+        let supply = U256::from_dec_str(&supply.0)? + minted;
+        tx.exec("update pool_info set lp_total_supply = ? where pair_address = ?",
+                vec![rbs::to_value!(Decimal::from_str(&supply.saturating_sub(*burned).to_string())?),
+                     rbs::to_value!(pair_address)])
+            .await?;
+    }
+    Ok(())
+}
+
+pub async fn set_lp_supply(rb: &Rbatis, pair_address: &str, supply: U256) -> anyhow::Result<()> {
+    rb.exec("update pool_info set lp_total_supply = ? where pair_address = ?",
+            vec![rbs::to_value!(Decimal::from_str(&supply.to_string())?), rbs::to_value!(pair_address)])
+        .await?;
+    Ok(())
+}
+
+/// store a new concentrated liquidity pool, a pool already stored is kept as it is
+pub(crate) async fn save_v3_pool(rb: &mut dyn Executor, pool: &V3Pool) -> anyhow::Result<()> {
+    rb.exec("insert into v3_pools (pair_address, fee, tick_spacing, sqrt_price_x96, tick, liquidity) \
+            values (?, ?, ?, ?, ?, ?) on conflict (pair_address) do nothing",
+            vec![rbs::to_value!(pool.pair_address.clone()), rbs::to_value!(pool.fee),
+                 rbs::to_value!(pool.tick_spacing), rbs::to_value!(pool.sqrt_price_x96.clone()),
+                 rbs::to_value!(pool.tick), rbs::to_value!(pool.liquidity.clone())])
+        .await?;
+    Ok(())
+}
+
+pub async fn get_v3_pools(rb: &Rbatis) -> anyhow::Result<Vec<V3Pool>> {
+    let pools: Vec<V3Pool> = rb
+        .query_decode("select * from v3_pools", vec![])
+        .await?;
+    Ok(pools)
+}
+
+/// the stored state of a concentrated liquidity pool, the default state of a pool not stored yet
+pub async fn get_v3_pool_state(rb: &Rbatis, pair_address: &str) -> anyhow::Result<PoolState> {
+    let mut state = PoolState::default();
+    let pools: Vec<PoolInfo> = rb
+        .query_decode("select * from pool_info where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    if let Some(pool) = pools.first() {
+        state.reserve0 = U256::from_dec_str(&pool.token_x_reserves.0)?;
+        state.reserve1 = U256::from_dec_str(&pool.token_y_reserves.0)?;
+        state.swap_count = pool.total_swap_count;
+        state.mint_count = pool.total_add_liq_count;
+        state.burn_count = pool.total_rm_liq_count;
+    }
+    let v3_pools: Vec<V3Pool> = rb
+        .query_decode("select * from v3_pools where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    if let Some(pool) = v3_pools.first() {
+        state.sqrt_price_x96 = U256::from_dec_str(&pool.sqrt_price_x96.0)?;
+        state.tick = pool.tick as i32;
+        state.liquidity = pool.liquidity.0.parse()?;
+    }
+    let ticks: Vec<V3Tick> = rb
+        .query_decode("select * from v3_ticks where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    for tick in ticks {
+        state.liquidity_net.insert(tick.tick as i32, tick.liquidity_net.0.parse()?);
+    }
+    Ok(state)
+}
+
+/// the transaction hashes and log indexes of the events of a pool stored in the block range
+pub async fn get_stored_logs(rb: &Rbatis, pair_address: &str, from_block: u64, to_block: u64)
+    -> anyhow::Result<HashSet<(String, i64)>> {
+    #[derive(serde::Deserialize)]
+    struct Row {
+        tx_hash: String,
+        log_index: i64,
+    }
+    let rows: Vec<Row> = rb
+        .query_decode("select tx_hash, log_index from events where pair_address = ? and block_number >= ? \
+                       and block_number <= ? and log_index is not null",
+                      vec![rbs::to_value!(pair_address), rbs::to_value!(from_block), rbs::to_value!(to_block)])
+        .await?;
+    Ok(rows.into_iter().map(|r| (r.tx_hash, r.log_index)).collect())
+}
+
+/// store new events of a concentrated liquidity pool with `state`, the state after them
+pub(crate) async fn store_v3_pool_events(tx: &mut RBatisTxExecutor, pair_address: &str, events: Vec<V3PoolEvent>,
+                                         state: &PoolState, pricing: &Pricing) -> anyhow::Result<()> {
+    let ticks: BTreeSet<i32> = events.iter().flat_map(|e| e.ticks()).collect();
+    let pools: Vec<PoolInfo> = tx
+        .query_decode("select * from pool_info where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    let reserves = match pools.first() {
+        Some(pool) => (U256::from_dec_str(&pool.token_x_reserves.0)?, U256::from_dec_str(&pool.token_y_reserves.0)?),
+        None => (U256::zero(), U256::zero()),
+    };
+    let points = write_v3_pool_events(tx, pair_address, events, reserves, state, ticks, pricing).await?;
+    let (volume_x, volume_y) = swap_volume(&points);
+    add_volume(tx, pair_address, volume_x, volume_y).await
+}
+
+/// replace every stored event and tick of a concentrated liquidity pool, used to reindex
+/// it from a full refetch
+pub async fn replace_v3_pool_events(rb: &Rbatis, pair_address: &str, events: Vec<V3PoolEvent>,
+                                    state: &PoolState, pricing: &Pricing) -> anyhow::Result<()> {
+    let mut tx = rb.acquire_begin().await?;
+    let result = async {
+        tx.exec("delete from events where pair_address = ?", vec![rbs::to_value!(pair_address)]).await?;
+        tx.exec("delete from v3_ticks where pair_address = ?", vec![rbs::to_value!(pair_address)]).await?;
+        tx.exec("delete from pool_candles where pair_address = ?", vec![rbs::to_value!(pair_address)]).await?;
+        delete_pool_stats(&mut tx, pair_address).await?;
+        let ticks = state.liquidity_net.keys().copied().collect();
+        let points = write_v3_pool_events(&mut tx, pair_address, events, (U256::zero(), U256::zero()), state,
+                                          ticks, pricing).await?;
+        reset_volume(&mut tx, pair_address, &points).await
+    }.await;
+    pricing.finish(tx, result).await
+}
+
+/// insert the events and write the pool state, the liquidity net of the `ticks` included, and
+/// reprice its tokens. `reserves` are those of the pool before the events. Returns the
+/// statistics points of the events.
+async fn write_v3_pool_events(tx: &mut RBatisTxExecutor, pair_address: &str, events: Vec<V3PoolEvent>,
+                              reserves: (U256, U256), state: &PoolState, ticks: BTreeSet<i32>,
+                              pricing: &Pricing) -> anyhow::Result<Vec<StatPoint>> {
+    let candle_points: Vec<CandlePoint> = events.iter().filter_map(CandlePoint::of_v3_event).collect();
+    update_candles(tx, pair_address, &candle_points).await?;
+    let fees: Vec<V3Pool> = tx
+        .query_decode("select * from v3_pools where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    let fee = fees.first().map(|pool| pool.fee as u64).unwrap_or_default();
+    let stat_points = StatPoint::of_v3_events(&events, reserves);
+    update_pool_stats(tx, pair_address, fee, &stat_points).await?;
+    let last = events.last().map(|e| (e.meta().block_number as i64, e.meta().block_timestamp.map(|t| t as i64)));
+    for event in events {
+        if !matches!(event, V3PoolEvent::Initialize { .. }) {
+            insert_event(tx, &Event::from(event)).await?;
+        }
+    }
+    tx.exec("update pool_info set total_add_liq_count = ?,total_rm_liq_count = ?,total_swap_count = ?, \
+        token_x_reserves = ?,token_y_reserves = ? where pair_address = ?",
+            vec![rbs::to_value!(state.mint_count),
+                 rbs::to_value!(state.burn_count),
+                 rbs::to_value!(state.swap_count),
+                 rbs::to_value!(Decimal::from_str(&state.reserve0.to_string()).unwrap()),
+                 rbs::to_value!(Decimal::from_str(&state.reserve1.to_string()).unwrap()),
+                 rbs::to_value!(pair_address)])
+        .await?;
+    tx.exec("update v3_pools set sqrt_price_x96 = ?, tick = ?, liquidity = ? where pair_address = ?",
+            vec![rbs::to_value!(Decimal::from_str(&state.sqrt_price_x96.to_string()).unwrap()),
+                 rbs::to_value!(state.tick),
+                 rbs::to_value!(Decimal::from_str(&state.liquidity.to_string()).unwrap()),
+                 rbs::to_value!(pair_address)])
+        .await?;
+    for tick in ticks {
+        match state.liquidity_net.get(&tick) {
+            Some(net) => {
+                tx.exec("insert into v3_ticks (pair_address, tick, liquidity_net) values (?, ?, ?) \
+                        on conflict (pair_address, tick) do update set liquidity_net = excluded.liquidity_net",
+                        vec![rbs::to_value!(pair_address), rbs::to_value!(tick),
+                             rbs::to_value!(Decimal::from_str(&net.to_string()).unwrap())])
                     .await?;
-            },
-            "total_swap_count" => {
-                rb.exec("update pool_info set total_swap_count = ? where pair_address = ?",
-                        vec![rbs::to_value!(count), rbs::to_value!(hex::encode(pair_address.as_bytes()))])
+            }
+            None => {
+                tx.exec("delete from v3_ticks where pair_address = ? and tick = ?",
+                        vec![rbs::to_value!(pair_address), rbs::to_value!(tick)])
                     .await?;
-            },
-            _ => {}
+            }
+        }
+    }
+    update_prices(tx, pricing, &[pair_address.to_string()], last.map(|(block, _)| block),
+                  last.and_then(|(_, timestamp)| timestamp)).await?;
+    Ok(stat_points)
+}
 
+/// commit the transaction when `result` is ok, roll it back otherwise
+pub(crate) async fn finish(mut tx: RBatisTxExecutor, result: anyhow::Result<()>) -> anyhow::Result<()> {
+    match result {
+        Ok(()) => {
+            tx.commit().await?;
+            Ok(())
+        }
+        Err(e) => {
+            let _ = tx.rollback().await;
+            Err(e)
         }
     }
-    //update pool reserves
-    for (pair_address,(reserve_x,reserve_y)) in last_synced_reserves {
-        let reserve_x_decimal = Decimal::from_str(&reserve_x.to_string()).unwrap();
-        let reserve_y_decimal = Decimal::from_str(&reserve_y.to_string()).unwrap();
-        rb.exec("update pool_info set token_x_reserves = ?,token_y_reserves = ? \
-        where pair_address = ?", vec![rbs::to_value!(reserve_x_decimal),
-                                      rbs::to_value!(reserve_y_decimal),
-                                      rbs::to_value!(hex::encode(pair_address))])
+}
+
+pub(crate) async fn save_reconciliation_issue(rb: &mut Rbatis, issue: &ReconciliationIssue) -> anyhow::Result<()> {
+    ReconciliationIssue::insert(rb, issue).await?;
+    Ok(())
+}
+
+pub(crate) async fn mark_reconciliation_issue_reindexed(rb: &Rbatis, pair_address: &str) -> anyhow::Result<()> {
+    rb.exec("update reconciliation_issues set reindexed = ? where pair_address = ?",
+            vec![rbs::to_value!(true), rbs::to_value!(pair_address)])
+        .await?;
+    Ok(())
+}
+
+/// the most recent reconciliation issues first
+pub async fn get_reconciliation_issues(rb: &Rbatis, limit: u64) -> anyhow::Result<Vec<ReconciliationIssue>> {
+    let issues: Vec<ReconciliationIssue> = rb
+        .query_decode("select * from reconciliation_issues order by id desc limit ?",
+                      vec![rbs::to_value!(limit)])
+        .await?;
+    Ok(issues)
+}
+
+/// store logs that failed to decode, a log already stored is kept as it is
+pub(crate) async fn save_failed_logs(rb: &Rbatis, logs: &[FailedLog]) -> anyhow::Result<()> {
+    for log in logs {
+        rb.exec("insert into failed_logs (block_number, log_index, tx_hash, address, topics, data, \
+                error, status, attempts, created_at) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+                on conflict (tx_hash, log_index) do nothing",
+                vec![rbs::to_value!(log.block_number), rbs::to_value!(log.log_index),
+                     rbs::to_value!(log.tx_hash.clone()), rbs::to_value!(log.address.clone()),
+                     rbs::to_value!(log.topics.clone()), rbs::to_value!(log.data.clone()),
+                     rbs::to_value!(log.error.clone()), rbs::to_value!(log.status.clone()),
+                     rbs::to_value!(log.attempts), rbs::to_value!(log.created_at)])
             .await?;
     }
-    tx.commit().await?;
+    Ok(())
+}
+
+/// the latest failed logs, of any status when `status` is `None`
+pub async fn get_failed_logs(rb: &Rbatis, status: Option<String>, limit: u64) -> anyhow::Result<Vec<FailedLog>> {
+    let logs: Vec<FailedLog> = match status {
+        Some(status) => rb
+            .query_decode("select * from failed_logs where status = ? order by id desc limit ?",
+                          vec![rbs::to_value!(status), rbs::to_value!(limit)])
+            .await?,
+        None => rb
+            .query_decode("select * from failed_logs order by id desc limit ?",
+                          vec![rbs::to_value!(limit)])
+            .await?,
+    };
+    Ok(logs)
+}
+
+/// mark failed logs, or only the one with `id`, to be retried by the watcher.
+/// returns the number of logs marked
+pub async fn request_failed_logs_retry(rb: &Rbatis, id: Option<i64>) -> anyhow::Result<u64> {
+    let result = match id {
+        Some(id) => rb
+            .exec("update failed_logs set status = 'retry' where status = 'failed' and id = ?",
+                  vec![rbs::to_value!(id)])
+            .await?,
+        None => rb
+            .exec("update failed_logs set status = 'retry' where status = 'failed'", vec![])
+            .await?,
+    };
+    Ok(result.rows_affected)
+}
+
+/// failed logs to retry in chain order
+pub async fn get_failed_logs_to_retry(rb: &Rbatis) -> anyhow::Result<Vec<FailedLog>> {
+    let logs: Vec<FailedLog> = rb
+        .query_decode("select * from failed_logs where status = 'retry' order by block_number, log_index",
+                      vec![])
+        .await?;
+    Ok(logs)
+}
+
+pub(crate) async fn update_failed_log(rb: &Rbatis, log: &FailedLog) -> anyhow::Result<()> {
+    rb.exec("update failed_logs set status = ?, error = ?, attempts = ? where id = ?",
+            vec![rbs::to_value!(log.status.clone()), rbs::to_value!(log.error.clone()),
+                 rbs::to_value!(log.attempts), rbs::to_value!(log.id)])
+        .await?;
     Ok(())
 }
 
@@ -157,6 +686,36 @@ mod test {
     use super::*;
     use ethabi::Uint;
     use web3::types::H160;
+    use crate::watcher::event::{EventData, PairMintEvent, PairSwapEvent, PairSyncEvent};
+
+    #[test]
+    fn test_swap_amounts() {
+        let meta = EventData { address: H160::random(), tx_hash: Default::default(), block_number: 0, log_index: 0,
+                               block_timestamp: None, tx_origin: None };
+        let swap = |amounts: [u64; 4]| Event::from(PairEvent::SwapPairEvent(PairSwapEvent {
+            meta: meta.clone(),
+            sender: H160::random(),
+            amount0_in: amounts[0].into(),
+            amount1_in: amounts[1].into(),
+            amount0_out: amounts[2].into(),
+            amount1_out: amounts[3].into(),
+            to: H160::random(),
+        }));
+        // both inputs non-zero, token1 in net
+        let event = swap([5, 100, 0, 0]);
+        assert_eq!(event.amount0_in.unwrap().0, "5");
+        assert_eq!(event.amount1_in.unwrap().0, "100");
+        assert_eq!(event.direction.as_deref(), Some("none"));
+        assert!(!event.flash);
+        let event = swap([5, 100, 40, 0]);
+        assert_eq!(event.direction.as_deref(), Some("y_to_x"));
+        assert_eq!((event.amount_x.unwrap().0, event.amount_y.unwrap().0), ("35".to_string(), "100".to_string()));
+        // token0 borrowed and repaid with the fee
+        let event = swap([1003, 0, 1000, 0]);
+        assert!(event.flash);
+        assert_eq!(event.direction.as_deref(), Some("none"));
+        assert_eq!(event.amount0_out.unwrap().0, "1000");
+    }
 
     #[tokio::test]
     async fn test_update_decimal() {
@@ -193,7 +752,6 @@ mod test {
         pool.resize(2);
 
         let count = 100;
-        let count_column = "total_swap_count".to_string();
         let pair_address = "0x558038F070A802182355A0FA4807575f30076CeD".to_string();
         rb.exec("update pool_info set  total_swap_count = ? where pair_address = ?",
                 vec![rbs::to_value!(count),rbs::to_value!(pair_address)])
@@ -201,4 +759,83 @@ mod test {
 
     }
 
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn test_sqlite_store_pair_events() {
+        let mut rb = sqlite::new_test_db("store_pair_events").await;
+        assert!(sqlite::is_sqlite(&rb));
+        // applying twice is a no-op
+        sqlite::run_migrations(&rb).await.unwrap();
+
+        let pair_address = H160::from_str("0x558038F070A802182355A0FA4807575f30076CeD").unwrap();
+        let pool = PoolInfo {
+            pair_address: hex::encode(pair_address),
+            token_x_symbol: "X".to_string(),
+            token_y_symbol: "Y".to_string(),
+            token_x_address: hex::encode(H160::random()),
+            token_y_address: hex::encode(H160::random()),
+            token_x_reserves: Decimal::from_str("0").unwrap(),
+            token_y_reserves: Decimal::from_str("0").unwrap(),
+            total_swap_count: 0,
+            total_add_liq_count: 0,
+            total_rm_liq_count: 0,
+            protocol: "uniswap_v2".to_string(),
+            factory_address: Some(hex::encode([0xfa; 20])),
+            lp_total_supply: None,
+            tvl_usd: None,
+            volume_usd: None,
+            created_block: None,
+        };
+        save_pool(&mut rb, &pool).await.unwrap();
+        let config = BackendConfig { v2_fees: vec![(H160::repeat_byte(0xfa), 25)], ..Default::default() };
+
+        // larger than u64 and f64 precision
+        let reserve_x = Uint::MAX;
+        let reserve_y = Uint::from_dec_str("123456789012345678901234567890").unwrap();
+        let meta = |log_index: u64| EventData { address: pair_address, tx_hash: Default::default(), block_number: 0,
+                                                log_index, block_timestamp: None, tx_origin: None };
+        let store = |events: Vec<PairEvent>| {
+            let (rb, config) = (rb.clone(), config.clone());
+            async move {
+                let mut tx = rb.acquire_begin().await.unwrap();
+                store_pair_events(&mut tx, events, &config, &Pricing::default()).await.unwrap();
+                tx.commit().await.unwrap();
+            }
+        };
+        store(vec![
+            PairEvent::SyncPairEvent(PairSyncEvent { meta: meta(0), reserve0: reserve_x, reserve1: reserve_y }),
+        ]).await;
+        store(vec![
+            PairEvent::MintPairEvent(PairMintEvent { meta: meta(1), sender: H160::random(), amount0: reserve_x, amount1: reserve_y, lp_amount: None }),
+        ]).await;
+
+        let pools = get_all_store_pools(&rb).await.unwrap();
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].token_x_reserves.0, reserve_x.to_string());
+        assert_eq!(pools[0].token_y_reserves.0, reserve_y.to_string());
+        assert_eq!(pools[0].total_add_liq_count, 1);
+        let events: Vec<Event> = rb.query_decode("select * from events", vec![]).await.unwrap();
+        assert_eq!(events[0].amount_x.clone().unwrap().0, reserve_x.to_string());
+
+        // the counts of later batches add up, by the type of each event
+        let swap = |log_index| PairEvent::SwapPairEvent(PairSwapEvent {
+            meta: meta(log_index), sender: H160::random(), amount0_in: 1.into(), amount1_in: 0.into(),
+            amount0_out: 0.into(), amount1_out: 1.into(), to: H160::random(),
+        });
+        let mint = PairEvent::MintPairEvent(PairMintEvent { meta: meta(3), sender: H160::random(), amount0: 1.into(), amount1: 1.into(), lp_amount: None });
+        store(vec![swap(2), mint, swap(4)]).await;
+        store(vec![swap(5)]).await;
+        // events already stored are left out of the counts
+        store(vec![swap(4), swap(5)]).await;
+        let pools = get_all_store_pools(&rb).await.unwrap();
+        assert_eq!((pools[0].total_add_liq_count, pools[0].total_swap_count, pools[0].total_rm_liq_count), (2, 3, 0));
+
+        // the fees of the statistics are those of the factory of the pair
+        store(vec![PairEvent::SwapPairEvent(PairSwapEvent {
+            meta: EventData { block_timestamp: Some(10), ..meta(6) }, sender: H160::random(),
+            amount0_in: 10_000.into(), amount1_in: 0.into(), amount0_out: 0.into(), amount1_out: 1.into(),
+            to: H160::random(),
+        })]).await;
+        let stats = crate::db::rollups::get_pool_stats(&rb, &hex::encode(pair_address), 3600, 0, 0).await.unwrap();
+        assert_eq!(stats[0].fees_x, U256::from(25));
+    }
+}
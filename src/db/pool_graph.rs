@@ -0,0 +1,74 @@
+//! In-memory graph of the V2 pairs shared by the watcher and the api.
+//!
+//! The graph is loaded from `pool_info` at startup. The watcher reloads the pairs whose events
+//! it stored, so `/v1/route` searches the stored reserves without reading every pair.
+use std::fmt;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use rbatis::Rbatis;
+use web3::types::U256;
+use crate::amm::router::{GraphPair, PairGraph};
+use crate::config::BackendConfig;
+use crate::db;
+use crate::db::listing;
+use crate::db::tables::PoolInfo;
+use crate::protocol::uniswap_v2;
+
+#[derive(Clone, Default)]
+pub struct PoolGraph {
+    graph: Arc<RwLock<PairGraph>>,
+}
+
+impl fmt::Debug for PoolGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolGraph")
+            .field("len", &self.read().len())
+            .finish()
+    }
+}
+
+/// the pair of a V2 pool, `None` for the other protocols
+fn graph_pair(config: &BackendConfig, pool: PoolInfo) -> anyhow::Result<Option<GraphPair>> {
+    if pool.protocol != uniswap_v2::NAME {
+        return Ok(None);
+    }
+    Ok(Some(GraphPair {
+        reserve_x: U256::from_dec_str(&pool.token_x_reserves.0)?,
+        reserve_y: U256::from_dec_str(&pool.token_y_reserves.0)?,
+        fee_bps: config.v2_fee_bps(pool.factory_address.as_deref()),
+        pair_address: pool.pair_address,
+        factory_address: pool.factory_address,
+        token_x: pool.token_x_address,
+        token_y: pool.token_y_address,
+    }))
+}
+
+impl PoolGraph {
+    /// a graph of every stored V2 pair
+    pub async fn load(rb: &Rbatis, config: &BackendConfig) -> anyhow::Result<Self> {
+        let graph = Self::default();
+        graph.upsert(config, db::get_all_store_pools(rb).await?)?;
+        log::info!("pool graph loaded with {} pairs", graph.read().len());
+        Ok(graph)
+    }
+
+    /// read the reserves of `addresses` again, after the events of their pools are stored
+    pub async fn reload(&self, rb: &Rbatis, config: &BackendConfig, addresses: &[String]) -> anyhow::Result<()> {
+        self.upsert(config, listing::get_pools_of(rb, addresses).await?)
+    }
+
+    fn upsert(&self, config: &BackendConfig, pools: Vec<PoolInfo>) -> anyhow::Result<()> {
+        let pairs = pools.into_iter()
+            .filter_map(|pool| graph_pair(config, pool).transpose())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut graph = self.graph.write().unwrap();
+        for pair in pairs {
+            graph.upsert(pair);
+        }
+        Ok(())
+    }
+
+    /// the graph, not to be held across an await
+    pub fn read(&self) -> RwLockReadGuard<'_, PairGraph> {
+        self.graph.read().unwrap()
+    }
+}
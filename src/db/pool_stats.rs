@@ -0,0 +1,201 @@
+//! Aggregates of the events of a pool, or of the pools of a token, over a time window, by the
+//! time of their blocks.
+use rbatis::Rbatis;
+use rbatis::rbdc::decimal::Decimal;
+use web3::types::U256;
+
+/// Counts and swapped amounts of the events of a pool since a time. A swap takes the
+/// `*_in` amounts in and pays the `*_out` amounts out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WindowStats {
+    pub swap_count: i64,
+    pub add_liq_count: i64,
+    pub rm_liq_count: i64,
+    pub amount0_in: U256,
+    pub amount1_in: U256,
+    pub amount0_out: U256,
+    pub amount1_out: U256,
+}
+
+/// the events of the pool in blocks at or after `since`, in unix seconds. Events stored
+/// before their block time was kept are left out until they are backfilled.
+pub async fn get_window_stats(rb: &Rbatis, pair_address: &str, since: i64) -> anyhow::Result<WindowStats> {
+    #[derive(serde::Deserialize)]
+    struct Row {
+        event_type: i8,
+        amount0_in: Option<Decimal>,
+        amount1_in: Option<Decimal>,
+        amount0_out: Option<Decimal>,
+        amount1_out: Option<Decimal>,
+    }
+    // the amounts are summed here, sqlite would sum them as floats
+    let rows: Vec<Row> = rb
+        .query_decode("select event_type, amount0_in, amount1_in, amount0_out, amount1_out from events \
+                       where pair_address = ? and block_timestamp >= ?",
+                      vec![rbs::to_value!(pair_address), rbs::to_value!(since)])
+        .await?;
+    let amount = |value: &Option<Decimal>| -> anyhow::Result<U256> {
+        match value {
+            Some(value) => Ok(U256::from_dec_str(&value.0)?),
+            None => Ok(U256::zero()),
+        }
+    };
+    let mut stats = WindowStats::default();
+    for row in rows {
+        match row.event_type {
+            1 => stats.add_liq_count += 1,
+            2 => stats.rm_liq_count += 1,
+            3 => {
+                stats.swap_count += 1;
+                stats.amount0_in += amount(&row.amount0_in)?;
+                stats.amount1_in += amount(&row.amount1_in)?;
+                stats.amount0_out += amount(&row.amount0_out)?;
+                stats.amount1_out += amount(&row.amount1_out)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(stats)
+}
+
+/// The swaps of a token across its pools since a time. `amount_in` was paid into the
+/// pools and `amount_out` out of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TokenWindowStats {
+    pub swap_count: i64,
+    pub amount_in: U256,
+    pub amount_out: U256,
+}
+
+/// the swaps of the pools of `token` in blocks at or after `since`, in unix seconds
+pub async fn get_token_window_stats(rb: &Rbatis, token: &str, since: i64) -> anyhow::Result<TokenWindowStats> {
+    #[derive(serde::Deserialize)]
+    struct Row {
+        amount0_in: Option<Decimal>,
+        amount1_in: Option<Decimal>,
+        amount0_out: Option<Decimal>,
+        amount1_out: Option<Decimal>,
+        token_x_address: String,
+    }
+    let rows: Vec<Row> = rb
+        .query_decode("select e.amount0_in, e.amount1_in, e.amount0_out, e.amount1_out, p.token_x_address \
+                       from events e join pool_info p on p.pair_address = e.pair_address \
+                       where (p.token_x_address = ? or p.token_y_address = ?) \
+                       and e.event_type = 3 and e.block_timestamp >= ?",
+                      vec![rbs::to_value!(token), rbs::to_value!(token), rbs::to_value!(since)])
+        .await?;
+    let amount = |value: &Option<Decimal>| -> anyhow::Result<U256> {
+        match value {
+            Some(value) => Ok(U256::from_dec_str(&value.0)?),
+            None => Ok(U256::zero()),
+        }
+    };
+    let mut stats = TokenWindowStats::default();
+    for row in rows {
+        stats.swap_count += 1;
+        let (amount_in, amount_out) = if row.token_x_address == token {
+            (&row.amount0_in, &row.amount0_out)
+        } else {
+            (&row.amount1_in, &row.amount1_out)
+        };
+        stats.amount_in += amount(amount_in)?;
+        stats.amount_out += amount(amount_out)?;
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+    use crate::db::{save_pool, sqlite};
+    use crate::db::tables::{Event, PoolInfo};
+
+    fn event(event_type: i8, block_timestamp: Option<i64>, amount0_in: u64, amount1_out: u64) -> Event {
+        let decimal = |amount: u64| Some(Decimal::from_str(&amount.to_string()).unwrap());
+        Event {
+            tx_hash: hex::encode([1; 32]),
+            event_type,
+            pair_address: hex::encode([2; 20]),
+            block_number: Some(1),
+            log_index: Some(0),
+            block_timestamp,
+            tx_origin: None,
+            from_account: hex::encode([3; 20]),
+            to_account: None,
+            amount_x: None,
+            amount_y: None,
+            lp_amount: None,
+            amount0_in: decimal(amount0_in),
+            amount1_in: decimal(0),
+            amount0_out: decimal(0),
+            amount1_out: decimal(amount1_out),
+            direction: None,
+            flash: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_window_stats() {
+        let mut rb = sqlite::new_test_db("window_stats").await;
+        // amounts beyond the precision of a float
+        let big = 10u64.pow(19) + 1;
+        for (log_index, event) in [
+            event(3, Some(100), big, 5),
+            event(3, Some(200), big, 7),
+            event(3, Some(50), 1, 1),
+            event(3, None, 1, 1),
+            event(1, Some(150), 0, 0),
+            event(2, Some(99), 0, 0),
+        ].into_iter().enumerate() {
+            Event::insert(&mut rb, &Event { log_index: Some(log_index as i64), ..event }).await.unwrap();
+        }
+        let stats = get_window_stats(&rb, &hex::encode([2; 20]), 100).await.unwrap();
+        assert_eq!(stats, WindowStats {
+            swap_count: 2,
+            add_liq_count: 1,
+            rm_liq_count: 0,
+            amount0_in: U256::from(big) * 2,
+            amount1_out: U256::from(12),
+            ..Default::default()
+        });
+        assert_eq!(get_window_stats(&rb, &hex::encode([9; 20]), 0).await.unwrap(), WindowStats::default());
+    }
+
+    #[tokio::test]
+    async fn test_token_window_stats() {
+        let mut rb = sqlite::new_test_db("token_window_stats").await;
+        let pool = |address: u8, token_x: u8, token_y: u8| PoolInfo {
+            pair_address: hex::encode([address; 20]),
+            token_x_symbol: String::new(),
+            token_y_symbol: String::new(),
+            token_x_address: hex::encode([token_x; 20]),
+            token_y_address: hex::encode([token_y; 20]),
+            token_x_reserves: Decimal::from_str("0").unwrap(),
+            token_y_reserves: Decimal::from_str("0").unwrap(),
+            total_swap_count: 0,
+            total_add_liq_count: 0,
+            total_rm_liq_count: 0,
+            protocol: "uniswap_v2".to_string(),
+            factory_address: None,
+            lp_total_supply: None,
+            tvl_usd: None,
+            volume_usd: None,
+            created_block: None,
+        };
+        // the token is x of pool 2 and y of pool 4
+        save_pool(&mut rb, &pool(2, 0x70, 0x71)).await.unwrap();
+        save_pool(&mut rb, &pool(4, 0x72, 0x70)).await.unwrap();
+        let in_pool = |pair: u8, event: Event| Event { pair_address: hex::encode([pair; 20]), ..event };
+        for (log_index, event) in [
+            in_pool(2, event(3, Some(100), 10, 5)),
+            in_pool(4, event(3, Some(100), 20, 3)),
+            in_pool(4, event(3, Some(10), 20, 3)),
+            in_pool(4, event(1, Some(100), 0, 0)),
+        ].into_iter().enumerate() {
+            Event::insert(&mut rb, &Event { log_index: Some(log_index as i64), ..event }).await.unwrap();
+        }
+        let stats = get_token_window_stats(&rb, &hex::encode([0x70; 20]), 100).await.unwrap();
+        assert_eq!(stats, TokenWindowStats { swap_count: 2, amount_in: U256::from(10), amount_out: U256::from(3) });
+    }
+}
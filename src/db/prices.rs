@@ -0,0 +1,679 @@
+//! Usd prices of tokens, routed through the pools from anchor stablecoins priced at one usd.
+//!
+//! A token is priced from the pools pairing it with tokens priced before it, averaged by the
+//! usd liquidity of each pool, and pools below a minimum liquidity can't set a price. Pools
+//! against the anchors and the wrapped native tokens are preferred, then those against the
+//! tokens closest to an anchor. Wrapped native tokens are priced from the anchors only.
+//!
+//! The watcher reprices the tokens of the pools whose reserves change in a batch and the
+//! tokens priced through them, in the transaction storing the batch, and every token once on
+//! startup. The graph is loaded once and kept in memory, a batch reads again only the pools
+//! it changed.
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use rbatis::Rbatis;
+use rbatis::executor::RBatisTxExecutor;
+use rbatis::rbdc::decimal::Decimal;
+use std::str::FromStr;
+use ethabi::ethereum_types::U512;
+use web3::types::{H160, U256};
+use crate::amm::units::mul_div;
+use crate::config::BackendConfig;
+use crate::db::tables::{PoolInfo, Token, TokenPrice, TokenPriceHistory, V3Pool};
+
+/// the decimals of a usd value
+pub const USD_DECIMALS: u8 = 18;
+const HOUR: i64 = 3600;
+
+pub fn usd_unit() -> U256 {
+    U256::exp10(USD_DECIMALS as usize)
+}
+
+fn exp10(decimals: u8) -> U256 {
+    U256::exp10(decimals as usize)
+}
+
+fn decimal(value: U256) -> Decimal {
+    Decimal::from_str(&value.to_string()).unwrap()
+}
+
+/// The tokens prices are routed from, and the price graph shared by the clones. Without
+/// anchors no token is priced.
+#[derive(Debug, Clone, Default)]
+pub struct Pricing {
+    anchors: HashSet<String>,
+    natives: HashSet<String>,
+    /// with `USD_DECIMALS`
+    min_liquidity: U256,
+    /// the stored pools and prices, `None` until first needed
+    graph: Arc<tokio::sync::Mutex<Option<PriceGraph>>>,
+}
+
+impl Pricing {
+    pub fn new(anchors: &[H160], natives: &[H160], min_liquidity_usd: u64) -> Self {
+        Self {
+            anchors: anchors.iter().map(hex::encode).collect(),
+            natives: natives.iter().map(hex::encode).collect(),
+            min_liquidity: U256::from(min_liquidity_usd) * usd_unit(),
+            graph: Arc::default(),
+        }
+    }
+
+    pub fn from_config(config: &BackendConfig) -> Self {
+        Self::new(&config.usd_anchors, &config.native_tokens, config.min_price_liquidity_usd)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.anchors.is_empty()
+    }
+
+    fn is_hub(&self, token: &str) -> bool {
+        self.anchors.contains(token) || self.natives.contains(token)
+    }
+
+    /// commit the transaction when `result` is ok, roll it back otherwise and drop the graph,
+    /// which may hold prices of the transaction, to load it again
+    pub(crate) async fn finish(&self, tx: RBatisTxExecutor, result: anyhow::Result<()>) -> anyhow::Result<()> {
+        let result = super::finish(tx, result).await;
+        if result.is_err() {
+            *self.graph.lock().await = None;
+        }
+        result
+    }
+}
+
+/// A pool in the price graph.
+#[derive(Debug, Clone)]
+pub struct GraphPool {
+    pub pair_address: String,
+    pub token_x: String,
+    pub token_y: String,
+    /// raw amounts of x and y of the same value: the reserves of a V2 pair, the square root
+    /// price of a V3 pool
+    pub value_x: U256,
+    pub value_y: U256,
+    pub reserve_x: U256,
+    pub reserve_y: U256,
+}
+
+impl GraphPool {
+    fn new(pool: &PoolInfo, v3: Option<&V3Pool>) -> anyhow::Result<Self> {
+        let reserve_x = U256::from_dec_str(&pool.token_x_reserves.0)?;
+        let reserve_y = U256::from_dec_str(&pool.token_y_reserves.0)?;
+        let (value_x, value_y) = match v3 {
+            // the raw price of x in y as a Q128.128
+            Some(v3) => {
+                let sqrt_price = U256::from_dec_str(&v3.sqrt_price_x96.0)?;
+                (U256::one() << 128, mul_div(sqrt_price, sqrt_price, U256::one() << 64).unwrap_or_default())
+            }
+            None => (reserve_x, reserve_y),
+        };
+        Ok(Self {
+            pair_address: pool.pair_address.clone(),
+            token_x: pool.token_x_address.clone(),
+            token_y: pool.token_y_address.clone(),
+            value_x,
+            value_y,
+            reserve_x,
+            reserve_y,
+        })
+    }
+
+    /// the other token, the values of `token` and of the other token and the reserve of the other
+    fn sides(&self, token: &str) -> (&str, U256, U256, U256) {
+        if self.token_x == token {
+            (&self.token_y, self.value_x, self.value_y, self.reserve_y)
+        } else {
+            (&self.token_x, self.value_y, self.value_x, self.reserve_x)
+        }
+    }
+}
+
+/// A usd price with `USD_DECIMALS` per whole token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsdPrice {
+    pub price: U256,
+    /// the count of pools between the token and an anchor
+    pub depth: i64,
+    /// the liquidity of the pools the price was taken from
+    pub liquidity: U256,
+}
+
+/// A price of a token through one pool.
+struct Quote<'a> {
+    quote: &'a str,
+    depth: i64,
+    price: U256,
+    liquidity: U256,
+}
+
+/// The pools between tokens of known decimals, and the prices of the tokens.
+#[derive(Debug, Default)]
+pub struct PriceGraph {
+    decimals: HashMap<String, u8>,
+    /// by pair address
+    pools: HashMap<String, GraphPool>,
+    /// the pair addresses of the pools of a token
+    pools_of: HashMap<String, BTreeSet<String>>,
+    pub prices: HashMap<String, UsdPrice>,
+}
+
+impl PriceGraph {
+    /// `pools` with a token of unknown decimals or without liquidity are left out
+    pub fn new(decimals: HashMap<String, u8>, pools: Vec<GraphPool>, prices: HashMap<String, UsdPrice>) -> Self {
+        let mut graph = Self { decimals, prices, ..Default::default() };
+        for pool in pools {
+            graph.upsert(pool);
+        }
+        graph
+    }
+
+    /// replace the pool with the same address, or remove it when a token has unknown decimals
+    /// or it has no liquidity
+    pub fn upsert(&mut self, pool: GraphPool) {
+        if let Some(old) = self.pools.remove(&pool.pair_address) {
+            for token in [&old.token_x, &old.token_y] {
+                if let Some(pools) = self.pools_of.get_mut(token) {
+                    pools.remove(&old.pair_address);
+                }
+            }
+        }
+        if !self.decimals.contains_key(&pool.token_x) || !self.decimals.contains_key(&pool.token_y)
+            || pool.value_x.is_zero() || pool.value_y.is_zero() {
+            return;
+        }
+        for token in [&pool.token_x, &pool.token_y] {
+            self.pools_of.entry(token.clone()).or_default().insert(pool.pair_address.clone());
+        }
+        self.pools.insert(pool.pair_address.clone(), pool);
+    }
+
+    fn pools_of<'a>(&'a self, token: &str) -> impl Iterator<Item = &'a GraphPool> + 'a {
+        self.pools_of.get(token).into_iter().flatten().filter_map(|address| self.pools.get(address))
+    }
+
+    fn neighbors<'a>(&'a self, token: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pools_of(token).map(move |pool| pool.sides(token).0)
+    }
+
+    /// the price of `token` through `pool` from the price of its other token
+    fn quote<'a>(&self, pool: &'a GraphPool, token: &str) -> Option<Quote<'a>> {
+        let (quote, value, quote_value, quote_reserve) = pool.sides(token);
+        let quote_price = self.prices.get(quote)?;
+        let (decimals, quote_decimals) = (*self.decimals.get(token)?, *self.decimals.get(quote)?);
+        let price = mul_div(quote_price.price.checked_mul(exp10(decimals))?, quote_value,
+                            value.checked_mul(exp10(quote_decimals))?)?;
+        // both sides of a pool hold the same value
+        let liquidity = mul_div(quote_reserve.checked_mul(2.into())?, quote_price.price, exp10(quote_decimals))?;
+        Some(Quote { quote, depth: quote_price.depth, price, liquidity })
+    }
+
+    /// the price of `token` from the prices of its neighbors
+    fn evaluate(&self, pricing: &Pricing, token: &str) -> Option<UsdPrice> {
+        if pricing.anchors.contains(token) {
+            return Some(UsdPrice { price: usd_unit(), depth: 0, liquidity: U256::zero() });
+        }
+        let mut quotes: Vec<Quote> = self.pools_of(token)
+            .filter_map(|pool| self.quote(pool, token))
+            .filter(|q| q.liquidity >= pricing.min_liquidity)
+            .collect();
+        if pricing.natives.contains(token) && quotes.iter().any(|q| pricing.anchors.contains(q.quote)) {
+            quotes.retain(|q| pricing.anchors.contains(q.quote));
+        } else if quotes.iter().any(|q| pricing.is_hub(q.quote)) {
+            quotes.retain(|q| pricing.is_hub(q.quote));
+        } else {
+            let depth = quotes.iter().map(|q| q.depth).min()?;
+            quotes.retain(|q| q.depth == depth);
+        }
+        let liquidity = quotes.iter().fold(U256::zero(), |total, q| total.saturating_add(q.liquidity));
+        if liquidity.is_zero() {
+            return None;
+        }
+        let weighted = quotes.iter()
+            .map(|q| q.price.full_mul(q.liquidity))
+            .fold(U512::zero(), |total, value| total + value);
+        let price = U256::try_from(weighted / U512::from(liquidity)).ok()?;
+        let depth = 1 + quotes.iter().map(|q| q.depth).max()?;
+        Some(UsdPrice { price, depth, liquidity })
+    }
+
+    /// price again the `seeds` and every token priced through them, and price the tokens they
+    /// make reachable. Returns the tokens whose price changed, `None` for those left without one.
+    pub fn reprice(&mut self, pricing: &Pricing, seeds: impl IntoIterator<Item = String>)
+        -> BTreeMap<String, Option<UsdPrice>> {
+        let old = self.prices.clone();
+        let mut affected: BTreeSet<String> = seeds.into_iter().collect();
+        let mut stack: Vec<String> = affected.iter().cloned().collect();
+        while let Some(token) = stack.pop() {
+            let Some(depth) = old.get(&token).map(|p| p.depth) else {
+                continue;
+            };
+            for neighbor in self.neighbors(&token) {
+                if !affected.contains(neighbor) && old.get(neighbor).is_some_and(|p| p.depth > depth) {
+                    affected.insert(neighbor.to_string());
+                    stack.push(neighbor.to_string());
+                }
+            }
+        }
+        for token in &affected {
+            self.prices.remove(token);
+        }
+        // the anchors, then the native tokens from them, then the other tokens in rounds, from
+        // the prices of the rounds before
+        for token in pricing.anchors.iter().chain(&pricing.natives) {
+            if !self.prices.contains_key(token) {
+                if let Some(price) = self.evaluate(pricing, token) {
+                    self.prices.insert(token.clone(), price);
+                }
+            }
+            affected.insert(token.clone());
+        }
+        let mut frontier = affected.clone();
+        while !frontier.is_empty() {
+            let priced: Vec<(String, UsdPrice)> = frontier.iter()
+                .filter(|token| !self.prices.contains_key(*token))
+                .filter_map(|token| Some((token.clone(), self.evaluate(pricing, token)?)))
+                .collect();
+            for (token, price) in &priced {
+                self.prices.insert(token.clone(), price.clone());
+                affected.insert(token.clone());
+            }
+            frontier = priced.iter()
+                .flat_map(|(token, _)| self.neighbors(token))
+                .filter(|token| !self.prices.contains_key(*token))
+                .map(str::to_string)
+                .collect();
+        }
+
+        affected.into_iter()
+            .filter(|token| old.get(token) != self.prices.get(token))
+            .map(|token| {
+                let price = self.prices.get(&token).cloned();
+                (token, price)
+            })
+            .collect()
+    }
+
+    /// price every token again
+    pub fn reprice_all(&mut self, pricing: &Pricing) -> BTreeMap<String, Option<UsdPrice>> {
+        let mut tokens: BTreeSet<String> = self.prices.keys().cloned().collect();
+        tokens.extend(self.pools_of.keys().cloned());
+        self.reprice(pricing, tokens)
+    }
+
+    /// the usd value of an amount of `token`
+    pub fn value(&self, token: &str, amount: U256) -> Option<U256> {
+        mul_div(amount, self.prices.get(token)?.price, exp10(*self.decimals.get(token)?))
+    }
+
+    /// the value of the reserves of a pool, twice that of the priced token when only one is
+    /// priced
+    pub fn tvl(&self, pool: &GraphPool) -> Option<U256> {
+        match (self.value(&pool.token_x, pool.reserve_x), self.value(&pool.token_y, pool.reserve_y)) {
+            (Some(x), Some(y)) => Some(x.saturating_add(y)),
+            (Some(value), None) | (None, Some(value)) => Some(value.saturating_mul(2.into())),
+            (None, None) => None,
+        }
+    }
+
+    /// the value of swapped amounts, in token x when it is priced
+    pub fn volume(&self, pool: &PoolInfo, volume_x: U256, volume_y: U256) -> U256 {
+        self.value(&pool.token_x_address, volume_x)
+            .or_else(|| self.value(&pool.token_y_address, volume_y))
+            .unwrap_or_default()
+    }
+}
+
+fn usd_price(price: TokenPrice) -> anyhow::Result<(String, UsdPrice)> {
+    Ok((price.token_address, UsdPrice {
+        price: U256::from_dec_str(&price.price_usd.0)?,
+        depth: price.depth,
+        liquidity: U256::from_dec_str(&price.liquidity_usd.0)?,
+    }))
+}
+
+async fn load_graph(tx: &mut RBatisTxExecutor) -> anyhow::Result<(PriceGraph, Vec<PoolInfo>)> {
+    let tokens: Vec<Token> = tx.query_decode("select * from tokens", vec![]).await?;
+    let pools: Vec<PoolInfo> = tx.query_decode("select * from pool_info", vec![]).await?;
+    let v3_pools: HashMap<String, V3Pool> = tx
+        .query_decode::<Vec<V3Pool>>("select * from v3_pools", vec![])
+        .await?
+        .into_iter()
+        .map(|p| (p.pair_address.clone(), p))
+        .collect();
+    let prices: Vec<TokenPrice> = tx.query_decode("select * from token_prices", vec![]).await?;
+    let graph_pools = pools.iter()
+        .map(|pool| GraphPool::new(pool, v3_pools.get(&pool.pair_address)))
+        .collect::<anyhow::Result<_>>()?;
+    let prices = prices.into_iter().map(usd_price).collect::<anyhow::Result<_>>()?;
+    let decimals = tokens.into_iter().map(|t| (t.address, t.decimals)).collect();
+    Ok((PriceGraph::new(decimals, graph_pools, prices), pools))
+}
+
+/// read `pools` and the decimals of their tokens again into the graph, returns their rows
+async fn reload_pools(tx: &mut RBatisTxExecutor, graph: &mut PriceGraph, pools: &[String])
+    -> anyhow::Result<Vec<PoolInfo>> {
+    let placeholders = vec!["?"; pools.len()].join(", ");
+    let params: Vec<rbs::Value> = pools.iter().map(|p| rbs::to_value!(p)).collect();
+    let rows: Vec<PoolInfo> = tx
+        .query_decode(&format!("select * from pool_info where pair_address in ({})", placeholders), params.clone())
+        .await?;
+    let v3_pools: HashMap<String, V3Pool> = tx
+        .query_decode::<Vec<V3Pool>>(&format!("select * from v3_pools where pair_address in ({})", placeholders),
+                                     params)
+        .await?
+        .into_iter()
+        .map(|p| (p.pair_address.clone(), p))
+        .collect();
+    let addresses: BTreeSet<&String> = rows.iter().flat_map(|p| [&p.token_x_address, &p.token_y_address]).collect();
+    if !addresses.is_empty() {
+        let tokens: Vec<Token> = tx
+            .query_decode(&format!("select * from tokens where address in ({})", vec!["?"; addresses.len()].join(", ")),
+                          addresses.iter().map(|a| rbs::to_value!(a)).collect())
+            .await?;
+        graph.decimals.extend(tokens.into_iter().map(|t| (t.address, t.decimals)));
+    }
+    for pool in &rows {
+        graph.upsert(GraphPool::new(pool, v3_pools.get(&pool.pair_address))?);
+    }
+    Ok(rows)
+}
+
+/// store the changed prices, in the history of the hour of `timestamp` when it is known, and
+/// the tvl of `pools` and of the pools of the changed tokens
+async fn write_prices(tx: &mut RBatisTxExecutor, graph: &PriceGraph, changed: &BTreeMap<String, Option<UsdPrice>>, pools: &HashSet<String>,
+                      block_number: Option<i64>, timestamp: Option<i64>) -> anyhow::Result<()> {
+    for (token, price) in changed {
+        let Some(price) = price else {
+            tx.exec("delete from token_prices where token_address = ?", vec![rbs::to_value!(token)]).await?;
+            continue;
+        };
+        tx.exec("insert into token_prices (token_address, price_usd, depth, liquidity_usd, block_number, updated_at) \
+                values (?, ?, ?, ?, ?, ?) on conflict (token_address) do update set \
+                price_usd = excluded.price_usd, depth = excluded.depth, liquidity_usd = excluded.liquidity_usd, \
+                block_number = excluded.block_number, updated_at = excluded.updated_at",
+                vec![rbs::to_value!(token), rbs::to_value!(decimal(price.price)), rbs::to_value!(price.depth),
+                     rbs::to_value!(decimal(price.liquidity)), rbs::to_value!(block_number),
+                     rbs::to_value!(timestamp)])
+            .await?;
+        if let Some(timestamp) = timestamp {
+            tx.exec("insert into token_price_history (token_address, period_start, price_usd, block_number) \
+                    values (?, ?, ?, ?) on conflict (token_address, period_start) do update set \
+                    price_usd = excluded.price_usd, block_number = excluded.block_number",
+                    vec![rbs::to_value!(token), rbs::to_value!(timestamp.div_euclid(HOUR) * HOUR),
+                         rbs::to_value!(decimal(price.price)), rbs::to_value!(block_number)])
+                .await?;
+        }
+    }
+    for pool in graph.pools.values() {
+        if !pools.contains(&pool.pair_address) && !changed.contains_key(&pool.token_x)
+            && !changed.contains_key(&pool.token_y) {
+            continue;
+        }
+        tx.exec("update pool_info set tvl_usd = ? where pair_address = ?",
+                vec![rbs::to_value!(graph.tvl(pool).map(decimal)), rbs::to_value!(pool.pair_address.clone())])
+            .await?;
+    }
+    Ok(())
+}
+
+/// reprice the tokens of `pools`, whose reserves changed at `block_number`, and the tokens
+/// priced through them
+pub(crate) async fn update_prices(tx: &mut RBatisTxExecutor, pricing: &Pricing, pools: &[String],
+                                  block_number: Option<i64>, timestamp: Option<i64>) -> anyhow::Result<()> {
+    if !pricing.is_enabled() || pools.is_empty() {
+        return Ok(());
+    }
+    let mut cached = pricing.graph.lock().await;
+    let graph = match cached.as_mut() {
+        Some(graph) => graph,
+        None => cached.insert(load_graph(tx).await?.0),
+    };
+    let rows = reload_pools(tx, graph, pools).await?;
+    let seeds: Vec<String> = rows.into_iter()
+        .flat_map(|p| [p.token_x_address, p.token_y_address])
+        .collect();
+    let changed = graph.reprice(pricing, seeds);
+    let pools: HashSet<String> = pools.iter().cloned().collect();
+    write_prices(tx, graph, &changed, &pools, block_number, timestamp).await
+}
+
+/// add the value of the swapped amounts of a pool, at the current prices, to its volume
+pub(crate) async fn add_volume(tx: &mut RBatisTxExecutor, pair_address: &str, volume_x: U256, volume_y: U256)
+    -> anyhow::Result<()> {
+    if volume_x.is_zero() && volume_y.is_zero() {
+        return Ok(());
+    }
+    let pools: Vec<PoolInfo> = tx
+        .query_decode("select * from pool_info where pair_address = ?", vec![rbs::to_value!(pair_address)])
+        .await?;
+    // pools stored before volumes were kept get theirs on startup
+    let Some(pool) = pools.into_iter().next() else {
+        return Ok(());
+    };
+    let Some(volume) = &pool.volume_usd else {
+        return Ok(());
+    };
+    let addresses = [pool.token_x_address.clone(), pool.token_y_address.clone()];
+    let prices: Vec<TokenPrice> = tx
+        .query_decode("select * from token_prices where token_address in (?, ?)",
+                      addresses.iter().map(|a| rbs::to_value!(a)).collect())
+        .await?;
+    let tokens: Vec<Token> = tx
+        .query_decode("select * from tokens where address in (?, ?)",
+                      addresses.iter().map(|a| rbs::to_value!(a)).collect())
+        .await?;
+    let prices = prices.into_iter().map(usd_price).collect::<anyhow::Result<_>>()?;
+    let graph = PriceGraph::new(tokens.into_iter().map(|t| (t.address, t.decimals)).collect(), vec![], prices);
+    let volume = U256::from_dec_str(&volume.0)?.saturating_add(graph.volume(&pool, volume_x, volume_y));
+    tx.exec("update pool_info set volume_usd = ? where pair_address = ?",
+            vec![rbs::to_value!(decimal(volume)), rbs::to_value!(pair_address)])
+        .await?;
+    Ok(())
+}
+
+/// reprice every token and the tvl of every pool, and value at the current prices the
+/// volume of the pools stored before volumes were kept. The graph is loaded again.
+pub async fn refresh_prices(rb: &Rbatis, pricing: &Pricing) -> anyhow::Result<()> {
+    let mut cached = pricing.graph.lock().await;
+    *cached = None;
+    let mut tx = rb.acquire_begin().await?;
+    let mut graph = PriceGraph::default();
+    let result = async {
+        let all_pools;
+        (graph, all_pools) = load_graph(&mut tx).await?;
+        let changed = if pricing.is_enabled() {
+            graph.reprice_all(pricing)
+        } else {
+            let removed = graph.prices.keys().map(|token| (token.clone(), None)).collect();
+            graph.prices.clear();
+            removed
+        };
+        let pools = all_pools.iter().map(|p| p.pair_address.clone()).collect();
+        write_prices(&mut tx, &graph, &changed, &pools, None, None).await?;
+
+        #[derive(serde::Deserialize)]
+        struct Volume {
+            volume_x: Decimal,
+            volume_y: Decimal,
+        }
+        for pool in all_pools.iter().filter(|p| p.volume_usd.is_none()) {
+            let days: Vec<Volume> = tx
+                .query_decode("select volume_x, volume_y from pool_day_stats where pair_address = ?",
+                              vec![rbs::to_value!(pool.pair_address.clone())])
+                .await?;
+            let mut volume = U256::zero();
+            for day in days {
+                let (x, y) = (U256::from_dec_str(&day.volume_x.0)?, U256::from_dec_str(&day.volume_y.0)?);
+                volume = volume.saturating_add(graph.volume(pool, x, y));
+            }
+            tx.exec("update pool_info set volume_usd = ? where pair_address = ?",
+                    vec![rbs::to_value!(decimal(volume)), rbs::to_value!(pool.pair_address.clone())])
+                .await?;
+        }
+        Ok(())
+    }.await;
+    super::finish(tx, result).await?;
+    *cached = Some(graph);
+    Ok(())
+}
+
+/// the current usd prices of those of `tokens` with one
+pub async fn get_token_prices(rb: &Rbatis, tokens: &[String]) -> anyhow::Result<HashMap<String, TokenPrice>> {
+    if tokens.is_empty() {
+        return Ok(HashMap::new());
+    }
+    let placeholders = vec!["?"; tokens.len()].join(", ");
+    let prices: Vec<TokenPrice> = rb
+        .query_decode(&format!("select * from token_prices where token_address in ({})", placeholders),
+                      tokens.iter().map(|t| rbs::to_value!(t)).collect())
+        .await?;
+    Ok(prices.into_iter().map(|p| (p.token_address.clone(), p)).collect())
+}
+
+/// the hourly usd prices of a token in the range, oldest first
+pub async fn get_price_history(rb: &Rbatis, token: &str, from: i64, to: i64) -> anyhow::Result<Vec<TokenPriceHistory>> {
+    let history: Vec<TokenPriceHistory> = rb
+        .query_decode("select * from token_price_history where token_address = ? and period_start >= ? \
+                       and period_start <= ? order by period_start",
+                      vec![rbs::to_value!(token), rbs::to_value!(from), rbs::to_value!(to)])
+        .await?;
+    Ok(history)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{save_pool, save_tokens, sqlite, store_pair_events};
+    use crate::watcher::event::{EventData, PairEvent, PairSyncEvent};
+
+    fn address(byte: u8) -> String {
+        hex::encode([byte; 20])
+    }
+
+    fn units(amount: u64, decimals: u8) -> U256 {
+        U256::from(amount) * exp10(decimals)
+    }
+
+    fn pool(byte: u8, x: u8, reserve_x: U256, y: u8, reserve_y: U256) -> GraphPool {
+        GraphPool {
+            pair_address: address(byte),
+            token_x: address(x),
+            token_y: address(y),
+            value_x: reserve_x,
+            value_y: reserve_y,
+            reserve_x,
+            reserve_y,
+        }
+    }
+
+    fn usd(price: &str) -> U256 {
+        crate::amm::units::parse_units(price, USD_DECIMALS).unwrap()
+    }
+
+    #[test]
+    fn test_reprice() {
+        // 1: usdc, 2: weth, 3: a token in pools with both, 4: a token in a thin pool, 5: a token
+        // only paired with 3
+        let (usdc, weth, token, thin, leaf) = (address(1), address(2), address(3), address(4), address(5));
+        let decimals: HashMap<String, u8> = [(1, 6), (2, 18), (3, 18), (4, 18), (5, 18)].into_iter()
+            .map(|(byte, decimals)| (address(byte), decimals))
+            .collect();
+        let pricing = Pricing::new(&[H160::repeat_byte(1)], &[H160::repeat_byte(2)], 1000);
+        let pools = |weth_usdc: u64, thin_usdc: u64| vec![
+            pool(0xa1, 2, units(100, 18), 1, units(weth_usdc, 6)),
+            pool(0xa2, 3, units(1000, 18), 2, units(1, 18)),
+            pool(0xa3, 3, units(1000, 18), 1, units(1000, 6)),
+            pool(0xa4, 4, units(thin_usdc / 100, 18), 1, units(thin_usdc, 6)),
+            pool(0xa5, 5, units(100, 18), 3, units(1000, 18)),
+        ];
+
+        let mut graph = PriceGraph::new(decimals.clone(), pools(200_000, 100), HashMap::new());
+        let changed = graph.reprice_all(&pricing);
+        let price = |graph: &PriceGraph, token: &str| graph.prices.get(token).map(|p| (p.price, p.depth));
+        assert_eq!(price(&graph, &usdc), Some((usd("1"), 0)));
+        assert_eq!(price(&graph, &weth), Some((usd("2000"), 1)));
+        // 2 usd through weth with 4000 usd of liquidity, 1 usd through usdc with 2000
+        assert_eq!(price(&graph, &token), Some((usd("1.666666666666666666"), 2)));
+        // 200 usd of liquidity is below the minimum
+        assert_eq!(price(&graph, &thin), None);
+        assert_eq!(price(&graph, &leaf), Some((usd("16.66666666666666666"), 3)));
+        assert_eq!(changed.len(), 4);
+        assert_eq!(graph.tvl(&graph.pools[&address(0xa1)]), Some(usd("400000")));
+
+        // a new price of weth reprices the tokens priced through it
+        let mut graph = PriceGraph::new(decimals.clone(), pools(300_000, 100), graph.prices.clone());
+        let changed = graph.reprice(&pricing, [weth.clone(), usdc.clone()]);
+        assert_eq!(changed.keys().cloned().collect::<Vec<_>>(), vec![weth.clone(), token.clone(), leaf.clone()]);
+        assert_eq!(price(&graph, &token), Some((usd("2.5"), 2)));
+        assert_eq!(price(&graph, &leaf), Some((usd("25"), 3)));
+
+        // liquidity added to the thin pool prices its token
+        let mut graph = PriceGraph::new(decimals, pools(300_000, 1000), graph.prices.clone());
+        let changed = graph.reprice(&pricing, [thin.clone(), usdc]);
+        assert_eq!(changed.into_iter().collect::<Vec<_>>(),
+                   vec![(thin, Some(UsdPrice { price: usd("100"), depth: 1, liquidity: usd("2000") }))]);
+    }
+
+    #[tokio::test]
+    async fn test_update_prices() {
+        let mut rb = sqlite::new_test_db("update_prices").await;
+        let token = |byte: u8, decimals: u8| Token {
+            address: address(byte),
+            symbol: format!("T{}", byte),
+            decimals,
+            name: String::new(),
+            total_supply: None,
+            metadata_status: "ok".to_string(),
+            metadata_attempts: 1,
+        };
+        save_tokens(&rb, &[token(1, 6), token(2, 18)]).await.unwrap();
+        save_pool(&mut rb, &PoolInfo {
+            pair_address: address(0xa1),
+            token_x_symbol: "T2".to_string(),
+            token_y_symbol: "T1".to_string(),
+            token_x_address: address(2),
+            token_y_address: address(1),
+            token_x_reserves: Decimal::from_str("0").unwrap(),
+            token_y_reserves: Decimal::from_str("0").unwrap(),
+            total_swap_count: 0,
+            total_add_liq_count: 0,
+            total_rm_liq_count: 0,
+            protocol: "uniswap_v2".to_string(),
+            factory_address: None,
+            lp_total_supply: None,
+            tvl_usd: None,
+            volume_usd: Some(Decimal::from_str("0").unwrap()),
+            created_block: None,
+        }).await.unwrap();
+        let pricing = Pricing::new(&[H160::repeat_byte(1)], &[], 1000);
+        for (block, usdc) in [(1u64, 200_000u64), (2, 300_000)] {
+            let meta = EventData { address: H160::repeat_byte(0xa1), tx_hash: Default::default(), block_number: block,
+                                   log_index: 0, block_timestamp: Some(7200 + block), tx_origin: None };
+            let mut tx = rb.acquire_begin().await.unwrap();
+            store_pair_events(&mut tx, vec![PairEvent::SyncPairEvent(PairSyncEvent {
+                meta, reserve0: units(100, 18), reserve1: units(usdc, 6),
+            })], &BackendConfig::default(), &pricing).await.unwrap();
+            tx.commit().await.unwrap();
+        }
+
+        let prices = get_token_prices(&rb, &[address(1), address(2)]).await.unwrap();
+        assert_eq!(prices[&address(2)].price_usd.0, usd("3000").to_string());
+        assert_eq!(prices[&address(2)].block_number, Some(2));
+        assert_eq!(prices[&address(1)].price_usd.0, usd("1").to_string());
+        let history = get_price_history(&rb, &address(2), 0, 7200).await.unwrap();
+        assert_eq!(history.iter().map(|h| (h.period_start, h.price_usd.0.clone())).collect::<Vec<_>>(),
+                   vec![(7200, usd("3000").to_string())]);
+        let pool = crate::db::get_pool(&rb, &address(0xa1)).await.unwrap().unwrap();
+        assert_eq!(pool.tvl_usd.unwrap().0, usd("600000").to_string());
+        // the graph is kept, and dropped with a rolled back transaction
+        assert_eq!(pricing.graph.lock().await.as_ref().unwrap().pools.len(), 1);
+        let tx = rb.acquire_begin().await.unwrap();
+        assert!(pricing.finish(tx, Err(anyhow::format_err!("failed"))).await.is_err());
+        assert!(pricing.graph.lock().await.is_none());
+
+        // without anchors the prices are dropped
+        refresh_prices(&rb, &Pricing::default()).await.unwrap();
+        assert!(get_token_prices(&rb, &[address(1), address(2)]).await.unwrap().is_empty());
+    }
+}
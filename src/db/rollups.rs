@@ -0,0 +1,357 @@
+//! Hourly and daily statistics of pools. Like the candles, the watcher turns every batch of
+//! events it stores into `StatPoint`s and merges them into the stored periods in the same
+//! transaction, so the statistics never lag behind the events.
+use std::collections::{BTreeMap, BTreeSet};
+use rbatis::Rbatis;
+use rbatis::executor::RBatisTxExecutor;
+use rbatis::rbdc::decimal::Decimal;
+use std::str::FromStr;
+use web3::types::U256;
+use crate::amm::units::mul_div;
+use crate::db::tables::PoolPeriodStats;
+use crate::protocol::uniswap_v3::{move_reserves, V3PoolEvent};
+use crate::watcher::event::{EventData, PairEvent};
+
+pub const HOUR: i64 = 3600;
+pub const DAY: i64 = 24 * 3600;
+/// the tables of the periods by their length in seconds
+const PERIODS: [(i64, &str); 2] = [(HOUR, "pool_hour_stats"), (DAY, "pool_day_stats")];
+/// fee rates are in millionths of the amount in
+const FEE_DENOMINATOR: u64 = 1_000_000;
+
+fn table(period: i64) -> anyhow::Result<&'static str> {
+    PERIODS.iter().find(|(p, _)| *p == period).map(|(_, table)| *table)
+        .ok_or_else(|| anyhow::format_err!("no statistics of {} seconds", period))
+}
+
+/// An event of a pool with what it changed: an add, a remove or a swap and its amounts,
+/// and the reserves after it when it moved them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatPoint {
+    pub block_number: i64,
+    pub log_index: i64,
+    pub timestamp: i64,
+    /// 1: add, 2: remove, 3: swap like `events.event_type`, `None` for other events
+    pub event_type: Option<i8>,
+    pub amount0_in: U256,
+    pub amount1_in: U256,
+    pub amount0_out: U256,
+    pub amount1_out: U256,
+    pub reserves: Option<(U256, U256)>,
+    /// the sender of the transaction of a swap
+    pub trader: Option<String>,
+}
+
+impl StatPoint {
+    /// `None` for an event without its block time
+    fn at(meta: &EventData, event_type: Option<i8>, reserves: Option<(U256, U256)>) -> Option<Self> {
+        Some(Self {
+            block_number: meta.block_number as i64,
+            log_index: meta.log_index as i64,
+            timestamp: meta.block_timestamp? as i64,
+            event_type,
+            amount0_in: U256::zero(),
+            amount1_in: U256::zero(),
+            amount0_out: U256::zero(),
+            amount1_out: U256::zero(),
+            reserves,
+            trader: None,
+        })
+    }
+
+    fn with_swap(mut self, amounts: [U256; 4], trader: String) -> Self {
+        [self.amount0_in, self.amount1_in, self.amount0_out, self.amount1_out] = amounts;
+        self.trader = Some(trader);
+        self
+    }
+
+    pub fn of_pair_event(event: &PairEvent) -> Option<Self> {
+        match event {
+            PairEvent::SyncPairEvent(sync) => Self::at(&sync.meta, None, Some((sync.reserve0, sync.reserve1))),
+            PairEvent::MintPairEvent(mint) => Self::at(&mint.meta, Some(1), None),
+            PairEvent::BurnPairEvent(burn) => Self::at(&burn.meta, Some(2), None),
+            PairEvent::SwapPairEvent(swap) => {
+                let trader = swap.meta.tx_origin.unwrap_or(swap.sender);
+                Some(Self::at(&swap.meta, Some(3), None)?.with_swap(
+                    [swap.amount0_in, swap.amount1_in, swap.amount0_out, swap.amount1_out],
+                    hex::encode(trader)))
+            }
+        }
+    }
+
+    /// the points of consecutive events of a concentrated liquidity pool, from its reserves
+    /// before the first of them
+    pub fn of_v3_events(events: &[V3PoolEvent], mut reserves: (U256, U256)) -> Vec<Self> {
+        let mut points = Vec::new();
+        for event in events {
+            move_reserves(&mut reserves.0, &mut reserves.1, event);
+            let point = match event {
+                V3PoolEvent::Initialize { .. } => None,
+                V3PoolEvent::Mint { meta, .. } => Self::at(meta, Some(1), Some(reserves)),
+                V3PoolEvent::Burn { meta, .. } => Self::at(meta, Some(2), Some(reserves)),
+                V3PoolEvent::Swap { meta, sender, amount0, amount1, .. } => {
+                    let paid = |amount: i128| U256::from(amount.max(0) as u128);
+                    let sent = |amount: i128| U256::from((-amount.min(0)) as u128);
+                    let trader = meta.tx_origin.unwrap_or(*sender);
+                    Self::at(meta, Some(3), Some(reserves)).map(|point| point.with_swap(
+                        [paid(*amount0), paid(*amount1), sent(*amount0), sent(*amount1)], hex::encode(trader)))
+                }
+                V3PoolEvent::Collect { meta, .. } | V3PoolEvent::Flash { meta, .. } => Self::at(meta, None, Some(reserves)),
+            };
+            points.extend(point);
+        }
+        points
+    }
+}
+
+/// the amounts of each token swapped in and out
+pub fn swap_volume(points: &[StatPoint]) -> (U256, U256) {
+    points.iter()
+        .filter(|p| p.event_type == Some(3))
+        .fold((U256::zero(), U256::zero()), |(x, y), p| (
+            x.saturating_add(p.amount0_in).saturating_add(p.amount0_out),
+            y.saturating_add(p.amount1_in).saturating_add(p.amount1_out),
+        ))
+}
+
+/// The statistics of a pool over a period.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeriodStats {
+    pub period_start: i64,
+    pub volume_x: U256,
+    pub volume_y: U256,
+    pub fees_x: U256,
+    pub fees_y: U256,
+    /// the closing reserves, `None` when no event of the period moved them
+    pub reserves: Option<(U256, U256)>,
+    reserve_position: Option<(i64, i64)>,
+    pub swap_count: i64,
+    pub add_liq_count: i64,
+    pub rm_liq_count: i64,
+    pub unique_traders: i64,
+}
+
+impl PeriodStats {
+    fn new(period_start: i64) -> Self {
+        Self {
+            period_start,
+            volume_x: U256::zero(),
+            volume_y: U256::zero(),
+            fees_x: U256::zero(),
+            fees_y: U256::zero(),
+            reserves: None,
+            reserve_position: None,
+            swap_count: 0,
+            add_liq_count: 0,
+            rm_liq_count: 0,
+            unique_traders: 0,
+        }
+    }
+
+    fn add(&mut self, point: &StatPoint, fee: U256) {
+        if let Some(reserves) = point.reserves {
+            let position = (point.block_number, point.log_index);
+            if self.reserve_position.is_none_or(|last| position > last) {
+                self.reserves = Some(reserves);
+                self.reserve_position = Some(position);
+            }
+        }
+        match point.event_type {
+            Some(1) => self.add_liq_count += 1,
+            Some(2) => self.rm_liq_count += 1,
+            Some(3) => {
+                let fees = |amount_in: U256| mul_div(amount_in, fee, FEE_DENOMINATOR.into()).unwrap_or_default();
+                self.volume_x = self.volume_x.saturating_add(point.amount0_in).saturating_add(point.amount0_out);
+                self.volume_y = self.volume_y.saturating_add(point.amount1_in).saturating_add(point.amount1_out);
+                self.fees_x = self.fees_x.saturating_add(fees(point.amount0_in));
+                self.fees_y = self.fees_y.saturating_add(fees(point.amount1_in));
+                self.swap_count += 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn from_row(row: &PoolPeriodStats) -> anyhow::Result<Self> {
+        let amount = |value: &Decimal| U256::from_dec_str(&value.0);
+        let reserves = match (&row.reserve_x, &row.reserve_y) {
+            (Some(x), Some(y)) => Some((amount(x)?, amount(y)?)),
+            _ => None,
+        };
+        Ok(Self {
+            period_start: row.period_start,
+            volume_x: amount(&row.volume_x)?,
+            volume_y: amount(&row.volume_y)?,
+            fees_x: amount(&row.fees_x)?,
+            fees_y: amount(&row.fees_y)?,
+            reserves,
+            reserve_position: row.reserve_block.zip(row.reserve_log_index),
+            swap_count: row.swap_count,
+            add_liq_count: row.add_liq_count,
+            rm_liq_count: row.rm_liq_count,
+            unique_traders: row.unique_traders,
+        })
+    }
+
+    fn to_row(&self, pair_address: &str) -> PoolPeriodStats {
+        let decimal = |value: U256| Decimal::from_str(&value.to_string()).unwrap();
+        PoolPeriodStats {
+            pair_address: pair_address.to_string(),
+            period_start: self.period_start,
+            volume_x: decimal(self.volume_x),
+            volume_y: decimal(self.volume_y),
+            fees_x: decimal(self.fees_x),
+            fees_y: decimal(self.fees_y),
+            reserve_x: self.reserves.map(|(x, _)| decimal(x)),
+            reserve_y: self.reserves.map(|(_, y)| decimal(y)),
+            reserve_block: self.reserve_position.map(|(block, _)| block),
+            reserve_log_index: self.reserve_position.map(|(_, log_index)| log_index),
+            swap_count: self.swap_count,
+            add_liq_count: self.add_liq_count,
+            rm_liq_count: self.rm_liq_count,
+            unique_traders: self.unique_traders,
+        }
+    }
+}
+
+/// merge the points of a pool into its hourly and daily statistics, `fee` is the fee rate
+/// of the pool in millionths
+pub(crate) async fn update_pool_stats(tx: &mut RBatisTxExecutor, pair_address: &str, fee: u64, points: &[StatPoint])
+    -> anyhow::Result<()> {
+    let (Some(first), Some(last)) = (points.iter().map(|p| p.timestamp).min(), points.iter().map(|p| p.timestamp).max()) else {
+        return Ok(());
+    };
+    for (period, table) in PERIODS {
+        let rows: Vec<PoolPeriodStats> = tx
+            .query_decode(&format!("select * from {} where pair_address = ? and period_start >= ? and period_start <= ?", table),
+                          vec![rbs::to_value!(pair_address), rbs::to_value!(first.div_euclid(period) * period),
+                               rbs::to_value!(last.div_euclid(period) * period)])
+            .await?;
+        let mut stats = rows.iter()
+            .map(|row| Ok((row.period_start, PeriodStats::from_row(row)?)))
+            .collect::<anyhow::Result<BTreeMap<_, _>>>()?;
+        let mut updated = BTreeSet::new();
+        for point in points {
+            let start = point.timestamp.div_euclid(period) * period;
+            stats.entry(start).or_insert_with(|| PeriodStats::new(start)).add(point, fee.into());
+            updated.insert(start);
+            if let Some(trader) = &point.trader {
+                tx.exec("insert into pool_stats_traders (pair_address, period_secs, period_start, trader) \
+                        values (?, ?, ?, ?) on conflict do nothing",
+                        vec![rbs::to_value!(pair_address), rbs::to_value!(period), rbs::to_value!(start),
+                             rbs::to_value!(trader)])
+                    .await?;
+            }
+        }
+        for start in updated {
+            #[derive(serde::Deserialize)]
+            struct Count {
+                count: i64,
+            }
+            let counts: Vec<Count> = tx
+                .query_decode("select count(*) as count from pool_stats_traders \
+                               where pair_address = ? and period_secs = ? and period_start = ?",
+                              vec![rbs::to_value!(pair_address), rbs::to_value!(period), rbs::to_value!(start)])
+                .await?;
+            let period_stats = stats.get_mut(&start).unwrap();
+            period_stats.unique_traders = counts.first().map(|c| c.count).unwrap_or_default();
+            let row = period_stats.to_row(pair_address);
+            tx.exec(&format!("insert into {} (pair_address, period_start, volume_x, volume_y, fees_x, fees_y, \
+                    reserve_x, reserve_y, reserve_block, reserve_log_index, swap_count, add_liq_count, \
+                    rm_liq_count, unique_traders) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+                    on conflict (pair_address, period_start) do update set \
+                    volume_x = excluded.volume_x, volume_y = excluded.volume_y, fees_x = excluded.fees_x, \
+                    fees_y = excluded.fees_y, reserve_x = excluded.reserve_x, reserve_y = excluded.reserve_y, \
+                    reserve_block = excluded.reserve_block, reserve_log_index = excluded.reserve_log_index, \
+                    swap_count = excluded.swap_count, add_liq_count = excluded.add_liq_count, \
+                    rm_liq_count = excluded.rm_liq_count, unique_traders = excluded.unique_traders", table),
+                    vec![rbs::to_value!(row.pair_address), rbs::to_value!(row.period_start),
+                         rbs::to_value!(row.volume_x), rbs::to_value!(row.volume_y),
+                         rbs::to_value!(row.fees_x), rbs::to_value!(row.fees_y),
+                         rbs::to_value!(row.reserve_x), rbs::to_value!(row.reserve_y),
+                         rbs::to_value!(row.reserve_block), rbs::to_value!(row.reserve_log_index),
+                         rbs::to_value!(row.swap_count), rbs::to_value!(row.add_liq_count),
+                         rbs::to_value!(row.rm_liq_count), rbs::to_value!(row.unique_traders)])
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// delete the statistics of a pool, before they are rolled up again from all its events
+pub(crate) async fn delete_pool_stats(tx: &mut RBatisTxExecutor, pair_address: &str) -> anyhow::Result<()> {
+    for (_, table) in PERIODS {
+        tx.exec(&format!("delete from {} where pair_address = ?", table), vec![rbs::to_value!(pair_address)]).await?;
+    }
+    tx.exec("delete from pool_stats_traders where pair_address = ?", vec![rbs::to_value!(pair_address)]).await?;
+    Ok(())
+}
+
+/// the statistics of a pool over the periods of `period` seconds starting from `from` to
+/// `to`, oldest first. Periods without any event have no statistics.
+pub async fn get_pool_stats(rb: &Rbatis, pair_address: &str, period: i64, from: i64, to: i64)
+    -> anyhow::Result<Vec<PeriodStats>> {
+    let rows: Vec<PoolPeriodStats> = rb
+        .query_decode(&format!("select * from {} where pair_address = ? and period_start >= ? \
+                                and period_start <= ? order by period_start", table(period)?),
+                      vec![rbs::to_value!(pair_address), rbs::to_value!(from), rbs::to_value!(to)])
+        .await?;
+    rows.iter().map(PeriodStats::from_row).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::{finish, sqlite};
+
+    fn swap(block: i64, timestamp: i64, amount0_in: u64, amount1_out: u64, trader: u8) -> StatPoint {
+        StatPoint {
+            block_number: block,
+            log_index: 1,
+            timestamp,
+            event_type: Some(3),
+            amount0_in: U256::from(amount0_in),
+            amount1_in: U256::zero(),
+            amount0_out: U256::zero(),
+            amount1_out: U256::from(amount1_out),
+            reserves: None,
+            trader: Some(hex::encode([trader; 20])),
+        }
+    }
+
+    fn sync(block: i64, timestamp: i64, reserve_x: u64) -> StatPoint {
+        StatPoint {
+            log_index: 0,
+            event_type: None,
+            reserves: Some((U256::from(reserve_x), U256::one())),
+            trader: None,
+            ..swap(block, timestamp, 0, 0, 0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_pool_stats() {
+        let rb = sqlite::new_test_db("update_pool_stats").await;
+        let pair = hex::encode([1; 20]);
+        // the syncs of a range stored before its swaps, then the next hour
+        for points in [
+            vec![sync(1, 10, 100), sync(2, 20, 200)],
+            vec![swap(1, 10, 10_000, 5, 0xa), swap(2, 20, 1000, 1, 0xb)],
+            vec![swap(3, HOUR + 1, 2000, 1, 0xa), sync(3, HOUR + 1, 300),
+                 StatPoint { event_type: Some(1), trader: None, ..sync(4, HOUR + 2, 400) }],
+        ] {
+            let mut tx = rb.acquire_begin().await.unwrap();
+            let result = update_pool_stats(&mut tx, &pair, 3000, &points).await;
+            finish(tx, result).await.unwrap();
+        }
+
+        let hours = get_pool_stats(&rb, &pair, HOUR, 0, HOUR).await.unwrap();
+        let summary = |s: &PeriodStats| (s.period_start, s.volume_x.as_u64(), s.fees_x.as_u64(),
+                                         s.reserves.unwrap().0.as_u64(), s.swap_count, s.add_liq_count, s.unique_traders);
+        assert_eq!(hours.iter().map(summary).collect::<Vec<_>>(),
+                   vec![(0, 11_000, 33, 200, 2, 0, 2), (HOUR, 2000, 6, 400, 1, 1, 1)]);
+        let days = get_pool_stats(&rb, &pair, DAY, 0, 0).await.unwrap();
+        assert_eq!(days.iter().map(summary).collect::<Vec<_>>(), vec![(0, 13_000, 39, 400, 3, 1, 2)]);
+        assert_eq!(days[0].volume_y, U256::from(7));
+        assert!(get_pool_stats(&rb, &pair, 60, 0, 0).await.is_err());
+    }
+}
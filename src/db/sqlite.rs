@@ -0,0 +1,118 @@
+use rbatis::Rbatis;
+use serde::Deserialize;
+
+/// diesel style migrations for the sqlite backend, in the order they must be applied.
+/// the postgres schema is still managed by `diesel migration run`, an embedded sqlite
+/// database has no such step so the backend applies these itself at startup.
+const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "20230428024756",
+        include_str!("../storage/sqlite_migrations/2023-04-28-024756_init/up.sql"),
+    ),
+    (
+        "20261019000001",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000001_reconciliation_issues/up.sql"),
+    ),
+    (
+        "20261019000002",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000002_token_metadata/up.sql"),
+    ),
+    (
+        "20261019000003",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000003_failed_logs/up.sql"),
+    ),
+    (
+        "20261019000004",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000004_uniswap_v3/up.sql"),
+    ),
+    (
+        "20261019000005",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000005_swap_amounts/up.sql"),
+    ),
+    (
+        "20261019000006",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000006_lp_amount/up.sql"),
+    ),
+    (
+        "20261019000007",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000007_pool_factory/up.sql"),
+    ),
+    (
+        "20261019000008",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000008_pool_detail/up.sql"),
+    ),
+    (
+        "20261019000009",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000009_account_activity/up.sql"),
+    ),
+    (
+        "20261019000010",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000010_pool_candles/up.sql"),
+    ),
+    (
+        "20261019000011",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000011_pool_stats/up.sql"),
+    ),
+    (
+        "20261019000012",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000012_token_prices/up.sql"),
+    ),
+    (
+        "20261019000013",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000013_unique_events/up.sql"),
+    ),
+    (
+        "20261019000014",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000014_unsigned_v3_amounts/up.sql"),
+    ),
+    (
+        "20261019000015",
+        include_str!("../storage/sqlite_migrations/2026-10-19-000015_backfill_jobs/up.sql"),
+    ),
+];
+
+#[derive(Debug, Deserialize)]
+struct AppliedMigration {
+    version: String,
+}
+
+pub fn is_sqlite(rb: &Rbatis) -> bool {
+    rb.driver_type().map(|d| d == "sqlite").unwrap_or_default()
+}
+
+/// apply every migration that is not yet recorded in `__diesel_schema_migrations`,
+/// the same bookkeeping table diesel uses, so the cli can still be pointed at the file.
+pub async fn run_migrations(rb: &Rbatis) -> anyhow::Result<()> {
+    rb.exec("CREATE TABLE IF NOT EXISTS __diesel_schema_migrations (\
+            version VARCHAR(50) PRIMARY KEY NOT NULL,\
+            run_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP)", vec![])
+        .await?;
+    let applied: Vec<AppliedMigration> = rb
+        .query_decode("select version from __diesel_schema_migrations", vec![])
+        .await?;
+    for (version, sql) in MIGRATIONS {
+        if applied.iter().any(|m| &m.version == version) {
+            continue;
+        }
+        let mut tx = rb.acquire_begin().await?;
+        tx.exec(sql, vec![]).await?;
+        tx.exec("insert into __diesel_schema_migrations (version) values (?)",
+                vec![rbs::to_value!(*version)])
+            .await?;
+        tx.commit().await?;
+        log::info!("sqlite migration {} applied", version);
+    }
+    Ok(())
+}
+
+/// a fresh, migrated sqlite database in the temp dir for tests that need storage
+#[cfg(test)]
+pub(crate) async fn new_test_db(name: &str) -> Rbatis {
+    let path = std::env::temp_dir().join(format!("backend_{}_{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let rb = Rbatis::new();
+    rb.init(rbdc_sqlite::driver::SqliteDriver {}, &format!("sqlite://{}", path.display())).unwrap();
+    rb.get_pool().expect("get pool failed").resize(1);
+    run_migrations(&rb).await.unwrap();
+    rb
+}
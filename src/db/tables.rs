@@ -1,28 +1,100 @@
-use rbatis::rbdc::common::datetime::DateTime;
-use num::BigUint;
+// the code generated by `rbatis::crud!` clones single records into slices
+#![allow(clippy::cloned_ref_to_slice_refs)]
 // use rbdc_pg::types::decimal;
 use rbatis::rbdc::decimal::Decimal;
-use crate::watcher::event::PairEvent;
+use crate::watcher::event::{EventData, PairEvent};
+use crate::protocol::uniswap_v3::V3PoolEvent;
 use std::str::FromStr;
-use web3::ethabi::Uint;
+use web3::types::{Bytes, Log, H160, H256, U256, U64};
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Token {
     pub address: String,
     pub symbol: String,
     pub decimals: u8,
+    pub name: String,
+    pub total_supply: Option<Decimal>,
+    /// `ok`, `partial` when some metadata calls reverted, `failed` when all of them did
+    pub metadata_status: String,
+    pub metadata_attempts: i64,
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct Event {
     pub tx_hash: String,
-    pub event_type: i8, //1:add_liq,2:swap,3:rm_liq
+    pub event_type: i8, //1:add_liq,2:rm_liq,3:swap,5:collect,6:flash
     pub pair_address: String,
+    pub block_number: Option<i64>,
+    pub log_index: Option<i64>,
+    /// unix seconds
+    pub block_timestamp: Option<i64>,
+    /// the sender of the transaction
+    pub tx_origin: Option<String>,
     pub from_account: String,
     pub to_account: Option<String>,
     pub amount_x: Option<Decimal>,
     pub amount_y: Option<Decimal>,
-    // pub lp_amount : Option<Decimal>
+    /// LP tokens minted or burned by a V2 liquidity event, the liquidity of a V3 position change
+    pub lp_amount: Option<Decimal>,
+    /// the amounts of a swap as emitted, `None` for other events
+    pub amount0_in: Option<Decimal>,
+    pub amount1_in: Option<Decimal>,
+    pub amount0_out: Option<Decimal>,
+    pub amount1_out: Option<Decimal>,
+    /// swaps only: `x_to_y` or `y_to_x` by the net flows, `none` when no token went in net
+    pub direction: Option<String>,
+    /// a swap that paid a token out and took the same token in, a flash swap repaying the loan
+    #[serde(deserialize_with = "deserialize_bool")]
+    pub flash: bool,
+}
+
+impl Event {
+    fn new(meta: &EventData, event_type: i8, from_account: H160, to_account: Option<H160>,
+           amount_x: Decimal, amount_y: Decimal) -> Self {
+        Self {
+            tx_hash: hex::encode(meta.tx_hash.as_bytes()),
+            event_type,
+            pair_address: hex::encode(meta.address.as_bytes()),
+            block_number: Some(meta.block_number as i64),
+            log_index: Some(meta.log_index as i64),
+            block_timestamp: meta.block_timestamp.map(|t| t as i64),
+            tx_origin: meta.tx_origin.map(|a| hex::encode(a.as_bytes())),
+            from_account: hex::encode(from_account.as_bytes()),
+            to_account: to_account.map(|a| hex::encode(a.as_bytes())),
+            amount_x: Some(amount_x),
+            amount_y: Some(amount_y),
+            lp_amount: None,
+            amount0_in: None,
+            amount1_in: None,
+            amount0_out: None,
+            amount1_out: None,
+            direction: None,
+            flash: false,
+        }
+    }
+
+    fn with_lp_amount<T: ToString>(mut self, lp_amount: Option<T>) -> Self {
+        self.lp_amount = lp_amount.map(decimal);
+        self
+    }
+
+    fn with_swap_amounts(mut self, amount0_in: U256, amount1_in: U256, amount0_out: U256, amount1_out: U256) -> Self {
+        let direction = if amount0_in > amount0_out && amount1_out > amount1_in {
+            "x_to_y"
+        } else if amount1_in > amount1_out && amount0_out > amount0_in {
+            "y_to_x"
+        } else {
+            "none"
+        };
+        self.amount0_in = Some(decimal(amount0_in));
+        self.amount1_in = Some(decimal(amount1_in));
+        self.amount0_out = Some(decimal(amount0_out));
+        self.amount1_out = Some(decimal(amount1_out));
+        self.direction = Some(direction.to_string());
+        self.flash = (!amount0_in.is_zero() && !amount0_out.is_zero())
+            || (!amount1_in.is_zero() && !amount1_out.is_zero());
+        self
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -38,6 +110,101 @@ pub struct PoolInfo {
     pub(crate) total_swap_count: i64,
     pub(crate) total_add_liq_count: i64,
     pub(crate) total_rm_liq_count: i64,
+    /// `uniswap_v2` or `uniswap_v3`
+    pub(crate) protocol: String,
+    pub(crate) factory_address: Option<String>,
+    /// LP token supply of a V2 pair, `None` for pools without an LP token
+    pub(crate) lp_total_supply: Option<Decimal>,
+    /// the reserves at the usd prices of the tokens, `None` until one of them has a price
+    pub(crate) tvl_usd: Option<Decimal>,
+    /// the swaps at the usd prices of the tokens when they were stored
+    pub(crate) volume_usd: Option<Decimal>,
+    /// the block of the factory log, `None` for pools stored before it was kept
+    pub(crate) created_block: Option<i64>,
+}
+
+/// Price and liquidity of a concentrated liquidity pool.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct V3Pool {
+    pub pair_address: String,
+    pub fee: i64,
+    pub tick_spacing: i64,
+    pub sqrt_price_x96: Decimal,
+    pub tick: i64,
+    pub liquidity: Decimal,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct V3Tick {
+    pub pair_address: String,
+    pub tick: i64,
+    pub liquidity_net: Decimal,
+}
+
+/// Open, high, low, close and volume of a pool over an interval. Prices are the raw price
+/// of token x in token y times `candles::PRICE_SCALE`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PoolCandle {
+    pub pair_address: String,
+    pub interval_secs: i64,
+    /// unix seconds
+    pub open_time: i64,
+    pub price_open: Option<Decimal>,
+    pub price_high: Option<Decimal>,
+    pub price_low: Option<Decimal>,
+    pub price_close: Option<Decimal>,
+    pub open_block: Option<i64>,
+    pub open_log_index: Option<i64>,
+    pub close_block: Option<i64>,
+    pub close_log_index: Option<i64>,
+    pub volume_x: Decimal,
+    pub volume_y: Decimal,
+    pub swap_count: i64,
+}
+
+/// The statistics of a pool over an hour or a day, a row of `pool_hour_stats` or
+/// `pool_day_stats`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PoolPeriodStats {
+    pub pair_address: String,
+    /// unix seconds
+    pub period_start: i64,
+    pub volume_x: Decimal,
+    pub volume_y: Decimal,
+    pub fees_x: Decimal,
+    pub fees_y: Decimal,
+    /// after the last event of the period that moved them
+    pub reserve_x: Option<Decimal>,
+    pub reserve_y: Option<Decimal>,
+    pub reserve_block: Option<i64>,
+    pub reserve_log_index: Option<i64>,
+    pub swap_count: i64,
+    pub add_liq_count: i64,
+    pub rm_liq_count: i64,
+    pub unique_traders: i64,
+}
+
+/// The usd price of a token, with `USD_DECIMALS` per whole token.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct TokenPrice {
+    pub token_address: String,
+    pub price_usd: Decimal,
+    /// the count of pools between the token and an anchor
+    pub depth: i64,
+    /// the liquidity of the pools the price was taken from
+    pub liquidity_usd: Decimal,
+    pub block_number: Option<i64>,
+    pub updated_at: Option<i64>,
+}
+
+/// The last usd price of a token in an hour.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct TokenPriceHistory {
+    pub token_address: String,
+    /// unix seconds
+    pub period_start: i64,
+    pub price_usd: Decimal,
+    pub block_number: Option<i64>,
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -45,58 +212,133 @@ pub struct LastSyncBlock {
     pub block_number: i64,
 }
 
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ReconciliationIssue {
+    pub id: Option<i64>,
+    pub pair_address: String,
+    pub block_number: i64,
+    pub stored_reserve_x: Decimal,
+    pub stored_reserve_y: Decimal,
+    pub chain_reserve_x: Decimal,
+    pub chain_reserve_y: Decimal,
+    #[serde(deserialize_with = "deserialize_bool")]
+    pub reindexed: bool,
+    pub created_at: i64,
+}
+
+/// A log that could not be decoded or applied, with enough of it to decode it again.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct FailedLog {
+    pub id: Option<i64>,
+    pub block_number: i64,
+    pub log_index: i64,
+    pub tx_hash: String,
+    pub address: String,
+    /// comma separated hex topics
+    pub topics: String,
+    pub data: String,
+    pub error: String,
+    /// `failed`, `retry` or `resolved`
+    pub status: String,
+    pub attempts: i64,
+    pub created_at: i64,
+}
+
+impl FailedLog {
+    pub fn new(log: &Log, error: String, created_at: i64) -> Self {
+        Self {
+            id: None,
+            block_number: log.block_number.unwrap_or_default().as_u64() as i64,
+            log_index: log.log_index.unwrap_or_default().as_u64() as i64,
+            tx_hash: hex::encode(log.transaction_hash.unwrap_or_default()),
+            address: hex::encode(log.address),
+            topics: log.topics.iter().map(hex::encode).collect::<Vec<_>>().join(","),
+            data: hex::encode(&log.data.0),
+            error,
+            status: "failed".to_string(),
+            attempts: 1,
+            created_at,
+        }
+    }
+
+    /// the log as it was received, to be decoded again
+    pub fn to_log(&self) -> anyhow::Result<Log> {
+        let topics = self.topics.split(',')
+            .filter(|t| !t.is_empty())
+            .map(H256::from_str)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Log {
+            address: H160::from_str(&self.address)?,
+            topics,
+            data: Bytes(hex::decode(&self.data)?),
+            block_hash: None,
+            block_number: Some(U64::from(self.block_number as u64)),
+            transaction_hash: Some(H256::from_str(&self.tx_hash)?),
+            transaction_index: None,
+            log_index: Some(U256::from(self.log_index as u64)),
+            transaction_log_index: None,
+            log_type: None,
+            removed: None,
+        })
+    }
+}
+
+/// A pool to reindex once, for events stored before the data they keep now.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct BackfillJob {
+    pub pair_address: String,
+    /// `pending`, `done` or `failed`
+    pub status: String,
+    pub attempts: i64,
+    /// the block the pool was reindexed up to
+    pub synced_block: Option<i64>,
+    pub error: Option<String>,
+    pub updated_at: i64,
+}
+
+/// sqlite has no boolean type and returns boolean columns as integers
+fn deserialize_bool<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    use serde::Deserialize;
+    match rbs::Value::deserialize(deserializer)? {
+        rbs::Value::Bool(b) => Ok(b),
+        rbs::Value::I32(i) => Ok(i != 0),
+        rbs::Value::I64(i) => Ok(i != 0),
+        rbs::Value::U32(i) => Ok(i != 0),
+        rbs::Value::U64(i) => Ok(i != 0),
+        v => Err(serde::de::Error::custom(format!("invalid boolean {}", v))),
+    }
+}
+
 rbatis::crud!(Event {}, "events");
 rbatis::crud!(PoolInfo {}, "pool_info");
 rbatis::crud!(Token {}, "tokens");
 rbatis::crud!(LastSyncBlock {}, "last_sync_block");
+rbatis::crud!(ReconciliationIssue {}, "reconciliation_issues");
+rbatis::crud!(FailedLog {}, "failed_logs");
+rbatis::crud!(V3Pool {}, "v3_pools");
+rbatis::crud!(V3Tick {}, "v3_ticks");
+rbatis::crud!(PoolCandle {}, "pool_candles");
 
 impl From<PairEvent> for Event {
     fn from(event: PairEvent) -> Self {
         match event {
             PairEvent::MintPairEvent(mint) => {
-                Self {
-                    tx_hash: hex::encode(mint.meta.tx_hash.as_bytes()),
-                    event_type: 1,
-                    pair_address: hex::encode(mint.meta.address.as_bytes()),
-                    from_account: hex::encode(mint.sender.as_bytes()),
-                    to_account: None,
-                    amount_x: Some(Decimal::from_str(&mint.amount0.to_string()).unwrap()),
-                    amount_y: Some(Decimal::from_str(&mint.amount1.to_string()).unwrap()),
-                }
+                Self::new(&mint.meta, 1, mint.sender, None,
+                          decimal(mint.amount0), decimal(mint.amount1))
+                    .with_lp_amount(mint.lp_amount)
             }
             PairEvent::BurnPairEvent(burn) => {
-                Self {
-                    tx_hash: hex::encode(burn.meta.tx_hash.as_bytes()),
-                    event_type: 2,
-                    pair_address: hex::encode(burn.meta.address.as_bytes()),
-                    from_account: hex::encode(burn.sender.as_bytes()),
-                    to_account: Some(hex::encode(burn.to.as_bytes())),
-                    amount_x: Some(Decimal::from_str(&burn.amount0.to_string()).unwrap()),
-                    amount_y: Some(Decimal::from_str(&burn.amount1.to_string()).unwrap()),
-                }
+                Self::new(&burn.meta, 2, burn.sender, Some(burn.to),
+                          decimal(burn.amount0), decimal(burn.amount1))
+                    .with_lp_amount(burn.lp_amount)
             }
             PairEvent::SwapPairEvent(swap) => {
-                let amount_x;
-                let amount_y;
-
-                if swap.amount0In == Uint::zero() {
-                    //y->x
-                    amount_x = Decimal::from_str(&swap.amount0Out.to_string()).unwrap();
-                    amount_y = Decimal::from_str(&swap.amount1In.to_string()).unwrap();
-                } else {
-                    //x->y
-                    amount_x = Decimal::from_str(&swap.amount0In.to_string()).unwrap();
-                    amount_y = Decimal::from_str(&swap.amount1Out.to_string()).unwrap();
-                }
-                Self {
-                    tx_hash: hex::encode(swap.meta.tx_hash.as_bytes()),
-                    event_type: 3,
-                    pair_address: hex::encode(swap.meta.address.as_bytes()),
-                    from_account: hex::encode(swap.sender.as_bytes()),
-                    to_account: Some(hex::encode(swap.to.as_bytes())),
-                    amount_x: Some(amount_x),
-                    amount_y: Some(amount_y),
-                }
+                // net flows, both inputs can be non-zero
+                let net = |paid: U256, sent: U256| if paid > sent { paid - sent } else { sent - paid };
+                Self::new(&swap.meta, 3, swap.sender, Some(swap.to),
+                          decimal(net(swap.amount0_in, swap.amount0_out)),
+                          decimal(net(swap.amount1_in, swap.amount1_out)))
+                    .with_swap_amounts(swap.amount0_in, swap.amount1_in, swap.amount0_out, swap.amount1_out)
             }
             PairEvent::SyncPairEvent(_) => {
                 //todo: sync event
@@ -106,3 +348,39 @@ impl From<PairEvent> for Event {
 
     }
 }
+
+fn decimal<T: ToString>(value: T) -> Decimal {
+    Decimal::from_str(&value.to_string()).unwrap()
+}
+
+impl From<V3PoolEvent> for Event {
+    fn from(event: V3PoolEvent) -> Self {
+        let meta = event.meta().clone();
+        let paid = |amount: i128| U256::from(amount.max(0) as u128);
+        let sent = |amount: i128| U256::from(amount.min(0).unsigned_abs());
+        match event {
+            V3PoolEvent::Mint { sender, owner, amount, amount0, amount1, .. } => {
+                Self::new(&meta, 1, sender, Some(owner), decimal(amount0), decimal(amount1))
+                    .with_lp_amount(Some(amount))
+            }
+            V3PoolEvent::Burn { owner, amount, amount0, amount1, .. } => {
+                Self::new(&meta, 2, owner, None, decimal(amount0), decimal(amount1))
+                    .with_lp_amount(Some(amount))
+            }
+            // signed, positive amounts are paid into the pool. The amounts are stored unsigned
+            // like the net flows of a V2 swap, the in and out columns tell which way they went
+            V3PoolEvent::Swap { sender, recipient, amount0, amount1, .. } => {
+                Self::new(&meta, 3, sender, Some(recipient), decimal(amount0.unsigned_abs()),
+                          decimal(amount1.unsigned_abs()))
+                    .with_swap_amounts(paid(amount0), paid(amount1), sent(amount0), sent(amount1))
+            }
+            V3PoolEvent::Collect { owner, recipient, amount0, amount1, .. } => {
+                Self::new(&meta, 5, owner, Some(recipient), decimal(amount0), decimal(amount1))
+            }
+            V3PoolEvent::Flash { sender, recipient, amount0, amount1, .. } => {
+                Self::new(&meta, 6, sender, Some(recipient), decimal(amount0), decimal(amount1))
+            }
+            V3PoolEvent::Initialize { .. } => panic!("Initialize event no need to store"),
+        }
+    }
+}
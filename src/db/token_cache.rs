@@ -0,0 +1,216 @@
+//! In-memory token metadata shared by the watcher and the api.
+//!
+//! The cache is an LRU warmed from the `tokens` table at startup. New tokens are put in
+//! the cache right away and queued to a background writer task, which persists them in
+//! batches. A batch that can't be written stays queued and the error is kept, so
+//! `flush` fails until the tokens are stored.
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use anyhow::format_err;
+use lru::LruCache;
+use rbatis::Rbatis;
+use tokio::sync::{mpsc, oneshot};
+use crate::db;
+use crate::db::tables::Token;
+
+pub const DEFAULT_CAPACITY: usize = 10000;
+const WRITE_BATCH_SIZE: usize = 100;
+const WRITE_INTERVAL: Duration = Duration::from_secs(1);
+
+enum WriterMessage {
+    Save(Token),
+    Flush(oneshot::Sender<Result<(), String>>),
+}
+
+#[derive(Clone)]
+pub struct TokenCache {
+    tokens: Arc<Mutex<LruCache<String, Token>>>,
+    writer: mpsc::UnboundedSender<WriterMessage>,
+    /// the error of the last failed write, cleared by the next successful one
+    write_error: Arc<Mutex<Option<String>>>,
+}
+
+impl fmt::Debug for TokenCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenCache")
+            .field("len", &self.len())
+            .field("write_error", &self.write_error())
+            .finish()
+    }
+}
+
+impl TokenCache {
+    /// load the stored tokens and spawn the writer task, a `capacity` of 0 uses the default
+    pub async fn start(db: Rbatis, capacity: usize) -> anyhow::Result<Self> {
+        let capacity = NonZeroUsize::new(capacity)
+            .unwrap_or(NonZeroUsize::new(DEFAULT_CAPACITY).unwrap());
+        let mut tokens = LruCache::new(capacity);
+        for token in db::get_tokens(&db).await? {
+            tokens.put(token.address.clone(), token);
+        }
+        log::info!("token cache warmed with {} tokens", tokens.len());
+        let (writer, receiver) = mpsc::unbounded_channel();
+        let write_error = Arc::new(Mutex::new(None));
+        tokio::spawn(run_writer(db, receiver, write_error.clone()));
+        Ok(Self {
+            tokens: Arc::new(Mutex::new(tokens)),
+            writer,
+            write_error,
+        })
+    }
+
+    /// `address` is hex encoded without `0x`, as in the `tokens` table
+    pub fn get(&self, address: &str) -> Option<Token> {
+        self.tokens.lock().unwrap().get(address).cloned()
+    }
+
+    /// the token from the cache, or from the `tokens` table, cached then, when it is missing
+    pub async fn load(&self, rb: &Rbatis, address: &str) -> anyhow::Result<Option<Token>> {
+        if let Some(token) = self.get(address) {
+            return Ok(Some(token));
+        }
+        let token = db::get_token(rb, address.to_string()).await?.into_iter().next();
+        if let Some(token) = &token {
+            self.put(token.clone());
+        }
+        Ok(token)
+    }
+
+    /// cache a token that is already stored
+    pub fn put(&self, token: Token) {
+        self.tokens.lock().unwrap().put(token.address.clone(), token);
+    }
+
+    /// cache a new token and queue it for the writer
+    pub fn save(&self, token: Token) -> anyhow::Result<()> {
+        self.put(token.clone());
+        self.writer.send(WriterMessage::Save(token))
+            .map_err(|_| format_err!("token writer stopped"))
+    }
+
+    /// wait until every queued token is written
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let (reply, result) = oneshot::channel();
+        self.writer.send(WriterMessage::Flush(reply))
+            .map_err(|_| format_err!("token writer stopped"))?;
+        result.await?
+            .map_err(|e| format_err!("failed to save tokens: {}", e))
+    }
+
+    pub fn write_error(&self) -> Option<String> {
+        self.write_error.lock().unwrap().clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tokens.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+async fn run_writer(
+    db: Rbatis,
+    mut receiver: mpsc::UnboundedReceiver<WriterMessage>,
+    write_error: Arc<Mutex<Option<String>>>,
+) {
+    let mut pending = Vec::new();
+    let mut interval = tokio::time::interval(WRITE_INTERVAL);
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(WriterMessage::Save(token)) => {
+                    pending.push(token);
+                    if pending.len() >= WRITE_BATCH_SIZE {
+                        let _ = write_pending(&db, &mut pending, &write_error).await;
+                    }
+                }
+                Some(WriterMessage::Flush(reply)) => {
+                    let _ = reply.send(write_pending(&db, &mut pending, &write_error).await);
+                }
+                None => {
+                    let _ = write_pending(&db, &mut pending, &write_error).await;
+                    break;
+                }
+            },
+            _ = interval.tick() => {
+                let _ = write_pending(&db, &mut pending, &write_error).await;
+            }
+        }
+    }
+}
+
+async fn write_pending(
+    db: &Rbatis,
+    pending: &mut Vec<Token>,
+    write_error: &Mutex<Option<String>>,
+) -> Result<(), String> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+    match db::save_tokens(db, pending).await {
+        Ok(()) => {
+            log::debug!("saved {} tokens", pending.len());
+            pending.clear();
+            *write_error.lock().unwrap() = None;
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("failed to save {} tokens, will retry: {:?}", pending.len(), e);
+            let e = e.to_string();
+            *write_error.lock().unwrap() = Some(e.clone());
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::sqlite::new_test_db;
+
+    fn token(address: &str) -> Token {
+        Token {
+            address: address.to_string(),
+            symbol: address.to_uppercase(),
+            decimals: 18,
+            name: String::new(),
+            total_supply: None,
+            metadata_status: "ok".to_string(),
+            metadata_attempts: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_cache() {
+        let db = new_test_db("token_cache").await;
+        let cache = TokenCache::start(db.clone(), 2).await.unwrap();
+        for address in ["aa", "bb", "cc"] {
+            cache.save(token(address)).unwrap();
+        }
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("aa").is_none());
+        cache.flush().await.unwrap();
+        assert_eq!(db::get_tokens(&db).await.unwrap().len(), 3);
+        // an evicted token is loaded from the table and cached again
+        assert_eq!(cache.load(&db, "aa").await.unwrap().unwrap().symbol, "AA");
+        assert!(cache.get("aa").is_some());
+        assert!(cache.load(&db, "ee").await.unwrap().is_none());
+
+        let warmed = TokenCache::start(db.clone(), 10).await.unwrap();
+        assert_eq!(warmed.get("aa").unwrap().symbol, "AA");
+
+        // a failed batch is kept and reported until it can be written
+        db.exec("alter table tokens rename to tokens_moved", vec![]).await.unwrap();
+        cache.save(token("dd")).unwrap();
+        assert!(cache.flush().await.is_err());
+        assert!(cache.write_error().is_some());
+        db.exec("alter table tokens_moved rename to tokens", vec![]).await.unwrap();
+        cache.flush().await.unwrap();
+        assert!(cache.write_error().is_none());
+        assert_eq!(db::get_token(&db, "dd".to_string()).await.unwrap().len(), 1);
+    }
+}
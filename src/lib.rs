@@ -0,0 +1,26 @@
+use rbatis::Rbatis;
+
+pub mod config;
+pub mod watcher;
+pub mod server;
+pub mod db;
+pub mod route;
+pub mod amm;
+pub mod protocol;
+
+/// make an Rbatis, the driver is selected by the scheme of `db_url`
+/// (`sqlite://` for sqlite, postgres otherwise)
+pub fn init_db(db_url:String,pool_size: usize) -> Rbatis {
+    let rb = Rbatis::new();
+    if db_url.starts_with("sqlite:") {
+        rb.init(rbdc_sqlite::driver::SqliteDriver {}, &db_url).unwrap();
+    } else {
+        rb.init(rbdc_pg::driver::PgDriver {}, &db_url).unwrap();
+    }
+    let pool = rb
+        .get_pool()
+        .expect("get pool failed");
+    pool.resize(pool_size);
+    log::info!("{} database init ok!", pool.driver_type());
+    rb
+}
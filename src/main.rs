@@ -1,43 +1,48 @@
 use dotenvy::dotenv;
-use crate::config::BackendConfig;
-use rbatis::Rbatis;
-use crate::server::AppState;
+use rust_backend::config::BackendConfig;
+use rust_backend::server::{self, AppState};
 use rbatis::rbdc::rt::block_on;
 use std::cell::RefCell;
 use futures::channel::mpsc;
 use futures::SinkExt;
 use futures::StreamExt;
-use crate::watcher::watch::run_watcher;
-
-pub mod config;
-pub mod watcher;
-pub mod server;
-pub mod db;
-pub mod route;
-
-/// make an Rbatis
-pub fn init_db(db_url:String,pool_size: usize) -> Rbatis {
-    let rb = Rbatis::new();
-    rb.init(rbdc_pg::driver::PgDriver {}, &db_url).unwrap();
-    let pool = rb
-        .get_pool()
-        .expect("get pool failed");
-    pool.resize(pool_size);
-    log::info!("postgres database init ok!");
-    return rb;
-}
+use rust_backend::{db, init_db, protocol};
+use rust_backend::watcher::watch::{run_watcher, ChainWatcher};
+use rust_backend::watcher::verify::verify_pools;
+use rust_backend::db::token_cache::TokenCache;
+use rust_backend::db::pool_graph::PoolGraph;
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
     dotenv().expect("Config file not found");
     let config = BackendConfig::from_env();
+    // fail before serving anything when the configured ABIs don't fit the protocol
+    protocol::adapter_from_config(&config).expect("invalid protocol configuration");
     let db = init_db(config.database_url.clone(), config.db_pool_size as usize);
+    if db::sqlite::is_sqlite(&db) {
+        db::sqlite::run_migrations(&db).await.expect("sqlite migrations failed");
+    }
+    if std::env::args().nth(1).as_deref() == Some("verify") {
+        // replay the stored pools through the reference simulator instead of serving
+        let mut watcher = ChainWatcher::new(config, db).await.expect("create watcher failed");
+        let problems = verify_pools(&mut watcher).await.expect("verify pools failed");
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        println!("verify finished, {} problems found", problems.len());
+        std::process::exit(if problems.is_empty() { 0 } else { 1 });
+    }
+    let token_cache = TokenCache::start(db.clone(), config.token_cache_size as usize).await
+        .expect("load token cache failed");
+    let pool_graph = PoolGraph::load(&db, &config).await.expect("load pool graph failed");
     let app_state = AppState {
         config:config.clone(),
-        db: db.clone()
+        db: db.clone(),
+        token_cache: token_cache.clone(),
+        pool_graph: pool_graph.clone(),
     };
     server::run_server(app_state).await;
-    let watcher_handlers = run_watcher(config,db).await;
+    let watcher_handlers = run_watcher(config,db,token_cache,pool_graph).await;
 
     // handle ctrl+c
     let (stop_signal_sender, mut stop_signal_receiver) = mpsc::channel(256);
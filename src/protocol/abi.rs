@@ -0,0 +1,170 @@
+//! Event ABIs of an adapter, embedded or loaded from the files named in the configuration.
+//!
+//! An adapter decodes every kind of event (`mint`, `swap`, ...) with a fixed layout and
+//! takes the event signature, and so its topic, from an ABI. A fork that renamed events
+//! or added its own is indexed by pointing `FACTORY_ABI_FILE` and `POOL_ABI_FILE` at its
+//! ABIs and, for renamed events, mapping the kinds to its event names in `EVENT_NAMES_FILE`.
+//! The ABIs are checked against the layouts when the adapter is created, so a mismatch
+//! stops the startup instead of failing every log.
+use std::collections::HashMap;
+use std::fs;
+use anyhow::{format_err, Context};
+use ethabi::{Contract, ParamType};
+use web3::types::H256;
+use crate::config::BackendConfig;
+use crate::protocol::topics::TopicRegistry;
+
+/// An event an adapter decodes.
+#[derive(Debug, Clone)]
+pub struct EventSpec {
+    /// the name the adapter refers to the event by
+    pub kind: &'static str,
+    /// the event name in the embedded ABI
+    pub name: &'static str,
+    /// emitted by the factory rather than by a pool
+    pub factory: bool,
+    /// type of every parameter and whether it is indexed, in order
+    pub inputs: Vec<(ParamType, bool)>,
+}
+
+impl EventSpec {
+    pub fn new(kind: &'static str, name: &'static str, factory: bool, inputs: Vec<(ParamType, bool)>) -> Self {
+        Self { kind, name, factory, inputs }
+    }
+}
+
+/// ABI files and event names replacing the embedded ones, `None` keeps the embedded one.
+#[derive(Debug, Clone, Default)]
+pub struct AbiFiles {
+    pub factory_abi: Option<String>,
+    pub pool_abi: Option<String>,
+    /// a json object from event kind to event name
+    pub event_names: Option<String>,
+}
+
+impl AbiFiles {
+    pub fn from_config(config: &BackendConfig) -> Self {
+        Self {
+            factory_abi: config.factory_abi_file.clone(),
+            pool_abi: config.pool_abi_file.clone(),
+            event_names: config.event_names_file.clone(),
+        }
+    }
+
+    fn read(path: &Option<String>, embedded: &str) -> anyhow::Result<String> {
+        match path {
+            Some(path) => fs::read_to_string(path).with_context(|| format!("read abi file {}", path)),
+            None => Ok(embedded.to_string()),
+        }
+    }
+
+    /// the topic of every event kind of `specs`, from these files or the embedded ABIs
+    pub fn load_topics(&self, specs: &[EventSpec], factory_abi: &str, pool_abi: &str)
+        -> anyhow::Result<TopicRegistry> {
+        let names: HashMap<String, String> = match &self.event_names {
+            Some(path) => {
+                let names = fs::read_to_string(path).with_context(|| format!("read event names file {}", path))?;
+                serde_json::from_str(&names).with_context(|| format!("invalid event names file {}", path))?
+            }
+            None => HashMap::new(),
+        };
+        let factory = Contract::load(Self::read(&self.factory_abi, factory_abi)?.as_bytes())
+            .context("invalid factory abi")?;
+        let pool = Contract::load(Self::read(&self.pool_abi, pool_abi)?.as_bytes())
+            .context("invalid pool abi")?;
+        load_topics(specs, &factory, &pool, &names)
+    }
+}
+
+/// integers of any size are decoded from a full word, so only the kind has to match
+fn compatible(expected: &ParamType, actual: &ParamType) -> bool {
+    match (expected, actual) {
+        (ParamType::Uint(_), ParamType::Uint(_)) | (ParamType::Int(_), ParamType::Int(_)) => true,
+        _ => expected == actual,
+    }
+}
+
+fn load_topics(specs: &[EventSpec], factory: &Contract, pool: &Contract, names: &HashMap<String, String>)
+    -> anyhow::Result<TopicRegistry> {
+    if let Some(kind) = names.keys().find(|k| !specs.iter().any(|s| s.kind == k.as_str())) {
+        return Err(format_err!("unknown event kind {} in event names, expected one of {:?}",
+                               kind, specs.iter().map(|s| s.kind).collect::<Vec<_>>()));
+    }
+    let mut topics = TopicRegistry::default();
+    for spec in specs {
+        let name = names.get(spec.kind).map(|n| n.as_str()).unwrap_or(spec.name);
+        let (contract, abi) = if spec.factory { (factory, "factory") } else { (pool, "pool") };
+        let event = contract.event(name)
+            .map_err(|_| format_err!("event {} for {} is not in the {} abi", name, spec.kind, abi))?;
+        let matches = event.inputs.len() == spec.inputs.len()
+            && event.inputs.iter().zip(&spec.inputs)
+                .all(|(input, (kind, indexed))| compatible(kind, &input.kind) && input.indexed == *indexed);
+        if !matches {
+            let expected: Vec<String> = spec.inputs.iter()
+                .map(|(kind, indexed)| format!("{}{}", kind, if *indexed { " indexed" } else { "" }))
+                .collect();
+            let signature: Vec<String> = event.inputs.iter().map(|i| i.kind.to_string()).collect();
+            return Err(format_err!("event {}({}) of the {} abi doesn't match {}, expected ({})",
+                                   event.name, signature.join(","), abi, spec.kind, expected.join(", ")));
+        }
+        topics.insert(spec.kind, H256::from(event.signature().0));
+    }
+    Ok(topics)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocol::uniswap_v2::UniswapV2;
+    use crate::protocol::adapter_from_config;
+
+    fn write(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("backend_{}_{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path.display().to_string()
+    }
+
+    #[test]
+    fn test_abi_files() {
+        // a fork that renamed Swap to Exchange and emits an extra event
+        let pair_abi = include_str!("../abi/pair_abi.json")
+            .replace("\"Swap\"", "\"Exchange\"")
+            .replacen('[', r#"[{"anonymous": false, "inputs": [], "name": "Skim", "type": "event"},"#, 1);
+        let config = BackendConfig {
+            pool_abi_file: Some(write("fork_pair_abi.json", &pair_abi)),
+            event_names_file: Some(write("fork_event_names.json", r#"{"swap": "Exchange"}"#)),
+            ..Default::default()
+        };
+        let protocol = adapter_from_config(&config).unwrap();
+        let topics = UniswapV2::embedded_topics();
+        let exchange = H256::from(ethabi::long_signature("Exchange", &[
+            ParamType::Address, ParamType::Uint(256), ParamType::Uint(256),
+            ParamType::Uint(256), ParamType::Uint(256), ParamType::Address,
+        ]).0);
+        assert_eq!(protocol.pool_topics(), vec![topics.topic("mint"), topics.topic("burn"), exchange, topics.topic("sync")]);
+        assert_eq!(protocol.factory_topics(), vec![topics.topic("create_pair")]);
+
+        // the renamed event is not mapped
+        let config = BackendConfig { event_names_file: None, ..config };
+        let e = adapter_from_config(&config).err().unwrap();
+        assert!(e.to_string().contains("event Swap for swap is not in the pool abi"), "{}", e);
+
+        let config = BackendConfig {
+            event_names_file: Some(write("typo_event_names.json", r#"{"swp": "Exchange"}"#)),
+            ..Default::default()
+        };
+        assert!(adapter_from_config(&config).err().unwrap().to_string().contains("unknown event kind swp"));
+
+        // Sync with an address where the reserves are expected
+        let pair_abi = include_str!("../abi/pair_abi.json").replace("uint112", "address");
+        let config = BackendConfig {
+            pool_abi_file: Some(write("bad_pair_abi.json", &pair_abi)),
+            ..Default::default()
+        };
+        let e = adapter_from_config(&config).err().unwrap();
+        assert!(e.to_string().contains("event Sync(address,address) of the pool abi doesn't match sync"), "{}", e);
+
+        let config = BackendConfig { factory_abi_file: Some("/nonexistent/abi.json".to_string()), ..Default::default() };
+        assert!(adapter_from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Protocol adapters: what the watcher needs to know about one kind of AMM.
+//!
+//! An adapter declares the factory and pool event topics to fetch, decodes their logs into
+//! domain events and applies those events to the indexed pool state. `ChainWatcher` only
+//! talks to the adapters of its factories, so a fork with different events or pool
+//! math is a new adapter registered in `adapter`, not a change to the watcher.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use anyhow::format_err;
+use web3::types::{Log, H160, H256, U256};
+use crate::watcher::event::{EventData, PairEvent};
+use crate::config::BackendConfig;
+use crate::protocol::abi::AbiFiles;
+use crate::protocol::uniswap_v3::V3PoolEvent;
+
+pub mod abi;
+pub mod topics;
+pub mod uniswap_v2;
+pub mod uniswap_v3;
+
+/// A pool announced by a factory log.
+#[derive(Debug, Clone)]
+pub struct PoolCreated {
+    pub pool: H160,
+    pub token0: H160,
+    pub token1: H160,
+    /// fee tier and tick spacing of a concentrated liquidity pool
+    pub fee: Option<u32>,
+    pub tick_spacing: Option<i32>,
+    /// the block of the factory log
+    pub block_number: u64,
+}
+
+/// A decoded pool log.
+#[derive(Debug)]
+pub enum PoolEvent {
+    /// Mint, Burn, Swap and Sync of a Uniswap V2 style pair
+    Pair(PairEvent),
+    /// Initialize, Mint, Burn, Swap, Collect and Flash of a Uniswap V3 style pool
+    V3(V3PoolEvent),
+}
+
+impl PoolEvent {
+    pub fn pool_address(&self) -> H160 {
+        match self {
+            PoolEvent::Pair(event) => event.get_pair_address(),
+            PoolEvent::V3(event) => event.meta().address,
+        }
+    }
+
+    pub fn meta_mut(&mut self) -> &mut EventData {
+        match self {
+            PoolEvent::Pair(event) => event.meta_mut(),
+            PoolEvent::V3(event) => event.meta_mut(),
+        }
+    }
+}
+
+/// The indexed state of a pool, as kept in `pool_info`, and `v3_pools` and `v3_ticks`
+/// for concentrated liquidity pools.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PoolState {
+    pub reserve0: U256,
+    pub reserve1: U256,
+    pub swap_count: i64,
+    pub mint_count: i64,
+    pub burn_count: i64,
+    pub sqrt_price_x96: U256,
+    pub tick: i32,
+    /// the liquidity in range at `tick`
+    pub liquidity: u128,
+    /// the liquidity added when the price crosses a tick upwards, ticks without any are left out
+    pub liquidity_net: BTreeMap<i32, i128>,
+}
+
+pub trait ProtocolAdapter: Send + Sync {
+    /// the name `PROTOCOL` selects the adapter by
+    fn name(&self) -> &'static str;
+
+    /// topics of the factory events announcing new pools
+    fn factory_topics(&self) -> Vec<H256>;
+
+    /// topics of the pool events to index
+    fn pool_topics(&self) -> Vec<H256>;
+
+    /// the pool topics fetched and stored together, group by group. By default each topic
+    /// is a group of its own, events that have to be applied in chain order across topics
+    /// belong in one group.
+    fn pool_topic_groups(&self) -> Vec<Vec<H256>> {
+        self.pool_topics().into_iter().map(|topic| vec![topic]).collect()
+    }
+
+    /// topic of the Transfer event of the pool token, for pools minting a fungible LP token.
+    /// The LP amount of a Mint or Burn is taken from the transfer from or to the zero
+    /// address in the same transaction.
+    fn lp_transfer_topic(&self) -> Option<H256> {
+        None
+    }
+
+    fn decode_factory_log(&self, log: &Log) -> anyhow::Result<PoolCreated>;
+
+    fn decode_pool_log(&self, log: &Log) -> anyhow::Result<PoolEvent>;
+
+    /// apply the next event of a pool, in (block, log index) order
+    fn apply(&self, state: &mut PoolState, event: &PoolEvent) -> anyhow::Result<()>;
+}
+
+/// the adapter of a protocol by name with the embedded ABIs, an empty name is Uniswap V2
+pub fn adapter(name: &str) -> anyhow::Result<Arc<dyn ProtocolAdapter>> {
+    adapter_with_abi(name, &AbiFiles::default())
+}
+
+/// the adapter selected by `PROTOCOL`, with the ABI files and event names of the configuration
+pub fn adapter_from_config(config: &BackendConfig) -> anyhow::Result<Arc<dyn ProtocolAdapter>> {
+    adapter_with_abi(&config.protocol, &AbiFiles::from_config(config))
+}
+
+fn adapter_with_abi(name: &str, files: &AbiFiles) -> anyhow::Result<Arc<dyn ProtocolAdapter>> {
+    match name {
+        "" | uniswap_v2::NAME => Ok(Arc::new(uniswap_v2::UniswapV2::with_abi(files)?)),
+        uniswap_v3::NAME => Ok(Arc::new(uniswap_v3::UniswapV3::with_abi(files)?)),
+        _ => Err(format_err!("unknown protocol {}", name)),
+    }
+}
@@ -0,0 +1,35 @@
+//! Event topics of an adapter, computed once from its ABIs.
+use std::collections::HashMap;
+use web3::types::H256;
+
+/// The topic of every event kind (`mint`, `swap`, ...) and the kind of every topic.
+#[derive(Debug, Clone, Default)]
+pub struct TopicRegistry {
+    topics: HashMap<&'static str, H256>,
+    kinds: HashMap<H256, &'static str>,
+}
+
+impl TopicRegistry {
+    pub fn insert(&mut self, kind: &'static str, topic: H256) {
+        self.topics.insert(kind, topic);
+        self.kinds.insert(topic, kind);
+    }
+
+    /// the topic of an event kind the adapter declared
+    pub fn topic(&self, kind: &str) -> H256 {
+        *self.topics.get(kind).unwrap_or_else(|| panic!("unknown event kind {}", kind))
+    }
+
+    /// the event kind of a log topic, `None` for an event that isn't indexed
+    pub fn kind(&self, topic: &H256) -> Option<&'static str> {
+        self.kinds.get(topic).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+}
@@ -0,0 +1,169 @@
+//! Uniswap V2 and the forks that kept its factory and pair events.
+use std::sync::OnceLock;
+use anyhow::format_err;
+use ethabi::ParamType;
+use web3::types::{Log, H256};
+use crate::protocol::{PoolCreated, PoolEvent, PoolState, ProtocolAdapter};
+use crate::protocol::abi::{AbiFiles, EventSpec};
+use crate::protocol::topics::TopicRegistry;
+use crate::watcher::event::{EventType, PairCreatedEvent, PairEvent};
+
+pub const NAME: &str = "uniswap_v2";
+
+const FACTORY_EVENTS: &str = include_str!("../abi/factory_abi.json");
+const PAIR_EVENTS: &str = include_str!("../abi/pair_abi.json");
+
+pub struct UniswapV2 {
+    topics: TopicRegistry,
+}
+
+impl UniswapV2 {
+    pub fn new() -> Self {
+        Self { topics: Self::embedded_topics().clone() }
+    }
+
+    /// an adapter for a fork whose ABIs differ from the embedded ones
+    pub fn with_abi(files: &AbiFiles) -> anyhow::Result<Self> {
+        Ok(Self { topics: files.load_topics(&Self::events(), FACTORY_EVENTS, PAIR_EVENTS)? })
+    }
+
+    /// the events decoded by `PairCreatedEvent` and `PairEvent`
+    fn events() -> Vec<EventSpec> {
+        use ParamType::{Address, Uint};
+        vec![
+            EventSpec::new("create_pair", "PairCreated", true,
+                           vec![(Address, true), (Address, true), (Address, false), (Uint(256), false)]),
+            EventSpec::new("mint", "Mint", false,
+                           vec![(Address, true), (Uint(256), false), (Uint(256), false)]),
+            EventSpec::new("burn", "Burn", false,
+                           vec![(Address, true), (Uint(256), false), (Uint(256), false), (Address, true)]),
+            EventSpec::new("swap", "Swap", false,
+                           vec![(Address, true), (Uint(256), false), (Uint(256), false),
+                                (Uint(256), false), (Uint(256), false), (Address, true)]),
+            EventSpec::new("sync", "Sync", false, vec![(Uint(112), false), (Uint(112), false)]),
+            EventSpec::new("transfer", "Transfer", false, vec![(Address, true), (Address, true), (Uint(256), false)]),
+        ]
+    }
+
+    /// the topics of the embedded ABIs by kind: `create_pair`, `mint`, `burn`, `swap`, `sync`
+    /// and `transfer`, computed on first use
+    pub fn embedded_topics() -> &'static TopicRegistry {
+        static TOPICS: OnceLock<TopicRegistry> = OnceLock::new();
+        TOPICS.get_or_init(|| {
+            AbiFiles::default().load_topics(&Self::events(), FACTORY_EVENTS, PAIR_EVENTS)
+                .expect("embedded abi error")
+        })
+    }
+
+    fn topic(&self, name: &str) -> H256 {
+        self.topics.topic(name)
+    }
+
+    /// a Mint, Burn, Swap or Sync log, told apart by the topics of this adapter's ABI
+    pub fn decode_pair_log(&self, log: &Log) -> anyhow::Result<PairEvent> {
+        let event_type = log.topics.first()
+            .and_then(|t| self.topics.kind(t))
+            .and_then(EventType::from_kind)
+            .ok_or_else(|| format_err!("unknown pair event topic {:?}", log.topics.first()))?;
+        PairEvent::decode(event_type, log.clone()).map_err(|e| format_err!("invalid pair log: {:?}", e))
+    }
+}
+
+impl Default for UniswapV2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProtocolAdapter for UniswapV2 {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn factory_topics(&self) -> Vec<H256> {
+        vec![self.topic("create_pair")]
+    }
+
+    fn pool_topics(&self) -> Vec<H256> {
+        ["mint", "burn", "swap", "sync"].iter().map(|t| self.topic(t)).collect()
+    }
+
+    fn lp_transfer_topic(&self) -> Option<H256> {
+        Some(self.topic("transfer"))
+    }
+
+    fn decode_factory_log(&self, log: &Log) -> anyhow::Result<PoolCreated> {
+        let event = PairCreatedEvent::try_from(log.clone())
+            .map_err(|e| format_err!("invalid PairCreated log: {:?}", e))?;
+        Ok(PoolCreated {
+            pool: event.pair_address,
+            token0: event.token0_address,
+            token1: event.token1_address,
+            fee: None,
+            tick_spacing: None,
+            block_number: log.block_number.unwrap_or_default().as_u64(),
+        })
+    }
+
+    fn decode_pool_log(&self, log: &Log) -> anyhow::Result<PoolEvent> {
+        Ok(PoolEvent::Pair(self.decode_pair_log(log)?))
+    }
+
+    /// the reserves follow the Sync events, Mint, Burn and Swap are counted
+    fn apply(&self, state: &mut PoolState, event: &PoolEvent) -> anyhow::Result<()> {
+        let PoolEvent::Pair(event) = event else {
+            return Err(format_err!("{:?} is not a pair event", event));
+        };
+        match event {
+            PairEvent::SyncPairEvent(sync) => {
+                state.reserve0 = sync.reserve0;
+                state.reserve1 = sync.reserve1;
+            }
+            PairEvent::MintPairEvent(_) => state.mint_count += 1,
+            PairEvent::BurnPairEvent(_) => state.burn_count += 1,
+            PairEvent::SwapPairEvent(_) => state.swap_count += 1,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use web3::types::{H160, U256};
+    use crate::protocol::adapter;
+    use crate::watcher::mock_rpc::MockChain;
+
+    #[test]
+    fn test_adapter() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let token0 = chain.deploy_token("A", 18);
+        let token1 = chain.deploy_token("B", 18);
+        let pair = chain.create_pair(token0, token1);
+        let lp = H160::from_low_u64_be(0xaaa);
+        chain.mint(pair, lp, U256::exp10(21), U256::exp10(21));
+        chain.swap(pair, lp, lp, U256::exp10(18), true);
+        chain.burn(pair, lp, lp, U256::exp10(20), U256::exp10(20));
+
+        let protocol = adapter("uniswap_v2").unwrap();
+        assert!(adapter("uniswap_v9").is_err());
+        let created = protocol.decode_factory_log(&chain.logs[0]).unwrap();
+        assert_eq!((created.pool, created.token0, created.token1), (pair, token0, token1));
+        let mut state = PoolState::default();
+        let transfer = protocol.lp_transfer_topic().unwrap();
+        for log in chain.logs.iter().filter(|l| l.address == pair && l.topics[0] != transfer) {
+            assert!(protocol.pool_topics().contains(&log.topics[0]));
+            protocol.apply(&mut state, &protocol.decode_pool_log(log).unwrap()).unwrap();
+        }
+        let expected = &chain.pairs[&pair];
+        assert_eq!(state, PoolState {
+            reserve0: expected.reserve0,
+            reserve1: expected.reserve1,
+            swap_count: 1,
+            mint_count: 1,
+            burn_count: 1,
+            ..Default::default()
+        });
+        assert!(protocol.decode_pool_log(&chain.logs[0]).is_err());
+    }
+}
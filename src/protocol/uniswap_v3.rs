@@ -0,0 +1,504 @@
+//! Uniswap V3 and the forks that kept its concentrated liquidity pool events.
+//!
+//! Besides the token balances and event counts kept for every pool, the state of a V3
+//! pool is its price (`sqrtPriceX96` and the current tick), the liquidity in range and
+//! the liquidity net of every initialized tick. `Burn` only credits the position, the
+//! tokens leave the pool with `Collect`.
+use std::sync::OnceLock;
+use anyhow::format_err;
+use ethabi::{ParamType, Token};
+use web3::types::{Log, H160, H256, U256};
+use crate::protocol::{PoolCreated, PoolEvent, PoolState, ProtocolAdapter};
+use crate::protocol::abi::{AbiFiles, EventSpec};
+use crate::protocol::topics::TopicRegistry;
+use crate::watcher::event::EventData;
+
+pub const NAME: &str = "uniswap_v3";
+
+const FACTORY_EVENTS: &str = include_str!("../abi/v3_factory_abi.json");
+const POOL_EVENTS: &str = include_str!("../abi/v3_pool_abi.json");
+
+/// A decoded V3 pool log.
+#[derive(Debug, Clone)]
+pub enum V3PoolEvent {
+    Initialize {
+        meta: EventData,
+        sqrt_price_x96: U256,
+        tick: i32,
+    },
+    Mint {
+        meta: EventData,
+        sender: H160,
+        owner: H160,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount: u128,
+        amount0: U256,
+        amount1: U256,
+    },
+    Burn {
+        meta: EventData,
+        owner: H160,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount: u128,
+        amount0: U256,
+        amount1: U256,
+    },
+    /// amounts are signed, positive when paid into the pool
+    Swap {
+        meta: EventData,
+        sender: H160,
+        recipient: H160,
+        amount0: i128,
+        amount1: i128,
+        sqrt_price_x96: U256,
+        liquidity: u128,
+        tick: i32,
+    },
+    Collect {
+        meta: EventData,
+        owner: H160,
+        recipient: H160,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount0: u128,
+        amount1: u128,
+    },
+    Flash {
+        meta: EventData,
+        sender: H160,
+        recipient: H160,
+        amount0: U256,
+        amount1: U256,
+        paid0: U256,
+        paid1: U256,
+    },
+}
+
+impl V3PoolEvent {
+    pub fn meta(&self) -> &EventData {
+        match self {
+            V3PoolEvent::Initialize { meta, .. } | V3PoolEvent::Mint { meta, .. }
+            | V3PoolEvent::Burn { meta, .. } | V3PoolEvent::Swap { meta, .. }
+            | V3PoolEvent::Collect { meta, .. } | V3PoolEvent::Flash { meta, .. } => meta,
+        }
+    }
+
+    pub fn meta_mut(&mut self) -> &mut EventData {
+        match self {
+            V3PoolEvent::Initialize { meta, .. } | V3PoolEvent::Mint { meta, .. }
+            | V3PoolEvent::Burn { meta, .. } | V3PoolEvent::Swap { meta, .. }
+            | V3PoolEvent::Collect { meta, .. } | V3PoolEvent::Flash { meta, .. } => meta,
+        }
+    }
+
+    /// the ticks whose liquidity net the event changes
+    pub fn ticks(&self) -> Vec<i32> {
+        match self {
+            V3PoolEvent::Mint { tick_lower, tick_upper, .. }
+            | V3PoolEvent::Burn { tick_lower, tick_upper, .. } => vec![*tick_lower, *tick_upper],
+            _ => vec![],
+        }
+    }
+}
+
+/// a two's complement `int256` word, `None` when it doesn't fit an `i128`
+pub fn decode_int(word: U256) -> Option<i128> {
+    if word.bit(255) {
+        let magnitude = !word;
+        if magnitude > U256::from(i128::MAX as u128) {
+            return None;
+        }
+        Some(-(magnitude.as_u128() as i128) - 1)
+    } else if word > U256::from(i128::MAX as u128) {
+        None
+    } else {
+        Some(word.as_u128() as i128)
+    }
+}
+
+/// `value` as a two's complement `int256` word
+pub fn encode_int(value: i128) -> U256 {
+    if value < 0 {
+        !U256::from((-(value + 1)) as u128)
+    } else {
+        U256::from(value as u128)
+    }
+}
+
+fn int24(word: U256) -> Result<i32, ethabi::Error> {
+    decode_int(word)
+        .and_then(|v| i32::try_from(v).ok())
+        .filter(|v| (-(1 << 23)..(1 << 23)).contains(v))
+        .ok_or_else(|| ethabi::Error::Other(format!("invalid int24 {}", word).into()))
+}
+
+fn int256(word: U256) -> Result<i128, ethabi::Error> {
+    decode_int(word).ok_or_else(|| ethabi::Error::Other(format!("int256 {} is out of range", word).into()))
+}
+
+fn uint128(word: U256) -> Result<u128, ethabi::Error> {
+    if word > U256::from(u128::MAX) {
+        return Err(ethabi::Error::Other(format!("invalid uint128 {}", word).into()));
+    }
+    Ok(word.as_u128())
+}
+
+fn topic(log: &Log, index: usize) -> Result<H256, ethabi::Error> {
+    log.topics.get(index).copied()
+        .ok_or_else(|| ethabi::Error::Other(format!("missing topic {}", index).into()))
+}
+
+fn topic_address(log: &Log, index: usize) -> Result<H160, ethabi::Error> {
+    Ok(H160::from_slice(&topic(log, index)?.as_bytes()[12..]))
+}
+
+fn topic_tick(log: &Log, index: usize) -> Result<i32, ethabi::Error> {
+    int24(U256::from_big_endian(topic(log, index)?.as_bytes()))
+}
+
+fn word(tokens: &[Token], index: usize) -> U256 {
+    match &tokens[index] {
+        Token::Int(v) | Token::Uint(v) => *v,
+        _ => unreachable!("decoded as an integer"),
+    }
+}
+
+fn address(tokens: &[Token], index: usize) -> H160 {
+    tokens[index].clone().into_address().expect("decoded as an address")
+}
+
+pub struct UniswapV3 {
+    topics: TopicRegistry,
+}
+
+impl UniswapV3 {
+    pub fn new() -> Self {
+        Self { topics: Self::embedded_topics().clone() }
+    }
+
+    /// an adapter for a fork whose ABIs differ from the embedded ones
+    pub fn with_abi(files: &AbiFiles) -> anyhow::Result<Self> {
+        Ok(Self { topics: files.load_topics(&Self::events(), FACTORY_EVENTS, POOL_EVENTS)? })
+    }
+
+    fn events() -> Vec<EventSpec> {
+        use ParamType::{Address, Int, Uint};
+        vec![
+            EventSpec::new("create_pool", "PoolCreated", true,
+                           vec![(Address, true), (Address, true), (Uint(24), true), (Int(24), false), (Address, false)]),
+            EventSpec::new("initialize", "Initialize", false, vec![(Uint(160), false), (Int(24), false)]),
+            EventSpec::new("mint", "Mint", false,
+                           vec![(Address, false), (Address, true), (Int(24), true), (Int(24), true),
+                                (Uint(128), false), (Uint(256), false), (Uint(256), false)]),
+            EventSpec::new("burn", "Burn", false,
+                           vec![(Address, true), (Int(24), true), (Int(24), true),
+                                (Uint(128), false), (Uint(256), false), (Uint(256), false)]),
+            EventSpec::new("swap", "Swap", false,
+                           vec![(Address, true), (Address, true), (Int(256), false), (Int(256), false),
+                                (Uint(160), false), (Uint(128), false), (Int(24), false)]),
+            EventSpec::new("collect", "Collect", false,
+                           vec![(Address, true), (Address, false), (Int(24), true), (Int(24), true),
+                                (Uint(128), false), (Uint(128), false)]),
+            EventSpec::new("flash", "Flash", false,
+                           vec![(Address, true), (Address, true), (Uint(256), false), (Uint(256), false),
+                                (Uint(256), false), (Uint(256), false)]),
+        ]
+    }
+
+    /// the topics of the embedded ABIs by kind: `create_pool`, `initialize`, `mint`, `burn`,
+    /// `swap`, `collect` and `flash`, computed on first use
+    pub fn embedded_topics() -> &'static TopicRegistry {
+        static TOPICS: OnceLock<TopicRegistry> = OnceLock::new();
+        TOPICS.get_or_init(|| {
+            AbiFiles::default().load_topics(&Self::events(), FACTORY_EVENTS, POOL_EVENTS)
+                .expect("embedded v3 abi error")
+        })
+    }
+
+    fn topic(&self, name: &str) -> H256 {
+        self.topics.topic(name)
+    }
+
+    fn decode(&self, log: &Log) -> Result<V3PoolEvent, ethabi::Error> {
+        let meta = EventData::from_log(log);
+        let name = log.topics.first()
+            .and_then(|t| self.topics.kind(t))
+            .filter(|kind| *kind != "create_pool")
+            .ok_or_else(|| ethabi::Error::Other(format!("unknown v3 pool event topic {:?}", log.topics.first()).into()))?;
+        let data = &log.data.0;
+        let event = match name {
+            "initialize" => {
+                let tokens = ethabi::decode(&[ParamType::Uint(160), ParamType::Int(24)], data)?;
+                V3PoolEvent::Initialize { meta, sqrt_price_x96: word(&tokens, 0), tick: int24(word(&tokens, 1))? }
+            }
+            "mint" => {
+                let tokens = ethabi::decode(&[ParamType::Address, ParamType::Uint(128),
+                                              ParamType::Uint(256), ParamType::Uint(256)], data)?;
+                V3PoolEvent::Mint {
+                    meta,
+                    sender: address(&tokens, 0),
+                    owner: topic_address(log, 1)?,
+                    tick_lower: topic_tick(log, 2)?,
+                    tick_upper: topic_tick(log, 3)?,
+                    amount: uint128(word(&tokens, 1))?,
+                    amount0: word(&tokens, 2),
+                    amount1: word(&tokens, 3),
+                }
+            }
+            "burn" => {
+                let tokens = ethabi::decode(&[ParamType::Uint(128), ParamType::Uint(256), ParamType::Uint(256)], data)?;
+                V3PoolEvent::Burn {
+                    meta,
+                    owner: topic_address(log, 1)?,
+                    tick_lower: topic_tick(log, 2)?,
+                    tick_upper: topic_tick(log, 3)?,
+                    amount: uint128(word(&tokens, 0))?,
+                    amount0: word(&tokens, 1),
+                    amount1: word(&tokens, 2),
+                }
+            }
+            "swap" => {
+                let tokens = ethabi::decode(&[ParamType::Int(256), ParamType::Int(256), ParamType::Uint(160),
+                                              ParamType::Uint(128), ParamType::Int(24)], data)?;
+                V3PoolEvent::Swap {
+                    meta,
+                    sender: topic_address(log, 1)?,
+                    recipient: topic_address(log, 2)?,
+                    amount0: int256(word(&tokens, 0))?,
+                    amount1: int256(word(&tokens, 1))?,
+                    sqrt_price_x96: word(&tokens, 2),
+                    liquidity: uint128(word(&tokens, 3))?,
+                    tick: int24(word(&tokens, 4))?,
+                }
+            }
+            "collect" => {
+                let tokens = ethabi::decode(&[ParamType::Address, ParamType::Uint(128), ParamType::Uint(128)], data)?;
+                V3PoolEvent::Collect {
+                    meta,
+                    owner: topic_address(log, 1)?,
+                    recipient: address(&tokens, 0),
+                    tick_lower: topic_tick(log, 2)?,
+                    tick_upper: topic_tick(log, 3)?,
+                    amount0: uint128(word(&tokens, 1))?,
+                    amount1: uint128(word(&tokens, 2))?,
+                }
+            }
+            _ => {
+                let tokens = ethabi::decode(&[ParamType::Uint(256), ParamType::Uint(256),
+                                              ParamType::Uint(256), ParamType::Uint(256)], data)?;
+                V3PoolEvent::Flash {
+                    meta,
+                    sender: topic_address(log, 1)?,
+                    recipient: topic_address(log, 2)?,
+                    amount0: word(&tokens, 0),
+                    amount1: word(&tokens, 1),
+                    paid0: word(&tokens, 2),
+                    paid1: word(&tokens, 3),
+                }
+            }
+        };
+        Ok(event)
+    }
+}
+
+impl Default for UniswapV3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// add a signed amount to a balance, a balance can't go below zero
+fn add_signed(balance: U256, amount: i128) -> U256 {
+    if amount < 0 {
+        balance.saturating_sub(U256::from(amount.unsigned_abs()))
+    } else {
+        balance.saturating_add(U256::from(amount as u128))
+    }
+}
+
+fn update_liquidity_net(state: &mut PoolState, tick: i32, delta: i128) {
+    let net = state.liquidity_net.entry(tick).or_default();
+    *net += delta;
+    if *net == 0 {
+        state.liquidity_net.remove(&tick);
+    }
+}
+
+/// change the liquidity of a position, `delta` is negative for a burn
+fn update_position(state: &mut PoolState, tick_lower: i32, tick_upper: i32, delta: i128) {
+    update_liquidity_net(state, tick_lower, delta);
+    update_liquidity_net(state, tick_upper, -delta);
+    if tick_lower <= state.tick && state.tick < tick_upper {
+        state.liquidity = if delta < 0 {
+            state.liquidity.saturating_sub(delta.unsigned_abs())
+        } else {
+            state.liquidity.saturating_add(delta as u128)
+        };
+    }
+}
+
+fn liquidity_delta(amount: u128) -> anyhow::Result<i128> {
+    i128::try_from(amount).map_err(|_| format_err!("liquidity {} is out of range", amount))
+}
+
+impl ProtocolAdapter for UniswapV3 {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn factory_topics(&self) -> Vec<H256> {
+        vec![self.topic("create_pool")]
+    }
+
+    fn pool_topics(&self) -> Vec<H256> {
+        ["initialize", "mint", "burn", "swap", "collect", "flash"].iter().map(|t| self.topic(t)).collect()
+    }
+
+    /// the liquidity in range depends on the tick at the time of a mint or burn, so all
+    /// events of a pool are applied in chain order
+    fn pool_topic_groups(&self) -> Vec<Vec<H256>> {
+        vec![self.pool_topics()]
+    }
+
+    fn decode_factory_log(&self, log: &Log) -> anyhow::Result<PoolCreated> {
+        let decode = || -> Result<PoolCreated, ethabi::Error> {
+            if topic(log, 0)? != self.topic("create_pool") {
+                return Err(ethabi::Error::Other("not a PoolCreated log".into()));
+            }
+            let tokens = ethabi::decode(&[ParamType::Int(24), ParamType::Address], &log.data.0)?;
+            let fee = U256::from_big_endian(topic(log, 3)?.as_bytes());
+            if fee > U256::from(0xffffffu32) {
+                return Err(ethabi::Error::Other(format!("invalid uint24 fee {}", fee).into()));
+            }
+            Ok(PoolCreated {
+                pool: address(&tokens, 1),
+                token0: topic_address(log, 1)?,
+                token1: topic_address(log, 2)?,
+                fee: Some(fee.as_u32()),
+                tick_spacing: Some(int24(word(&tokens, 0))?),
+                block_number: log.block_number.unwrap_or_default().as_u64(),
+            })
+        };
+        decode().map_err(|e| format_err!("invalid PoolCreated log: {:?}", e))
+    }
+
+    fn decode_pool_log(&self, log: &Log) -> anyhow::Result<PoolEvent> {
+        let event = self.decode(log)
+            .map_err(|e| format_err!("invalid v3 pool log: {:?}", e))?;
+        Ok(PoolEvent::V3(event))
+    }
+
+    fn apply(&self, state: &mut PoolState, event: &PoolEvent) -> anyhow::Result<()> {
+        let PoolEvent::V3(event) = event else {
+            return Err(format_err!("{:?} is not a v3 pool event", event));
+        };
+        move_reserves(&mut state.reserve0, &mut state.reserve1, event);
+        match event {
+            V3PoolEvent::Initialize { sqrt_price_x96, tick, .. } => {
+                state.sqrt_price_x96 = *sqrt_price_x96;
+                state.tick = *tick;
+            }
+            V3PoolEvent::Mint { tick_lower, tick_upper, amount, .. } => {
+                update_position(state, *tick_lower, *tick_upper, liquidity_delta(*amount)?);
+                state.mint_count += 1;
+            }
+            V3PoolEvent::Burn { tick_lower, tick_upper, amount, .. } => {
+                update_position(state, *tick_lower, *tick_upper, -liquidity_delta(*amount)?);
+                state.burn_count += 1;
+            }
+            V3PoolEvent::Swap { sqrt_price_x96, liquidity, tick, .. } => {
+                state.sqrt_price_x96 = *sqrt_price_x96;
+                state.liquidity = *liquidity;
+                state.tick = *tick;
+                state.swap_count += 1;
+            }
+            V3PoolEvent::Collect { .. } | V3PoolEvent::Flash { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+/// the reserves are the token balances of the pool, moved by Mint, Swap, Collect and
+/// the fees paid by Flash
+pub fn move_reserves(reserve0: &mut U256, reserve1: &mut U256, event: &V3PoolEvent) {
+    match event {
+        V3PoolEvent::Mint { amount0, amount1, .. } => {
+            *reserve0 = reserve0.saturating_add(*amount0);
+            *reserve1 = reserve1.saturating_add(*amount1);
+        }
+        V3PoolEvent::Swap { amount0, amount1, .. } => {
+            *reserve0 = add_signed(*reserve0, *amount0);
+            *reserve1 = add_signed(*reserve1, *amount1);
+        }
+        V3PoolEvent::Collect { amount0, amount1, .. } => {
+            *reserve0 = reserve0.saturating_sub(U256::from(*amount0));
+            *reserve1 = reserve1.saturating_sub(U256::from(*amount1));
+        }
+        V3PoolEvent::Flash { paid0, paid1, .. } => {
+            *reserve0 = reserve0.saturating_add(*paid0);
+            *reserve1 = reserve1.saturating_add(*paid1);
+        }
+        V3PoolEvent::Initialize { .. } | V3PoolEvent::Burn { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::protocol::adapter;
+    use crate::watcher::mock_rpc::MockChain;
+
+    #[test]
+    fn test_int_words() {
+        for value in [0, 1, -1, 887272, -887272, i128::MAX, i128::MIN] {
+            assert_eq!(decode_int(encode_int(value)), Some(value));
+        }
+        assert_eq!(encode_int(-1), U256::MAX);
+        assert_eq!(decode_int(U256::from(u128::MAX)), None);
+    }
+
+    #[test]
+    fn test_adapter() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let factory = H160::from_low_u64_be(0xfac3);
+        let token0 = chain.deploy_token("A", 18);
+        let token1 = chain.deploy_token("B", 6);
+        let pool = chain.create_v3_pool(factory, token0, token1, 3000, 60);
+        let lp = H160::from_low_u64_be(0xaaa);
+        let e18 = U256::exp10(18);
+        chain.v3_initialize(pool, U256::from(2).pow(96.into()), 0);
+        chain.v3_mint(pool, lp, (-120, 120), 1000, (e18, e18));
+        // out of range, adds no liquidity at the current tick
+        chain.v3_mint(pool, lp, (60, 180), 500, (0.into(), e18));
+        chain.v3_swap(pool, lp, (1000, -900), U256::from(2).pow(95.into()), -70);
+        chain.v3_burn(pool, lp, (-120, 120), 400, (e18 / 2, 0.into()));
+        chain.v3_collect(pool, lp, (-120, 120), (1000, 0));
+        chain.v3_flash(pool, lp, (e18, 0.into()), (3.into(), 0.into()));
+
+        let protocol = adapter("uniswap_v3").unwrap();
+        let created = protocol.decode_factory_log(&chain.logs[0]).unwrap();
+        assert_eq!((created.pool, created.token0, created.token1), (pool, token0, token1));
+        assert_eq!((created.fee, created.tick_spacing), (Some(3000), Some(60)));
+        assert_eq!(protocol.pool_topic_groups(), vec![protocol.pool_topics()]);
+        let mut state = PoolState::default();
+        for log in chain.logs.iter().filter(|l| l.address == pool) {
+            protocol.apply(&mut state, &protocol.decode_pool_log(log).unwrap()).unwrap();
+        }
+        assert_eq!(state, PoolState {
+            reserve0: e18 + 1000 - 1000 + 3,
+            reserve1: e18 * 2 - 900,
+            swap_count: 1,
+            mint_count: 2,
+            burn_count: 1,
+            sqrt_price_x96: U256::from(2).pow(95.into()),
+            tick: -70,
+            liquidity: 600,
+            liquidity_net: BTreeMap::from([(-120, 600), (60, 500), (120, -600), (180, -500)]),
+        });
+        assert!(protocol.decode_pool_log(&chain.logs[0]).is_err());
+        assert!(protocol.decode_factory_log(&chain.logs[1]).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use crate::server::AppState;
+use crate::db;
+use crate::db::token_cache::TokenCache;
+use crate::db::accounts::{self, Activity};
+use crate::db::listing::{self, EventQuery};
+use crate::protocol::uniswap_v3;
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::err::BackendError;
+use crate::route::pool_events::{parse_descending, parse_event_cursor, parse_event_types, pool_event, PoolEvent};
+use crate::route::pools::{get_pool_tokens, Amount};
+use crate::watcher::token::PLACEHOLDER_DECIMALS;
+
+#[derive(Debug, Deserialize)]
+pub struct ActivityQuery {
+    /// comma separated `swap`, `add` and `remove`, all of them by default
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    /// the events of one pool
+    pub pool: Option<String>,
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    /// unix seconds
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+    /// `asc` (default), oldest first, or `desc`
+    pub order: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+/// An event of an account with the pool it happened in.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountEvent {
+    pub pair_address: String,
+    pub token_x_symbol: String,
+    pub token_y_symbol: String,
+    #[serde(flatten)]
+    pub event: PoolEvent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityPage {
+    pub events: Vec<AccountEvent>,
+    /// `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenVolume {
+    pub address: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub volume: Amount,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountSummary {
+    pub swap_count: i64,
+    pub add_liq_count: i64,
+    pub rm_liq_count: i64,
+    /// the amounts swapped in and out, by token
+    pub volume: Vec<TokenVolume>,
+    pub pools_touched: usize,
+    pub pools: Vec<String>,
+    pub first_block: Option<i64>,
+    pub first_timestamp: Option<i64>,
+    pub last_block: Option<i64>,
+    pub last_timestamp: Option<i64>,
+}
+
+fn parse_query(account: String, query: &ActivityQuery) -> Result<EventQuery, String> {
+    let pair_address = match &query.pool {
+        Some(pool) => Some(normalize_address(pool).ok_or_else(|| format!("invalid pool address {}", pool))?),
+        None => None,
+    };
+    Ok(EventQuery {
+        pair_address,
+        event_types: parse_event_types(&query.event_type)?,
+        account: Some(account),
+        from_block: query.from_block,
+        to_block: query.to_block,
+        from_time: query.from_time,
+        to_time: query.to_time,
+        descending: parse_descending(&query.order)?,
+        after: parse_event_cursor(&query.cursor)?,
+        limit: query.limit.unwrap_or(100).clamp(1, 1000),
+        ..Default::default()
+    })
+}
+
+async fn get_activity_page(rb: &rbatis::Rbatis, tokens: &TokenCache, query: &EventQuery) -> anyhow::Result<ActivityPage> {
+    let (events, next) = listing::get_events(rb, query).await?;
+    // (symbol x, symbol y, decimals x, decimals y, v3) of the pools of the page
+    let mut pools = HashMap::new();
+    let mut account_events = Vec::with_capacity(events.len());
+    for event in events {
+        if !pools.contains_key(&event.pair_address) {
+            let Some(pool) = db::get_pool(rb, &event.pair_address).await? else {
+                log::warn!("event {}:{:?} of unknown pool {}", event.tx_hash, event.log_index, event.pair_address);
+                continue;
+            };
+            let (token_x, token_y) = get_pool_tokens(rb, tokens, &pool).await?;
+            pools.insert(event.pair_address.clone(), (token_x.symbol, token_y.symbol, token_x.decimals,
+                                                      token_y.decimals, pool.protocol == uniswap_v3::NAME));
+        }
+        let (symbol_x, symbol_y, decimals_x, decimals_y, v3) = pools[&event.pair_address].clone();
+        account_events.push(AccountEvent {
+            pair_address: event.pair_address.clone(),
+            token_x_symbol: symbol_x,
+            token_y_symbol: symbol_y,
+            event: pool_event(event, decimals_x, decimals_y, v3)?,
+        });
+    }
+    Ok(ActivityPage { events: account_events, next_cursor: next.map(|c| c.encode()) })
+}
+
+async fn get_summary(rb: &rbatis::Rbatis, account: &str) -> anyhow::Result<AccountSummary> {
+    let summary = accounts::get_account_summary(rb, account).await?;
+    let mut volume = Vec::with_capacity(summary.volume.len());
+    for (address, amount) in summary.volume {
+        let token = db::get_token(rb, address.clone()).await?.into_iter().next();
+        let (symbol, decimals) = token.map(|t| (t.symbol, t.decimals)).unwrap_or((String::new(), PLACEHOLDER_DECIMALS));
+        volume.push(TokenVolume { address, symbol, decimals, volume: Amount::new(amount, decimals) });
+    }
+    let block = |activity: Option<Activity>| activity.map(|a| a.block_number);
+    let time = |activity: Option<Activity>| activity.and_then(|a| a.block_timestamp);
+    Ok(AccountSummary {
+        swap_count: summary.swap_count,
+        add_liq_count: summary.add_liq_count,
+        rm_liq_count: summary.rm_liq_count,
+        volume,
+        pools_touched: summary.pools.len(),
+        pools: summary.pools.into_iter().collect(),
+        first_block: block(summary.first_activity),
+        first_timestamp: time(summary.first_activity),
+        last_block: block(summary.last_activity),
+        last_timestamp: time(summary.last_activity),
+    })
+}
+
+/// a page of the swaps, adds and removes an account sent, received or sent the transaction of
+pub async fn get_account_activity(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ActivityQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let event_query = normalize_address(&path)
+        .ok_or_else(|| format!("invalid account address {}", path))
+        .and_then(|account| parse_query(account, &query));
+    let event_query = match event_query {
+        Ok(query) => query,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_activity_page(&rb, &data.token_cache, &event_query).await {
+        Ok(page) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(page)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("get_account_activity from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get account activity failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+/// the trades, volume and pools of an account
+pub async fn get_account_summary(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let Some(account) = normalize_address(&path) else {
+        let resp = BackendResponse {
+            code: BackendError::InvalidParameters,
+            error: Some(format!("invalid account address {}", path)),
+            data: None::<()>,
+        };
+        return Ok(HttpResponse::Ok().json(resp));
+    };
+
+    match get_summary(&rb, &account).await {
+        Ok(summary) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(summary)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("get_account_summary from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get account summary failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
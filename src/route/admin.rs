@@ -0,0 +1,96 @@
+use actix_web::{web, HttpResponse};
+use actix_web::dev::{Service, ServiceRequest};
+use actix_web::http::header::AUTHORIZATION;
+use futures::future::{ready, Either};
+use futures::TryFutureExt;
+use crate::server::AppState;
+use crate::route::BackendResponse;
+use crate::route::err::BackendError;
+use crate::route::failed_logs::{get_failed_logs, retry_failed_logs};
+use crate::route::reconciliation_issues::get_reconciliation_issues;
+
+/// whether the request carries `Authorization: Bearer <ADMIN_TOKEN>`, never without a token
+/// configured
+fn authorized(req: &ServiceRequest) -> bool {
+    let Some(token) = req.app_data::<web::Data<AppState>>().and_then(|d| d.config.admin_token.clone()) else {
+        return false;
+    };
+    let bearer = req.headers().get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    match bearer {
+        // compare every byte, the time taken doesn't tell how much of the token matched
+        Some(bearer) => bearer.len() == token.len()
+            && bearer.bytes().zip(token.bytes()).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0,
+        None => false,
+    }
+}
+
+/// the `/admin` routes, behind the `ADMIN_TOKEN` bearer token
+pub fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::scope("/admin")
+        .wrap_fn(|req, srv| {
+            if authorized(&req) {
+                return Either::Left(srv.call(req).map_ok(|res| res.map_into_left_body()));
+            }
+            let resp = BackendResponse {
+                code: BackendError::Unauthorized,
+                error: Some("a valid admin bearer token is required".to_string()),
+                data: None::<()>,
+            };
+            Either::Right(ready(Ok(req.into_response(HttpResponse::Unauthorized().json(resp))
+                .map_into_right_body())))
+        })
+        .route("/reconciliation_issues", web::get().to(get_reconciliation_issues))
+        .route("/failed_logs", web::get().to(get_failed_logs))
+        .route("/failed_logs/retry", web::post().to(retry_failed_logs)));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::{call_service, init_service, TestRequest};
+    use actix_web::http::StatusCode;
+    use actix_web::App;
+    use crate::config::BackendConfig;
+    use crate::db::pool_graph::PoolGraph;
+    use crate::db::sqlite::new_test_db;
+    use crate::db::token_cache::TokenCache;
+
+    #[actix_rt::test]
+    async fn test_admin_token() {
+        let db = new_test_db("admin_token").await;
+        for (admin_token, authorization, status) in [
+            (None, None, StatusCode::UNAUTHORIZED),
+            (None, Some("Bearer "), StatusCode::UNAUTHORIZED),
+            (Some("secret"), None, StatusCode::UNAUTHORIZED),
+            (Some("secret"), Some("Bearer secreT"), StatusCode::UNAUTHORIZED),
+            (Some("secret"), Some("Bearer secret2"), StatusCode::UNAUTHORIZED),
+            (Some("secret"), Some("secret"), StatusCode::UNAUTHORIZED),
+            (Some("secret"), Some("Bearer secret"), StatusCode::OK),
+        ] {
+            let state = AppState {
+                config: BackendConfig { admin_token: admin_token.map(str::to_string), ..Default::default() },
+                db: db.clone(),
+                token_cache: TokenCache::start(db.clone(), 0).await.unwrap(),
+                pool_graph: PoolGraph::default(),
+            };
+            let app = init_service(App::new()
+                .app_data(web::Data::new(state))
+                .configure(admin_routes)).await;
+            let requests = [
+                TestRequest::get().uri("/admin/reconciliation_issues"),
+                TestRequest::get().uri("/admin/failed_logs"),
+                TestRequest::post().uri("/admin/failed_logs/retry"),
+            ];
+            for request in requests {
+                let request = match authorization {
+                    Some(authorization) => request.insert_header((AUTHORIZATION, authorization)),
+                    None => request,
+                };
+                let resp = call_service(&app, request.to_request()).await;
+                assert_eq!(resp.status(), status, "{:?} {:?}", admin_token, authorization);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use crate::server::AppState;
+use crate::amm::units::price;
+use crate::db;
+use crate::db::token_cache::TokenCache;
+use crate::db::candles::{self, price_scale, Candle, INTERVALS};
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::err::BackendError;
+use crate::route::pools::{get_pool_tokens, Amount, PoolToken};
+
+/// the candles of a request when `from` isn't set
+const DEFAULT_CANDLES: i64 = 500;
+const MAX_CANDLES: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct CandlesQuery {
+    /// one of `INTERVALS`, `1h` by default
+    pub interval: Option<String>,
+    /// unix seconds, candles opening in the range are returned
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// the token prices are quoted in: `y` (default), `x` or the address of either token
+    pub quote: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CandleToken {
+    pub address: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl From<PoolToken> for CandleToken {
+    fn from(token: PoolToken) -> Self {
+        Self { address: token.address, symbol: token.symbol, decimals: token.decimals }
+    }
+}
+
+/// The prices of one base token in quote tokens over an interval.
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceCandle {
+    pub open_time: i64,
+    /// `None` until the price changes of the swaps of the candle are indexed
+    pub open: Option<String>,
+    pub high: Option<String>,
+    pub low: Option<String>,
+    pub close: Option<String>,
+    pub volume_base: Amount,
+    pub volume_quote: Amount,
+    pub swap_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CandlePage {
+    pub pair_address: String,
+    pub interval: String,
+    pub base: CandleToken,
+    pub quote: CandleToken,
+    /// oldest first, intervals without events have no candle
+    pub candles: Vec<PriceCandle>,
+}
+
+/// The parsed parameters of a candles request.
+struct CandleRange {
+    interval: (&'static str, i64),
+    from: i64,
+    to: i64,
+    quote: Option<String>,
+}
+
+fn parse_query(query: &CandlesQuery) -> Result<CandleRange, String> {
+    let name = query.interval.as_deref().unwrap_or("1h");
+    let interval = INTERVALS.iter().find(|(n, _)| *n == name).copied()
+        .ok_or_else(|| format!("unknown interval {}, expected one of {:?}", name, INTERVALS.map(|(n, _)| n)))?;
+    let to = match query.to {
+        Some(to) => to,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.as_secs() as i64,
+    };
+    let from = query.from.unwrap_or(to - interval.1 * DEFAULT_CANDLES);
+    if from > to {
+        return Err(format!("from {} is after to {}", from, to));
+    }
+    if (to - from) / interval.1 >= MAX_CANDLES {
+        return Err(format!("the range spans more than {} candles of {}", MAX_CANDLES, interval.0));
+    }
+    let quote = match query.quote.as_deref() {
+        None | Some("y") | Some("x") => query.quote.clone(),
+        Some(quote) => Some(normalize_address(quote)
+            .ok_or_else(|| format!("invalid quote {}, expected x, y or a token address", quote))?),
+    };
+    Ok(CandleRange { interval, from, to, quote })
+}
+
+/// a candle of prices of x in y, or of y in x when `inverse`
+fn price_candle(candle: Candle, decimals_x: u8, decimals_y: u8, inverse: bool) -> PriceCandle {
+    let scale = price_scale();
+    let Candle { open_time, open, high, low, close, volume_x, volume_y, swap_count, .. } = candle;
+    if inverse {
+        let format = |value: Option<_>| value.and_then(|value| price(value, decimals_y, scale, decimals_x));
+        PriceCandle {
+            open_time,
+            open: format(open),
+            high: format(low),
+            low: format(high),
+            close: format(close),
+            volume_base: Amount::new(volume_y, decimals_y),
+            volume_quote: Amount::new(volume_x, decimals_x),
+            swap_count,
+        }
+    } else {
+        let format = |value: Option<_>| value.and_then(|value| price(scale, decimals_x, value, decimals_y));
+        PriceCandle {
+            open_time,
+            open: format(open),
+            high: format(high),
+            low: format(low),
+            close: format(close),
+            volume_base: Amount::new(volume_x, decimals_x),
+            volume_quote: Amount::new(volume_y, decimals_y),
+            swap_count,
+        }
+    }
+}
+
+enum CandleError {
+    /// a quote token the pool doesn't have
+    InvalidQuote(String),
+    Db(anyhow::Error),
+}
+
+impl From<anyhow::Error> for CandleError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+/// `None` when the pool isn't indexed
+async fn get_candle_page(rb: &rbatis::Rbatis, tokens: &TokenCache, pair_address: &str, range: CandleRange)
+    -> Result<Option<CandlePage>, CandleError> {
+    let Some(pool) = db::get_pool(rb, pair_address).await? else {
+        return Ok(None);
+    };
+    let (token_x, token_y) = get_pool_tokens(rb, tokens, &pool).await?;
+    let inverse = match range.quote.as_deref() {
+        None | Some("y") => false,
+        Some("x") => true,
+        Some(quote) if quote == token_y.address => false,
+        Some(quote) if quote == token_x.address => true,
+        Some(quote) => return Err(CandleError::InvalidQuote(format!("{} isn't a token of the pool", quote))),
+    };
+    let (interval_name, interval) = range.interval;
+    let candles = candles::get_candles(rb, pair_address, interval,
+                                       candles::open_time(range.from, interval), range.to).await?;
+    let (decimals_x, decimals_y) = (token_x.decimals, token_y.decimals);
+    let (base, quote) = if inverse { (token_y, token_x) } else { (token_x, token_y) };
+    Ok(Some(CandlePage {
+        pair_address: pool.pair_address,
+        interval: interval_name.to_string(),
+        base: base.into(),
+        quote: quote.into(),
+        candles: candles.into_iter().map(|c| price_candle(c, decimals_x, decimals_y, inverse)).collect(),
+    }))
+}
+
+/// the OHLCV candles of a pool over a time range
+pub async fn get_pool_candles(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<CandlesQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let parsed = normalize_address(&path)
+        .ok_or_else(|| format!("invalid pool address {}", path))
+        .and_then(|address| Ok((address, parse_query(&query)?)));
+    let (pair_address, range) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_candle_page(&rb, &data.token_cache, &pair_address, range).await {
+        Ok(Some(page)) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(page)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Ok(None) => {
+            let resp = BackendResponse {
+                code: BackendError::NotFound,
+                error: Some(format!("pool {} not found", path)),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(CandleError::InvalidQuote(e)) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(CandleError::Db(e)) => {
+            log::warn!("get_pool_candles from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get pool candles failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
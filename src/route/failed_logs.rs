@@ -0,0 +1,76 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use crate::server::AppState;
+use crate::db;
+use crate::route::BackendResponse;
+use crate::route::err::BackendError;
+
+#[derive(Debug, Deserialize)]
+pub struct FailedLogsQuery {
+    pub status: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RetryQuery {
+    pub id: Option<i64>,
+}
+
+pub async fn get_failed_logs(
+    data: web::Data<AppState>,
+    query: web::Query<FailedLogsQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let limit = query.limit.unwrap_or(100).min(1000);
+
+    match db::get_failed_logs(&rb, query.status.clone(), limit).await {
+        Ok(logs) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(logs)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("get_failed_logs from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get failed logs failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+/// mark failed logs for the watcher to decode again on its next sync,
+/// returns the number of logs marked
+pub async fn retry_failed_logs(
+    data: web::Data<AppState>,
+    query: web::Query<RetryQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+
+    match db::request_failed_logs_retry(&rb, query.id).await {
+        Ok(count) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(count)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("retry_failed_logs in db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("retry failed logs failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
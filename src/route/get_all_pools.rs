@@ -1,23 +1,49 @@
+use std::collections::HashMap;
 use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Serialize;
 use crate::server::AppState;
 use crate::db;
+use crate::db::tables::{PoolInfo, V3Pool};
 use crate::route::BackendResponse;
 use crate::route::err::BackendError;
 
+/// A V2 pair or a V3 pool, with its price and liquidity when it is a V3 pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolListing {
+    #[serde(flatten)]
+    pub pool: PoolInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v3: Option<V3Pool>,
+}
+
+async fn get_pool_listings(rb: &rbatis::Rbatis) -> anyhow::Result<Vec<PoolListing>> {
+    let mut v3_pools: HashMap<String, V3Pool> = db::get_v3_pools(rb).await?.into_iter()
+        .map(|p| (p.pair_address.clone(), p))
+        .collect();
+    Ok(db::get_all_store_pools(rb).await?.into_iter().map(|pool| {
+        let v3 = v3_pools.remove(&pool.pair_address);
+        PoolListing { pool, v3 }
+    }).collect())
+}
+
+/// every pool in one response, deprecated in favour of the paged `/v1/pools`
 pub async fn get_all_pools(
     data: web::Data<AppState>,
     _req: HttpRequest,
 ) -> actix_web::Result<HttpResponse> {
     let rb = data.db.clone();
 
-    match db::get_all_store_pools(&rb).await {
+    match get_pool_listings(&rb).await {
         Ok(pools) => {
             let resp = BackendResponse {
                 code: BackendError::Ok,
                 error: None,
                 data: Some(pools)
             };
-            Ok(HttpResponse::Ok().json(resp))
+            Ok(HttpResponse::Ok()
+                .insert_header(("Deprecation", "true"))
+                .insert_header(("Link", "</v1/pools>; rel=\"successor-version\""))
+                .json(resp))
         },
         Err(e) => {
             log::warn!("get_all_pools from db failed,{:?}",e);
@@ -30,4 +56,4 @@ pub async fn get_all_pools(
         }
     }
 
-}
\ No newline at end of file
+}
@@ -1,7 +1,18 @@
 use crate::route::err::BackendError;
 use serde::Serialize;
 
+pub(crate) mod admin;
 pub(crate) mod get_all_pools;
+pub(crate) mod reconciliation_issues;
+pub(crate) mod failed_logs;
+pub(crate) mod pools;
+pub(crate) mod pool_events;
+pub(crate) mod candles;
+pub(crate) mod stats;
+pub(crate) mod accounts;
+pub(crate) mod tokens;
+pub(crate) mod quote;
+pub(crate) mod router;
 mod err;
 
 #[derive(Debug, Serialize, Clone)]
@@ -9,4 +20,15 @@ pub struct BackendResponse<T: Clone + Serialize> {
     pub code: BackendError,
     pub error: Option<String>,
     pub data: Option<T>
-}
\ No newline at end of file
+}
+
+/// an address in the form it is stored, lower case hex without `0x`, from one with or
+/// without `0x` in any case
+pub(crate) fn normalize_address(address: &str) -> Option<String> {
+    let address = address.strip_prefix("0x").or_else(|| address.strip_prefix("0X")).unwrap_or(address);
+    if address.len() == 40 && address.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(address.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
@@ -0,0 +1,329 @@
+use std::str::FromStr;
+use actix_web::{web, HttpRequest, HttpResponse};
+use rbatis::rbdc::decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use web3::types::U256;
+use crate::server::AppState;
+use crate::amm::units::parse_units;
+use crate::db;
+use crate::db::token_cache::TokenCache;
+use crate::db::listing::{self, Cursor, EventQuery, EVENT_TYPES};
+use crate::db::tables::Event;
+use crate::protocol::uniswap_v3;
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::err::BackendError;
+use crate::route::pools::{get_pool_tokens, Amount, LP_DECIMALS};
+
+#[derive(Debug, Deserialize)]
+pub struct PoolEventsQuery {
+    /// comma separated `swap`, `add` and `remove`, all of them by default
+    #[serde(rename = "type")]
+    pub event_type: Option<String>,
+    /// the sender, the recipient or the sender of the transaction
+    pub account: Option<String>,
+    pub from_block: Option<i64>,
+    pub to_block: Option<i64>,
+    /// unix seconds
+    pub from_time: Option<i64>,
+    pub to_time: Option<i64>,
+    /// in whole tokens
+    pub min_amount_x: Option<String>,
+    pub min_amount_y: Option<String>,
+    /// `asc` (default), oldest first, or `desc`
+    pub order: Option<String>,
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+/// A swap, add or remove with its amounts raw and in whole tokens.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolEvent {
+    pub tx_hash: String,
+    pub block_number: i64,
+    pub log_index: i64,
+    pub block_timestamp: Option<i64>,
+    /// `swap`, `add` or `remove`
+    pub event_type: String,
+    pub from_account: String,
+    pub to_account: Option<String>,
+    pub amount_x: Option<Amount>,
+    pub amount_y: Option<Amount>,
+    /// the LP tokens of an add or remove of a V2 pair
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lp_amount: Option<Amount>,
+    /// the liquidity of an add or remove of a V3 pool
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub liquidity: Option<String>,
+    pub amount0_in: Option<Amount>,
+    pub amount1_in: Option<Amount>,
+    pub amount0_out: Option<Amount>,
+    pub amount1_out: Option<Amount>,
+    pub direction: Option<String>,
+    pub flash: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolEventPage {
+    pub events: Vec<PoolEvent>,
+    /// `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// the event types of a comma separated list of their names
+pub(crate) fn parse_event_types(types: &Option<String>) -> Result<Vec<i8>, String> {
+    match types {
+        Some(types) => types.split(',').map(|name| {
+            let name = name.trim();
+            EVENT_TYPES.iter().find(|(n, _)| *n == name).map(|(_, t)| *t)
+                .ok_or_else(|| format!("unknown event type {}, expected some of {:?}", name,
+                                       EVENT_TYPES.map(|(n, _)| n)))
+        }).collect(),
+        None => Ok(vec![]),
+    }
+}
+
+pub(crate) fn parse_descending(order: &Option<String>) -> Result<bool, String> {
+    match order.as_deref() {
+        None | Some("asc") => Ok(false),
+        Some("desc") => Ok(true),
+        Some(order) => Err(format!("unknown order {}, expected asc or desc", order)),
+    }
+}
+
+/// the block number and log index cursor of an event page
+pub(crate) fn parse_event_cursor(cursor: &Option<String>) -> Result<Option<Cursor>, String> {
+    match cursor {
+        Some(cursor) => Ok(Some(Cursor::decode(cursor).ok()
+            .filter(|c| i64::from_str(&c.value).is_ok() && i64::from_str(&c.key).is_ok())
+            .ok_or_else(|| format!("invalid cursor {}", cursor))?)),
+        None => Ok(None),
+    }
+}
+
+/// the event query, without the minimum amounts that need the decimals of the tokens
+fn parse_query(pair_address: String, query: &PoolEventsQuery) -> Result<EventQuery, String> {
+    let account = match &query.account {
+        Some(account) => Some(normalize_address(account).ok_or_else(|| format!("invalid account address {}", account))?),
+        None => None,
+    };
+    Ok(EventQuery {
+        pair_address: Some(pair_address),
+        event_types: parse_event_types(&query.event_type)?,
+        account,
+        from_block: query.from_block,
+        to_block: query.to_block,
+        from_time: query.from_time,
+        to_time: query.to_time,
+        min_amount_x: None,
+        min_amount_y: None,
+        descending: parse_descending(&query.order)?,
+        after: parse_event_cursor(&query.cursor)?,
+        limit: query.limit.unwrap_or(100).clamp(1, 1000),
+    })
+}
+
+pub(crate) fn pool_event(event: Event, decimals_x: u8, decimals_y: u8, v3: bool) -> anyhow::Result<PoolEvent> {
+    let amount = |value: &Option<Decimal>, decimals: u8| -> anyhow::Result<Option<Amount>> {
+        match value {
+            Some(value) => Ok(Some(Amount::new(U256::from_dec_str(&value.0)?, decimals))),
+            None => Ok(None),
+        }
+    };
+    let event_type = EVENT_TYPES.iter().find(|(_, t)| *t == event.event_type)
+        .map(|(name, _)| name.to_string())
+        .unwrap_or_else(|| event.event_type.to_string());
+    let (lp_amount, liquidity) = if v3 {
+        (None, event.lp_amount.as_ref().map(|l| l.0.clone()))
+    } else {
+        (amount(&event.lp_amount, LP_DECIMALS)?, None)
+    };
+    Ok(PoolEvent {
+        amount_x: amount(&event.amount_x, decimals_x)?,
+        amount_y: amount(&event.amount_y, decimals_y)?,
+        amount0_in: amount(&event.amount0_in, decimals_x)?,
+        amount1_in: amount(&event.amount1_in, decimals_y)?,
+        amount0_out: amount(&event.amount0_out, decimals_x)?,
+        amount1_out: amount(&event.amount1_out, decimals_y)?,
+        tx_hash: event.tx_hash,
+        block_number: event.block_number.unwrap_or_default(),
+        log_index: event.log_index.unwrap_or_default(),
+        block_timestamp: event.block_timestamp,
+        event_type,
+        from_account: event.from_account,
+        to_account: event.to_account,
+        lp_amount,
+        liquidity,
+        direction: event.direction,
+        flash: event.flash,
+    })
+}
+
+enum PageError {
+    /// a minimum amount with more decimals than its token
+    InvalidAmount(String),
+    Db(anyhow::Error),
+}
+
+impl From<anyhow::Error> for PageError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+/// `None` when the pool isn't indexed
+async fn get_event_page(rb: &rbatis::Rbatis, tokens: &TokenCache, pair_address: &str, mut query: EventQuery, params: &PoolEventsQuery)
+    -> Result<Option<PoolEventPage>, PageError> {
+    let Some(pool) = db::get_pool(rb, pair_address).await? else {
+        return Ok(None);
+    };
+    let (token_x, token_y) = get_pool_tokens(rb, tokens, &pool).await?;
+    for (min, param, decimals, name) in [
+        (&mut query.min_amount_x, &params.min_amount_x, token_x.decimals, "min_amount_x"),
+        (&mut query.min_amount_y, &params.min_amount_y, token_y.decimals, "min_amount_y"),
+    ] {
+        if let Some(param) = param {
+            *min = Some(parse_units(param, decimals)
+                .ok_or_else(|| PageError::InvalidAmount(format!("invalid {} {}, the token has {} decimals",
+                                                                name, param, decimals)))?);
+        }
+    }
+    let (events, next) = listing::get_events(rb, &query).await?;
+    let v3 = pool.protocol == uniswap_v3::NAME;
+    let events = events.into_iter()
+        .map(|event| pool_event(event, token_x.decimals, token_y.decimals, v3))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Some(PoolEventPage { events, next_cursor: next.map(|c| c.encode()) }))
+}
+
+/// a page of the swaps, adds and removes of a pool in chain order
+pub async fn get_pool_events(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<PoolEventsQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let parsed = normalize_address(&path)
+        .ok_or_else(|| format!("invalid pool address {}", path))
+        .and_then(|address| Ok((parse_query(address.clone(), &query)?, address)));
+    let (event_query, pair_address) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_event_page(&rb, &data.token_cache, &pair_address, event_query, &query).await {
+        Ok(Some(page)) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(page)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Ok(None) => {
+            let resp = BackendResponse {
+                code: BackendError::NotFound,
+                error: Some(format!("pool {} not found", path)),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(PageError::InvalidAmount(e)) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(PageError::Db(e)) => {
+            log::warn!("get_pool_events from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get pool events failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::{test, App};
+    use web3::types::H160;
+    use crate::config::BackendConfig;
+    use crate::db::pool_graph::PoolGraph;
+    use crate::db::sqlite::new_test_db;
+    use crate::db::token_cache::TokenCache;
+    use crate::route::accounts::get_account_activity;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer};
+    use crate::watcher::watch::ChainWatcher;
+
+    #[actix_rt::test]
+    async fn test_v3_pool_events() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let v3_factory = H160::from_low_u64_be(0xfac3);
+        let token_x = chain.deploy_token("TKC", 18);
+        let token_y = chain.deploy_token("TKD", 6);
+        let pool = chain.create_v3_pool(v3_factory, token_x, token_y, 500, 10);
+        let trader = H160::from_low_u64_be(0xbbb);
+        chain.v3_initialize(pool, U256::from(2).pow(96.into()), 0);
+        chain.v3_mint(pool, trader, (-100, 100), 5000, (U256::exp10(18), U256::exp10(6)));
+        // 3 of token y in, 0.002 of token x out
+        chain.v3_swap(pool, trader, (-2_000_000_000_000_000, 3_000_000), U256::from(2).pow(95.into()), -150);
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("v3_pool_events_route").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            v3_factory_address: Some(v3_factory),
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config.clone(), db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        let state = AppState {
+            config,
+            db: db.clone(),
+            token_cache: TokenCache::start(db.clone(), 0).await.unwrap(),
+            pool_graph: PoolGraph::default(),
+        };
+        let app = test::init_service(App::new()
+            .app_data(web::Data::new(state))
+            .route("/v1/pools/{address}/events", web::get().to(get_pool_events))
+            .route("/v1/accounts/{address}/activity", web::get().to(get_account_activity))).await;
+        let get = |uri: String| test::TestRequest::get().uri(&uri).to_request();
+
+        let page: serde_json::Value = test::call_and_read_body_json(
+            &app, get(format!("/v1/pools/0x{}/events?type=swap", hex::encode(pool)))).await;
+        let swap = &page["data"]["events"][0];
+        assert_eq!(swap["amount_x"]["raw"], "2000000000000000");
+        assert_eq!(swap["amount_y"]["normalized"], "3");
+        assert_eq!(swap["amount0_out"]["raw"], "2000000000000000");
+        assert_eq!(swap["amount1_in"]["raw"], "3000000");
+        assert_eq!(swap["direction"], "y_to_x");
+
+        // the amount paid out of the pool is matched by a minimum
+        for (min_amount_x, count) in [("0.002", 1), ("0.0021", 0)] {
+            let page: serde_json::Value = test::call_and_read_body_json(
+                &app, get(format!("/v1/pools/0x{}/events?type=swap&min_amount_x={}", hex::encode(pool),
+                                  min_amount_x))).await;
+            assert_eq!(page["data"]["events"].as_array().unwrap().len(), count);
+        }
+
+        let page: serde_json::Value = test::call_and_read_body_json(
+            &app, get(format!("/v1/accounts/0x{}/activity", hex::encode(trader)))).await;
+        let events = page["data"]["events"].as_array().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["amount_x"]["raw"], "2000000000000000");
+    }
+}
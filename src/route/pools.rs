@@ -0,0 +1,374 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use rbatis::rbdc::decimal::Decimal;
+use web3::types::U256;
+use crate::config::BackendConfig;
+use crate::server::AppState;
+use crate::db;
+use crate::db::token_cache::TokenCache;
+use crate::db::listing::{self, Cursor, PoolQuery, PoolSort};
+use crate::db::pool_stats::{self, WindowStats};
+use crate::db::prices::USD_DECIMALS;
+use crate::db::tables::{PoolInfo, Token, V3Pool};
+use crate::amm::units::{format_units, mul_div, price, sqrt_price_prices};
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::err::BackendError;
+use crate::route::get_all_pools::PoolListing;
+use crate::watcher::token::{PLACEHOLDER_DECIMALS, STATUS_FAILED};
+
+/// the fields of a pool listing `fields` selects from
+const POOL_FIELDS: [&str; 16] = [
+    "pair_address", "token_x_symbol", "token_y_symbol", "token_x_address", "token_y_address",
+    "token_x_reserves", "token_y_reserves", "total_swap_count", "total_add_liq_count",
+    "total_rm_liq_count", "protocol", "factory_address", "lp_total_supply", "tvl_usd", "volume_usd", "v3",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct PoolsQuery {
+    /// a token address on either side
+    pub token: Option<String>,
+    /// a token symbol on either side, in any case
+    pub symbol: Option<String>,
+    pub factory: Option<String>,
+    pub protocol: Option<String>,
+    pub sort: Option<String>,
+    /// `asc` (default) or `desc`
+    pub order: Option<String>,
+    /// the `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+    /// comma separated fields of each pool, all of them by default
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolPage {
+    pub pools: Vec<serde_json::Value>,
+    /// `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+pub(crate) fn parse_query(query: &PoolsQuery) -> Result<(PoolQuery, Option<Vec<String>>), String> {
+    let address = |name: &str, value: &Option<String>| match value {
+        Some(value) => normalize_address(value).map(Some).ok_or_else(|| format!("invalid {} address {}", name, value)),
+        None => Ok(None),
+    };
+    let sort = match &query.sort {
+        Some(sort) => PoolSort::from_name(sort)
+            .ok_or_else(|| format!("unknown sort {}, expected one of {:?}", sort, PoolSort::NAMES))?,
+        None => PoolSort::default(),
+    };
+    let descending = match query.order.as_deref() {
+        None | Some("asc") => false,
+        Some("desc") => true,
+        Some(order) => return Err(format!("unknown order {}, expected asc or desc", order)),
+    };
+    let after = match &query.cursor {
+        Some(cursor) => Some(Cursor::decode(cursor).map_err(|_| format!("invalid cursor {}", cursor))?),
+        None => None,
+    };
+    let fields = match &query.fields {
+        Some(fields) => {
+            let fields: Vec<String> = fields.split(',').map(|f| f.trim().to_string()).collect();
+            if let Some(field) = fields.iter().find(|f| !POOL_FIELDS.contains(&f.as_str())) {
+                return Err(format!("unknown field {}, expected some of {:?}", field, POOL_FIELDS));
+            }
+            Some(fields)
+        }
+        None => None,
+    };
+    let pool_query = PoolQuery {
+        token: address("token", &query.token)?,
+        symbol: query.symbol.clone(),
+        factory: address("factory", &query.factory)?,
+        protocol: query.protocol.clone(),
+        sort,
+        descending,
+        after,
+        limit: query.limit.unwrap_or(100).clamp(1, 1000),
+    };
+    Ok((pool_query, fields))
+}
+
+pub(crate) async fn get_pool_page(rb: &rbatis::Rbatis, query: &PoolQuery, fields: Option<Vec<String>>) -> anyhow::Result<PoolPage> {
+    let (pools, next) = listing::get_pools(rb, query).await?;
+    let addresses: Vec<String> = pools.iter().map(|p| p.pair_address.clone()).collect();
+    let mut v3_pools: HashMap<String, _> = listing::get_v3_pools_of(rb, &addresses).await?.into_iter()
+        .map(|p| (p.pair_address.clone(), p))
+        .collect();
+    let pools = pools.into_iter().map(|pool| {
+        let v3 = v3_pools.remove(&pool.pair_address);
+        let mut listing = serde_json::to_value(PoolListing { pool, v3 })?;
+        if let (Some(fields), Some(listing)) = (&fields, listing.as_object_mut()) {
+            listing.retain(|key, _| fields.contains(key));
+        }
+        Ok(listing)
+    }).collect::<anyhow::Result<_>>()?;
+    Ok(PoolPage { pools, next_cursor: next.map(|c| c.encode()) })
+}
+
+/// a page of pools, `next_cursor` is passed as `cursor` for the page after it
+pub async fn list_pools(
+    data: web::Data<AppState>,
+    query: web::Query<PoolsQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let (pool_query, fields) = match parse_query(&query) {
+        Ok(query) => query,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_pool_page(&rb, &pool_query, fields).await {
+        Ok(page) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(page)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("list_pools from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get pools failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+/// the fee of a swap is taken from the amount in, in millionths
+const FEE_DENOMINATOR: u64 = 1_000_000;
+/// the decimals of the LP token of a V2 pair
+pub(crate) const LP_DECIMALS: u8 = 18;
+const DAY: i64 = 24 * 3600;
+
+/// An amount in raw units and in whole tokens.
+#[derive(Debug, Clone, Serialize)]
+pub struct Amount {
+    pub raw: String,
+    pub normalized: String,
+}
+
+impl Amount {
+    pub fn new(raw: U256, decimals: u8) -> Self {
+        Self { raw: raw.to_string(), normalized: format_units(raw, decimals) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolToken {
+    pub address: String,
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u8,
+    pub metadata_status: String,
+    pub reserve: Amount,
+}
+
+/// Swap volume and fees, by token, and event counts of a pool over a window.
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolWindow {
+    pub volume_x: Amount,
+    pub volume_y: Amount,
+    pub fees_x: Amount,
+    pub fees_y: Amount,
+    pub swap_count: i64,
+    pub add_liq_count: i64,
+    pub rm_liq_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolDetail {
+    pub pair_address: String,
+    pub protocol: String,
+    pub factory_address: Option<String>,
+    pub token_x: PoolToken,
+    pub token_y: PoolToken,
+    /// the price of one token x in token y, `None` without liquidity
+    pub price_x_in_y: Option<String>,
+    pub price_y_in_x: Option<String>,
+    /// `None` for pools without an LP token
+    pub lp_total_supply: Option<Amount>,
+    /// the share of the amount in a swap pays, e.g. `0.003`
+    pub fee_rate: String,
+    /// the reserves in usd, `None` until a token of the pool has a usd price
+    pub tvl_usd: Option<String>,
+    /// every swap in usd at the prices when it was stored
+    pub volume_usd: Option<String>,
+    pub total_swap_count: i64,
+    pub total_add_liq_count: i64,
+    pub total_rm_liq_count: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub v3: Option<V3Pool>,
+    pub last_24h: PoolWindow,
+    pub last_7d: PoolWindow,
+}
+
+async fn get_pool_token(rb: &rbatis::Rbatis, tokens: &TokenCache, address: &str, symbol: &str, reserve: &Decimal)
+    -> anyhow::Result<PoolToken> {
+    // a token is stored with its pool, even when its metadata calls fail
+    let token = tokens.load(rb, address).await?.unwrap_or_else(|| Token {
+        address: address.to_string(),
+        symbol: symbol.to_string(),
+        decimals: PLACEHOLDER_DECIMALS,
+        name: String::new(),
+        total_supply: None,
+        metadata_status: STATUS_FAILED.to_string(),
+        metadata_attempts: 0,
+    });
+    Ok(PoolToken {
+        reserve: Amount::new(U256::from_dec_str(&reserve.0)?, token.decimals),
+        address: token.address,
+        symbol: token.symbol,
+        name: token.name,
+        decimals: token.decimals,
+        metadata_status: token.metadata_status,
+    })
+}
+
+/// the tokens of a pool with their reserves
+pub(crate) async fn get_pool_tokens(rb: &rbatis::Rbatis, tokens: &TokenCache, pool: &PoolInfo)
+    -> anyhow::Result<(PoolToken, PoolToken)> {
+    Ok((get_pool_token(rb, tokens, &pool.token_x_address, &pool.token_x_symbol, &pool.token_x_reserves).await?,
+        get_pool_token(rb, tokens, &pool.token_y_address, &pool.token_y_symbol, &pool.token_y_reserves).await?))
+}
+
+fn pool_window(stats: &WindowStats, fee: U256, decimals_x: u8, decimals_y: u8) -> PoolWindow {
+    let fees = |amount_in: U256| mul_div(amount_in, fee, FEE_DENOMINATOR.into()).unwrap_or_default();
+    PoolWindow {
+        volume_x: Amount::new(stats.amount0_in.saturating_add(stats.amount0_out), decimals_x),
+        volume_y: Amount::new(stats.amount1_in.saturating_add(stats.amount1_out), decimals_y),
+        fees_x: Amount::new(fees(stats.amount0_in), decimals_x),
+        fees_y: Amount::new(fees(stats.amount1_in), decimals_y),
+        swap_count: stats.swap_count,
+        add_liq_count: stats.add_liq_count,
+        rm_liq_count: stats.rm_liq_count,
+    }
+}
+
+/// the prices of one token x in token y and of one token y in token x, from the reserves of
+/// a V2 pair and from the square root price of a V3 pool, `None` without liquidity
+pub(crate) fn pool_prices(pool: &PoolInfo, v3: Option<&V3Pool>, decimals_x: u8, decimals_y: u8)
+    -> anyhow::Result<(Option<String>, Option<String>)> {
+    Ok(match v3 {
+        Some(v3) => {
+            let sqrt_price = U256::from_dec_str(&v3.sqrt_price_x96.0)?;
+            if sqrt_price.is_zero() {
+                (None, None)
+            } else {
+                sqrt_price_prices(sqrt_price, decimals_x, decimals_y).unzip()
+            }
+        }
+        None => {
+            let reserve_x = U256::from_dec_str(&pool.token_x_reserves.0)?;
+            let reserve_y = U256::from_dec_str(&pool.token_y_reserves.0)?;
+            (price(reserve_x, decimals_x, reserve_y, decimals_y), price(reserve_y, decimals_y, reserve_x, decimals_x))
+        }
+    })
+}
+
+/// a stored usd value in dollars
+pub(crate) fn usd(value: &Decimal) -> anyhow::Result<String> {
+    Ok(format_units(U256::from_dec_str(&value.0)?, USD_DECIMALS))
+}
+
+async fn get_pool_detail(rb: &rbatis::Rbatis, tokens: &TokenCache, config: &BackendConfig, address: &str)
+    -> anyhow::Result<Option<PoolDetail>> {
+    let Some(pool) = db::get_pool(rb, address).await? else {
+        return Ok(None);
+    };
+    let PoolInfo { pair_address, protocol, factory_address, lp_total_supply, tvl_usd, volume_usd, .. } = pool.clone();
+    let (token_x, token_y) = get_pool_tokens(rb, tokens, &pool).await?;
+    let v3 = listing::get_v3_pools_of(rb, std::slice::from_ref(&pair_address)).await?.into_iter().next();
+
+    let (price_x_in_y, price_y_in_x) = pool_prices(&pool, v3.as_ref(), token_x.decimals, token_y.decimals)?;
+    let fee = match &v3 {
+        Some(v3) => U256::from(v3.fee),
+        None => U256::from(config.v2_fee_bps(factory_address.as_deref()) * 100),
+    };
+    let lp_total_supply = match lp_total_supply {
+        Some(supply) => Some(Amount::new(U256::from_dec_str(&supply.0)?, LP_DECIMALS)),
+        None => None,
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let last_24h = pool_stats::get_window_stats(rb, &pair_address, now - DAY).await?;
+    let last_7d = pool_stats::get_window_stats(rb, &pair_address, now - 7 * DAY).await?;
+    Ok(Some(PoolDetail {
+        last_24h: pool_window(&last_24h, fee, token_x.decimals, token_y.decimals),
+        last_7d: pool_window(&last_7d, fee, token_x.decimals, token_y.decimals),
+        pair_address,
+        protocol,
+        factory_address,
+        token_x,
+        token_y,
+        price_x_in_y,
+        price_y_in_x,
+        lp_total_supply,
+        fee_rate: format_units(fee, 6),
+        tvl_usd: tvl_usd.map(|v| usd(&v)).transpose()?,
+        volume_usd: volume_usd.map(|v| usd(&v)).transpose()?,
+        total_swap_count: pool.total_swap_count,
+        total_add_liq_count: pool.total_add_liq_count,
+        total_rm_liq_count: pool.total_rm_liq_count,
+        v3,
+    }))
+}
+
+/// a pool with the metadata of its tokens and its metrics, by its address with or without `0x`
+pub async fn get_pool(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let Some(address) = normalize_address(&path) else {
+        let resp = BackendResponse {
+            code: BackendError::InvalidParameters,
+            error: Some(format!("invalid pool address {}", path)),
+            data: None::<()>,
+        };
+        return Ok(HttpResponse::Ok().json(resp));
+    };
+
+    match get_pool_detail(&rb, &data.token_cache, &data.config, &address).await {
+        Ok(Some(pool)) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(pool)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Ok(None) => {
+            let resp = BackendResponse {
+                code: BackendError::NotFound,
+                error: Some(format!("pool {} not found", path)),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(e) => {
+            log::warn!("get_pool from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get pool failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use web3::types::U256;
+use crate::server::AppState;
+use crate::config::BackendConfig;
+use crate::db::token_cache::TokenCache;
+use crate::db::listing;
+use crate::db::tables::{PoolInfo, Token};
+use crate::amm::quote::{self, Hop, Quote};
+use crate::amm::units::{format_units, parse_units, price, PRICE_DECIMALS};
+use crate::protocol::uniswap_v2;
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::candles::CandleToken;
+use crate::route::err::BackendError;
+use crate::route::pools::Amount;
+use crate::watcher::token::STATUS_FAILED;
+
+/// the slippage of a quote when `slippage_bps` isn't set, 0.5%
+const DEFAULT_SLIPPAGE_BPS: u64 = 50;
+const MAX_SLIPPAGE_BPS: u64 = 5000;
+
+#[derive(Debug, Deserialize)]
+pub struct QuoteQuery {
+    pub token_in: String,
+    pub token_out: String,
+    /// whole tokens sent, e.g. `1.5`, or `amount_out` received
+    pub amount_in: Option<String>,
+    pub amount_out: Option<String>,
+    pub slippage_bps: Option<u64>,
+}
+
+/// The parsed parameters of a quote request.
+pub(crate) struct QuoteRequest {
+    pub token_in: String,
+    pub token_out: String,
+    /// whole tokens, and whether they are sent or received
+    pub amount: String,
+    pub exact_in: bool,
+    pub slippage_bps: u64,
+}
+
+pub(crate) fn parse_query(token_in: &str, token_out: &str, amount_in: &Option<String>, amount_out: &Option<String>,
+                          slippage_bps: Option<u64>) -> Result<QuoteRequest, String> {
+    let token_in = normalize_address(token_in).ok_or_else(|| format!("invalid token_in address {}", token_in))?;
+    let token_out = normalize_address(token_out).ok_or_else(|| format!("invalid token_out address {}", token_out))?;
+    if token_in == token_out {
+        return Err(format!("token_in and token_out are both {}", token_in));
+    }
+    let (amount, exact_in) = match (amount_in, amount_out) {
+        (Some(amount), None) => (amount.clone(), true),
+        (None, Some(amount)) => (amount.clone(), false),
+        _ => return Err("expected one of amount_in and amount_out".to_string()),
+    };
+    let slippage_bps = slippage_bps.unwrap_or(DEFAULT_SLIPPAGE_BPS);
+    if slippage_bps > MAX_SLIPPAGE_BPS {
+        return Err(format!("slippage_bps {} above {}", slippage_bps, MAX_SLIPPAGE_BPS));
+    }
+    Ok(QuoteRequest { token_in, token_out, amount, exact_in, slippage_bps })
+}
+
+/// A swap through one V2 pair at its stored reserves.
+#[derive(Debug, Clone, Serialize)]
+pub struct SwapQuote {
+    pub pair_address: String,
+    pub factory_address: Option<String>,
+    pub fee_rate: String,
+    pub token_in: CandleToken,
+    pub token_out: CandleToken,
+    /// `exact_in` or `exact_out`, the other amount is quoted
+    pub kind: String,
+    pub amount_in: Amount,
+    pub amount_out: Amount,
+    /// the price of one token in in tokens out at the reserves, and the one of the swap
+    pub spot_price: String,
+    pub execution_price: String,
+    /// the output short of the one at the spot price after the fee, 0.01 is 1%
+    pub price_impact: String,
+    pub slippage_bps: u64,
+    /// the amount out when the price moves by the slippage, and the amount in of an exact out swap
+    pub minimum_amount_out: Amount,
+    pub maximum_amount_in: Amount,
+}
+
+pub(crate) enum QuoteError {
+    /// an amount the token decimals can't hold, or one the pools can't fill
+    InvalidAmount(String),
+    /// a token or a pair that isn't indexed
+    NotFound(String),
+    Db(anyhow::Error),
+}
+
+impl From<anyhow::Error> for QuoteError {
+    fn from(e: anyhow::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+/// a token with known decimals, amounts of the others can't be parsed
+pub(crate) async fn get_quote_token(rb: &rbatis::Rbatis, tokens: &TokenCache, address: &str) -> Result<Token, QuoteError> {
+    let token = tokens.load(rb, address).await?
+        .ok_or_else(|| QuoteError::NotFound(format!("token {} not found", address)))?;
+    if token.metadata_status == STATUS_FAILED {
+        return Err(QuoteError::InvalidAmount(format!("the decimals of token {} are unknown", address)));
+    }
+    Ok(token)
+}
+
+/// the reserves of a V2 pair in the direction from `token_in` and its fee
+pub(crate) fn pair_hop(config: &BackendConfig, pool: &PoolInfo, token_in: &str) -> anyhow::Result<Hop> {
+    let reserve_x = U256::from_dec_str(&pool.token_x_reserves.0)?;
+    let reserve_y = U256::from_dec_str(&pool.token_y_reserves.0)?;
+    let (reserve_in, reserve_out) = if pool.token_x_address == token_in {
+        (reserve_x, reserve_y)
+    } else {
+        (reserve_y, reserve_x)
+    };
+    Ok(Hop { reserve_in, reserve_out, fee_bps: config.v2_fee_bps(pool.factory_address.as_deref()) })
+}
+
+/// the amount in and out of a quote and their limits at the slippage
+pub(crate) fn quote_limits(quote: &Quote, exact_in: bool, slippage_bps: u64) -> anyhow::Result<(U256, U256)> {
+    Ok(if exact_in {
+        (quote.amount_in(), quote::minimum_amount_out(quote.amount_out(), slippage_bps)?)
+    } else {
+        (quote::maximum_amount_in(quote.amount_in(), slippage_bps)?, quote.amount_out())
+    })
+}
+
+/// the quote of the pair between the tokens giving the most out, or taking the least in
+async fn get_swap_quote(rb: &rbatis::Rbatis, tokens: &TokenCache, config: &BackendConfig, request: QuoteRequest)
+    -> Result<SwapQuote, QuoteError> {
+    let token_in = get_quote_token(rb, tokens, &request.token_in).await?;
+    let token_out = get_quote_token(rb, tokens, &request.token_out).await?;
+    let decimals = if request.exact_in { token_in.decimals } else { token_out.decimals };
+    let amount = parse_units(&request.amount, decimals)
+        .filter(|amount| !amount.is_zero())
+        .ok_or_else(|| QuoteError::InvalidAmount(format!("invalid amount {}, the token has {} decimals",
+                                                         request.amount, decimals)))?;
+
+    let pools = listing::get_pools_between(rb, &token_in.address, &token_out.address, uniswap_v2::NAME).await?;
+    if pools.is_empty() {
+        return Err(QuoteError::NotFound(format!("no pair between {} and {}", token_in.address, token_out.address)));
+    }
+    let mut best: Option<(PoolInfo, Hop, Quote)> = None;
+    let mut last_error = None;
+    for pool in pools {
+        let hop = pair_hop(config, &pool, &token_in.address)?;
+        let quote = if request.exact_in {
+            quote::quote_exact_in(amount, &[hop])
+        } else {
+            quote::quote_exact_out(amount, &[hop])
+        };
+        match quote {
+            Ok(quote) => {
+                let better = best.as_ref().is_none_or(|(_, _, b)| if request.exact_in {
+                    quote.amount_out() > b.amount_out()
+                } else {
+                    quote.amount_in() < b.amount_in()
+                });
+                if better {
+                    best = Some((pool, hop, quote));
+                }
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    let Some((pool, hop, quote)) = best else {
+        let e = last_error.map(|e| e.to_string()).unwrap_or_default();
+        return Err(QuoteError::InvalidAmount(format!("no pair can fill {}: {}", request.amount, e)));
+    };
+
+    let (decimals_in, decimals_out) = (token_in.decimals, token_out.decimals);
+    let (maximum_amount_in, minimum_amount_out) = quote_limits(&quote, request.exact_in, request.slippage_bps)?;
+    Ok(SwapQuote {
+        pair_address: pool.pair_address,
+        factory_address: pool.factory_address,
+        fee_rate: format_units(U256::from(hop.fee_bps), 4),
+        kind: if request.exact_in { "exact_in" } else { "exact_out" }.to_string(),
+        amount_in: Amount::new(quote.amount_in(), decimals_in),
+        amount_out: Amount::new(quote.amount_out(), decimals_out),
+        spot_price: price(hop.reserve_in, decimals_in, hop.reserve_out, decimals_out).unwrap_or_default(),
+        execution_price: price(quote.amount_in(), decimals_in, quote.amount_out(), decimals_out).unwrap_or_default(),
+        price_impact: format_units(quote.price_impact, PRICE_DECIMALS),
+        slippage_bps: request.slippage_bps,
+        minimum_amount_out: Amount::new(minimum_amount_out, decimals_out),
+        maximum_amount_in: Amount::new(maximum_amount_in, decimals_in),
+        token_in: CandleToken { address: token_in.address, symbol: token_in.symbol, decimals: decimals_in },
+        token_out: CandleToken { address: token_out.address, symbol: token_out.symbol, decimals: decimals_out },
+    })
+}
+
+/// the amount out of a swap of an exact amount in through the best V2 pair, or the amount in of
+/// an exact amount out, at the stored reserves
+pub async fn get_quote(
+    data: web::Data<AppState>,
+    query: web::Query<QuoteQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let request = match parse_query(&query.token_in, &query.token_out, &query.amount_in, &query.amount_out,
+                                    query.slippage_bps) {
+        Ok(request) => request,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_swap_quote(&rb, &data.token_cache, &data.config, request).await {
+        Ok(quote) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(quote)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(QuoteError::InvalidAmount(e)) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(QuoteError::NotFound(e)) => {
+            let resp = BackendResponse {
+                code: BackendError::NotFound,
+                error: Some(e),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(QuoteError::Db(e)) => {
+            log::warn!("get_quote from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get quote failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use crate::server::AppState;
+use crate::db;
+use crate::route::BackendResponse;
+use crate::route::err::BackendError;
+
+#[derive(Debug, Deserialize)]
+pub struct IssuesQuery {
+    pub limit: Option<u64>,
+}
+
+pub async fn get_reconciliation_issues(
+    data: web::Data<AppState>,
+    query: web::Query<IssuesQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let limit = query.limit.unwrap_or(100).min(1000);
+
+    match db::get_reconciliation_issues(&rb, limit).await {
+        Ok(issues) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(issues)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("get_reconciliation_issues from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get reconciliation issues failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
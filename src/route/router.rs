@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::format_err;
+use ethabi::{ParamType, Token as AbiToken};
+use serde::{Deserialize, Serialize};
+use web3::types::{H160, U256};
+use crate::server::AppState;
+use crate::amm::quote;
+use crate::amm::router::{Route, RouteSplit};
+use crate::amm::units::{format_units, parse_units, price, PRICE_DECIMALS};
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::candles::CandleToken;
+use crate::route::err::BackendError;
+use crate::route::pools::Amount;
+use crate::route::quote::{get_quote_token, parse_query, QuoteError, QuoteRequest};
+use crate::watcher::token::PLACEHOLDER_DECIMALS;
+
+const DEFAULT_MAX_HOPS: usize = 3;
+const MAX_HOPS: usize = 4;
+const MAX_ROUTES: usize = 4;
+/// the deadline of the router calls when `deadline` isn't set, from now
+const DEFAULT_DEADLINE_SECS: u64 = 1200;
+/// `UniswapV2Router02.swapExactTokensForTokens`
+const SWAP_FUNCTION: &str = "swapExactTokensForTokens";
+
+#[derive(Debug, Deserialize)]
+pub struct RouteQuery {
+    pub token_in: String,
+    pub token_out: String,
+    /// whole tokens sent, e.g. `1.5`
+    pub amount_in: Option<String>,
+    /// the most pairs of a route, 3 by default
+    pub max_hops: Option<usize>,
+    /// the most routes the input is split through, 1 (no split) by default
+    pub max_routes: Option<usize>,
+    pub slippage_bps: Option<u64>,
+    /// the receiver of the output, the router calldata is only encoded with it
+    pub recipient: Option<String>,
+    /// unix seconds
+    pub deadline: Option<u64>,
+}
+
+/// The parsed parameters of a route request.
+struct RouteRequest {
+    quote: QuoteRequest,
+    max_hops: usize,
+    max_routes: usize,
+    recipient: Option<H160>,
+    deadline: u64,
+}
+
+fn parse_route_query(query: &RouteQuery) -> Result<RouteRequest, String> {
+    if query.amount_in.is_none() {
+        return Err("amount_in is required".to_string());
+    }
+    let quote = parse_query(&query.token_in, &query.token_out, &query.amount_in, &None, query.slippage_bps)?;
+    let max_hops = query.max_hops.unwrap_or(DEFAULT_MAX_HOPS);
+    if !(1..=MAX_HOPS).contains(&max_hops) {
+        return Err(format!("max_hops {} isn't between 1 and {}", max_hops, MAX_HOPS));
+    }
+    let max_routes = query.max_routes.unwrap_or(1);
+    if !(1..=MAX_ROUTES).contains(&max_routes) {
+        return Err(format!("max_routes {} isn't between 1 and {}", max_routes, MAX_ROUTES));
+    }
+    let recipient = match &query.recipient {
+        Some(recipient) => Some(normalize_address(recipient)
+            .and_then(|a| H160::from_str(&a).ok())
+            .ok_or_else(|| format!("invalid recipient address {}", recipient))?),
+        None => None,
+    };
+    let deadline = match query.deadline {
+        Some(deadline) => deadline,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.as_secs()
+            + DEFAULT_DEADLINE_SECS,
+    };
+    Ok(RouteRequest { quote, max_hops, max_routes, recipient, deadline })
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteHop {
+    pub pair_address: String,
+    pub token_in: String,
+    pub token_out: String,
+    pub fee_rate: String,
+    pub amount_in: Amount,
+    pub amount_out: Amount,
+}
+
+/// The arguments of `swapExactTokensForTokens` on the router of the factory of a route.
+#[derive(Debug, Clone, Serialize)]
+pub struct RouterCall {
+    pub function: String,
+    /// raw amounts
+    pub amount_in: String,
+    pub amount_out_min: String,
+    /// `0x` addresses of the tokens from the input to the output
+    pub path: Vec<String>,
+    pub to: Option<String>,
+    pub deadline: u64,
+    /// the encoded call, with a recipient
+    pub calldata: Option<String>,
+}
+
+/// A swap through the pairs of one factory.
+#[derive(Debug, Clone, Serialize)]
+pub struct SwapRoute {
+    pub factory_address: Option<String>,
+    pub amount_in: Amount,
+    pub amount_out: Amount,
+    pub minimum_amount_out: Amount,
+    pub price_impact: String,
+    pub hops: Vec<RouteHop>,
+    pub router_call: RouterCall,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutePlan {
+    pub token_in: CandleToken,
+    pub token_out: CandleToken,
+    pub amount_in: Amount,
+    /// the sums of the routes
+    pub amount_out: Amount,
+    pub minimum_amount_out: Amount,
+    pub execution_price: String,
+    /// the output short of the one at the spot prices of the routes, 0.01 is 1%
+    pub price_impact: String,
+    pub slippage_bps: u64,
+    /// swapped one after the other, each one from the reserves the ones before it left
+    pub routes: Vec<SwapRoute>,
+}
+
+fn router_call(route: &Route, amount_out_min: U256, recipient: Option<H160>, deadline: u64) -> anyhow::Result<RouterCall> {
+    let path = route.tokens.iter()
+        .map(|t| H160::from_str(t).map_err(|e| format_err!("invalid token address {} in the route, {}", t, e)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let amount_in = route.quote.amount_in();
+    let calldata = recipient.map(|to| {
+        let params = [ParamType::Uint(256), ParamType::Uint(256), ParamType::Array(Box::new(ParamType::Address)),
+            ParamType::Address, ParamType::Uint(256)];
+        let mut data = ethabi::short_signature(SWAP_FUNCTION, &params).to_vec();
+        data.extend(ethabi::encode(&[
+            AbiToken::Uint(amount_in),
+            AbiToken::Uint(amount_out_min),
+            AbiToken::Array(path.iter().map(|t| AbiToken::Address(*t)).collect()),
+            AbiToken::Address(to),
+            AbiToken::Uint(deadline.into()),
+        ]));
+        format!("0x{}", hex::encode(data))
+    });
+    Ok(RouterCall {
+        function: SWAP_FUNCTION.to_string(),
+        amount_in: amount_in.to_string(),
+        amount_out_min: amount_out_min.to_string(),
+        path: path.iter().map(|t| format!("{:?}", t)).collect(),
+        to: recipient.map(|to| format!("{:?}", to)),
+        deadline,
+        calldata,
+    })
+}
+
+/// a route and its minimum amount out
+fn swap_route(route: Route, decimals: &HashMap<String, u8>, request: &RouteRequest) -> anyhow::Result<(SwapRoute, U256)> {
+    let decimals_of = |token: &str| decimals.get(token).copied().unwrap_or(PLACEHOLDER_DECIMALS);
+    let (token_in, token_out) = (&route.tokens[0], &route.tokens[route.tokens.len() - 1]);
+    let minimum_amount_out = quote::minimum_amount_out(route.quote.amount_out(), request.quote.slippage_bps)?;
+    let hops = route.pairs.iter().enumerate().map(|(k, pair)| RouteHop {
+        pair_address: pair.pair_address.clone(),
+        token_in: route.tokens[k].clone(),
+        token_out: route.tokens[k + 1].clone(),
+        fee_rate: format_units(U256::from(pair.fee_bps), 4),
+        amount_in: Amount::new(route.quote.amounts[k], decimals_of(&route.tokens[k])),
+        amount_out: Amount::new(route.quote.amounts[k + 1], decimals_of(&route.tokens[k + 1])),
+    }).collect();
+    Ok((SwapRoute {
+        factory_address: route.pairs[0].factory_address.clone(),
+        amount_in: Amount::new(route.quote.amount_in(), decimals_of(token_in)),
+        amount_out: Amount::new(route.quote.amount_out(), decimals_of(token_out)),
+        minimum_amount_out: Amount::new(minimum_amount_out, decimals_of(token_out)),
+        price_impact: format_units(route.quote.price_impact, PRICE_DECIMALS),
+        router_call: router_call(&route, minimum_amount_out, request.recipient, request.deadline)?,
+        hops,
+    }, minimum_amount_out))
+}
+
+/// the best route between the tokens in the pool graph
+async fn get_route_plan(data: &AppState, request: RouteRequest) -> Result<RoutePlan, QuoteError> {
+    let rb = &data.db;
+    let token_in = get_quote_token(rb, &data.token_cache, &request.quote.token_in).await?;
+    let token_out = get_quote_token(rb, &data.token_cache, &request.quote.token_out).await?;
+    let amount_in = parse_units(&request.quote.amount, token_in.decimals)
+        .filter(|amount| !amount.is_zero())
+        .ok_or_else(|| QuoteError::InvalidAmount(format!("invalid amount {}, the token has {} decimals",
+                                                         request.quote.amount, token_in.decimals)))?;
+
+    let split: Option<RouteSplit> = data.pool_graph.read()
+        .best_route(&token_in.address, &token_out.address, amount_in, request.max_hops, request.max_routes);
+    let Some(split) = split else {
+        return Err(QuoteError::NotFound(format!("no route of up to {} pairs from {} to {} can fill {}",
+                                                request.max_hops, token_in.address, token_out.address,
+                                                request.quote.amount)));
+    };
+
+    let mut decimals = HashMap::from([(token_in.address.clone(), token_in.decimals),
+                                      (token_out.address.clone(), token_out.decimals)]);
+    for token in split.routes.iter().flat_map(|r| &r.tokens) {
+        if !decimals.contains_key(token) {
+            if let Some(stored) = data.token_cache.load(rb, token).await? {
+                decimals.insert(token.clone(), stored.decimals);
+            }
+        }
+    }
+    let (routes, minimums): (Vec<_>, Vec<_>) = split.routes.into_iter()
+        .map(|route| swap_route(route, &decimals, &request))
+        .collect::<anyhow::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+    let minimum_amount_out = minimums.into_iter().fold(U256::zero(), |sum, m| sum + m);
+
+    let (decimals_in, decimals_out) = (token_in.decimals, token_out.decimals);
+    Ok(RoutePlan {
+        amount_in: Amount::new(amount_in, decimals_in),
+        amount_out: Amount::new(split.amount_out, decimals_out),
+        minimum_amount_out: Amount::new(minimum_amount_out, decimals_out),
+        execution_price: price(amount_in, decimals_in, split.amount_out, decimals_out).unwrap_or_default(),
+        price_impact: format_units(split.price_impact, PRICE_DECIMALS),
+        slippage_bps: request.quote.slippage_bps,
+        routes,
+        token_in: CandleToken { address: token_in.address, symbol: token_in.symbol, decimals: decimals_in },
+        token_out: CandleToken { address: token_out.address, symbol: token_out.symbol, decimals: decimals_out },
+    })
+}
+
+/// the route through the indexed V2 pairs giving the most for an exact amount in, optionally
+/// split, with the router call of each of its swaps
+pub async fn get_route(
+    data: web::Data<AppState>,
+    query: web::Query<RouteQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let request = match parse_route_query(&query) {
+        Ok(request) => request,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_route_plan(&data, request).await {
+        Ok(plan) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(plan)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(QuoteError::InvalidAmount(e)) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(QuoteError::NotFound(e)) => {
+            let resp = BackendResponse {
+                code: BackendError::NotFound,
+                error: Some(e),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(QuoteError::Db(e)) => {
+            log::warn!("get_route from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get route failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use actix_web::test::{call_and_read_body_json, init_service, TestRequest};
+    use actix_web::App;
+    use crate::config::BackendConfig;
+    use crate::db::pool_graph::PoolGraph;
+    use crate::db::sqlite::new_test_db;
+    use crate::db::token_cache::TokenCache;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer};
+    use crate::watcher::watch::ChainWatcher;
+
+    fn query(amount_in: Option<&str>) -> RouteQuery {
+        RouteQuery {
+            token_in: format!("0x{}", "a".repeat(40)),
+            token_out: "B".repeat(40),
+            amount_in: amount_in.map(|a| a.to_string()),
+            max_hops: None,
+            max_routes: None,
+            slippage_bps: None,
+            recipient: None,
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_route_query() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let request = parse_route_query(&query(Some("1.5"))).unwrap();
+        assert_eq!(request.quote.token_out, "b".repeat(40));
+        assert_eq!((request.max_hops, request.max_routes, request.recipient), (DEFAULT_MAX_HOPS, 1, None));
+        assert!((now + DEFAULT_DEADLINE_SECS..=now + DEFAULT_DEADLINE_SECS + 1).contains(&request.deadline));
+
+        let request = parse_route_query(&RouteQuery {
+            max_hops: Some(MAX_HOPS),
+            max_routes: Some(MAX_ROUTES),
+            recipient: Some(format!("0x{}", "C".repeat(40))),
+            deadline: Some(42),
+            ..query(Some("1"))
+        }).unwrap();
+        assert_eq!((request.max_hops, request.max_routes, request.deadline), (MAX_HOPS, MAX_ROUTES, 42));
+        assert_eq!(request.recipient, Some(H160::repeat_byte(0xcc)));
+
+        let invalid = [
+            query(None),
+            RouteQuery { max_hops: Some(0), ..query(Some("1")) },
+            RouteQuery { max_hops: Some(MAX_HOPS + 1), ..query(Some("1")) },
+            RouteQuery { max_routes: Some(MAX_ROUTES + 1), ..query(Some("1")) },
+            RouteQuery { recipient: Some("0x1234".to_string()), ..query(Some("1")) },
+            RouteQuery { token_out: format!("0x{}", "a".repeat(40)), ..query(Some("1")) },
+        ];
+        for query in invalid {
+            assert!(parse_route_query(&query).is_err(), "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn test_router_call_invalid_token() {
+        let mut graph = crate::amm::router::PairGraph::default();
+        graph.upsert(crate::amm::router::GraphPair {
+            pair_address: "p".to_string(),
+            factory_address: None,
+            token_x: "a".repeat(40),
+            token_y: "not an address".to_string(),
+            reserve_x: U256::exp10(21),
+            reserve_y: U256::exp10(21),
+            fee_bps: crate::amm::v2::FEE_BPS,
+        });
+        let route = graph.best_route(&"a".repeat(40), "not an address", U256::exp10(18), 1, 1).unwrap()
+            .routes.remove(0);
+        let e = router_call(&route, U256::one(), None, 0).unwrap_err();
+        assert!(e.to_string().contains("invalid token address not an address"));
+    }
+
+    /// decode the `swapExactTokensForTokens` arguments of router calldata
+    fn decode_calldata(calldata: &str) -> Vec<AbiToken> {
+        let data = hex::decode(calldata.strip_prefix("0x").unwrap()).unwrap();
+        let params = [ParamType::Uint(256), ParamType::Uint(256), ParamType::Array(Box::new(ParamType::Address)),
+            ParamType::Address, ParamType::Uint(256)];
+        assert_eq!(data[..4], ethabi::short_signature(SWAP_FUNCTION, &params));
+        ethabi::decode(&params, &data[4..]).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_get_route() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let token_a = chain.deploy_token("TKA", 18);
+        let token_b = chain.deploy_token("TKB", 18);
+        let token_c = chain.deploy_token("TKC", 18);
+        let lp = H160::from_low_u64_be(0xaaa);
+        // a direct pair, and a path through b, both as deep
+        for (x, y) in [(token_a, token_c), (token_a, token_b), (token_b, token_c)] {
+            let pair = chain.create_pair(x, y);
+            chain.mint(pair, lp, U256::exp10(21), U256::exp10(21));
+        }
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("get_route").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config.clone(), db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        let state = AppState {
+            pool_graph: PoolGraph::load(&db, &config).await.unwrap(),
+            config,
+            db: db.clone(),
+            token_cache: TokenCache::start(db.clone(), 0).await.unwrap(),
+        };
+        let app = init_service(App::new()
+            .app_data(web::Data::new(state))
+            .route("/v1/route", web::get().to(get_route))).await;
+        let recipient = H160::from_low_u64_be(0xbbb);
+        let uri = |max_routes: usize| format!(
+            "/v1/route?token_in=0x{}&token_out=0x{}&amount_in=100&max_routes={}&recipient={:?}&deadline=1800000000",
+            hex::encode(token_a), hex::encode(token_c), max_routes, recipient);
+
+        let plan: serde_json::Value = call_and_read_body_json(
+            &app, TestRequest::get().uri(&uri(1)).to_request()).await;
+        let routes = plan["data"]["routes"].as_array().unwrap();
+        assert_eq!(routes.len(), 1);
+        let call = &routes[0]["router_call"];
+        assert_eq!(call["path"], serde_json::json!([format!("{:?}", token_a), format!("{:?}", token_c)]));
+        let args = decode_calldata(call["calldata"].as_str().unwrap());
+        assert_eq!(args[0], AbiToken::Uint(U256::exp10(20)));
+        assert_eq!(args[1].clone().into_uint().unwrap().to_string(), routes[0]["minimum_amount_out"]["raw"]);
+        assert_eq!(args[2], AbiToken::Array(vec![AbiToken::Address(token_a), AbiToken::Address(token_c)]));
+        assert_eq!(args[3], AbiToken::Address(recipient));
+        assert_eq!(args[4], AbiToken::Uint(1_800_000_000u64.into()));
+
+        // split between the direct pair and the path through b, each with its own call
+        let plan: serde_json::Value = call_and_read_body_json(
+            &app, TestRequest::get().uri(&uri(2)).to_request()).await;
+        let routes = plan["data"]["routes"].as_array().unwrap();
+        assert_eq!(routes.len(), 2);
+        let mut paths = Vec::new();
+        let (mut amount_in, mut minimum) = (U256::zero(), U256::zero());
+        for route in routes {
+            let args = decode_calldata(route["router_call"]["calldata"].as_str().unwrap());
+            amount_in += args[0].clone().into_uint().unwrap();
+            let amount_out_min = args[1].clone().into_uint().unwrap();
+            assert_eq!(amount_out_min.to_string(), route["minimum_amount_out"]["raw"]);
+            minimum += amount_out_min;
+            paths.push(args[2].clone().into_array().unwrap().into_iter()
+                .map(|t| t.into_address().unwrap()).collect::<Vec<_>>());
+            assert_eq!(args[4], AbiToken::Uint(1_800_000_000u64.into()));
+        }
+        paths.sort_by_key(|p| p.len());
+        assert_eq!(paths, vec![vec![token_a, token_c], vec![token_a, token_b, token_c]]);
+        assert_eq!(amount_in, U256::exp10(20));
+        assert_eq!(minimum.to_string(), plan["data"]["minimum_amount_out"]["raw"]);
+
+        // without a recipient the call is not encoded
+        let plan: serde_json::Value = call_and_read_body_json(&app, TestRequest::get()
+            .uri(&format!("/v1/route?token_in=0x{}&token_out=0x{}&amount_in=1",
+                          hex::encode(token_a), hex::encode(token_c))).to_request()).await;
+        assert!(plan["data"]["routes"][0]["router_call"]["calldata"].is_null());
+    }
+}
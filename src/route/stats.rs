@@ -0,0 +1,171 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use crate::server::AppState;
+use crate::db;
+use crate::db::token_cache::TokenCache;
+use crate::db::rollups::{self, PeriodStats, DAY, HOUR};
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::candles::CandleToken;
+use crate::route::err::BackendError;
+use crate::route::pools::{get_pool_tokens, Amount};
+
+/// the periods of a request when `from` isn't set
+const DEFAULT_PERIODS: i64 = 168;
+const MAX_PERIODS: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct StatsQuery {
+    /// `hour` (default) or `day`
+    pub period: Option<String>,
+    /// unix seconds, periods starting in the range are returned
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// The activity of a pool over a period.
+#[derive(Debug, Clone, Serialize)]
+pub struct PeriodStatsEntry {
+    pub period_start: i64,
+    /// the amounts swapped in and out of the pool
+    pub volume_x: Amount,
+    pub volume_y: Amount,
+    /// the fees of the amounts swapped in, at the fee rate of the pool
+    pub fees_x: Amount,
+    pub fees_y: Amount,
+    /// the reserves after the last event of the period that moved them
+    pub reserve_x: Option<Amount>,
+    pub reserve_y: Option<Amount>,
+    pub swap_count: i64,
+    pub add_liq_count: i64,
+    pub rm_liq_count: i64,
+    /// the distinct senders of the transactions of the swaps
+    pub unique_traders: i64,
+}
+
+impl PeriodStatsEntry {
+    fn new(stats: PeriodStats, decimals_x: u8, decimals_y: u8) -> Self {
+        Self {
+            period_start: stats.period_start,
+            volume_x: Amount::new(stats.volume_x, decimals_x),
+            volume_y: Amount::new(stats.volume_y, decimals_y),
+            fees_x: Amount::new(stats.fees_x, decimals_x),
+            fees_y: Amount::new(stats.fees_y, decimals_y),
+            reserve_x: stats.reserves.map(|(x, _)| Amount::new(x, decimals_x)),
+            reserve_y: stats.reserves.map(|(_, y)| Amount::new(y, decimals_y)),
+            swap_count: stats.swap_count,
+            add_liq_count: stats.add_liq_count,
+            rm_liq_count: stats.rm_liq_count,
+            unique_traders: stats.unique_traders,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsPage {
+    pub pair_address: String,
+    pub period: String,
+    pub token_x: CandleToken,
+    pub token_y: CandleToken,
+    /// oldest first, periods without events have no statistics
+    pub stats: Vec<PeriodStatsEntry>,
+}
+
+/// The parsed parameters of a stats request.
+struct StatsRange {
+    period: (&'static str, i64),
+    from: i64,
+    to: i64,
+}
+
+fn parse_query(query: &StatsQuery) -> Result<StatsRange, String> {
+    let period = match query.period.as_deref() {
+        None | Some("hour") => ("hour", HOUR),
+        Some("day") => ("day", DAY),
+        Some(period) => return Err(format!("unknown period {}, expected hour or day", period)),
+    };
+    let to = match query.to {
+        Some(to) => to,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.as_secs() as i64,
+    };
+    let from = query.from.unwrap_or(to - period.1 * DEFAULT_PERIODS);
+    if from > to {
+        return Err(format!("from {} is after to {}", from, to));
+    }
+    if (to - from) / period.1 >= MAX_PERIODS {
+        return Err(format!("the range spans more than {} periods of a {}", MAX_PERIODS, period.0));
+    }
+    Ok(StatsRange { period, from, to })
+}
+
+/// `None` when the pool isn't indexed
+async fn get_stats_page(rb: &rbatis::Rbatis, tokens: &TokenCache, pair_address: &str, range: StatsRange)
+    -> anyhow::Result<Option<StatsPage>> {
+    let Some(pool) = db::get_pool(rb, pair_address).await? else {
+        return Ok(None);
+    };
+    let (token_x, token_y) = get_pool_tokens(rb, tokens, &pool).await?;
+    let (period_name, period) = range.period;
+    let stats = rollups::get_pool_stats(rb, pair_address, period,
+                                        range.from.div_euclid(period) * period, range.to).await?;
+    let (decimals_x, decimals_y) = (token_x.decimals, token_y.decimals);
+    Ok(Some(StatsPage {
+        pair_address: pool.pair_address,
+        period: period_name.to_string(),
+        token_x: token_x.into(),
+        token_y: token_y.into(),
+        stats: stats.into_iter().map(|s| PeriodStatsEntry::new(s, decimals_x, decimals_y)).collect(),
+    }))
+}
+
+/// the hourly or daily statistics of a pool over a time range
+pub async fn get_pool_stats(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<StatsQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let parsed = normalize_address(&path)
+        .ok_or_else(|| format!("invalid pool address {}", path))
+        .and_then(|address| Ok((address, parse_query(&query)?)));
+    let (pair_address, range) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_stats_page(&rb, &data.token_cache, &pair_address, range).await {
+        Ok(Some(page)) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(page)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Ok(None) => {
+            let resp = BackendResponse {
+                code: BackendError::NotFound,
+                error: Some(format!("pool {} not found", path)),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(e) => {
+            log::warn!("get_pool_stats from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get pool stats failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
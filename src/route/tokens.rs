@@ -0,0 +1,437 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use actix_web::{web, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use web3::types::U256;
+use crate::server::AppState;
+use crate::db;
+use crate::db::listing::{self, Cursor, TokenQuery};
+use crate::db::pool_stats;
+use crate::db::prices::{self, USD_DECIMALS};
+use crate::amm::units::{format_units, mul_div};
+use crate::db::tables::{PoolInfo, Token};
+use crate::route::{normalize_address, BackendResponse};
+use crate::route::err::BackendError;
+use crate::route::pools::{get_pool_page, parse_query, pool_prices, usd, Amount, PoolsQuery};
+
+const DAY: i64 = 24 * 3600;
+
+#[derive(Debug, Deserialize)]
+pub struct TokensQuery {
+    /// a part of the symbol or the name in any case, or an address
+    pub search: Option<String>,
+    /// the `next_cursor` of the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenListing {
+    pub address: String,
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u8,
+    pub total_supply: Option<String>,
+    pub metadata_status: String,
+    /// `None` for a token not reachable from the usd anchors through enough liquidity
+    pub price_usd: Option<String>,
+}
+
+impl From<Token> for TokenListing {
+    fn from(token: Token) -> Self {
+        Self {
+            address: token.address,
+            symbol: token.symbol,
+            name: token.name,
+            decimals: token.decimals,
+            total_supply: token.total_supply.map(|s| s.0),
+            metadata_status: token.metadata_status,
+            price_usd: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenPage {
+    pub tokens: Vec<TokenListing>,
+    /// `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+/// The price of a token in the other token of its deepest pool.
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenPrice {
+    pub pair_address: String,
+    pub quote_address: String,
+    pub quote_symbol: String,
+    pub price: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenWindow {
+    /// the amounts swapped in and out of its pools
+    pub volume: Amount,
+    pub swap_count: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenDetail {
+    pub address: String,
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u8,
+    pub total_supply: Option<Amount>,
+    pub metadata_status: String,
+    /// `None` when none of its pools has liquidity
+    pub price: Option<TokenPrice>,
+    /// routed from the usd anchors, `None` when the token isn't reachable through enough liquidity
+    pub price_usd: Option<String>,
+    /// the count of pools between the token and a usd anchor
+    pub price_usd_depth: Option<i64>,
+    /// the reserves of the token in all its pools
+    pub total_liquidity: Amount,
+    /// `total_liquidity` in usd
+    pub total_liquidity_usd: Option<String>,
+    pub pool_count: usize,
+    pub last_24h: TokenWindow,
+}
+
+fn parse_tokens_query(query: &TokensQuery) -> Result<TokenQuery, String> {
+    let after = match &query.cursor {
+        Some(cursor) => Some(Cursor::decode(cursor).map_err(|_| format!("invalid cursor {}", cursor))?),
+        None => None,
+    };
+    Ok(TokenQuery {
+        search: query.search.clone().filter(|s| !s.is_empty()),
+        after,
+        limit: query.limit.unwrap_or(100).clamp(1, 1000),
+    })
+}
+
+/// the reserve of `token` in a pool
+fn token_reserve(pool: &PoolInfo, token: &str) -> anyhow::Result<U256> {
+    let reserve = if pool.token_x_address == token { &pool.token_x_reserves } else { &pool.token_y_reserves };
+    Ok(U256::from_dec_str(&reserve.0)?)
+}
+
+/// the price of `token` in the first of `pools` with liquidity, deepest first
+async fn get_token_price(rb: &rbatis::Rbatis, token: &Token, mut pools: Vec<(U256, PoolInfo)>)
+    -> anyhow::Result<Option<TokenPrice>> {
+    pools.sort_by(|(a, _), (b, _)| b.cmp(a));
+    let addresses: Vec<String> = pools.iter().map(|(_, p)| p.pair_address.clone()).collect();
+    let v3_pools = listing::get_v3_pools_of(rb, &addresses).await?;
+    for (reserve, pool) in pools {
+        if reserve.is_zero() {
+            break;
+        }
+        let is_x = pool.token_x_address == token.address;
+        let (quote_address, quote_symbol) = if is_x {
+            (&pool.token_y_address, &pool.token_y_symbol)
+        } else {
+            (&pool.token_x_address, &pool.token_x_symbol)
+        };
+        let Some(quote) = db::get_token(rb, quote_address.clone()).await?.into_iter().next() else {
+            continue;
+        };
+        let v3 = v3_pools.iter().find(|v3| v3.pair_address == pool.pair_address);
+        let (price_x_in_y, price_y_in_x) = if is_x {
+            pool_prices(&pool, v3, token.decimals, quote.decimals)?
+        } else {
+            pool_prices(&pool, v3, quote.decimals, token.decimals)?
+        };
+        if let Some(price) = if is_x { price_x_in_y } else { price_y_in_x } {
+            return Ok(Some(TokenPrice {
+                quote_address: quote_address.clone(),
+                quote_symbol: quote_symbol.clone(),
+                pair_address: pool.pair_address,
+                price,
+            }));
+        }
+    }
+    Ok(None)
+}
+
+async fn get_token_detail(rb: &rbatis::Rbatis, address: &str) -> anyhow::Result<Option<TokenDetail>> {
+    let Some(token) = db::get_token(rb, address.to_string()).await?.into_iter().next() else {
+        return Ok(None);
+    };
+    let pools = listing::get_pools_of_token(rb, address).await?;
+    let pool_count = pools.len();
+    let pools = pools.into_iter()
+        .map(|pool| Ok((token_reserve(&pool, address)?, pool)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let total_liquidity = pools.iter().fold(U256::zero(), |total, (reserve, _)| total.saturating_add(*reserve));
+    let price = get_token_price(rb, &token, pools).await?;
+    let usd_price = prices::get_token_prices(rb, std::slice::from_ref(&token.address)).await?.remove(&token.address);
+    let (price_usd, price_usd_depth, total_liquidity_usd) = match usd_price {
+        Some(usd_price) => {
+            let price = U256::from_dec_str(&usd_price.price_usd.0)?;
+            let liquidity = mul_div(total_liquidity, price, U256::exp10(token.decimals as usize)).unwrap_or_default();
+            (Some(format_units(price, USD_DECIMALS)), Some(usd_price.depth), Some(format_units(liquidity, USD_DECIMALS)))
+        }
+        None => (None, None, None),
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let last_24h = pool_stats::get_token_window_stats(rb, address, now - DAY).await?;
+    let total_supply = match &token.total_supply {
+        Some(supply) => Some(Amount::new(U256::from_dec_str(&supply.0)?, token.decimals)),
+        None => None,
+    };
+    Ok(Some(TokenDetail {
+        total_liquidity: Amount::new(total_liquidity, token.decimals),
+        total_liquidity_usd,
+        price_usd,
+        price_usd_depth,
+        last_24h: TokenWindow {
+            volume: Amount::new(last_24h.amount_in.saturating_add(last_24h.amount_out), token.decimals),
+            swap_count: last_24h.swap_count,
+        },
+        address: token.address,
+        symbol: token.symbol,
+        name: token.name,
+        decimals: token.decimals,
+        total_supply,
+        metadata_status: token.metadata_status,
+        price,
+        pool_count,
+    }))
+}
+
+async fn get_token_page(rb: &rbatis::Rbatis, query: &TokenQuery) -> anyhow::Result<TokenPage> {
+    let (tokens, next) = listing::get_tokens(rb, query).await?;
+    let addresses: Vec<String> = tokens.iter().map(|t| t.address.clone()).collect();
+    let usd_prices = prices::get_token_prices(rb, &addresses).await?;
+    let tokens = tokens.into_iter().map(|token| {
+        let price_usd = match usd_prices.get(&token.address) {
+            Some(price) => Some(usd(&price.price_usd)?),
+            None => None,
+        };
+        Ok(TokenListing { price_usd, ..TokenListing::from(token) })
+    }).collect::<anyhow::Result<_>>()?;
+    Ok(TokenPage { tokens, next_cursor: next.map(|c| c.encode()) })
+}
+
+/// a page of tokens ordered by address, `next_cursor` is passed as `cursor` for the page after it
+pub async fn list_tokens(
+    data: web::Data<AppState>,
+    query: web::Query<TokensQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let token_query = match parse_tokens_query(&query) {
+        Ok(query) => query,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_token_page(&rb, &token_query).await {
+        Ok(page) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(page)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("list_tokens from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get tokens failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+/// a token with its price, liquidity and volume, by its address with or without `0x`
+pub async fn get_token(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let Some(address) = normalize_address(&path) else {
+        let resp = BackendResponse {
+            code: BackendError::InvalidParameters,
+            error: Some(format!("invalid token address {}", path)),
+            data: None::<()>,
+        };
+        return Ok(HttpResponse::Ok().json(resp));
+    };
+
+    match get_token_detail(&rb, &address).await {
+        Ok(Some(token)) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(token)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Ok(None) => {
+            let resp = BackendResponse {
+                code: BackendError::NotFound,
+                error: Some(format!("token {} not found", path)),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+        Err(e) => {
+            log::warn!("get_token from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get token failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+/// a page of the pools of a token, with the parameters of `/v1/pools`
+pub async fn list_token_pools(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<PoolsQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let parsed = normalize_address(&path)
+        .ok_or_else(|| format!("invalid token address {}", path))
+        .and_then(|address| {
+            let (pool_query, fields) = parse_query(&query)?;
+            Ok((listing::PoolQuery { token: Some(address), ..pool_query }, fields))
+        });
+    let (pool_query, fields) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_pool_page(&rb, &pool_query, fields).await {
+        Ok(page) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(page)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("list_token_pools from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get token pools failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
+
+/// the hours of a price history request when `from` isn't set
+const DEFAULT_PRICE_HOURS: i64 = 168;
+const MAX_PRICE_HOURS: i64 = 1000;
+const HOUR: i64 = 3600;
+
+#[derive(Debug, Deserialize)]
+pub struct PriceHistoryQuery {
+    /// unix seconds, hours starting in the range are returned
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// The last usd price of a token in an hour.
+#[derive(Debug, Clone, Serialize)]
+pub struct HourPrice {
+    pub period_start: i64,
+    pub price_usd: String,
+    pub block_number: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PriceHistory {
+    pub address: String,
+    /// oldest first, hours without a price change have no price
+    pub prices: Vec<HourPrice>,
+}
+
+fn parse_history_query(query: &PriceHistoryQuery) -> Result<(i64, i64), String> {
+    let to = match query.to {
+        Some(to) => to,
+        None => SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.as_secs() as i64,
+    };
+    let from = query.from.unwrap_or(to - HOUR * DEFAULT_PRICE_HOURS);
+    if from > to {
+        return Err(format!("from {} is after to {}", from, to));
+    }
+    if (to - from) / HOUR >= MAX_PRICE_HOURS {
+        return Err(format!("the range spans more than {} hours", MAX_PRICE_HOURS));
+    }
+    Ok((from.div_euclid(HOUR) * HOUR, to))
+}
+
+async fn get_price_history(rb: &rbatis::Rbatis, address: String, from: i64, to: i64) -> anyhow::Result<PriceHistory> {
+    let prices = prices::get_price_history(rb, &address, from, to).await?.into_iter()
+        .map(|p| Ok(HourPrice { period_start: p.period_start, price_usd: usd(&p.price_usd)?, block_number: p.block_number }))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(PriceHistory { address, prices })
+}
+
+/// the hourly usd prices of a token over a time range
+pub async fn get_token_prices(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<PriceHistoryQuery>,
+    _req: HttpRequest,
+) -> actix_web::Result<HttpResponse> {
+    let rb = data.db.clone();
+    let parsed = normalize_address(&path)
+        .ok_or_else(|| format!("invalid token address {}", path))
+        .and_then(|address| Ok((address, parse_history_query(&query)?)));
+    let (address, (from, to)) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            let resp = BackendResponse {
+                code: BackendError::InvalidParameters,
+                error: Some(e),
+                data: None::<()>,
+            };
+            return Ok(HttpResponse::Ok().json(resp));
+        }
+    };
+
+    match get_price_history(&rb, address, from, to).await {
+        Ok(history) => {
+            let resp = BackendResponse {
+                code: BackendError::Ok,
+                error: None,
+                data: Some(history)
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        },
+        Err(e) => {
+            log::warn!("get_token_prices from db failed,{:?}",e);
+            let resp = BackendResponse {
+                code: BackendError::DbErr,
+                error: Some("get token prices failed".to_string()),
+                data: None::<()>,
+            };
+            Ok(HttpResponse::Ok().json(resp))
+        }
+    }
+}
@@ -1,19 +1,32 @@
 use crate::config::BackendConfig;
+use crate::db::token_cache::TokenCache;
+use crate::db::pool_graph::PoolGraph;
 use actix_web::{HttpServer, web};
 use std::net::SocketAddr;
 use actix_web::App;
 use crate::route::get_all_pools::get_all_pools;
+use crate::route::pools::{get_pool, list_pools};
+use crate::route::pool_events::get_pool_events;
+use crate::route::candles::get_pool_candles;
+use crate::route::stats::get_pool_stats;
+use crate::route::accounts::{get_account_activity, get_account_summary};
+use crate::route::tokens::{get_token, get_token_prices, list_token_pools, list_tokens};
+use crate::route::quote::get_quote;
+use crate::route::router::get_route;
+use crate::route::admin::admin_routes;
 
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub config: BackendConfig,
     pub db: rbatis::Rbatis,
+    pub token_cache: TokenCache,
+    pub pool_graph: PoolGraph,
 }
 
-pub(crate) async fn run_server(app_state: AppState) {
+pub async fn run_server(app_state: AppState) {
     let works_number = app_state.config.workers_number;
     let bind_to = SocketAddr::new("0.0.0.0".parse().unwrap(),
-                                  app_state.config.server_port as u16);
+                                  app_state.config.server_port);
     HttpServer::new(move || {
         // let mut cors = Cors::default();
         // if app_state.config.admin.enable_http_cors {
@@ -23,6 +36,20 @@ pub(crate) async fn run_server(app_state: AppState) {
             // .wrap(cors)
             .app_data(web::Data::new(app_state.clone()))
             .route("/get_all_pools", web::get().to(get_all_pools))
+            .route("/v1/pools", web::get().to(list_pools))
+            .route("/v1/pools/{address}", web::get().to(get_pool))
+            .route("/v1/pools/{address}/events", web::get().to(get_pool_events))
+            .route("/v1/pools/{address}/candles", web::get().to(get_pool_candles))
+            .route("/v1/pools/{address}/stats", web::get().to(get_pool_stats))
+            .route("/v1/tokens", web::get().to(list_tokens))
+            .route("/v1/tokens/{address}", web::get().to(get_token))
+            .route("/v1/tokens/{address}/pools", web::get().to(list_token_pools))
+            .route("/v1/tokens/{address}/prices", web::get().to(get_token_prices))
+            .route("/v1/quote", web::get().to(get_quote))
+            .route("/v1/route", web::get().to(get_route))
+            .route("/v1/accounts/{address}/activity", web::get().to(get_account_activity))
+            .route("/v1/accounts/{address}/summary", web::get().to(get_account_summary))
+            .configure(admin_routes)
     })
         .workers(works_number as usize)
         .bind(&bind_to)
@@ -0,0 +1,14 @@
+-- pools whose stored reserves differ from getReserves() at the last synced block
+CREATE TABLE reconciliation_issues (
+    id serial NOT NULL,
+    pair_address text NOT NULL,
+    block_number bigint NOT NULL,
+    stored_reserve_x numeric NOT NULL,
+    stored_reserve_y numeric NOT NULL,
+    chain_reserve_x numeric NOT NULL,
+    chain_reserve_y numeric NOT NULL,
+    reindexed boolean NOT NULL,
+    created_at bigint NOT NULL, -- unix timestamp
+    PRIMARY KEY (id)
+);
+CREATE INDEX reconciliation_issues_pair_address ON reconciliation_issues (pair_address);
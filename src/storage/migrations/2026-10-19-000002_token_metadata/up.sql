@@ -0,0 +1,6 @@
+-- token metadata beyond symbol and decimals, and whether it had to fall back to placeholders
+-- metadata_status: ok / partial (some calls reverted) / failed (every call reverted)
+ALTER TABLE tokens ADD COLUMN name text NOT NULL DEFAULT '';
+ALTER TABLE tokens ADD COLUMN total_supply numeric;
+ALTER TABLE tokens ADD COLUMN metadata_status text NOT NULL DEFAULT 'ok';
+ALTER TABLE tokens ADD COLUMN metadata_attempts integer NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+DROP TABLE v3_ticks;
+DROP TABLE v3_pools;
+ALTER TABLE pool_info DROP COLUMN protocol;
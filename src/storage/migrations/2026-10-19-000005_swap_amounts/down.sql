@@ -0,0 +1,6 @@
+ALTER TABLE events DROP COLUMN flash;
+ALTER TABLE events DROP COLUMN direction;
+ALTER TABLE events DROP COLUMN amount1_out;
+ALTER TABLE events DROP COLUMN amount0_out;
+ALTER TABLE events DROP COLUMN amount1_in;
+ALTER TABLE events DROP COLUMN amount0_in;
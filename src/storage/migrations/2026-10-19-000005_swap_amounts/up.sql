@@ -0,0 +1,8 @@
+-- the four amounts of a Uniswap V2 swap, amount_x/amount_y only keep the net flows.
+-- existing swaps are backfilled by the watcher from re-fetched logs.
+ALTER TABLE events ADD COLUMN amount0_in numeric;
+ALTER TABLE events ADD COLUMN amount1_in numeric;
+ALTER TABLE events ADD COLUMN amount0_out numeric;
+ALTER TABLE events ADD COLUMN amount1_out numeric;
+ALTER TABLE events ADD COLUMN direction text; -- x_to_y / y_to_x / none
+ALTER TABLE events ADD COLUMN flash boolean NOT NULL DEFAULT false; -- a token both paid out and in
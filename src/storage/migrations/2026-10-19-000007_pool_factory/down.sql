@@ -0,0 +1,3 @@
+DROP INDEX pool_info_token_y_address;
+DROP INDEX pool_info_token_x_address;
+ALTER TABLE pool_info DROP COLUMN factory_address;
@@ -0,0 +1,6 @@
+ALTER TABLE pool_info DROP COLUMN lp_total_supply;
+DROP INDEX events_pair_time;
+DROP INDEX events_pair_position;
+ALTER TABLE events DROP COLUMN block_timestamp;
+ALTER TABLE events DROP COLUMN log_index;
+ALTER TABLE events DROP COLUMN block_number;
@@ -0,0 +1,20 @@
+-- open, high, low, close and volume of every pool by interval, built by the watcher from the
+-- swaps and Sync events it stores. Prices are the raw price of token x in token y times 10^36,
+-- the positions of the first and last price keep open and close right across batches.
+CREATE TABLE pool_candles (
+    pair_address text NOT NULL,
+    interval_secs bigint NOT NULL,
+    open_time bigint NOT NULL, -- unix seconds
+    price_open numeric,
+    price_high numeric,
+    price_low numeric,
+    price_close numeric,
+    open_block bigint,
+    open_log_index bigint,
+    close_block bigint,
+    close_log_index bigint,
+    volume_x numeric NOT NULL,
+    volume_y numeric NOT NULL,
+    swap_count bigint NOT NULL,
+    PRIMARY KEY (pair_address, interval_secs, open_time)
+);
@@ -0,0 +1,48 @@
+-- hourly and daily statistics of every pool, rolled up by the watcher in the transaction that
+-- stores each batch of events. Volumes are the amounts in and out of swaps, fees are taken
+-- from the amounts in at the fee rate of the pool, the reserves are those after the last
+-- event of the period, at the position of reserve_block and reserve_log_index.
+CREATE TABLE pool_hour_stats (
+    pair_address text NOT NULL,
+    period_start bigint NOT NULL, -- unix seconds
+    volume_x numeric NOT NULL,
+    volume_y numeric NOT NULL,
+    fees_x numeric NOT NULL,
+    fees_y numeric NOT NULL,
+    reserve_x numeric,
+    reserve_y numeric,
+    reserve_block bigint,
+    reserve_log_index bigint,
+    swap_count bigint NOT NULL,
+    add_liq_count bigint NOT NULL,
+    rm_liq_count bigint NOT NULL,
+    unique_traders bigint NOT NULL,
+    PRIMARY KEY (pair_address, period_start)
+);
+
+CREATE TABLE pool_day_stats (
+    pair_address text NOT NULL,
+    period_start bigint NOT NULL, -- unix seconds
+    volume_x numeric NOT NULL,
+    volume_y numeric NOT NULL,
+    fees_x numeric NOT NULL,
+    fees_y numeric NOT NULL,
+    reserve_x numeric,
+    reserve_y numeric,
+    reserve_block bigint,
+    reserve_log_index bigint,
+    swap_count bigint NOT NULL,
+    add_liq_count bigint NOT NULL,
+    rm_liq_count bigint NOT NULL,
+    unique_traders bigint NOT NULL,
+    PRIMARY KEY (pair_address, period_start)
+);
+
+-- the senders of the swap transactions of a pool by period, to count unique traders
+CREATE TABLE pool_stats_traders (
+    pair_address text NOT NULL,
+    period_secs bigint NOT NULL,
+    period_start bigint NOT NULL,
+    trader text NOT NULL,
+    PRIMARY KEY (pair_address, period_secs, period_start, trader)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE pool_info DROP COLUMN volume_usd;
+ALTER TABLE pool_info DROP COLUMN tvl_usd;
+DROP TABLE token_price_history;
+DROP TABLE token_prices;
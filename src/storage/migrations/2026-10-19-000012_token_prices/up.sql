@@ -0,0 +1,25 @@
+-- the usd price of every token reachable from the anchor tokens through pools with enough
+-- liquidity, with 18 decimals per whole token. depth is the count of pools between the token
+-- and an anchor, liquidity_usd the liquidity of the pools its price was taken from.
+CREATE TABLE token_prices (
+    token_address text PRIMARY KEY,
+    price_usd numeric NOT NULL,
+    depth bigint NOT NULL,
+    liquidity_usd numeric NOT NULL,
+    block_number bigint,
+    updated_at bigint -- unix seconds
+);
+
+-- the last usd price of every token by hour
+CREATE TABLE token_price_history (
+    token_address text NOT NULL,
+    period_start bigint NOT NULL, -- unix seconds
+    price_usd numeric NOT NULL,
+    block_number bigint,
+    PRIMARY KEY (token_address, period_start)
+);
+
+-- the reserves of a pool at the usd prices of its tokens, and its swap volume at the prices
+-- of the tokens when the swaps were stored, both with 18 decimals
+ALTER TABLE pool_info ADD COLUMN tvl_usd numeric;
+ALTER TABLE pool_info ADD COLUMN volume_usd numeric;
@@ -0,0 +1,5 @@
+-- a log is stored once, a block range stored again after a failure keeps the events it
+-- already has. events stored before their log index was kept have none and stay as they are.
+DELETE FROM events a USING events b
+WHERE a.id > b.id AND a.tx_hash = b.tx_hash AND a.log_index = b.log_index;
+CREATE UNIQUE INDEX events_tx_log ON events (tx_hash, log_index);
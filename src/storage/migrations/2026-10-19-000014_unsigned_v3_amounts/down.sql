@@ -0,0 +1,3 @@
+-- the signs can't be told apart from the amounts alone, they are taken from the in and out columns
+UPDATE events SET amount_x = amount0_in - amount0_out, amount_y = amount1_in - amount1_out
+WHERE pair_address IN (SELECT pair_address FROM pool_info WHERE protocol = 'uniswap_v3') AND event_type = 3;
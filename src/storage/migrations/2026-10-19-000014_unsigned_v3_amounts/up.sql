@@ -0,0 +1,4 @@
+-- the swaps of V3 pools were stored with the signed amounts of their logs, they keep the
+-- amounts unsigned like those of V2 pairs, the in and out columns tell which way they went
+UPDATE events SET amount_x = abs(amount_x), amount_y = abs(amount_y)
+WHERE event_type = 3 AND (amount_x < 0 OR amount_y < 0);
@@ -0,0 +1,2 @@
+DROP TABLE backfill_jobs;
+ALTER TABLE pool_info DROP COLUMN created_block;
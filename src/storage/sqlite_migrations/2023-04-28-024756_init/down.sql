@@ -0,0 +1,4 @@
+DROP TABLE last_sync_block;
+DROP TABLE tokens;
+DROP TABLE pool_info;
+DROP TABLE events;
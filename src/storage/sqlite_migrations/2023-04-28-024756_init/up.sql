@@ -0,0 +1,44 @@
+-- Your SQL goes here
+-- sqlite port of migrations/2023-04-28-024756_init
+-- sqlite has no arbitrary precision numeric type, a column declared as numeric
+-- would coerce uint256 values to REAL, so amounts and reserves are stored as
+-- decimal strings in text columns
+-- store amm events
+CREATE TABLE events (
+     id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+     tx_hash text NOT NULL,
+     event_type integer NOT NULL,-- add_liq/swap/rm_liq
+     pair_address text NOT NULL,
+     from_account text NOT NULL,
+     to_account text,
+     amount_x text , -- while swap if x->y :x>0&&y==0 else y>0 && x==0;while add_liq x,y > 0
+     amount_y text
+);
+
+-- store amm pool info
+CREATE TABLE pool_info (
+    id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+    pair_address text NOT NULL,
+    token_x_symbol text NOT NULL,
+    token_y_symbol text NOT NULL,
+    token_x_address text NOT NULL,
+    token_y_address text NOT NULL,
+    token_x_reserves text NOT NULL,
+    token_y_reserves text NOT NULL,
+    total_swap_count integer NOT NULL,
+    total_add_liq_count integer NOT NULL,
+    total_rm_liq_count integer NOT NULL
+);
+
+-- store tokens info
+CREATE TABLE tokens (
+   address text NOT NULL,
+   symbol text NOT NULL,
+   decimals integer NOT NULL,
+   PRIMARY KEY (address)
+);
+-- last sync block number
+CREATE TABLE last_sync_block (
+   block_number bigint NOT NULL,
+   PRIMARY KEY (block_number)
+);
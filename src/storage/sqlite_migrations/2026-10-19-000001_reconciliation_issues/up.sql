@@ -0,0 +1,13 @@
+-- pools whose stored reserves differ from getReserves() at the last synced block
+CREATE TABLE reconciliation_issues (
+    id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+    pair_address text NOT NULL,
+    block_number bigint NOT NULL,
+    stored_reserve_x text NOT NULL,
+    stored_reserve_y text NOT NULL,
+    chain_reserve_x text NOT NULL,
+    chain_reserve_y text NOT NULL,
+    reindexed boolean NOT NULL,
+    created_at bigint NOT NULL -- unix timestamp
+);
+CREATE INDEX reconciliation_issues_pair_address ON reconciliation_issues (pair_address);
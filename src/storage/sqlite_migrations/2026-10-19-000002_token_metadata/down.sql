@@ -0,0 +1,4 @@
+ALTER TABLE tokens DROP COLUMN metadata_attempts;
+ALTER TABLE tokens DROP COLUMN metadata_status;
+ALTER TABLE tokens DROP COLUMN total_supply;
+ALTER TABLE tokens DROP COLUMN name;
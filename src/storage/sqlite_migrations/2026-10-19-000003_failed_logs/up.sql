@@ -0,0 +1,17 @@
+-- logs that could not be decoded or applied, kept instead of aborting the sync
+-- status: failed / retry (requested by an admin, picked up by the next sync) / resolved
+CREATE TABLE failed_logs (
+    id integer NOT NULL PRIMARY KEY AUTOINCREMENT,
+    block_number bigint NOT NULL,
+    log_index bigint NOT NULL,
+    tx_hash text NOT NULL,
+    address text NOT NULL,
+    topics text NOT NULL, -- comma separated hex topics
+    data text NOT NULL, -- hex
+    error text NOT NULL,
+    status text NOT NULL,
+    attempts integer NOT NULL,
+    created_at bigint NOT NULL, -- unix timestamp
+    UNIQUE (tx_hash, log_index)
+);
+CREATE INDEX failed_logs_status ON failed_logs (status);
@@ -0,0 +1,21 @@
+-- the protocol adapter that indexes a pool: uniswap_v2 / uniswap_v3
+ALTER TABLE pool_info ADD COLUMN protocol text NOT NULL DEFAULT 'uniswap_v2';
+
+-- price and liquidity of concentrated liquidity pools
+CREATE TABLE v3_pools (
+    pair_address text NOT NULL,
+    fee integer NOT NULL, -- in hundredths of a bip
+    tick_spacing integer NOT NULL,
+    sqrt_price_x96 text NOT NULL,
+    tick integer NOT NULL,
+    liquidity text NOT NULL, -- in range at tick
+    PRIMARY KEY (pair_address)
+);
+
+-- liquidity net of every initialized tick
+CREATE TABLE v3_ticks (
+    pair_address text NOT NULL,
+    tick integer NOT NULL,
+    liquidity_net text NOT NULL,
+    PRIMARY KEY (pair_address, tick)
+);
@@ -0,0 +1,8 @@
+-- the four amounts of a Uniswap V2 swap, amount_x/amount_y only keep the net flows.
+-- existing swaps are backfilled by the watcher from re-fetched logs.
+ALTER TABLE events ADD COLUMN amount0_in text;
+ALTER TABLE events ADD COLUMN amount1_in text;
+ALTER TABLE events ADD COLUMN amount0_out text;
+ALTER TABLE events ADD COLUMN amount1_out text;
+ALTER TABLE events ADD COLUMN direction text; -- x_to_y / y_to_x / none
+ALTER TABLE events ADD COLUMN flash integer NOT NULL DEFAULT 0; -- a token both paid out and in
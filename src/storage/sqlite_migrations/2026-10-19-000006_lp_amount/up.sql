@@ -0,0 +1,3 @@
+-- LP tokens minted or burned by a liquidity event, from the pair's Transfer from or to
+-- the zero address in the same transaction; the liquidity of the position for V3
+ALTER TABLE events ADD COLUMN lp_amount text;
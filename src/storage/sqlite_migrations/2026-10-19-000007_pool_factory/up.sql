@@ -0,0 +1,4 @@
+-- the factory that created a pool, set for existing pools by the watcher at startup
+ALTER TABLE pool_info ADD COLUMN factory_address text;
+CREATE INDEX pool_info_token_x_address ON pool_info (token_x_address);
+CREATE INDEX pool_info_token_y_address ON pool_info (token_y_address);
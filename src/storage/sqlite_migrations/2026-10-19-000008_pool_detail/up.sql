@@ -0,0 +1,9 @@
+-- position and time of an event, existing events are backfilled by the watcher
+ALTER TABLE events ADD COLUMN block_number integer;
+ALTER TABLE events ADD COLUMN log_index integer;
+ALTER TABLE events ADD COLUMN block_timestamp integer; -- unix seconds
+CREATE INDEX events_pair_position ON events (pair_address, block_number, log_index);
+CREATE INDEX events_pair_time ON events (pair_address, block_timestamp);
+
+-- LP tokens of a V2 pair, from the transfers from and to the zero address
+ALTER TABLE pool_info ADD COLUMN lp_total_supply text;
@@ -0,0 +1,5 @@
+DROP INDEX events_position;
+DROP INDEX events_tx_origin;
+DROP INDEX events_to_account;
+DROP INDEX events_from_account;
+ALTER TABLE events DROP COLUMN tx_origin;
@@ -0,0 +1,6 @@
+-- the sender of the transaction of an event, existing events are backfilled by the watcher
+ALTER TABLE events ADD COLUMN tx_origin text;
+CREATE INDEX events_from_account ON events (from_account);
+CREATE INDEX events_to_account ON events (to_account);
+CREATE INDEX events_tx_origin ON events (tx_origin);
+CREATE INDEX events_position ON events (block_number, log_index);
@@ -0,0 +1,20 @@
+-- open, high, low, close and volume of every pool by interval, built by the watcher from the
+-- swaps and Sync events it stores. Prices are the raw price of token x in token y times 10^36,
+-- the positions of the first and last price keep open and close right across batches.
+CREATE TABLE pool_candles (
+    pair_address text NOT NULL,
+    interval_secs integer NOT NULL,
+    open_time integer NOT NULL, -- unix seconds
+    price_open text,
+    price_high text,
+    price_low text,
+    price_close text,
+    open_block integer,
+    open_log_index integer,
+    close_block integer,
+    close_log_index integer,
+    volume_x text NOT NULL,
+    volume_y text NOT NULL,
+    swap_count integer NOT NULL,
+    PRIMARY KEY (pair_address, interval_secs, open_time)
+);
@@ -0,0 +1,3 @@
+DROP TABLE pool_stats_traders;
+DROP TABLE pool_day_stats;
+DROP TABLE pool_hour_stats;
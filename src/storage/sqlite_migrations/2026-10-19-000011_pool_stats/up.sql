@@ -0,0 +1,48 @@
+-- hourly and daily statistics of every pool, rolled up by the watcher in the transaction that
+-- stores each batch of events. Volumes are the amounts in and out of swaps, fees are taken
+-- from the amounts in at the fee rate of the pool, the reserves are those after the last
+-- event of the period, at the position of reserve_block and reserve_log_index.
+CREATE TABLE pool_hour_stats (
+    pair_address text NOT NULL,
+    period_start integer NOT NULL, -- unix seconds
+    volume_x text NOT NULL,
+    volume_y text NOT NULL,
+    fees_x text NOT NULL,
+    fees_y text NOT NULL,
+    reserve_x text,
+    reserve_y text,
+    reserve_block integer,
+    reserve_log_index integer,
+    swap_count integer NOT NULL,
+    add_liq_count integer NOT NULL,
+    rm_liq_count integer NOT NULL,
+    unique_traders integer NOT NULL,
+    PRIMARY KEY (pair_address, period_start)
+);
+
+CREATE TABLE pool_day_stats (
+    pair_address text NOT NULL,
+    period_start integer NOT NULL, -- unix seconds
+    volume_x text NOT NULL,
+    volume_y text NOT NULL,
+    fees_x text NOT NULL,
+    fees_y text NOT NULL,
+    reserve_x text,
+    reserve_y text,
+    reserve_block integer,
+    reserve_log_index integer,
+    swap_count integer NOT NULL,
+    add_liq_count integer NOT NULL,
+    rm_liq_count integer NOT NULL,
+    unique_traders integer NOT NULL,
+    PRIMARY KEY (pair_address, period_start)
+);
+
+-- the senders of the swap transactions of a pool by period, to count unique traders
+CREATE TABLE pool_stats_traders (
+    pair_address text NOT NULL,
+    period_secs integer NOT NULL,
+    period_start integer NOT NULL,
+    trader text NOT NULL,
+    PRIMARY KEY (pair_address, period_secs, period_start, trader)
+);
@@ -0,0 +1,5 @@
+-- a log is stored once, a block range stored again after a failure keeps the events it
+-- already has. events stored before their log index was kept have none and stay as they are.
+DELETE FROM events WHERE log_index IS NOT NULL AND id NOT IN
+    (SELECT min(id) FROM events WHERE log_index IS NOT NULL GROUP BY tx_hash, log_index);
+CREATE UNIQUE INDEX events_tx_log ON events (tx_hash, log_index);
@@ -0,0 +1,7 @@
+-- the signs are taken from the in and out columns, a token paid out of the pool is negative
+UPDATE events SET amount_x = '-' || amount_x
+WHERE event_type = 3 AND amount0_out <> '0' AND amount_x <> '0'
+AND pair_address IN (SELECT pair_address FROM pool_info WHERE protocol = 'uniswap_v3');
+UPDATE events SET amount_y = '-' || amount_y
+WHERE event_type = 3 AND amount1_out <> '0' AND amount_y <> '0'
+AND pair_address IN (SELECT pair_address FROM pool_info WHERE protocol = 'uniswap_v3');
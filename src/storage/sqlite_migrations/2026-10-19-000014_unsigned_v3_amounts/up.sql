@@ -0,0 +1,5 @@
+-- the swaps of V3 pools were stored with the signed amounts of their logs, they keep the
+-- amounts unsigned like those of V2 pairs, the in and out columns tell which way they went.
+-- amounts are decimal strings here
+UPDATE events SET amount_x = substr(amount_x, 2) WHERE event_type = 3 AND amount_x LIKE '-%';
+UPDATE events SET amount_y = substr(amount_y, 2) WHERE event_type = 3 AND amount_y LIKE '-%';
@@ -0,0 +1,14 @@
+-- the block of the factory log of a pool, a reindex of the pool starts there
+ALTER TABLE pool_info ADD COLUMN created_block integer;
+
+-- pools reindexed once because their events were stored before the data they keep now.
+-- status is pending, done or failed, synced_block the block a done pool was reindexed up to
+CREATE TABLE backfill_jobs (
+    pair_address text PRIMARY KEY,
+    status text NOT NULL,
+    attempts integer NOT NULL,
+    synced_block integer,
+    error text,
+    updated_at integer NOT NULL -- unix seconds
+);
+CREATE INDEX backfill_jobs_status ON backfill_jobs (status);
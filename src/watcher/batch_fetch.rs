@@ -0,0 +1,81 @@
+//! Values the node returns one request per key, e.g. blocks or transactions.
+//!
+//! The keys missing from an LRU cache are sent once each, in json-rpc batches, and the
+//! values kept in the cache, a range synced again after an error doesn't fetch them twice.
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use anyhow::format_err;
+use jsonrpc_core as rpc;
+use lru::LruCache;
+use web3::{BatchTransport, Transport, Web3};
+use crate::watcher::multicall::DEFAULT_BATCH_SIZE;
+use crate::watcher::transport::WatcherTransport;
+
+const CACHE_SIZE: usize = 10000;
+
+#[derive(Clone)]
+pub struct BatchFetcher<K, V> {
+    web3: Web3<WatcherTransport>,
+    cache: Arc<Mutex<LruCache<K, V>>>,
+    method: &'static str,
+    params: fn(&K) -> Vec<rpc::Value>,
+    /// the value of a key from the result of its request, `None` when the node has none
+    parse: fn(&K, rpc::Value) -> anyhow::Result<Option<V>>,
+}
+
+impl<K: Hash + Eq, V> fmt::Debug for BatchFetcher<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchFetcher")
+            .field("method", &self.method)
+            .field("len", &self.cache.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> BatchFetcher<K, V> {
+    pub fn new(web3: Web3<WatcherTransport>, method: &'static str, params: fn(&K) -> Vec<rpc::Value>,
+               parse: fn(&K, rpc::Value) -> anyhow::Result<Option<V>>) -> Self {
+        let cache = LruCache::new(NonZeroUsize::new(CACHE_SIZE).unwrap());
+        Self { web3, cache: Arc::new(Mutex::new(cache)), method, params, parse }
+    }
+
+    /// the value of every key of `keys` the node has one for
+    pub async fn get(&self, keys: &[K]) -> anyhow::Result<HashMap<K, V>> {
+        let mut values = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let mut cache = self.cache.lock().unwrap();
+            let mut seen = HashSet::new();
+            for key in keys {
+                match cache.get(key) {
+                    Some(value) => {
+                        values.insert(key.clone(), value.clone());
+                    }
+                    None if seen.insert(key) => missing.push(key.clone()),
+                    None => {}
+                }
+            }
+        }
+        let transport = self.web3.transport();
+        for chunk in missing.chunks(DEFAULT_BATCH_SIZE) {
+            let requests: Vec<_> = chunk.iter()
+                .map(|key| transport.prepare(self.method, (self.params)(key)))
+                .collect();
+            let responses = transport.send_batch(requests).await?;
+            if responses.len() != chunk.len() {
+                return Err(format_err!("expected {} {} responses, got {}", chunk.len(), self.method, responses.len()));
+            }
+            let mut cache = self.cache.lock().unwrap();
+            for (key, response) in chunk.iter().zip(responses) {
+                if let Some(value) = (self.parse)(key, response?)? {
+                    cache.put(key.clone(), value.clone());
+                    values.insert(key.clone(), value);
+                }
+            }
+        }
+        Ok(values)
+    }
+}
@@ -0,0 +1,32 @@
+//! Block timestamps of the indexed events.
+//!
+//! Logs don't carry the time of their block, so the blocks are fetched with
+//! `eth_getBlockByNumber` by a `BatchFetcher`.
+use std::collections::HashMap;
+use anyhow::format_err;
+use web3::types::{BlockNumber, U64};
+use web3::{helpers, Web3};
+use crate::watcher::batch_fetch::BatchFetcher;
+use crate::watcher::transport::WatcherTransport;
+
+#[derive(Debug, Clone)]
+pub struct BlockTimes(BatchFetcher<u64, u64>);
+
+impl BlockTimes {
+    pub fn new(web3: Web3<WatcherTransport>) -> Self {
+        Self(BatchFetcher::new(web3, "eth_getBlockByNumber",
+                               |block| vec![helpers::serialize(&BlockNumber::Number((*block).into())),
+                                            helpers::serialize(&false)],
+                               |block, value| {
+                                   let time: U64 = value.get("timestamp").cloned()
+                                       .and_then(|t| serde_json::from_value(t).ok())
+                                       .ok_or_else(|| format_err!("block {} has no timestamp", block))?;
+                                   Ok(Some(time.as_u64()))
+                               }))
+    }
+
+    /// the timestamp in unix seconds of every block of `blocks`
+    pub async fn get(&self, blocks: &[u64]) -> anyhow::Result<HashMap<u64, u64>> {
+        self.0.get(blocks).await
+    }
+}
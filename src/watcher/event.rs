@@ -1,12 +1,30 @@
 use web3::types::{H256, Log, H160};
 use ethabi::{decode, ParamType, Address,Uint};
-use crate::watcher::watch::ChainWatcher;
 
 #[derive(Debug, Clone)]
 pub struct EventData {
     pub address: Address,
     pub tx_hash: H256,
-    // block_number: u64,
+    pub block_number: u64,
+    /// position in the block, orders the logs of a transaction
+    pub log_index: u64,
+    /// not part of the log, set by the watcher from the block
+    pub block_timestamp: Option<u64>,
+    /// the sender of the transaction, set by the watcher from the transaction
+    pub tx_origin: Option<Address>,
+}
+
+impl EventData {
+    pub fn from_log(log: &Log) -> Self {
+        Self {
+            address: log.address,
+            tx_hash: log.transaction_hash.unwrap_or_default(),
+            block_number: log.block_number.unwrap_or_default().as_u64(),
+            log_index: log.log_index.unwrap_or_default().as_u64(),
+            block_timestamp: None,
+            tx_origin: None,
+        }
+    }
 }
 #[derive(Debug, Clone)]
 pub struct PairCreatedEvent {
@@ -20,7 +38,9 @@ pub struct PairMintEvent {
     pub meta: EventData,
     pub sender: Address,
     pub amount0: Uint,
-    pub amount1: Uint
+    pub amount1: Uint,
+    /// LP tokens minted to the provider, not part of the log
+    pub lp_amount: Option<Uint>,
 }
 #[derive(Debug, Clone)]
 pub struct PairBurnEvent {
@@ -28,7 +48,9 @@ pub struct PairBurnEvent {
     pub sender: Address,
     pub to: Address,
     pub amount0: Uint,
-    pub amount1: Uint
+    pub amount1: Uint,
+    /// LP tokens burned, not part of the log
+    pub lp_amount: Option<Uint>,
 }
 #[derive(Debug, Clone)]
 pub struct PairSyncEvent {
@@ -40,13 +62,22 @@ pub struct PairSyncEvent {
 pub struct PairSwapEvent {
     pub meta: EventData,
     pub sender:Address,
-    pub amount0In: Uint,
-    pub amount1In: Uint,
-    pub amount0Out: Uint,
-    pub amount1Out: Uint,
+    pub amount0_in: Uint,
+    pub amount1_in: Uint,
+    pub amount0_out: Uint,
+    pub amount1_out: Uint,
     pub to: Address
 }
 
+/// A transfer of the pair's LP token, mints are from and burns to the zero address.
+#[derive(Debug, Clone)]
+pub struct PairTransferEvent {
+    pub meta: EventData,
+    pub from: Address,
+    pub to: Address,
+    pub value: Uint,
+}
+
 #[derive(Debug)]
 pub enum PairEvent {
     MintPairEvent(PairMintEvent),
@@ -78,6 +109,15 @@ impl PairEvent {
             }
         }
     }
+    pub fn meta_mut(&mut self) -> &mut EventData {
+        match self {
+            Self::MintPairEvent(mint) => &mut mint.meta,
+            Self::BurnPairEvent(burn) => &mut burn.meta,
+            Self::SwapPairEvent(swap) => &mut swap.meta,
+            Self::SyncPairEvent(sync) => &mut sync.meta,
+        }
+    }
+
     pub fn event_type(&self) ->EventType {
         match self {
             Self::MintPairEvent(_) => {
@@ -95,7 +135,7 @@ impl PairEvent {
         }
     }
 
-    pub fn get_table_column_name(&self) -> &str {
+    pub fn get_table_column_name(&self) -> &'static str {
         match self {
             Self::MintPairEvent(_) => {
                 "total_add_liq_count"
@@ -112,30 +152,29 @@ impl PairEvent {
 }
 
 impl EventType {
-    pub fn from_log_topic(topic: H256) -> Self {
-        let topics = ChainWatcher::get_topics();
-        let mint_topic = topics.get("mint").unwrap().clone();
-        let burn_topic = topics.get("burn").unwrap().clone();
-        let swap_topic = topics.get("swap").unwrap().clone();
-        let sync_topic = topics.get("sync").unwrap().clone();
-        if topic == mint_topic {
-            Self::AddLiq
-        } else if topic == burn_topic {
-            Self::RmvLiq
-        } else if topic == swap_topic {
-            Self::Swap
-        } else if topic == sync_topic {
-            Self::Sync
-        } else {
-            panic!("Unreachable")
+    /// the pair event of a `UniswapV2` event kind
+    pub fn from_kind(kind: &str) -> Option<Self> {
+        match kind {
+            "mint" => Some(Self::AddLiq),
+            "burn" => Some(Self::RmvLiq),
+            "swap" => Some(Self::Swap),
+            "sync" => Some(Self::Sync),
+            _ => None,
         }
     }
 }
+/// the address in an indexed topic
+fn topic_address(event: &Log, index: usize) -> Result<H160, ethabi::Error> {
+    let topic = event.topics.get(index)
+        .ok_or_else(|| ethabi::Error::Other(format!("missing topic {}", index).into()))?;
+    Ok(H160::from_slice(&topic.as_bytes()[12..]))
+}
+
 impl TryFrom<Log> for PairCreatedEvent {
     type Error = ethabi::Error;
 
     fn try_from(event: Log) -> Result<Self, Self::Error> {
-        let mut dec_ev = decode(
+        let dec_ev = decode(
             &[
                 ParamType::Address,//pair_address
                 ParamType::Uint(256), // all_pairs length
@@ -143,26 +182,34 @@ impl TryFrom<Log> for PairCreatedEvent {
             &event.data.0,
         )?;
         Ok(PairCreatedEvent {
-            token0_address: H160::from_slice(&event.topics[1].as_bytes()[12..]),
-            token1_address: H160::from_slice(&event.topics[2].as_bytes()[12..]),
-            pair_address: dec_ev[0].clone().into_address().unwrap().clone(),
-            all_pairs_length: dec_ev[1].clone().into_uint().unwrap().clone(),
+            token0_address: topic_address(&event, 1)?,
+            token1_address: topic_address(&event, 2)?,
+            pair_address: dec_ev[0].clone().into_address().unwrap(),
+            all_pairs_length: dec_ev[1].clone().into_uint().unwrap(),
         })
     }
 }
-impl TryFrom<Log> for PairEvent {
+impl TryFrom<Log> for PairTransferEvent {
     type Error = ethabi::Error;
 
-    fn try_from(event: Log) -> anyhow::Result<Self, Self::Error> {
-        let meta = EventData {
-            address: event.address,
-            tx_hash: event.transaction_hash.unwrap_or_default()
-        };
+    fn try_from(event: Log) -> Result<Self, Self::Error> {
+        let dec_ev = decode(&[ParamType::Uint(256)], &event.data.0)?;
+        Ok(PairTransferEvent {
+            meta: EventData::from_log(&event),
+            from: topic_address(&event, 1)?,
+            to: topic_address(&event, 2)?,
+            value: dec_ev[0].clone().into_uint().unwrap(),
+        })
+    }
+}
 
-        let event_type = EventType::from_log_topic(event.topics[0]);
+impl PairEvent {
+    /// decode a log of a known event type, whatever its topic is
+    pub fn decode(event_type: EventType, event: Log) -> Result<Self, ethabi::Error> {
+        let meta = EventData::from_log(&event);
         let pair_event = match event_type {
             EventType::AddLiq => {
-                let mut dec_ev = decode(
+                let dec_ev = decode(
                     &[
                         ParamType::Uint(256), // amount0
                         ParamType::Uint(256), // amount1
@@ -171,13 +218,14 @@ impl TryFrom<Log> for PairEvent {
                 )?;
                 PairEvent::MintPairEvent(PairMintEvent {
                     meta,
-                    sender: H160::from_slice(&event.topics[1].as_bytes()[12..]),
+                    sender: topic_address(&event, 1)?,
                     amount0: dec_ev[0].clone().into_uint().unwrap(),
                     amount1: dec_ev[1].clone().into_uint().unwrap(),
+                    lp_amount: None,
                 })
             },
             EventType::RmvLiq => {
-                let mut dec_ev = decode(
+                let dec_ev = decode(
                     &[
                         ParamType::Uint(256), // amount0
                         ParamType::Uint(256), // amount1
@@ -186,14 +234,15 @@ impl TryFrom<Log> for PairEvent {
                 )?;
                 PairEvent::BurnPairEvent(PairBurnEvent {
                     meta,
-                    sender: H160::from_slice(&event.topics[1].as_bytes()[12..]),
+                    sender: topic_address(&event, 1)?,
                     amount0: dec_ev[0].clone().into_uint().unwrap(),
                     amount1: dec_ev[1].clone().into_uint().unwrap(),
-                    to: H160::from_slice(&event.topics[2].as_bytes()[12..]),
+                    to: topic_address(&event, 2)?,
+                    lp_amount: None,
                 })
             },
             EventType::Swap => {
-                let mut dec_ev = decode(
+                let dec_ev = decode(
                     &[
                         ParamType::Uint(256), // amount0in
                         ParamType::Uint(256), // amount1in
@@ -204,16 +253,16 @@ impl TryFrom<Log> for PairEvent {
                 )?;
                 PairEvent::SwapPairEvent(PairSwapEvent {
                     meta,
-                    sender: H160::from_slice(&event.topics[1].as_bytes()[12..]),
-                    amount0In: dec_ev[0].clone().into_uint().unwrap(),
-                    amount1In: dec_ev[1].clone().into_uint().unwrap(),
-                    amount0Out: dec_ev[2].clone().into_uint().unwrap(),
-                    amount1Out: dec_ev[3].clone().into_uint().unwrap(),
-                    to: H160::from_slice(&event.topics[1].as_bytes()[12..])
+                    sender: topic_address(&event, 1)?,
+                    amount0_in: dec_ev[0].clone().into_uint().unwrap(),
+                    amount1_in: dec_ev[1].clone().into_uint().unwrap(),
+                    amount0_out: dec_ev[2].clone().into_uint().unwrap(),
+                    amount1_out: dec_ev[3].clone().into_uint().unwrap(),
+                    to: topic_address(&event, 2)?
                 })
             },
             EventType::Sync => {
-                let mut dec_ev = decode(
+                let dec_ev = decode(
                     &[
                         ParamType::Uint(112), // reserve0
                         ParamType::Uint(112), // reserve1
@@ -226,9 +275,6 @@ impl TryFrom<Log> for PairEvent {
                     reserve1: dec_ev[1].clone().into_uint().unwrap(),
                 })
             },
-            _ => {
-                panic!("Not supported")
-            }
         };
         Ok(pair_event)
     }
@@ -0,0 +1,665 @@
+//! An in-process json-rpc node for tests, simulating a Uniswap V2 factory and its pairs,
+//! and the logs of Uniswap V3 pools.
+//!
+//! It serves `eth_blockNumber`, `eth_getLogs`, `eth_getBlockByNumber`,
+//! `eth_getTransactionByHash` and `eth_call` (the erc20 metadata getters,
+//! `getReserves` and `feeTo`) over http, so the watcher can be exercised end to end with the real
+//! web3 transport, and faults (rpc errors, slow responses, reorgs and oversized log
+//! ranges) can be injected between requests.
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::dev::ServerHandle;
+use ethabi::{encode, ParamType, Token};
+use jsonrpc_core as rpc;
+use web3::types::{Bytes, Log, H160, H256, U256, U64};
+use crate::watcher::transport::LogFilter;
+use crate::amm::v2::MINIMUM_LIQUIDITY;
+use crate::protocol::uniswap_v2::UniswapV2;
+use crate::protocol::uniswap_v3::{encode_int, UniswapV3};
+
+const SYMBOL: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+const NAME: [u8; 4] = [0x06, 0xfd, 0xde, 0x03];
+const TOTAL_SUPPLY: [u8; 4] = [0x18, 0x16, 0x0d, 0xdd];
+const GET_RESERVES: [u8; 4] = [0x09, 0x02, 0xf1, 0xac];
+const FEE_TO: [u8; 4] = [0x01, 0x7e, 0x7e, 0x58];
+/// the time of block 0
+pub const GENESIS_TIME: u64 = 1_700_000_000;
+pub const BLOCK_TIME: u64 = 12;
+
+/// A fault applied to the next request for `method` (any method when `None`).
+#[derive(Debug, Clone)]
+pub enum Fault {
+    /// answer with a json-rpc error, only to requests for `from_block` or later blocks when set
+    Error { method: Option<String>, from_block: Option<u64>, code: i64, message: String },
+    /// answer normally, but only after the delay
+    Delay { method: Option<String>, delay: Duration },
+}
+
+impl Fault {
+    fn method(&self) -> &Option<String> {
+        match self {
+            Fault::Error { method, .. } | Fault::Delay { method, .. } => method,
+        }
+    }
+}
+
+/// An erc20 token, a getter set to `None` reverts.
+#[derive(Debug, Clone, Default)]
+pub struct MockToken {
+    pub symbol: Option<String>,
+    pub name: Option<String>,
+    pub decimals: Option<u8>,
+    pub total_supply: Option<U256>,
+    /// return `symbol` and `name` as `bytes32` like MKR does
+    pub bytes32: bool,
+}
+
+impl MockToken {
+    pub fn new(symbol: &str, decimals: u8) -> Self {
+        Self {
+            symbol: Some(symbol.to_string()),
+            name: Some(format!("{} Token", symbol)),
+            decimals: Some(decimals),
+            total_supply: Some(U256::exp10(decimals as usize + 9)),
+            bytes32: false,
+        }
+    }
+
+    fn encode_string(&self, value: &str) -> Vec<u8> {
+        if self.bytes32 {
+            let mut word = value.as_bytes().to_vec();
+            word.resize(32, 0);
+            word
+        } else {
+            encode(&[Token::String(value.to_string())])
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MockPair {
+    pub token0: H160,
+    pub token1: H160,
+    pub reserve0: U256,
+    pub reserve1: U256,
+    /// LP token supply, including the minimum liquidity locked by the first mint
+    pub total_supply: U256,
+    /// the product of the reserves after the last mint or burn while `feeTo` is set
+    pub k_last: U256,
+}
+
+/// A V3 pool, only the state its logs carry is tracked, the price is set by the test.
+#[derive(Debug, Clone, Default)]
+pub struct MockV3Pool {
+    pub token0: H160,
+    pub token1: H160,
+    pub tick: i32,
+    pub liquidity_net: BTreeMap<i32, i128>,
+}
+
+impl MockV3Pool {
+    /// the liquidity of the positions in range at the current tick
+    pub fn liquidity(&self) -> u128 {
+        self.liquidity_net.range(..=self.tick).map(|(_, net)| net).sum::<i128>() as u128
+    }
+}
+
+/// The simulated chain. Every state changing call is mined in its own block.
+#[derive(Debug, Default)]
+pub struct MockChain {
+    pub factory: H160,
+    /// the `feeTo` of the factory, mints and burns mint the protocol fee to it when set
+    pub fee_to: Option<H160>,
+    pub block_number: u64,
+    pub tokens: HashMap<H160, MockToken>,
+    pub pairs: HashMap<H160, MockPair>,
+    pub v3_pools: HashMap<H160, MockV3Pool>,
+    /// the address of a Multicall3 contract, if deployed
+    pub multicall: Option<H160>,
+    pub logs: Vec<Log>,
+    /// `eth_getLogs` over more blocks than this is rejected like a node with a range limit
+    pub max_block_range: Option<u64>,
+    pub faults: VecDeque<Fault>,
+    /// every request method served, in order
+    pub requests: Vec<String>,
+    /// the account sending the transactions mined next
+    pub tx_origin: H160,
+    /// the sender of every mined transaction
+    tx_origins: HashMap<H256, H160>,
+    /// seconds skipped from a block on, on top of the block time
+    warps: BTreeMap<u64, u64>,
+    next_address: u64,
+    next_tx: u64,
+}
+
+impl MockChain {
+    pub fn new(factory: H160) -> Self {
+        Self { factory, tx_origin: H160::from_low_u64_be(0xe0a), ..Default::default() }
+    }
+
+    fn new_address(&mut self) -> H160 {
+        self.next_address += 1;
+        H160::from_low_u64_be(0x1000 + self.next_address)
+    }
+
+    pub fn deploy_token(&mut self, symbol: &str, decimals: u8) -> H160 {
+        self.deploy_token_with(MockToken::new(symbol, decimals))
+    }
+
+    pub fn deploy_token_with(&mut self, token: MockToken) -> H160 {
+        let address = self.new_address();
+        self.tokens.insert(address, token);
+        address
+    }
+
+    pub fn deploy_multicall(&mut self) -> H160 {
+        let address = self.new_address();
+        self.multicall = Some(address);
+        address
+    }
+
+    /// mine `count` empty blocks
+    pub fn mine(&mut self, count: u64) {
+        self.block_number += count;
+    }
+
+    /// let `seconds` pass before the next block
+    pub fn warp(&mut self, seconds: u64) {
+        *self.warps.entry(self.block_number + 1).or_default() += seconds;
+    }
+
+    /// the time of a block in unix seconds, blocks are `BLOCK_TIME` apart unless warped
+    pub fn timestamp(&self, block: u64) -> u64 {
+        GENESIS_TIME + block * BLOCK_TIME + self.warps.range(..=block).map(|(_, s)| s).sum::<u64>()
+    }
+
+    fn emit(&mut self, logs: Vec<(H160, Vec<H256>, Vec<Token>)>) {
+        self.emit_raw(logs.into_iter().map(|(address, topics, data)| (address, topics, encode(&data))).collect());
+    }
+
+    /// mine a transaction emitting the logs as given, e.g. malformed ones
+    pub fn emit_raw(&mut self, logs: Vec<(H160, Vec<H256>, Vec<u8>)>) {
+        self.block_number += 1;
+        self.next_tx += 1;
+        self.tx_origins.insert(H256::from_low_u64_be(self.next_tx), self.tx_origin);
+        let block_hash = H256::from_low_u64_be(self.block_number);
+        for (index, (address, topics, data)) in logs.into_iter().enumerate() {
+            self.logs.push(Log {
+                address,
+                topics,
+                data: Bytes(data),
+                block_hash: Some(block_hash),
+                block_number: Some(U64::from(self.block_number)),
+                transaction_hash: Some(H256::from_low_u64_be(self.next_tx)),
+                transaction_index: Some(0.into()),
+                log_index: Some(index.into()),
+                transaction_log_index: Some(index.into()),
+                log_type: None,
+                removed: Some(false),
+            });
+        }
+    }
+
+    fn sync_log(&self, pair: H160) -> (H160, Vec<H256>, Vec<Token>) {
+        let state = &self.pairs[&pair];
+        (pair, vec![topic("sync")], vec![Token::Uint(state.reserve0), Token::Uint(state.reserve1)])
+    }
+
+    pub fn create_pair(&mut self, token0: H160, token1: H160) -> H160 {
+        let pair = self.new_address();
+        self.pairs.insert(pair, MockPair { token0, token1, ..Default::default() });
+        let log = (
+            self.factory,
+            vec![topic("create_pair"), H256::from(token0), H256::from(token1)],
+            vec![Token::Address(pair), Token::Uint(self.pairs.len().into())],
+        );
+        self.emit(vec![log]);
+        pair
+    }
+
+    fn transfer_log(pair: H160, from: H160, to: H160, value: U256) -> (H160, Vec<H256>, Vec<Token>) {
+        (pair, vec![topic("transfer"), H256::from(from), H256::from(to)], vec![Token::Uint(value)])
+    }
+
+    /// `UniswapV2Pair._mintFee`, the Transfer of the protocol fee to `fee_to` if any
+    fn mint_fee(&mut self, pair: H160) -> Option<(H160, Vec<H256>, Vec<Token>)> {
+        let state = self.pairs.get_mut(&pair).expect("unknown pair");
+        let fee_to = self.fee_to?;
+        if state.k_last.is_zero() {
+            return None;
+        }
+        let root_k = (state.reserve0 * state.reserve1).integer_sqrt();
+        let root_k_last = state.k_last.integer_sqrt();
+        if root_k <= root_k_last {
+            return None;
+        }
+        let liquidity = state.total_supply * (root_k - root_k_last) / (root_k * 5 + root_k_last);
+        if liquidity.is_zero() {
+            return None;
+        }
+        state.total_supply += liquidity;
+        Some(Self::transfer_log(pair, H160::zero(), fee_to, liquidity))
+    }
+
+    fn update_k_last(&mut self, pair: H160) {
+        let state = self.pairs.get_mut(&pair).expect("unknown pair");
+        state.k_last = match self.fee_to {
+            Some(_) => state.reserve0 * state.reserve1,
+            None => U256::zero(),
+        };
+    }
+
+    /// add liquidity, the LP tokens go to `sender`, returns their amount
+    pub fn mint(&mut self, pair: H160, sender: H160, amount0: U256, amount1: U256) -> U256 {
+        let mut logs: Vec<_> = self.mint_fee(pair).into_iter().collect();
+        let state = self.pairs.get_mut(&pair).expect("unknown pair");
+        let liquidity = if state.total_supply.is_zero() || state.reserve0.is_zero() || state.reserve1.is_zero() {
+            let minimum = U256::from(MINIMUM_LIQUIDITY);
+            if state.total_supply.is_zero() {
+                state.total_supply = minimum;
+                logs.push(Self::transfer_log(pair, H160::zero(), H160::zero(), minimum));
+            }
+            (amount0 * amount1).integer_sqrt().saturating_sub(minimum)
+        } else {
+            cmp::min(amount0 * state.total_supply / state.reserve0, amount1 * state.total_supply / state.reserve1)
+        };
+        state.total_supply += liquidity;
+        state.reserve0 += amount0;
+        state.reserve1 += amount1;
+        self.update_k_last(pair);
+        logs.push(Self::transfer_log(pair, H160::zero(), sender, liquidity));
+        logs.push(self.sync_log(pair));
+        logs.push((pair, vec![topic("mint"), H256::from(sender)], vec![Token::Uint(amount0), Token::Uint(amount1)]));
+        self.emit(logs);
+        liquidity
+    }
+
+    /// remove liquidity, `sender` sends the LP tokens for the amounts to the pair which burns
+    /// them, returns their amount
+    pub fn burn(&mut self, pair: H160, sender: H160, to: H160, amount0: U256, amount1: U256) -> U256 {
+        let fee = self.mint_fee(pair);
+        let state = self.pairs.get_mut(&pair).expect("unknown pair");
+        let liquidity = if state.reserve0.is_zero() {
+            U256::zero()
+        } else {
+            amount0 * state.total_supply / state.reserve0
+        };
+        state.total_supply -= liquidity;
+        state.reserve0 -= amount0;
+        state.reserve1 -= amount1;
+        self.update_k_last(pair);
+        let sync = self.sync_log(pair);
+        let burn = (pair, vec![topic("burn"), H256::from(sender), H256::from(to)],
+                    vec![Token::Uint(amount0), Token::Uint(amount1)]);
+        let mut logs = vec![Self::transfer_log(pair, sender, pair, liquidity)];
+        logs.extend(fee);
+        logs.extend([Self::transfer_log(pair, pair, H160::zero(), liquidity), sync, burn]);
+        self.emit(logs);
+        liquidity
+    }
+
+    /// swap `amount_in` of token0 (or token1 when `zero_for_one` is false) with the 0.3% fee
+    pub fn swap(&mut self, pair: H160, sender: H160, to: H160, amount_in: U256, zero_for_one: bool) -> U256 {
+        let state = self.pairs.get_mut(&pair).expect("unknown pair");
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (state.reserve0, state.reserve1)
+        } else {
+            (state.reserve1, state.reserve0)
+        };
+        let amount_in_with_fee = amount_in * 997;
+        let amount_out = amount_in_with_fee * reserve_out / (reserve_in * 1000 + amount_in_with_fee);
+        let (amounts, reserves) = if zero_for_one {
+            ([amount_in, 0.into(), 0.into(), amount_out],
+             (state.reserve0 + amount_in, state.reserve1 - amount_out))
+        } else {
+            ([0.into(), amount_in, amount_out, 0.into()],
+             (state.reserve0 - amount_out, state.reserve1 + amount_in))
+        };
+        state.reserve0 = reserves.0;
+        state.reserve1 = reserves.1;
+        let sync = self.sync_log(pair);
+        let swap = (pair, vec![topic("swap"), H256::from(sender), H256::from(to)],
+                    amounts.iter().map(|a| Token::Uint(*a)).collect());
+        self.emit(vec![sync, swap]);
+        amount_out
+    }
+
+    pub fn create_v3_pool(&mut self, factory: H160, token0: H160, token1: H160, fee: u32, tick_spacing: i32) -> H160 {
+        let pool = self.new_address();
+        self.v3_pools.insert(pool, MockV3Pool { token0, token1, ..Default::default() });
+        let log = (
+            factory,
+            vec![v3_topic("create_pool"), H256::from(token0), H256::from(token1), H256::from_low_u64_be(fee as u64)],
+            vec![Token::Int(encode_int(tick_spacing as i128)), Token::Address(pool)],
+        );
+        self.emit(vec![log]);
+        pool
+    }
+
+    pub fn v3_initialize(&mut self, pool: H160, sqrt_price_x96: U256, tick: i32) {
+        self.v3_pools.get_mut(&pool).expect("unknown v3 pool").tick = tick;
+        self.emit(vec![(pool, vec![v3_topic("initialize")],
+                        vec![Token::Uint(sqrt_price_x96), Token::Int(encode_int(tick as i128))])]);
+    }
+
+    /// add `amount` of liquidity between the ticks, paying `amounts` of the tokens
+    pub fn v3_mint(&mut self, pool: H160, owner: H160, (tick_lower, tick_upper): (i32, i32), amount: u128,
+                   amounts: (U256, U256)) {
+        self.update_v3_position(pool, tick_lower, tick_upper, amount as i128);
+        let topics = vec![v3_topic("mint"), H256::from(owner), tick_topic(tick_lower), tick_topic(tick_upper)];
+        self.emit(vec![(pool, topics, vec![Token::Address(owner), Token::Uint(amount.into()),
+                                           Token::Uint(amounts.0), Token::Uint(amounts.1)])]);
+    }
+
+    pub fn v3_burn(&mut self, pool: H160, owner: H160, (tick_lower, tick_upper): (i32, i32), amount: u128,
+                   amounts: (U256, U256)) {
+        self.update_v3_position(pool, tick_lower, tick_upper, -(amount as i128));
+        let topics = vec![v3_topic("burn"), H256::from(owner), tick_topic(tick_lower), tick_topic(tick_upper)];
+        self.emit(vec![(pool, topics, vec![Token::Uint(amount.into()),
+                                           Token::Uint(amounts.0), Token::Uint(amounts.1)])]);
+    }
+
+    /// a swap paying in the positive and taking out the negative `amounts`, ending at the
+    /// given price
+    pub fn v3_swap(&mut self, pool: H160, trader: H160, amounts: (i128, i128), sqrt_price_x96: U256, tick: i32) {
+        let state = self.v3_pools.get_mut(&pool).expect("unknown v3 pool");
+        state.tick = tick;
+        let liquidity = state.liquidity();
+        self.emit(vec![(pool, vec![v3_topic("swap"), H256::from(trader), H256::from(trader)], vec![
+            Token::Int(encode_int(amounts.0)), Token::Int(encode_int(amounts.1)), Token::Uint(sqrt_price_x96),
+            Token::Uint(liquidity.into()), Token::Int(encode_int(tick as i128)),
+        ])]);
+    }
+
+    pub fn v3_collect(&mut self, pool: H160, owner: H160, (tick_lower, tick_upper): (i32, i32), amounts: (u128, u128)) {
+        let topics = vec![v3_topic("collect"), H256::from(owner), tick_topic(tick_lower), tick_topic(tick_upper)];
+        self.emit(vec![(pool, topics, vec![Token::Address(owner), Token::Uint(amounts.0.into()),
+                                           Token::Uint(amounts.1.into())])]);
+    }
+
+    pub fn v3_flash(&mut self, pool: H160, trader: H160, amounts: (U256, U256), paid: (U256, U256)) {
+        self.emit(vec![(pool, vec![v3_topic("flash"), H256::from(trader), H256::from(trader)], vec![
+            Token::Uint(amounts.0), Token::Uint(amounts.1), Token::Uint(paid.0), Token::Uint(paid.1),
+        ])]);
+    }
+
+    fn update_v3_position(&mut self, pool: H160, tick_lower: i32, tick_upper: i32, delta: i128) {
+        let state = self.v3_pools.get_mut(&pool).expect("unknown v3 pool");
+        *state.liquidity_net.entry(tick_lower).or_default() += delta;
+        *state.liquidity_net.entry(tick_upper).or_default() -= delta;
+    }
+
+    /// drop the last `depth` blocks, the next state change is mined on the new fork
+    pub fn reorg(&mut self, depth: u64) {
+        let fork_point = self.block_number.saturating_sub(depth);
+        self.logs.retain(|l| l.block_number.map(|n| n.as_u64()).unwrap_or_default() <= fork_point);
+        self.warps.retain(|block, _| *block <= fork_point);
+        self.block_number = fork_point;
+    }
+
+    pub fn fail_next(&mut self, method: &str, code: i64, message: &str) {
+        self.faults.push_back(Fault::Error {
+            method: Some(method.to_string()), from_block: None, code, message: message.to_string(),
+        });
+    }
+
+    /// fail the next `eth_getLogs` from, or `eth_getBlockByNumber` of, `block` or a later one
+    pub fn fail_from_block(&mut self, method: &str, block: u64, code: i64, message: &str) {
+        self.faults.push_back(Fault::Error {
+            method: Some(method.to_string()), from_block: Some(block), code, message: message.to_string(),
+        });
+    }
+
+    pub fn delay_next(&mut self, method: &str, delay: Duration) {
+        self.faults.push_back(Fault::Delay { method: Some(method.to_string()), delay });
+    }
+
+    fn take_fault(&mut self, method: &str, params: &[rpc::Value]) -> Option<Fault> {
+        let block = self.request_block(method, params);
+        let position = self.faults.iter().position(|f| {
+            let from_block = match f {
+                Fault::Error { from_block, .. } => *from_block,
+                Fault::Delay { .. } => None,
+            };
+            f.method().as_deref().map(|m| m == method).unwrap_or(true)
+                && from_block.map(|from| block.map(|b| b >= from).unwrap_or(false)).unwrap_or(true)
+        })?;
+        self.faults.remove(position)
+    }
+
+    /// the first block a request is for
+    fn request_block(&self, method: &str, params: &[rpc::Value]) -> Option<u64> {
+        let param = params.first()?;
+        match method {
+            "eth_getLogs" => LogFilter::parse(param, self.block_number).ok().map(|f| f.from),
+            "eth_getBlockByNumber" => serde_json::from_value::<U64>(param.clone()).ok().map(|n| n.as_u64()),
+            _ => None,
+        }
+    }
+
+    fn handle(&mut self, method: &str, params: &[rpc::Value]) -> Result<rpc::Value, rpc::Error> {
+        match method {
+            "eth_blockNumber" => Ok(serde_json::to_value(U64::from(self.block_number)).unwrap()),
+            "eth_getLogs" => {
+                let filter = params.first()
+                    .ok_or_else(|| rpc::Error::invalid_params("missing filter"))?;
+                let filter = LogFilter::parse(filter, self.block_number)
+                    .map_err(|e| rpc::Error::invalid_params(format!("{:?}", e)))?;
+                if let Some(max) = self.max_block_range {
+                    if filter.to.saturating_sub(filter.from) + 1 > max {
+                        return Err(rpc::Error {
+                            code: rpc::ErrorCode::ServerError(-32005),
+                            message: format!("block range is too wide, maximum is {}", max),
+                            data: None,
+                        });
+                    }
+                }
+                let logs: Vec<&Log> = self.logs.iter().filter(|l| filter.matches(l)).collect();
+                Ok(serde_json::to_value(logs).unwrap())
+            }
+            "eth_getBlockByNumber" => {
+                let number: U64 = params.first().cloned()
+                    .and_then(|n| serde_json::from_value(n).ok())
+                    .ok_or_else(|| rpc::Error::invalid_params("missing block number"))?;
+                if number.as_u64() > self.block_number {
+                    return Ok(rpc::Value::Null);
+                }
+                Ok(serde_json::json!({
+                    "number": number,
+                    "hash": H256::from_low_u64_be(number.as_u64()),
+                    "timestamp": U64::from(self.timestamp(number.as_u64())),
+                }))
+            }
+            "eth_getTransactionByHash" => {
+                let hash: H256 = params.first().cloned()
+                    .and_then(|h| serde_json::from_value(h).ok())
+                    .ok_or_else(|| rpc::Error::invalid_params("missing transaction hash"))?;
+                Ok(match self.tx_origins.get(&hash) {
+                    Some(from) => serde_json::json!({ "hash": hash, "from": from }),
+                    None => rpc::Value::Null,
+                })
+            }
+            "eth_call" => {
+                let call = params.first()
+                    .ok_or_else(|| rpc::Error::invalid_params("missing call"))?;
+                let to: H160 = call.get("to").cloned()
+                    .and_then(|t| serde_json::from_value(t).ok())
+                    .ok_or_else(|| rpc::Error::invalid_params("missing to"))?;
+                let data: Bytes = call.get("data").or_else(|| call.get("input")).cloned()
+                    .and_then(|d| serde_json::from_value(d).ok())
+                    .unwrap_or_default();
+                let output = self.call(to, &data.0)
+                    .ok_or_else(|| rpc::Error {
+                        code: rpc::ErrorCode::ServerError(3),
+                        message: "execution reverted".to_string(),
+                        data: None,
+                    })?;
+                Ok(serde_json::to_value(Bytes(output)).unwrap())
+            }
+            _ => Err(rpc::Error::method_not_found()),
+        }
+    }
+
+    fn call(&self, to: H160, data: &[u8]) -> Option<Vec<u8>> {
+        let selector = data.get(..4)?;
+        if Some(to) == self.multicall {
+            return self.aggregate3(selector, &data[4..]);
+        }
+        if let Some(token) = self.tokens.get(&to) {
+            return match selector {
+                s if s == SYMBOL => token.symbol.as_ref().map(|v| token.encode_string(v)),
+                s if s == NAME => token.name.as_ref().map(|v| token.encode_string(v)),
+                s if s == DECIMALS => token.decimals.map(|v| encode(&[Token::Uint(v.into())])),
+                s if s == TOTAL_SUPPLY => token.total_supply.map(|v| encode(&[Token::Uint(v)])),
+                _ => None,
+            };
+        }
+        if to == self.factory && selector == FEE_TO {
+            return Some(encode(&[Token::Address(self.fee_to.unwrap_or_default())]));
+        }
+        if let Some(pair) = self.pairs.get(&to) {
+            if selector == GET_RESERVES {
+                return Some(encode(&[
+                    Token::Uint(pair.reserve0),
+                    Token::Uint(pair.reserve1),
+                    Token::Uint(self.block_number.into()),
+                ]));
+            }
+            return None;
+        }
+        // an address without code returns nothing
+        Some(vec![])
+    }
+}
+
+impl MockChain {
+    /// Multicall3 `aggregate3((address,bool,bytes)[])`, every call may fail
+    fn aggregate3(&self, selector: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+        let call = ParamType::Tuple(vec![ParamType::Address, ParamType::Bool, ParamType::Bytes]);
+        let params = vec![ParamType::Array(Box::new(call))];
+        if selector != ethabi::short_signature("aggregate3", &params) {
+            return None;
+        }
+        let calls = ethabi::decode(&params, data).ok()?.pop()?.into_array()?;
+        let mut results = Vec::new();
+        for call in calls {
+            let output = match &call {
+                Token::Tuple(fields) => match &fields[..] {
+                    [Token::Address(target), Token::Bool(_), Token::Bytes(data)] => self.call(*target, data),
+                    _ => return None,
+                },
+                _ => return None,
+            };
+            results.push(Token::Tuple(vec![
+                Token::Bool(output.is_some()),
+                Token::Bytes(output.unwrap_or_default()),
+            ]));
+        }
+        Some(encode(&[Token::Array(results)]))
+    }
+}
+
+fn topic(name: &str) -> H256 {
+    UniswapV2::embedded_topics().topic(name)
+}
+
+fn v3_topic(name: &str) -> H256 {
+    UniswapV3::embedded_topics().topic(name)
+}
+
+fn tick_topic(tick: i32) -> H256 {
+    let mut topic = [0u8; 32];
+    encode_int(tick as i128).to_big_endian(&mut topic);
+    H256::from(topic)
+}
+
+async fn serve_call(chain: &Mutex<MockChain>, call: rpc::MethodCall) -> rpc::Output {
+    let params = match call.params {
+        rpc::Params::Array(params) => params,
+        rpc::Params::Map(map) => vec![rpc::Value::Object(map)],
+        rpc::Params::None => vec![],
+    };
+    let (fault, result) = {
+        let mut chain = chain.lock().unwrap();
+        chain.requests.push(call.method.clone());
+        match chain.take_fault(&call.method, &params) {
+            Some(Fault::Error { code, message, .. }) => (None, Err(rpc::Error {
+                code: rpc::ErrorCode::ServerError(code), message, data: None,
+            })),
+            fault => (fault, chain.handle(&call.method, &params)),
+        }
+    };
+    if let Some(Fault::Delay { delay, .. }) = fault {
+        tokio::time::sleep(delay).await;
+    }
+    rpc::Output::from(result, call.id, call.jsonrpc)
+}
+
+async fn rpc_handler(chain: web::Data<Arc<Mutex<MockChain>>>, body: web::Bytes) -> HttpResponse {
+    let request: rpc::Request = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let response = match request {
+        rpc::Request::Single(rpc::Call::MethodCall(call)) => {
+            rpc::Response::Single(serve_call(&chain, call).await)
+        }
+        rpc::Request::Batch(calls) => {
+            let mut outputs = Vec::new();
+            for call in calls {
+                if let rpc::Call::MethodCall(call) = call {
+                    outputs.push(serve_call(&chain, call).await);
+                }
+            }
+            rpc::Response::Batch(outputs)
+        }
+        _ => return HttpResponse::BadRequest().body("unsupported request"),
+    };
+    HttpResponse::Ok().json(response)
+}
+
+/// The http server of a `MockChain`, stopped when dropped.
+pub struct MockRpcServer {
+    pub url: String,
+    pub chain: Arc<Mutex<MockChain>>,
+    handle: ServerHandle,
+}
+
+impl MockRpcServer {
+    /// bind to a free local port and serve `chain` from the current runtime
+    pub fn start(chain: MockChain) -> Self {
+        let chain = Arc::new(Mutex::new(chain));
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock rpc server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let data = web::Data::new(chain.clone());
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .route("/", web::post().to(rpc_handler))
+        })
+            .workers(1)
+            .disable_signals()
+            .listen(listener)
+            .expect("listen mock rpc server")
+            .run();
+        let handle = server.handle();
+        tokio::spawn(server);
+        Self { url, chain, handle }
+    }
+
+    pub fn chain(&self) -> std::sync::MutexGuard<'_, MockChain> {
+        self.chain.lock().unwrap()
+    }
+}
+
+impl Drop for MockRpcServer {
+    fn drop(&mut self) {
+        let handle = self.handle.clone();
+        tokio::spawn(async move { handle.stop(false).await });
+    }
+}
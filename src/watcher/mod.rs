@@ -1,2 +1,12 @@
 pub mod watch;
-pub mod event;
\ No newline at end of file
+pub mod event;
+pub mod transport;
+pub mod verify;
+pub mod reconcile;
+pub mod token;
+pub mod multicall;
+pub mod batch_fetch;
+pub mod block_time;
+pub mod tx_origin;
+#[cfg(test)]
+pub mod mock_rpc;
\ No newline at end of file
@@ -0,0 +1,192 @@
+//! Batched view calls.
+//!
+//! Calls are aggregated into Multicall3 `aggregate3` calls with `allowFailure` set, so
+//! one reverting call doesn't fail the others. When no Multicall3 address is configured,
+//! or the contract isn't deployed on the chain, they are sent as json-rpc batches of
+//! `eth_call` instead. A chunk whose `aggregate3` call fails on the node is sent as a
+//! batch too, the next chunks try Multicall3 again.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use anyhow::format_err;
+use ethabi::{ParamType, Token};
+use web3::types::{BlockId, BlockNumber, Bytes, CallRequest, H160};
+use web3::{helpers, BatchTransport, Transport, Web3};
+use crate::watcher::transport::WatcherTransport;
+
+/// Multicall3, deployed at the same address on most evm chains
+pub const MULTICALL3_ADDRESS: &str = "ca11bde05977b3631167028862be2a173976ca11";
+pub const DEFAULT_BATCH_SIZE: usize = 500;
+
+/// A call of a view function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ViewCall {
+    pub target: H160,
+    pub data: Vec<u8>,
+}
+
+impl ViewCall {
+    /// a call of a function without arguments
+    pub fn new(target: H160, selector: [u8; 4]) -> Self {
+        Self { target, data: selector.to_vec() }
+    }
+}
+
+fn aggregate3_params() -> Vec<ParamType> {
+    vec![ParamType::Array(Box::new(ParamType::Tuple(vec![
+        ParamType::Address, ParamType::Bool, ParamType::Bytes,
+    ])))]
+}
+
+#[derive(Debug, Clone)]
+pub struct Multicall {
+    web3: Web3<WatcherTransport>,
+    address: Option<H160>,
+    batch_size: usize,
+    /// set after `aggregate3` returned nothing or something else than its results, i.e.
+    /// Multicall3 isn't deployed on this chain
+    unavailable: Arc<AtomicBool>,
+}
+
+impl Multicall {
+    /// `batch_size` is the number of calls per request, 0 uses the default
+    pub fn new(web3: Web3<WatcherTransport>, address: Option<H160>, batch_size: usize) -> Self {
+        let batch_size = if batch_size == 0 { DEFAULT_BATCH_SIZE } else { batch_size };
+        Self { web3, address, batch_size, unavailable: Default::default() }
+    }
+
+    /// The output of every call in order, `None` for a call that reverted or returned
+    /// nothing (e.g. no code at the target). Transport errors fail the whole batch.
+    pub async fn call(&self, calls: &[ViewCall], block: Option<u64>) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let block = match block {
+            Some(number) => BlockId::Number(BlockNumber::Number(number.into())),
+            None => BlockId::Number(BlockNumber::Latest),
+        };
+        let mut outputs = Vec::with_capacity(calls.len());
+        for chunk in calls.chunks(self.batch_size) {
+            let aggregated = match self.address {
+                Some(address) if !self.unavailable.load(Ordering::Relaxed) => {
+                    self.aggregate3(address, chunk, block).await?
+                }
+                _ => None,
+            };
+            match aggregated {
+                Some(results) => outputs.extend(results),
+                None => outputs.extend(self.rpc_batch(chunk, block).await?),
+            }
+        }
+        Ok(outputs)
+    }
+
+    /// `None` when Multicall3 can't be used for the calls and they have to be sent another way
+    async fn aggregate3(&self, address: H160, calls: &[ViewCall], block: BlockId)
+        -> anyhow::Result<Option<Vec<Option<Vec<u8>>>>> {
+        let params = aggregate3_params();
+        let mut data = ethabi::short_signature("aggregate3", &params).to_vec();
+        data.extend(ethabi::encode(&[Token::Array(calls.iter().map(|c| Token::Tuple(vec![
+            Token::Address(c.target), Token::Bool(true), Token::Bytes(c.data.clone()),
+        ])).collect())]));
+        let request = CallRequest { to: Some(address), data: Some(Bytes(data)), ..Default::default() };
+        let output = match self.web3.eth().call(request, Some(block)).await {
+            Ok(output) => output.0,
+            Err(web3::Error::Rpc(e)) => {
+                log::warn!("multicall aggregate3 of {} calls failed, send them in a json-rpc batch: {}",
+                           calls.len(), e.message);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let results = ethabi::decode(&[ParamType::Array(Box::new(ParamType::Tuple(vec![
+            ParamType::Bool, ParamType::Bytes,
+        ])))], &output).ok()
+            .and_then(|tokens| tokens.into_iter().next()?.into_array());
+        let results = match results {
+            Some(results) if results.len() == calls.len() => results,
+            _ => {
+                log::warn!("multicall {:?} returned an invalid response, use json-rpc batches", address);
+                self.unavailable.store(true, Ordering::Relaxed);
+                return Ok(None);
+            }
+        };
+        Ok(Some(results.into_iter().map(|result| match result {
+            Token::Tuple(fields) => match &fields[..] {
+                [Token::Bool(true), Token::Bytes(output)] if !output.is_empty() => Some(output.clone()),
+                _ => None,
+            },
+            _ => None,
+        }).collect()))
+    }
+
+    async fn rpc_batch(&self, calls: &[ViewCall], block: BlockId) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        let transport = self.web3.transport();
+        let requests: Vec<_> = calls.iter().map(|c| {
+            let request = CallRequest { to: Some(c.target), data: Some(Bytes(c.data.clone())), ..Default::default() };
+            transport.prepare("eth_call", vec![helpers::serialize(&request), helpers::serialize(&block)])
+        }).collect();
+        let responses = transport.send_batch(requests).await?;
+        if responses.len() != calls.len() {
+            return Err(format_err!("expected {} eth_call responses, got {}", calls.len(), responses.len()));
+        }
+        responses.into_iter().map(|response| match response {
+            Ok(value) => {
+                let output: Bytes = serde_json::from_value(value)?;
+                Ok(if output.0.is_empty() { None } else { Some(output.0) })
+            }
+            Err(web3::Error::Rpc(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer, MockToken};
+    use web3::transports::Http;
+
+    const SYMBOL: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+    const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+
+    #[actix_rt::test]
+    async fn test_multicall() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let multicall = chain.deploy_multicall();
+        let token = chain.deploy_token("TKA", 18);
+        let no_decimals = chain.deploy_token_with(MockToken { decimals: None, ..MockToken::new("ND", 0) });
+        let server = MockRpcServer::start(chain);
+        let web3 = Web3::new(WatcherTransport::Http(Http::new(&server.url).unwrap()));
+        let calls = vec![
+            ViewCall::new(token, SYMBOL),
+            ViewCall::new(no_decimals, DECIMALS),
+            ViewCall::new(token, DECIMALS),
+            ViewCall::new(H160::from_low_u64_be(0xdead), SYMBOL),
+        ];
+        let expected = vec![
+            Some(ethabi::encode(&[Token::String("TKA".to_string())])),
+            None,
+            Some(ethabi::encode(&[Token::Uint(18.into())])),
+            None,
+        ];
+
+        // two aggregate3 calls of at most three calls each
+        let outputs = Multicall::new(web3.clone(), Some(multicall), 3).call(&calls, None).await.unwrap();
+        assert_eq!(outputs, expected);
+        assert_eq!(std::mem::take(&mut server.chain().requests), vec!["eth_call", "eth_call"]);
+
+        // without Multicall3, or with a wrong address, every call is sent in one json-rpc batch
+        for address in [None, Some(H160::from_low_u64_be(0xbad))] {
+            let multicall = Multicall::new(web3.clone(), address, 0);
+            assert_eq!(multicall.call(&calls, Some(1)).await.unwrap(), expected);
+            assert_eq!(multicall.call(&calls, Some(1)).await.unwrap(), expected);
+        }
+        let requests = std::mem::take(&mut server.chain().requests);
+        assert_eq!(requests.len(), 4 * calls.len() + 1);
+
+        // an aggregate3 call failing on the node sends only its chunk in a batch
+        let multicall = Multicall::new(web3.clone(), Some(multicall), 3);
+        server.chain().fail_next("eth_call", -32000, "out of gas");
+        assert_eq!(multicall.call(&calls, None).await.unwrap(), expected);
+        assert_eq!(std::mem::take(&mut server.chain().requests).len(), 1 + 3 + 1);
+        assert_eq!(multicall.call(&calls, None).await.unwrap(), expected);
+        assert_eq!(std::mem::take(&mut server.chain().requests).len(), 2);
+    }
+}
@@ -0,0 +1,194 @@
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rbatis::rbdc::decimal::Decimal;
+use ethabi::ParamType;
+use web3::types::{H160, U256};
+use crate::db;
+use crate::db::tables::ReconciliationIssue;
+use crate::protocol::uniswap_v2;
+use crate::watcher::multicall::ViewCall;
+use crate::watcher::watch::ChainWatcher;
+
+const GET_RESERVES: [u8; 4] = [0x09, 0x02, 0xf1, 0xac];
+
+/// Compares the reserves stored in `pool_info` with `getReserves()` of the pair at the
+/// last synced block for a random sample of pools, and records every mismatch in
+/// `reconciliation_issues`. With `reconcile_reindex` set the events of a drifted pair
+/// are fetched again from the chain and replace the stored ones. A sample is checked
+/// under the sync lock of the watcher, the last synced block and the stored reserves
+/// can't move meanwhile.
+pub struct Reconciler {
+    pub watcher: ChainWatcher,
+}
+
+impl Reconciler {
+    pub fn new(watcher: ChainWatcher) -> Self {
+        Self { watcher }
+    }
+
+    /// `getReserves()` of the pairs at `block` in one batch, `None` where the call failed
+    pub async fn get_reserves(&self, pairs: &[H160], block: u64) -> anyhow::Result<Vec<Option<(U256, U256)>>> {
+        let calls: Vec<ViewCall> = pairs.iter().map(|p| ViewCall::new(*p, GET_RESERVES)).collect();
+        let outputs = self.watcher.multicall.call(&calls, Some(block)).await?;
+        Ok(outputs.into_iter().map(|output| {
+            let tokens = ethabi::decode(&[ParamType::Uint(112), ParamType::Uint(112), ParamType::Uint(32)],
+                                        &output?).ok()?;
+            Some((tokens[0].clone().into_uint()?, tokens[1].clone().into_uint()?))
+        }).collect())
+    }
+
+    /// check one sample of pools, returns the issues found
+    pub async fn reconcile(&mut self) -> anyhow::Result<Vec<ReconciliationIssue>> {
+        let db = self.watcher.db.clone();
+        let sync_lock = self.watcher.sync_lock.clone();
+        let _guard = sync_lock.lock().await;
+        let last_synced_block = db::get_last_sync_block(&db).await?;
+        let sample = db::get_sample_pools(&db, uniswap_v2::NAME, self.watcher.config.reconcile_sample_size as u64).await?;
+        let (pools, pairs): (Vec<_>, Vec<_>) = sample.into_iter().filter_map(|pool| {
+            match H160::from_str(&pool.pair_address) {
+                Ok(pair) => Some((pool, pair)),
+                Err(e) => {
+                    log::warn!("pool {} has an invalid address, skip it: {:?}", pool.pair_address, e);
+                    None
+                }
+            }
+        }).unzip();
+        let reserves = self.get_reserves(&pairs, last_synced_block).await?;
+        let mut issues = Vec::new();
+        for (pool, reserves) in pools.into_iter().zip(reserves) {
+            let (reserve_x, reserve_y) = match reserves {
+                Some(reserves) => reserves,
+                None => {
+                    log::warn!("getReserves of pool {} failed, skip it", pool.pair_address);
+                    continue;
+                }
+            };
+            let stored = (U256::from_dec_str(&pool.token_x_reserves.0).ok(),
+                          U256::from_dec_str(&pool.token_y_reserves.0).ok());
+            if stored == (Some(reserve_x), Some(reserve_y)) {
+                continue;
+            }
+            issues.push(ReconciliationIssue {
+                id: None,
+                pair_address: pool.pair_address.clone(),
+                block_number: last_synced_block as i64,
+                stored_reserve_x: pool.token_x_reserves.clone(),
+                stored_reserve_y: pool.token_y_reserves.clone(),
+                chain_reserve_x: Decimal::from_str(&reserve_x.to_string()).unwrap(),
+                chain_reserve_y: Decimal::from_str(&reserve_y.to_string()).unwrap(),
+                reindexed: false,
+                created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+            });
+        }
+        for issue in &issues {
+            log::warn!("pool {} reserves drifted at block {}: stored ({}, {}), chain ({}, {})",
+                       issue.pair_address, issue.block_number,
+                       issue.stored_reserve_x.0, issue.stored_reserve_y.0,
+                       issue.chain_reserve_x.0, issue.chain_reserve_y.0);
+            db::save_reconciliation_issue(&mut self.watcher.db, issue).await?;
+        }
+        // every issue is recorded first, a pair failing to reindex stays unreindexed
+        if self.watcher.config.reconcile_reindex {
+            for issue in &issues {
+                if let Err(e) = self.reindex_pair(&issue.pair_address, last_synced_block).await {
+                    log::warn!("reindex of pool {} failed {:?}", issue.pair_address, e);
+                }
+            }
+        }
+        Ok(issues)
+    }
+
+    /// fetch every event of the pair up to `to_block` again and replace the stored ones
+    pub async fn reindex_pair(&mut self, pair_address: &str, to_block: u64) -> anyhow::Result<()> {
+        self.watcher.reindex_pair(H160::from_str(pair_address)?, to_block).await?;
+        db::mark_reconciliation_issue_reindexed(&self.watcher.db, pair_address).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use crate::config::BackendConfig;
+    use crate::db::sqlite::new_test_db;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer};
+
+    #[actix_rt::test]
+    async fn test_reconcile() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let multicall = chain.deploy_multicall();
+        let token_a = chain.deploy_token("TKA", 18);
+        let token_b = chain.deploy_token("TKB", 18);
+        let token_c = chain.deploy_token("TKC", 18);
+        let pair = chain.create_pair(token_a, token_b);
+        let other = chain.create_pair(token_a, token_c);
+        let lp = H160::from_low_u64_be(0xaaa);
+        chain.mint(pair, lp, U256::exp10(21), U256::exp10(21));
+        chain.swap(pair, lp, lp, U256::exp10(18), true);
+        chain.mint(other, lp, U256::exp10(20), U256::exp10(20));
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("reconcile").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            reconcile_sample_size: 10,
+            reconcile_reindex: true,
+            multicall_address: Some(multicall),
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config.clone(), db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let mut reconciler = Reconciler::new(ChainWatcher::new(config, db.clone()).await.unwrap());
+        reconciler.watcher.sync_lock = watcher.sync_lock.clone();
+        assert!(reconciler.reconcile().await.unwrap().is_empty());
+
+        // a malformed stored address is skipped, the rest of the sample is checked
+        db.exec("insert into pool_info (pair_address, token_x_symbol, token_y_symbol, token_x_address, \
+                token_y_address, token_x_reserves, token_y_reserves, total_swap_count, total_add_liq_count, \
+                total_rm_liq_count, protocol) values ('not an address', 'A', 'B', 'a', 'b', '0', '0', 0, 0, 0, ?)",
+                vec![rbs::to_value!(uniswap_v2::NAME)]).await.unwrap();
+        assert!(reconciler.reconcile().await.unwrap().is_empty());
+        db.exec("delete from pool_info where pair_address = 'not an address'", vec![]).await.unwrap();
+
+        // a sample waits for the range the watcher is storing
+        let guard = watcher.sync_lock.lock().await;
+        assert!(tokio::time::timeout(Duration::from_millis(200), reconciler.reconcile()).await.is_err());
+        drop(guard);
+
+        let drift = |pair: H160| db.exec("update pool_info set token_x_reserves = ?, total_swap_count = ? \
+                                          where pair_address = ?",
+                                         vec![rbs::to_value!("1"), rbs::to_value!(7), rbs::to_value!(hex::encode(pair))]);
+        drift(pair).await.unwrap();
+        let issues = reconciler.reconcile().await.unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].pair_address, hex::encode(pair));
+        assert_eq!(issues[0].stored_reserve_x.0, "1");
+
+        let stored = db::get_reconciliation_issues(&db, 10).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert!(stored[0].reindexed);
+        let pool = db::get_pool(&db, &hex::encode(pair)).await.unwrap().unwrap();
+        let state = server.chain().pairs[&pair].clone();
+        assert_eq!(pool.token_x_reserves.0, state.reserve0.to_string());
+        assert_eq!(pool.total_swap_count, 1);
+        assert!(reconciler.reconcile().await.unwrap().is_empty());
+
+        // the reindex of the first drifted pool fails, the issue of the other one is still recorded
+        drift(pair).await.unwrap();
+        drift(other).await.unwrap();
+        server.chain().fail_next("eth_getLogs", -32000, "node unavailable");
+        assert_eq!(reconciler.reconcile().await.unwrap().len(), 2);
+        let stored = db::get_reconciliation_issues(&db, 2).await.unwrap();
+        let mut pairs: Vec<_> = stored.iter().map(|i| i.pair_address.clone()).collect();
+        pairs.sort();
+        let mut expected = vec![hex::encode(pair), hex::encode(other)];
+        expected.sort();
+        assert_eq!(pairs, expected);
+        assert_eq!(stored.iter().filter(|i| i.reindexed).count(), 1);
+        // the next sample reindexes the pool left drifted
+        assert_eq!(reconciler.reconcile().await.unwrap().len(), 1);
+        assert!(reconciler.reconcile().await.unwrap().is_empty());
+        assert!(reconciler.reconcile().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,160 @@
+//! Erc20 metadata lookup that tolerates non standard tokens.
+//!
+//! `symbol()` and `name()` are decoded as `string` or as `bytes32` (MKR, SAI), and a
+//! reverting or missing getter falls back to placeholder metadata instead of failing the
+//! sync. The token is then stored with `metadata_status` `partial` or `failed` and looked
+//! up again by `ChainWatcher::retry_token_metadata`.
+use ethabi::ParamType;
+use rbatis::rbdc::decimal::Decimal;
+use std::str::FromStr;
+use web3::types::{H160, U256};
+use crate::db::tables::Token;
+use crate::watcher::multicall::{Multicall, ViewCall};
+
+const SYMBOL: [u8; 4] = [0x95, 0xd8, 0x9b, 0x41];
+const NAME: [u8; 4] = [0x06, 0xfd, 0xde, 0x03];
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+const TOTAL_SUPPLY: [u8; 4] = [0x18, 0x16, 0x0d, 0xdd];
+
+pub const PLACEHOLDER_SYMBOL: &str = "UNKNOWN";
+pub const PLACEHOLDER_NAME: &str = "Unknown Token";
+/// `decimals()` is optional in erc20, a token without it has no fractional unit
+pub const PLACEHOLDER_DECIMALS: u8 = 0;
+/// failed lookups are retried on every sync until this many attempts were made
+pub const MAX_METADATA_ATTEMPTS: i64 = 10;
+
+pub const STATUS_OK: &str = "ok";
+pub const STATUS_PARTIAL: &str = "partial";
+pub const STATUS_FAILED: &str = "failed";
+
+/// decode a `string` or `bytes32` return value
+pub fn decode_string(output: &[u8]) -> Option<String> {
+    let value = if output.len() == 32 {
+        decode_bytes32(output)
+    } else {
+        match ethabi::decode(&[ParamType::String], output) {
+            Ok(tokens) => tokens.into_iter().next().and_then(|t| t.into_string()),
+            Err(_) => decode_bytes32(output.get(..32)?),
+        }
+    }?;
+    let value = value.trim().to_string();
+    if value.is_empty() { None } else { Some(value) }
+}
+
+fn decode_bytes32(word: &[u8]) -> Option<String> {
+    let end = word.iter().position(|b| *b == 0).unwrap_or(word.len());
+    String::from_utf8(word[..end].to_vec()).ok()
+}
+
+fn decode_uint(output: &[u8]) -> Option<U256> {
+    ethabi::decode(&[ParamType::Uint(256)], output).ok()?
+        .into_iter().next()?.into_uint()
+}
+
+/// fetch `symbol()`, `name()`, `decimals()` and `totalSupply()` of the tokens in one batch.
+/// A getter that reverts gets a placeholder, transport errors fail the whole batch so
+/// that nothing is stored while the node is unreachable.
+pub async fn fetch_tokens_metadata(multicall: &Multicall, addresses: &[H160]) -> anyhow::Result<Vec<Token>> {
+    let calls: Vec<ViewCall> = addresses.iter()
+        .flat_map(|a| [SYMBOL, NAME, DECIMALS, TOTAL_SUPPLY].map(|selector| ViewCall::new(*a, selector)))
+        .collect();
+    let outputs = multicall.call(&calls, None).await?;
+    Ok(addresses.iter().zip(outputs.chunks(4)).map(|(address, outputs)| {
+        token_metadata(*address, outputs)
+    }).collect())
+}
+
+fn token_metadata(address: H160, outputs: &[Option<Vec<u8>>]) -> Token {
+    let symbol = outputs[0].as_ref().and_then(|o| decode_string(o));
+    let name = outputs[1].as_ref().and_then(|o| decode_string(o));
+    let decimals = outputs[2].as_ref()
+        .and_then(|o| decode_uint(o))
+        .and_then(|d| if d <= U256::from(u8::MAX) { Some(d.as_u32() as u8) } else { None });
+    let total_supply = outputs[3].as_ref().and_then(|o| decode_uint(o));
+
+    let found = [symbol.is_some(), name.is_some(), decimals.is_some(), total_supply.is_some()];
+    let metadata_status = if found.iter().all(|f| *f) {
+        STATUS_OK
+    } else if found.iter().any(|f| *f) {
+        STATUS_PARTIAL
+    } else {
+        STATUS_FAILED
+    };
+    if metadata_status != STATUS_OK {
+        log::warn!("token {:?} metadata is {}: symbol {:?}, name {:?}, decimals {:?}, total supply {:?}",
+                   address, metadata_status, symbol, name, decimals, total_supply);
+    }
+    Token {
+        address: hex::encode(address.as_bytes()),
+        symbol: symbol.unwrap_or_else(|| PLACEHOLDER_SYMBOL.to_string()),
+        decimals: decimals.unwrap_or(PLACEHOLDER_DECIMALS),
+        name: name.unwrap_or_else(|| PLACEHOLDER_NAME.to_string()),
+        total_supply: total_supply.map(|s| Decimal::from_str(&s.to_string()).unwrap()),
+        metadata_status: metadata_status.to_string(),
+        metadata_attempts: 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::BackendConfig;
+    use crate::db;
+    use crate::db::sqlite::new_test_db;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer, MockToken};
+    use crate::watcher::watch::ChainWatcher;
+
+    #[actix_rt::test]
+    async fn test_token_metadata_fallbacks() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let multicall = chain.deploy_multicall();
+        let standard = chain.deploy_token("TKA", 18);
+        let mkr = chain.deploy_token_with(MockToken { bytes32: true, ..MockToken::new("MKR", 18) });
+        let no_decimals = chain.deploy_token_with(MockToken { decimals: None, ..MockToken::new("ND", 0) });
+        let reverting = chain.deploy_token_with(MockToken::default());
+        let no_code = H160::from_low_u64_be(0xdead);
+        let pairs = [
+            chain.create_pair(standard, mkr),
+            chain.create_pair(no_decimals, reverting),
+            chain.create_pair(standard, no_code),
+        ];
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("token_metadata").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            multicall_address: Some(multicall),
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+        assert_eq!(db::get_all_store_pools(&db).await.unwrap().len(), pairs.len());
+
+        let token = |address: H160| {
+            let db = db.clone();
+            async move { db::get_token(&db, hex::encode(address)).await.unwrap().remove(0) }
+        };
+        let standard = token(standard).await;
+        assert_eq!((standard.symbol.as_str(), standard.name.as_str(), standard.decimals), ("TKA", "TKA Token", 18));
+        assert_eq!(standard.total_supply.unwrap().0, U256::exp10(27).to_string());
+        assert_eq!(standard.metadata_status, STATUS_OK);
+        let mkr = token(mkr).await;
+        assert_eq!((mkr.symbol.as_str(), mkr.name.as_str(), mkr.metadata_status.as_str()), ("MKR", "MKR Token", STATUS_OK));
+        let nd = token(no_decimals).await;
+        assert_eq!((nd.symbol.as_str(), nd.decimals, nd.metadata_status.as_str()), ("ND", PLACEHOLDER_DECIMALS, STATUS_PARTIAL));
+        for address in [reverting, no_code] {
+            let failed = token(address).await;
+            assert_eq!((failed.symbol.as_str(), failed.metadata_status.as_str()), (PLACEHOLDER_SYMBOL, STATUS_FAILED));
+        }
+
+        // the token was fixed (e.g. a proxy upgrade), the next sync picks it up
+        server.chain().tokens.insert(reverting, MockToken::new("FIX", 6));
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let fixed = token(reverting).await;
+        assert_eq!((fixed.symbol.as_str(), fixed.decimals, fixed.metadata_status.as_str()), ("FIX", 6, STATUS_OK));
+        let pool = db::get_all_store_pools(&db).await.unwrap().into_iter()
+            .find(|p| p.pair_address == hex::encode(pairs[1])).unwrap();
+        assert_eq!(pool.token_y_symbol, "FIX");
+        assert_eq!(token(no_code).await.metadata_attempts, 2);
+    }
+}
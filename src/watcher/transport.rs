@@ -0,0 +1,445 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures::future::{BoxFuture, FutureExt};
+use serde::{Deserialize, Serialize};
+use web3::transports::Http;
+use web3::types::{Log, H160, H256};
+use web3::{helpers, BatchTransport, RequestId, Transport};
+use jsonrpc_core as rpc;
+use crate::config::BackendConfig;
+
+/// the json-rpc error code of an `eth_getLogs` range the node rejects as too wide or as
+/// returning too many logs
+pub const LOG_RANGE_TOO_WIDE: i64 = -32005;
+
+/// One json-rpc request and its response, a line of a recorded jsonl file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedCall {
+    pub method: String,
+    pub params: Vec<rpc::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<rpc::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<rpc::Error>,
+}
+
+impl RecordedCall {
+    fn response(&self) -> web3::error::Result<rpc::Value> {
+        match &self.error {
+            Some(e) => Err(web3::Error::Rpc(e.clone())),
+            None => Ok(self.result.clone().unwrap_or(rpc::Value::Null)),
+        }
+    }
+}
+
+/// The transport used by `ChainWatcher`: a live node, a live node whose responses are
+/// also appended to a jsonl file, or a replay of such a file with no network at all. The
+/// requests to a node are retried when `rpc_retries` is set.
+#[derive(Debug, Clone)]
+pub enum WatcherTransport {
+    Http(Http),
+    Record(RecordingTransport),
+    Replay(ReplayTransport),
+    Retry(RetryTransport),
+}
+
+impl WatcherTransport {
+    pub fn from_config(config: &BackendConfig) -> anyhow::Result<Self> {
+        if let Some(path) = &config.replay_log_file {
+            log::info!("replay chain responses from {}", path);
+            return Ok(Self::Replay(ReplayTransport::from_file(path)?));
+        }
+        let mut client = reqwest::Client::builder();
+        if config.rpc_timeout_secs > 0 {
+            client = client.timeout(Duration::from_secs(config.rpc_timeout_secs));
+        }
+        let http = Http::with_client(client.build()?, config.remote_web3_url.parse()?);
+        let transport = match &config.record_log_file {
+            Some(path) => {
+                log::info!("record chain responses to {}", path);
+                Self::Record(RecordingTransport::new(http, path)?)
+            }
+            None => Self::Http(http),
+        };
+        if config.rpc_retries == 0 {
+            return Ok(transport);
+        }
+        Ok(Self::Retry(RetryTransport::new(transport, config.rpc_retries,
+                                           Duration::from_millis(config.rpc_retry_backoff_ms))))
+    }
+}
+
+impl Transport for WatcherTransport {
+    type Out = BoxFuture<'static, web3::error::Result<rpc::Value>>;
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        match self {
+            Self::Http(t) => t.prepare(method, params),
+            Self::Record(t) => t.prepare(method, params),
+            Self::Replay(t) => t.prepare(method, params),
+            Self::Retry(t) => t.inner.prepare(method, params),
+        }
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> Self::Out {
+        match self {
+            Self::Http(t) => t.send(id, request).boxed(),
+            Self::Record(t) => t.send(id, request),
+            Self::Replay(t) => t.send(id, request),
+            Self::Retry(t) => t.send(id, request),
+        }
+    }
+}
+
+impl BatchTransport for WatcherTransport {
+    type Batch = BoxFuture<'static, web3::error::Result<Vec<web3::error::Result<rpc::Value>>>>;
+
+    fn send_batch<T>(&self, requests: T) -> Self::Batch
+    where
+        T: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        match self {
+            Self::Http(t) => t.send_batch(requests),
+            Self::Record(t) => t.send_batch(requests),
+            Self::Replay(t) => t.send_batch(requests),
+            Self::Retry(t) => t.send_batch(requests),
+        }
+    }
+}
+
+fn method_call(request: rpc::Call) -> web3::error::Result<(String, Vec<rpc::Value>)> {
+    match request {
+        rpc::Call::MethodCall(call) => {
+            let params = match call.params {
+                rpc::Params::Array(params) => params,
+                rpc::Params::Map(map) => vec![rpc::Value::Object(map)],
+                rpc::Params::None => vec![],
+            };
+            Ok((call.method, params))
+        }
+        _ => Err(web3::Error::Transport(
+            web3::error::TransportError::Message("only method calls are supported".to_string()))),
+    }
+}
+
+/// Sends the requests through another transport again after a failure of the node, up to
+/// `retries` times, waiting `backoff` before the first retry and twice as long before each
+/// next one. A batch is sent again when it fails as a whole.
+#[derive(Debug, Clone)]
+pub struct RetryTransport {
+    inner: Box<WatcherTransport>,
+    retries: u32,
+    backoff: Duration,
+}
+
+impl RetryTransport {
+    pub fn new(inner: WatcherTransport, retries: u32, backoff: Duration) -> Self {
+        Self { inner: Box::new(inner), retries, backoff }
+    }
+
+    /// whether the request may succeed when sent again: a reverted call or a request the
+    /// node rejects, e.g. a log range too wide, fails again
+    fn is_transient(method: &str, error: &web3::Error) -> bool {
+        match error {
+            web3::Error::Transport(_) | web3::Error::Unreachable => true,
+            web3::Error::Rpc(e) => method != "eth_call" && !matches!(e.code,
+                rpc::ErrorCode::ParseError | rpc::ErrorCode::InvalidRequest | rpc::ErrorCode::MethodNotFound
+                | rpc::ErrorCode::InvalidParams | rpc::ErrorCode::ServerError(LOG_RANGE_TOO_WIDE)),
+            _ => false,
+        }
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> BoxFuture<'static, web3::error::Result<rpc::Value>> {
+        let method = match &request {
+            rpc::Call::MethodCall(call) => call.method.clone(),
+            _ => String::new(),
+        };
+        let this = self.clone();
+        async move {
+            let (mut attempt, mut delay) = (0, this.backoff);
+            loop {
+                match this.inner.send(id, request.clone()).await {
+                    Err(e) if attempt < this.retries && Self::is_transient(&method, &e) => {
+                        attempt += 1;
+                        log::warn!("{} failed, retry {} in {:?}: {:?}", method, attempt, delay, e);
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    response => return response,
+                }
+            }
+        }.boxed()
+    }
+
+    fn send_batch<T>(&self, requests: T) -> BoxFuture<'static, web3::error::Result<Vec<web3::error::Result<rpc::Value>>>>
+    where
+        T: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<(RequestId, rpc::Call)> = requests.into_iter().collect();
+        let this = self.clone();
+        async move {
+            let (mut attempt, mut delay) = (0, this.backoff);
+            loop {
+                match this.inner.send_batch(requests.clone()).await {
+                    Err(e) if attempt < this.retries && Self::is_transient("batch", &e) => {
+                        attempt += 1;
+                        log::warn!("batch of {} requests failed, retry {} in {:?}: {:?}", requests.len(), attempt, delay, e);
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    responses => return responses,
+                }
+            }
+        }.boxed()
+    }
+}
+
+/// Forwards every request to a live node and appends the request and its
+/// response to a jsonl file that `ReplayTransport` can serve later.
+#[derive(Debug, Clone)]
+pub struct RecordingTransport {
+    inner: Http,
+    file: Arc<Mutex<File>>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Http, path: &str) -> anyhow::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { inner, file: Arc::new(Mutex::new(file)) })
+    }
+
+    fn record(file: &Mutex<File>, call: &RecordedCall) {
+        let line = match serde_json::to_string(call) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("serialize recorded call {} failed, {:?}", call.method, e);
+                return;
+            }
+        };
+        let mut file = file.lock().unwrap();
+        if let Err(e) = writeln!(file, "{}", line) {
+            log::warn!("write recorded call {} failed, {:?}", call.method, e);
+        }
+    }
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        self.inner.prepare(method, params)
+    }
+
+    fn send(&self, id: RequestId, request: rpc::Call) -> BoxFuture<'static, web3::error::Result<rpc::Value>> {
+        let (method, params) = match method_call(request.clone()) {
+            Ok(call) => call,
+            Err(e) => return futures::future::ready(Err(e)).boxed(),
+        };
+        let response = self.inner.send(id, request);
+        let file = self.file.clone();
+        async move {
+            let response = response.await;
+            Self::record_response(&file, method, params, &response);
+            response
+        }.boxed()
+    }
+
+    fn record_response(file: &Mutex<File>, method: String, params: Vec<rpc::Value>,
+                       response: &web3::error::Result<rpc::Value>) {
+        let call = match response {
+            Ok(result) => RecordedCall { method, params, result: Some(result.clone()), error: None },
+            Err(web3::Error::Rpc(e)) => RecordedCall { method, params, result: None, error: Some(e.clone()) },
+            // transport failures are not a property of the chain, don't replay them
+            Err(_) => return,
+        };
+        Self::record(file, &call);
+    }
+
+    /// a batch is recorded as its single calls, so it can be replayed batched or not
+    fn send_batch<T>(&self, requests: T) -> BoxFuture<'static, web3::error::Result<Vec<web3::error::Result<rpc::Value>>>>
+    where
+        T: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let requests: Vec<(RequestId, rpc::Call)> = requests.into_iter().collect();
+        let calls = match requests.iter().map(|(_, r)| method_call(r.clone())).collect::<web3::error::Result<Vec<_>>>() {
+            Ok(calls) => calls,
+            Err(e) => return futures::future::ready(Err(e)).boxed(),
+        };
+        let responses = self.inner.send_batch(requests);
+        let file = self.file.clone();
+        async move {
+            let responses = responses.await?;
+            for ((method, params), response) in calls.into_iter().zip(&responses) {
+                Self::record_response(&file, method, params, response);
+            }
+            Ok(responses)
+        }.boxed()
+    }
+}
+
+/// Serves json-rpc responses from a jsonl file written by `RecordingTransport`.
+///
+/// A request is answered by the recorded call with the same method and params. When
+/// the same request was recorded several times the responses are served in order and
+/// the last one is repeated, e.g. successive `eth_blockNumber` polls. An `eth_getLogs`
+/// request that was never recorded as such (a different sync step, say) is answered
+/// by filtering all recorded logs by its address, topics and block range.
+#[derive(Debug, Clone)]
+pub struct ReplayTransport {
+    calls: Arc<Mutex<Vec<(RecordedCall, bool)>>>,
+    logs: Arc<Vec<Log>>,
+    id: Arc<AtomicUsize>,
+}
+
+impl ReplayTransport {
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut calls = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let call: RecordedCall = serde_json::from_str(&line)
+                .map_err(|e| anyhow::format_err!("{}:{} is not a recorded call: {}", path, number + 1, e))?;
+            calls.push(call);
+        }
+        Ok(Self::new(calls))
+    }
+
+    pub fn new(calls: Vec<RecordedCall>) -> Self {
+        let mut logs: Vec<Log> = Vec::new();
+        for call in calls.iter().filter(|c| c.method == "eth_getLogs") {
+            let recorded: Vec<Log> = call.result.clone()
+                .and_then(|r| serde_json::from_value(r).ok())
+                .unwrap_or_default();
+            for log in recorded {
+                let duplicated = logs.iter().any(|l| {
+                    l.transaction_hash == log.transaction_hash && l.log_index == log.log_index
+                        && l.block_number == log.block_number
+                });
+                if !duplicated {
+                    logs.push(log);
+                }
+            }
+        }
+        logs.sort_by_key(|l| (l.block_number, l.log_index));
+        Self {
+            calls: Arc::new(Mutex::new(calls.into_iter().map(|c| (c, false)).collect())),
+            logs: Arc::new(logs),
+            id: Default::default(),
+        }
+    }
+
+    fn prepare(&self, method: &str, params: Vec<rpc::Value>) -> (RequestId, rpc::Call) {
+        let id = self.id.fetch_add(1, Ordering::AcqRel);
+        (id, helpers::build_request(id, method, params))
+    }
+
+    fn send(&self, _id: RequestId, request: rpc::Call) -> BoxFuture<'static, web3::error::Result<rpc::Value>> {
+        let response = method_call(request)
+            .and_then(|(method, params): (String, Vec<rpc::Value>)| self.replay(&method, &params));
+        futures::future::ready(response).boxed()
+    }
+
+    fn send_batch<T>(&self, requests: T) -> BoxFuture<'static, web3::error::Result<Vec<web3::error::Result<rpc::Value>>>>
+    where
+        T: IntoIterator<Item = (RequestId, rpc::Call)>,
+    {
+        let mut responses = Vec::new();
+        for (_, request) in requests {
+            let response = method_call(request)
+                .and_then(|(method, params): (String, Vec<rpc::Value>)| self.replay(&method, &params));
+            match response {
+                Err(web3::Error::Transport(e)) => return futures::future::ready(Err(web3::Error::Transport(e))).boxed(),
+                response => responses.push(response),
+            }
+        }
+        futures::future::ready(Ok(responses)).boxed()
+    }
+
+    fn replay(&self, method: &str, params: &[rpc::Value]) -> web3::error::Result<rpc::Value> {
+        {
+            let mut calls = self.calls.lock().unwrap();
+            let mut matched = calls.iter_mut()
+                .filter(|(c, _)| c.method == method && c.params == params)
+                .peekable();
+            let mut last = None;
+            while let Some((call, served)) = matched.next() {
+                if !*served || matched.peek().is_none() {
+                    *served = true;
+                    last = Some(call.response());
+                    break;
+                }
+            }
+            if let Some(response) = last {
+                return response;
+            }
+        }
+        if method == "eth_getLogs" {
+            if let Some(filter) = params.first() {
+                return self.filter_logs(filter);
+            }
+        }
+        Err(web3::Error::Transport(web3::error::TransportError::Message(
+            format!("no recorded response for {} {}", method, rpc::Value::Array(params.to_vec())))))
+    }
+
+    fn filter_logs(&self, filter: &rpc::Value) -> web3::error::Result<rpc::Value> {
+        let filter = LogFilter::parse(filter, u64::MAX)?;
+        let logs: Vec<&Log> = self.logs.iter().filter(|log| filter.matches(log)).collect();
+        serde_json::to_value(logs).map_err(|e| web3::Error::Decoder(e.to_string()))
+    }
+}
+
+/// The address, topics and block range of an `eth_getLogs` filter object,
+/// applied the way a node would apply them.
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    pub from: u64,
+    pub to: u64,
+    pub addresses: Vec<H160>,
+    pub topics: Vec<Vec<H256>>,
+}
+
+impl LogFilter {
+    /// `latest` is the block number the `latest` and `pending` tags resolve to
+    pub fn parse(filter: &rpc::Value, latest: u64) -> web3::error::Result<Self> {
+        let invalid = |field: &str| web3::Error::InvalidResponse(format!("invalid eth_getLogs {}", field));
+        let block = |field: &str, default: u64| -> web3::error::Result<u64> {
+            match filter.get(field).and_then(|b| b.as_str()) {
+                None => Ok(default),
+                Some("latest") | Some("pending") => Ok(latest),
+                Some("earliest") => Ok(0),
+                Some(number) => u64::from_str_radix(number.trim_start_matches("0x"), 16)
+                    .map_err(|_| invalid(field)),
+            }
+        };
+        let from = block("fromBlock", latest)?;
+        let to = block("toBlock", latest)?;
+        // an empty address list means any address, the same as a missing one
+        let addresses: Vec<H160> = match filter.get("address") {
+            None | Some(rpc::Value::Null) => vec![],
+            Some(rpc::Value::Array(a)) => serde_json::from_value(rpc::Value::Array(a.clone()))
+                .map_err(|_| invalid("address"))?,
+            Some(a) => vec![serde_json::from_value::<H160>(a.clone()).map_err(|_| invalid("address"))?],
+        };
+        let topics: Vec<Vec<H256>> = match filter.get("topics") {
+            Some(rpc::Value::Array(topics)) => topics.iter().map(|t| match t {
+                rpc::Value::Null => Ok(vec![]),
+                rpc::Value::Array(_) => serde_json::from_value(t.clone()).map_err(|_| invalid("topics")),
+                _ => Ok(vec![serde_json::from_value::<H256>(t.clone()).map_err(|_| invalid("topics"))?]),
+            }).collect::<web3::error::Result<_>>()?,
+            _ => vec![],
+        };
+        Ok(Self { from, to, addresses, topics })
+    }
+
+    pub fn matches(&self, log: &Log) -> bool {
+        let number = log.block_number.map(|n| n.as_u64()).unwrap_or_default();
+        number >= self.from && number <= self.to
+            && (self.addresses.is_empty() || self.addresses.contains(&log.address))
+            && self.topics.iter().enumerate().all(|(i, wanted)| {
+                wanted.is_empty() || log.topics.get(i).map(|t| wanted.contains(t)).unwrap_or(false)
+            })
+    }
+}
@@ -0,0 +1,64 @@
+//! Senders of the transactions of the indexed events.
+//!
+//! A log names the contracts and the accounts passed to the pool, usually a router, not the
+//! account that signed the transaction. The transactions are fetched with
+//! `eth_getTransactionByHash` by a `BatchFetcher`, which keeps their senders as the events
+//! of a transaction are synced by topic.
+use std::collections::HashMap;
+use anyhow::format_err;
+use jsonrpc_core as rpc;
+use web3::types::{H160, H256};
+use web3::{helpers, Web3};
+use crate::watcher::batch_fetch::BatchFetcher;
+use crate::watcher::transport::WatcherTransport;
+
+#[derive(Debug, Clone)]
+pub struct TxOrigins(BatchFetcher<H256, H160>);
+
+impl TxOrigins {
+    pub fn new(web3: Web3<WatcherTransport>) -> Self {
+        Self(BatchFetcher::new(web3, "eth_getTransactionByHash", |tx| vec![helpers::serialize(tx)], |tx, value| {
+            // a node without the transaction, e.g. one pruning old ones, answers null
+            if value == rpc::Value::Null {
+                log::warn!("transaction {:?} not found, its events are stored without a sender", tx);
+                return Ok(None);
+            }
+            let origin: H160 = value.get("from").cloned()
+                .and_then(|from| serde_json::from_value(from).ok())
+                .ok_or_else(|| format_err!("transaction {:?} has no sender", tx))?;
+            Ok(Some(origin))
+        }))
+    }
+
+    /// the sender of every transaction of `txs` the node has
+    pub async fn get(&self, txs: &[H256]) -> anyhow::Result<HashMap<H256, H160>> {
+        self.0.get(txs).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer};
+    use web3::transports::Http;
+
+    #[actix_rt::test]
+    async fn test_tx_origins() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let token_a = chain.deploy_token("TKA", 18);
+        let token_b = chain.deploy_token("TKB", 18);
+        chain.create_pair(token_a, token_b);
+        let (mined, origin) = (chain.logs[0].transaction_hash.unwrap(), chain.tx_origin);
+        let server = MockRpcServer::start(chain);
+        let origins = TxOrigins::new(Web3::new(WatcherTransport::Http(Http::new(&server.url).unwrap())));
+
+        // a transaction the node doesn't have is left out
+        let unknown = H256::from_low_u64_be(0xdead);
+        let found = origins.get(&[mined, unknown, mined]).await.unwrap();
+        assert_eq!(found, HashMap::from([(mined, origin)]));
+        assert_eq!(std::mem::take(&mut server.chain().requests).len(), 2);
+        // the sender found is cached, the missing one asked again
+        assert_eq!(origins.get(&[mined, unknown]).await.unwrap().len(), 1);
+        assert_eq!(server.chain().requests, vec!["eth_getTransactionByHash"]);
+    }
+}
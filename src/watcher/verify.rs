@@ -0,0 +1,177 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::str::FromStr;
+use ethabi::ParamType;
+use web3::types::{H160, U256};
+use crate::amm::v2::PairSimulator;
+use crate::db;
+use crate::protocol::uniswap_v2;
+use crate::watcher::multicall::ViewCall;
+use crate::watcher::watch::ChainWatcher;
+
+const VERIFY_STEP: u64 = 1000;
+const FEE_TO: [u8; 4] = [0x01, 0x7e, 0x7e, 0x58];
+
+/// whether `feeTo()` of each factory is set at `block`, a factory whose call fails has none
+async fn fee_on(watcher: &ChainWatcher, factories: &[H160], block: u64) -> anyhow::Result<HashMap<H160, bool>> {
+    let calls: Vec<ViewCall> = factories.iter().map(|f| ViewCall::new(*f, FEE_TO)).collect();
+    let outputs = watcher.multicall.call(&calls, Some(block)).await?;
+    Ok(factories.iter().zip(outputs).map(|(factory, output)| {
+        let fee_to = output
+            .and_then(|output| ethabi::decode(&[ParamType::Address], &output).ok())
+            .and_then(|mut tokens| tokens.pop()?.into_address());
+        (*factory, fee_to.map(|a| !a.is_zero()).unwrap_or_default())
+    }).collect())
+}
+
+/// Replay the events of every stored pool up to the last synced block through the
+/// reference pair simulator and compare the result with the stored pool state.
+/// The protocol fee is simulated for the pairs of factories whose `feeTo()` is set at the
+/// last synced block. Returns one line per problem found, empty when the database is consistent.
+pub async fn verify_pools(watcher: &mut ChainWatcher) -> anyhow::Result<Vec<String>> {
+    // the reference simulator is a V2 pair
+    let pools: Vec<_> = db::get_all_store_pools(&watcher.db).await?.into_iter()
+        .filter(|p| p.protocol == uniswap_v2::NAME)
+        .collect();
+    let last_synced_block = db::get_last_sync_block(&watcher.db).await?;
+    let mut problems = Vec::new();
+    if pools.is_empty() {
+        return Ok(problems);
+    }
+    let pairs: Vec<H160> = pools.iter().map(|p| H160::from_str(&p.pair_address).unwrap()).collect();
+    let factories = pools.iter()
+        .map(|p| match &p.factory_address {
+            Some(factory) => H160::from_str(factory),
+            None => Ok(watcher.config.contract_address),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut distinct = factories.clone();
+    distinct.sort();
+    distinct.dedup();
+    let fee_on = fee_on(watcher, &distinct, last_synced_block).await?;
+    // a pair stops being simulated after its first inconsistent event
+    let mut simulators: HashMap<H160, Result<PairSimulator, String>> = pairs.iter().zip(&factories)
+        .map(|(p, f)| (*p, Ok(PairSimulator::new(fee_on[f]))))
+        .collect();
+
+    let mut start_block = 1u64;
+    while start_block <= last_synced_block {
+        let end_block = cmp::min(last_synced_block, start_block + VERIFY_STEP - 1);
+        let events = watcher.get_pair_events(start_block, end_block, pairs.clone()).await?;
+        for event in events {
+            let pair_address = event.get_pair_address();
+            if let Some(Ok(simulator)) = simulators.get_mut(&pair_address) {
+                if let Err(e) = simulator.apply(&event) {
+                    simulators.insert(pair_address, Err(e.to_string()));
+                }
+            }
+        }
+        start_block = end_block + 1;
+    }
+
+    for (pool, pair) in pools.iter().zip(pairs) {
+        let mut simulator = match simulators.remove(&pair).unwrap() {
+            Ok(simulator) => simulator,
+            Err(e) => {
+                problems.push(format!("pool {}: invalid event stream, {}", pool.pair_address, e));
+                continue;
+            }
+        };
+        simulator.flush();
+        let stored = (U256::from_dec_str(&pool.token_x_reserves.0).ok(),
+                      U256::from_dec_str(&pool.token_y_reserves.0).ok());
+        if stored != (Some(simulator.reserve0), Some(simulator.reserve1)) {
+            problems.push(format!("pool {}: stored reserves ({}, {}), replayed reserves ({}, {})",
+                                  pool.pair_address, pool.token_x_reserves.0, pool.token_y_reserves.0,
+                                  simulator.reserve0, simulator.reserve1));
+        }
+        if let Some(stored) = &pool.lp_total_supply {
+            if U256::from_dec_str(&stored.0).ok() != Some(simulator.total_supply) {
+                problems.push(format!("pool {}: stored lp supply {}, replayed lp supply {}",
+                                      pool.pair_address, stored.0, simulator.total_supply));
+            }
+        }
+        let counts = [
+            ("swap", pool.total_swap_count, simulator.swap_count),
+            ("add liquidity", pool.total_add_liq_count, simulator.mint_count),
+            ("remove liquidity", pool.total_rm_liq_count, simulator.burn_count),
+        ];
+        for (name, stored, replayed) in counts {
+            if stored as u64 != replayed {
+                problems.push(format!("pool {}: stored {} count {}, replayed {}",
+                                      pool.pair_address, name, stored, replayed));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::BackendConfig;
+    use crate::db::sqlite::new_test_db;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer};
+
+    #[actix_rt::test]
+    async fn test_verify_pools() {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let token_a = chain.deploy_token("TKA", 18);
+        let token_b = chain.deploy_token("TKB", 18);
+        let pair = chain.create_pair(token_a, token_b);
+        let lp = H160::from_low_u64_be(0xaaa);
+        chain.mint(pair, lp, U256::exp10(21), U256::exp10(21));
+        chain.swap(pair, lp, lp, U256::exp10(18), true);
+        chain.burn(pair, lp, lp, U256::exp10(20), U256::exp10(20));
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("verify_pools").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+        assert_eq!(verify_pools(&mut watcher).await.unwrap(), Vec::<String>::new());
+
+        db.exec("update pool_info set token_x_reserves = ?", vec![rbs::to_value!("1")]).await.unwrap();
+        let problems = verify_pools(&mut watcher).await.unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("stored reserves (1, "));
+    }
+
+    #[actix_rt::test]
+    async fn test_verify_pools_fee_on() {
+        assert_eq!(FEE_TO, ethabi::short_signature("feeTo", &[]));
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        chain.fee_to = Some(H160::from_low_u64_be(0xfee));
+        let token_a = chain.deploy_token("TKA", 18);
+        let token_b = chain.deploy_token("TKB", 18);
+        let pair = chain.create_pair(token_a, token_b);
+        let lp = H160::from_low_u64_be(0xaaa);
+        chain.mint(pair, lp, U256::exp10(21), U256::exp10(21));
+        chain.swap(pair, lp, lp, U256::exp10(20), true);
+        chain.mint(pair, lp, U256::exp10(20), U256::exp10(20));
+        chain.swap(pair, lp, lp, U256::exp10(20), false);
+        chain.burn(pair, lp, lp, U256::exp10(20), U256::exp10(20));
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("verify_pools_fee_on").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let pools = db::get_all_store_pools(&db).await.unwrap();
+        assert_eq!(pools[0].lp_total_supply.clone().unwrap().0,
+                   server.chain().pairs[&pair].total_supply.to_string());
+        assert_eq!(verify_pools(&mut watcher).await.unwrap(), Vec::<String>::new());
+
+        // replayed without the protocol fee the supply falls short
+        server.chain().fee_to = None;
+        let problems = verify_pools(&mut watcher).await.unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("stored lp supply"));
+    }
+}
@@ -1,39 +1,61 @@
-use std::time::Duration;
-use ethabi::{decode, ParamType, Address};
-use std::fmt::Debug;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use web3::{
-    transports::http,
     types::{BlockNumber, FilterBuilder, Log},
     Web3,
 };
 use crate::config::BackendConfig;
-use crate::db::tables::{Event, PoolInfo, LastSyncBlock, Token};
+use crate::db::tables::{PoolInfo, LastSyncBlock, FailedLog, V3Pool};
 use crate::db;
-use web3::types::{H160, H256, CallRequest, Bytes};
-use web3::transports::Http;
-use std::convert::{TryFrom, TryInto};
-use web3::ethabi::Uint;
-use std::collections::HashMap;
-use std::cmp::min;
+use crate::db::token_cache::TokenCache;
+use crate::db::pool_graph::PoolGraph;
+use crate::db::prices::{self, Pricing};
+use web3::types::{H160, H256, U256};
+use jsonrpc_core::ErrorCode;
+use crate::watcher::transport::{WatcherTransport, LOG_RANGE_TOO_WIDE};
+use crate::watcher::reconcile::Reconciler;
+use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use anyhow::format_err;
 use std::cmp;
 use tokio::task::JoinHandle;
-use anyhow::format_err;
-use num::BigUint;
-use futures::FutureExt;
-use web3::contract::{Contract, Options};
+use futures::future::BoxFuture;
+use rbatis::executor::RBatisTxExecutor;
 use rbatis::rbdc::decimal::Decimal;
 use std::str::FromStr;
-use crate::watcher::event::{ PairCreatedEvent, PairEvent};
+use crate::watcher::event::{EventData, PairEvent, PairTransferEvent};
+use crate::protocol::{self, PoolCreated, PoolEvent, PoolState, ProtocolAdapter};
+use crate::protocol::uniswap_v3::{self, V3PoolEvent};
+use crate::watcher::token::{fetch_tokens_metadata, MAX_METADATA_ATTEMPTS};
+use crate::watcher::multicall::Multicall;
+use crate::watcher::block_time::BlockTimes;
+use crate::watcher::tx_origin::TxOrigins;
 
-const FACTORY_EVENTS: &str = include_str!("../abi/factory_abi.json");
-const PAIR_EVENTS: &str = include_str!("../abi/pair_abi.json");
+const REINDEX_STEP: u64 = 1000;
+/// the pools reindexed by a backfill between two syncs
+const BACKFILL_BATCH: u64 = 10;
+const MAX_BACKFILL_ATTEMPTS: i64 = 3;
+
+/// A factory and the pools it created, indexed by the adapter of its protocol.
+pub struct Factory {
+    pub address: H160,
+    pub protocol: Arc<dyn ProtocolAdapter>,
+    pub pools: Vec<H160>,
+}
 
 pub struct ChainWatcher {
     pub config: BackendConfig,
-    pub web3: Web3<Http>,
+    pub web3: Web3<WatcherTransport>,
     pub db: rbatis::Rbatis,
-    pub all_pairs: Vec<H160>,
-    pub pair_topics: HashMap<String,H256>,
+    pub factories: Vec<Factory>,
+    pub token_cache: TokenCache,
+    pub pool_graph: PoolGraph,
+    pub multicall: Multicall,
+    pub block_times: BlockTimes,
+    pub tx_origins: TxOrigins,
+    pub pricing: Pricing,
+    /// held while a block range is synced or a pool is reindexed, shared with the reconciler
+    /// so that a reindex never rewinds a pool past a range stored meanwhile
+    pub sync_lock: Arc<tokio::sync::Mutex<()>>,
 }
 impl ChainWatcher {
     // pub fn build_contract(abi_string: &str,web3_url:&str,contract_address:&str) -> Contract<Provider<Http>>{
@@ -68,254 +90,1225 @@ impl ChainWatcher {
     //     Self::build_contract(abi_string,&config.remote_web3_url,&token_address)
     // }
     //
-    pub async fn get_token_symbol(&mut self, address: H160) ->anyhow::Result<String> {
-        //todo: use memory cache
-        let abi_string = r#"[ {
-              "constant": true,
-              "inputs": [],
-              "name": "symbol",
-              "outputs": [
-                {
-                  "internalType": "string",
-                  "name": "",
-                  "type": "string"
-                }
-              ],
-              "payable": false,
-              "stateMutability": "view",
-              "type": "function"
-            },
-            {
-              "constant": true,
-              "inputs": [],
-              "name": "decimals",
-              "outputs": [
-                {
-                  "internalType": "uint8",
-                  "name": "",
-                  "type": "uint8"
-                }
-              ],
-              "payable": false,
-              "stateMutability": "view",
-              "type": "function"
+    /// symbols of the tokens from the cache, the db or, in one batch, the chain
+    pub async fn get_token_symbols(&mut self, addresses: &[H160]) ->anyhow::Result<HashMap<H160, String>> {
+        let mut symbols = HashMap::new();
+        let mut missing = Vec::new();
+        for address in addresses {
+            if symbols.contains_key(address) || missing.contains(address) {
+                continue;
             }
-        ]"#;
-        let token= db::get_token(&self.db,hex::encode(address.as_bytes())).await?;
-        println!("get token is {:?}",token);
-        let token_symbol = if token.is_empty() {
-            //get from chain
-            let erc20_abi = ethabi::Contract::load(abi_string.as_bytes()).unwrap();
-            let erc20_contract = Contract::new(self.web3.eth(), address, erc20_abi);
-            let symbol:String = erc20_contract.query("symbol",(),None, Options::default(), None)
-                .await?;
-            let decimals: u8 = erc20_contract.query("decimals",(),None, Options::default(), None)
-                .await?;
-            let new_token = Token {
-                address: hex::encode(address.as_bytes()),
-                symbol: symbol.clone(),
-                decimals
-            };
-            /// ignore the error
-            /// todo: should use another task to save in batches
-            db::save_token(&mut self.db,new_token).await;
-            symbol
-        } else {
-            token[0].symbol.clone()
-        };
-        Ok(token_symbol)
-    }
-
-    pub fn get_topics() -> HashMap<String,H256> {
-        let mut topics = HashMap::new();
-        let factory_contract = ethabi::Contract::load(FACTORY_EVENTS.as_bytes()).unwrap();
-        let create_topic = factory_contract
-            .event("PairCreated")
-            .expect("factory contract abi error")
-            .signature();
-
-        topics.insert(String::from("create_pair"),H256::from(create_topic.0));
-
-        let pair_contract = ethabi::Contract::load(PAIR_EVENTS.as_bytes()).unwrap();
-        let mint_topic = pair_contract
-            .event("Mint")
-            .expect("pair contract abi error")
-            .signature();
-        let burn_topic = pair_contract
-            .event("Burn")
-            .expect("pair contract abi error")
-            .signature();
-        let swap_topic = pair_contract
-            .event("Swap")
-            .expect("pair contract abi error")
-            .signature();
-        let sync_topic = pair_contract
-            .event("Sync")
-            .expect("pair contract abi error")
-            .signature();
-        topics.insert(String::from("mint"),H256::from(mint_topic.0));
-        topics.insert(String::from("burn"),H256::from(burn_topic.0));
-        topics.insert(String::from("swap"),H256::from(swap_topic.0));
-        topics.insert(String::from("sync"),H256::from(sync_topic.0));
-        topics
+            let key = hex::encode(address.as_bytes());
+            if let Some(token) = self.token_cache.get(&key) {
+                symbols.insert(*address, token.symbol);
+            } else if let Some(token) = db::get_token(&self.db, key).await?.pop() {
+                symbols.insert(*address, token.symbol.clone());
+                self.token_cache.put(token);
+            } else {
+                missing.push(*address);
+            }
+        }
+        //get from chain, a token with non standard metadata gets placeholders
+        for (address, token) in missing.iter().zip(fetch_tokens_metadata(&self.multicall, &missing).await?) {
+            symbols.insert(*address, token.symbol.clone());
+            self.token_cache.save(token)?;
+        }
+        Ok(symbols)
+    }
+
+    /// look up the metadata of tokens stored with placeholders again
+    pub async fn retry_token_metadata(&mut self) -> anyhow::Result<()> {
+        let tokens = db::get_tokens_to_retry(&self.db, MAX_METADATA_ATTEMPTS).await?;
+        if tokens.is_empty() {
+            return Ok(());
+        }
+        let addresses = tokens.iter().map(|t| H160::from_str(&t.address)).collect::<Result<Vec<_>, _>>()?;
+        let new_tokens = fetch_tokens_metadata(&self.multicall, &addresses).await?;
+        for (token, mut new_token) in tokens.into_iter().zip(new_tokens) {
+            new_token.metadata_attempts = token.metadata_attempts + 1;
+            if new_token.metadata_status != token.metadata_status {
+                log::info!("token {} metadata is {} now, symbol {}",
+                           token.address, new_token.metadata_status, new_token.symbol);
+            }
+            db::update_token_metadata(&self.db, &new_token).await?;
+            self.token_cache.put(new_token);
+        }
+        Ok(())
     }
+
     pub async fn new(config:BackendConfig,db: rbatis::Rbatis) -> anyhow::Result<Self> {
-        let transport = web3::transports::Http::new(&config.remote_web3_url).unwrap();
+        let transport = WatcherTransport::from_config(&config)?;
+        let token_cache = TokenCache::start(db.clone(), config.token_cache_size as usize).await?;
+        let pool_graph = PoolGraph::load(&db, &config).await?;
+        Self::new_with_transport(config, db, transport, token_cache, pool_graph).await
+    }
+
+    pub async fn new_with_transport(
+        config:BackendConfig,
+        db: rbatis::Rbatis,
+        transport: WatcherTransport,
+        token_cache: TokenCache,
+        pool_graph: PoolGraph,
+    ) -> anyhow::Result<Self> {
         let web3 = Web3::new(transport);
-        let topics = Self::get_topics();
-        let pools = db::get_all_store_pools(&db).await?;
-        let all_pairs: Vec<H160> = pools.iter().map(|p| H160::from_str(&p.pair_address).unwrap()).collect();
+        let block_times = BlockTimes::new(web3.clone());
+        let tx_origins = TxOrigins::new(web3.clone());
+        let multicall = Multicall::new(web3.clone(), config.multicall_address,
+                                       config.multicall_batch_size as usize);
+        let mut factories = vec![Factory {
+            address: config.contract_address,
+            protocol: protocol::adapter_from_config(&config)?,
+            pools: vec![],
+        }];
+        if let Some(address) = config.v3_factory_address {
+            factories.push(Factory { address, protocol: protocol::adapter(uniswap_v3::NAME)?, pools: vec![] });
+        }
+        for factory in &factories {
+            db::set_missing_pool_factory(&db, factory.protocol.name(), &hex::encode(factory.address)).await?;
+        }
+        for pool in db::get_all_store_pools(&db).await? {
+            match factories.iter_mut().find(|f| f.protocol.name() == pool.protocol) {
+                Some(factory) => factory.pools.push(H160::from_str(&pool.pair_address)?),
+                None => log::warn!("pool {} of protocol {} has no factory configured, it isn't synced",
+                                   pool.pair_address, pool.protocol),
+            }
+        }
         Ok(Self {
             web3,
+            pricing: Pricing::from_config(&config),
             config,
             db,
-            all_pairs,
-            pair_topics:topics
+            factories,
+            token_cache,
+            pool_graph,
+            multicall,
+            block_times,
+            tx_origins,
+            sync_lock: Arc::default(),
         })
     }
 
+    /// the stored reserves of `pairs` in the pool graph, the events are stored even when it fails
+    async fn reload_pool_graph(&self, pairs: &[String]) {
+        if let Err(e) = self.pool_graph.reload(&self.db, &self.config, pairs).await {
+            log::error!("reload pool graph failed {:?}", e);
+        }
+    }
+
+    /// the factory that created `pool`
+    fn factory_of(&self, pool: H160) -> Option<&Factory> {
+        self.factories.iter().find(|f| f.pools.contains(&pool))
+    }
+
     async fn sync_pair_created_events(
         &mut self,
         from: u64,
         to: u64,
-    ) -> anyhow::Result<()> {
-        let create_pair_topic = self.pair_topics.get(&String::from("create_pair")).unwrap().clone();
-        println!("sync_pair_created_events {:?} {:?} {:?}",from,to,create_pair_topic);
-        let logs: Vec<PairCreatedEvent> = self.sync_events(from,to,
-                         vec![self.config.contract_address],
-                         vec![create_pair_topic]).await?;
+        factory: usize,
+    ) -> anyhow::Result<Vec<(PoolInfo, Option<V3Pool>)>> {
+        let address = self.factories[factory].address;
+        let protocol = self.factories[factory].protocol.clone();
+        log::debug!("sync_pair_created_events {:?} {:?} {:?}",from,to,protocol.factory_topics());
+        let logs: Vec<PoolCreated> = self.sync_events(from,to,
+                         vec![address],
+                         protocol.factory_topics(),
+                         |log| protocol.decode_factory_log(log)).await?;
+        self.new_pools(factory, logs).await
+    }
+
+    /// store the pools created by `logs` on their own, out of the block ranges of the sync
+    async fn save_pair_created_events(&mut self, factory: usize, logs: Vec<PoolCreated>) -> anyhow::Result<()> {
+        let pools = self.new_pools(factory, logs).await?;
+        self.token_cache.flush().await?;
+        let mut tx = self.db.acquire_begin().await?;
+        let result = save_pools(&mut tx, &pools).await;
+        db::finish(tx, result).await
+    }
+
+    /// the rows of the pools created by `logs`, synced with the pools of the factory from now on
+    async fn new_pools(&mut self, factory: usize, logs: Vec<PoolCreated>)
+        -> anyhow::Result<Vec<(PoolInfo, Option<V3Pool>)>> {
+        let protocol = self.factories[factory].protocol.name();
+        let tokens: Vec<H160> = logs.iter()
+            .flat_map(|e| [e.token0, e.token1])
+            .collect();
+        let symbols = self.get_token_symbols(&tokens).await?;
+        let mut pools = Vec::with_capacity(logs.len());
         for event in logs {
-            let token_x_symbol = self.get_token_symbol(event.token0_address).await.unwrap();
-            let token_y_symbol = self.get_token_symbol(event.token1_address).await.unwrap();
-            println!("Get PairCreated event : pair_address = {:?}, token0 {} address is {:?}, \
-            token1 {} address is {:?}",event.pair_address.to_string(),
-                     token_x_symbol,
-                     hex::encode(event.token0_address),
-                     token_y_symbol,
-                     hex::encode(event.token1_address));
+            let token_x_symbol = symbols[&event.token0].clone();
+            let token_y_symbol = symbols[&event.token1].clone();
+            log::debug!("Get PairCreated event : pair_address = {:?}, token0 {} address is {:?}, \
+            token1 {} address is {:?}",event.pool.to_string(),
+                        token_x_symbol,
+                        hex::encode(event.token0),
+                        token_y_symbol,
+                        hex::encode(event.token1));
             let pool = PoolInfo {
-                pair_address: hex::encode(event.pair_address),
+                pair_address: hex::encode(event.pool),
                 token_x_symbol,
                 token_y_symbol,
-                token_x_address: hex::encode(event.token0_address),
-                token_y_address: hex::encode(event.token1_address),
+                token_x_address: hex::encode(event.token0),
+                token_y_address: hex::encode(event.token1),
                 token_x_reserves: Decimal::from_str("0").unwrap(),
                 token_y_reserves: Decimal::from_str("0").unwrap(),
                 total_swap_count: 0,
                 total_add_liq_count: 0,
-                total_rm_liq_count: 0
+                total_rm_liq_count: 0,
+                protocol: protocol.to_string(),
+                factory_address: Some(hex::encode(self.factories[factory].address)),
+                lp_total_supply: self.factories[factory].protocol.lp_transfer_topic()
+                    .map(|_| Decimal::from_str("0").unwrap()),
+                tvl_usd: None,
+                volume_usd: Some(Decimal::from_str("0").unwrap()),
+                created_block: Some(event.block_number as i64),
+            };
+            let v3_pool = match (event.fee, event.tick_spacing) {
+                (Some(fee), Some(tick_spacing)) => Some(V3Pool {
+                    pair_address: hex::encode(event.pool),
+                    fee: fee as i64,
+                    tick_spacing: tick_spacing as i64,
+                    sqrt_price_x96: Decimal::from_str("0").unwrap(),
+                    tick: 0,
+                    liquidity: Decimal::from_str("0").unwrap(),
+                }),
+                _ => None,
             };
+            // a range synced again after a failure creates its pools again
+            if !self.factories[factory].pools.contains(&event.pool) {
+                self.factories[factory].pools.push(event.pool);
+            }
+            pools.push((pool, v3_pool));
+        }
+        Ok(pools)
+    }
 
-            self.all_pairs.push(event.pair_address);
-            /// todo: should use another task to save in batches
-            db::save_pool(&mut self.db,&pool).await?;
+    /// the events of the pools of `factory` with `topics` in the block range, in chain order,
+    /// with their LP amounts, block times and transaction senders
+    async fn sync_pair_events(
+        &mut self,
+        from: u64,
+        to: u64,
+        factory: usize,
+        topics: Vec<H256>,
+        transfers: &[PairTransferEvent],
+    ) -> anyhow::Result<Vec<PoolEvent>> {
+        let pools = self.factories[factory].pools.clone();
+        // an empty address filter would match the logs of every contract
+        if pools.is_empty() {
+            return Ok(vec![]);
         }
+        let protocol = self.factories[factory].protocol.clone();
+        let mut logs: Vec<PoolEvent> = self.sync_events(from,to, pools, topics,
+                                                        |log| protocol.decode_pool_log(log)).await?;
+        attach_lp_amounts(transfers, &mut logs);
+        self.attach_chain_data(&mut logs).await?;
+        Ok(logs)
+    }
 
+    /// sync the pools created and the events of every factory in the block range. All of it is
+    /// stored in one transaction that sets the end of the range as the last synced block, so
+    /// a range that fails is synced again from its start and none is stored twice.
+    async fn sync_range(&mut self, from: u64, to: u64) -> anyhow::Result<()> {
+        let sync_lock = self.sync_lock.clone();
+        let _guard = sync_lock.lock().await;
+        let mut pools = Vec::new();
+        for factory in 0..self.factories.len() {
+            pools.extend(self.sync_pair_created_events(from, to, factory).await?);
+        }
+        let mut pair_events = Vec::new();
+        let mut v3_pools = Vec::new();
+        let mut lp_changes = HashMap::new();
+        for factory in 0..self.factories.len() {
+            let protocol = self.factories[factory].protocol.clone();
+            let transfers = match protocol.lp_transfer_topic() {
+                Some(topic) => {
+                    let pools = self.factories[factory].pools.clone();
+                    self.get_lp_transfers(from, to, pools, topic).await?
+                }
+                None => vec![],
+            };
+            lp_changes.extend(lp_supply_changes(&transfers));
+            let mut v3_events: HashMap<H160, Vec<V3PoolEvent>> = HashMap::new();
+            for topics in protocol.pool_topic_groups() {
+                for event in self.sync_pair_events(from, to, factory, topics, &transfers).await? {
+                    match event {
+                        PoolEvent::Pair(event) => pair_events.push(event),
+                        PoolEvent::V3(event) => v3_events.entry(event.meta().address).or_default().push(event),
+                    }
+                }
+            }
+            // concentrated liquidity state depends on the stored state, the ranges before
+            // this one are committed
+            for (pool, mut events) in v3_events {
+                let pair_address = hex::encode(pool);
+                // the liquidity of a position must move once, an event stored before is not
+                // applied again
+                let stored = db::get_stored_logs(&self.db, &pair_address, from, to).await?;
+                events.retain(|e| !stored.contains(&(hex::encode(e.meta().tx_hash), e.meta().log_index as i64)));
+                let mut state = db::get_v3_pool_state(&self.db, &pair_address).await?;
+                for event in &events {
+                    protocol.apply(&mut state, &PoolEvent::V3(event.clone()))?;
+                }
+                v3_pools.push((pair_address, events, state));
+            }
+        }
+        // the pools refer to their tokens
+        self.token_cache.flush().await?;
+        let pairs: BTreeSet<String> = pair_events.iter().map(|e| hex::encode(e.get_pair_address())).collect();
+        let mut tx = self.db.acquire_begin().await?;
+        let result = async {
+            save_pools(&mut tx, &pools).await?;
+            db::add_lp_supply(&mut tx, &lp_changes).await?;
+            db::store_pair_events(&mut tx, pair_events, &self.config, &self.pricing).await?;
+            for (pair_address, events, state) in v3_pools {
+                db::store_v3_pool_events(&mut tx, &pair_address, events, &state, &self.pricing).await?;
+            }
+            db::upsert_last_sync_block(&mut tx, LastSyncBlock { block_number: to as i64 }).await
+        }.await;
+        self.pricing.finish(tx, result).await?;
+        if !pairs.is_empty() {
+            self.reload_pool_graph(&pairs.into_iter().collect::<Vec<_>>()).await;
+        }
         Ok(())
     }
 
-    async fn sync_pair_events(
+    /// Mint, Burn, Swap and Sync events of `pairs` in the block range, in chain order
+    pub async fn get_pair_events(
+        &mut self,
+        from: u64,
+        to: u64,
+        pairs: Vec<H160>,
+    ) -> anyhow::Result<Vec<PairEvent>> {
+        let events = self.get_pool_events(from, to, pairs).await?;
+        Ok(events.into_iter().filter_map(|e| match e {
+            PoolEvent::Pair(e) => Some(e),
+            _ => None,
+        }).collect())
+    }
+
+    /// every indexed event of `pools`, created by one factory, in the block range in chain order
+    pub async fn get_pool_events(
+        &mut self,
+        from: u64,
+        to: u64,
+        pools: Vec<H160>,
+    ) -> anyhow::Result<Vec<PoolEvent>> {
+        Ok(self.fetch_pool_events(from, to, pools).await?.0)
+    }
+
+    /// the events of `pools` in the block range with the LP token transfers from and to the
+    /// zero address the LP amounts of their Mint and Burn events were taken from
+    async fn fetch_pool_events(
+        &mut self,
+        from: u64,
+        to: u64,
+        pools: Vec<H160>,
+    ) -> anyhow::Result<(Vec<PoolEvent>, Vec<PairTransferEvent>)> {
+        let protocol = match pools.first().and_then(|p| self.factory_of(*p)) {
+            Some(factory) => factory.protocol.clone(),
+            None => return Ok((vec![], vec![])),
+        };
+        let mut events = self.sync_events(from, to, pools.clone(), protocol.pool_topics(),
+                                          |log| protocol.decode_pool_log(log)).await?;
+        let transfers = match protocol.lp_transfer_topic() {
+            Some(topic) => self.get_lp_transfers(from, to, pools, topic).await?,
+            None => vec![],
+        };
+        attach_lp_amounts(&transfers, &mut events);
+        Ok((events, transfers))
+    }
+
+    /// the transfers of the LP tokens of `pools` from or to the zero address, in chain order
+    async fn get_lp_transfers(
         &mut self,
         from: u64,
         to: u64,
-        pair_type: &str,
-    ) -> anyhow::Result<()> {
-        let topics = vec![self.pair_topics.get(pair_type).unwrap().clone()];
-        let logs: Vec<PairEvent> = self.sync_events(from,to, self.all_pairs.clone(), topics).await?;
-        if !logs.is_empty() {
-            db::store_pair_events(&mut self.db, logs).await?;
+        pools: Vec<H160>,
+        transfer_topic: H256,
+    ) -> anyhow::Result<Vec<PairTransferEvent>> {
+        if pools.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut transfers: Vec<PairTransferEvent> = Vec::new();
+        let zero = Some(vec![H256::zero()]);
+        for (from_topic, to_topic) in [(zero.clone(), None), (None, zero)] {
+            let filter = FilterBuilder::default()
+                .address(pools.clone())
+                .topics(Some(vec![transfer_topic]), from_topic, to_topic, None);
+            for log in self.get_logs(from, to, filter).await? {
+                match PairTransferEvent::try_from(log) {
+                    Ok(transfer) => transfers.push(transfer),
+                    Err(e) => log::warn!("invalid LP token transfer: {:?}", e),
+                }
+            }
+        }
+        // the minimum liquidity of the first mint goes from and to the zero address, so
+        // both filters match it
+        transfers.sort_by_key(|t| (t.meta.block_number, t.meta.log_index));
+        transfers.dedup_by_key(|t| (t.meta.tx_hash, t.meta.log_index));
+        Ok(transfers)
+    }
+
+    /// set the time of the block and the sender of the transaction of every event
+    async fn attach_chain_data(&mut self, events: &mut [PoolEvent]) -> anyhow::Result<()> {
+        let (blocks, txs): (Vec<u64>, Vec<H256>) = events.iter_mut()
+            .map(|e| (e.meta_mut().block_number, e.meta_mut().tx_hash))
+            .unzip();
+        let times = self.block_times.get(&blocks).await?;
+        let origins = self.tx_origins.get(&txs).await?;
+        for event in events.iter_mut() {
+            let meta = event.meta_mut();
+            meta.block_timestamp = times.get(&meta.block_number).copied();
+            meta.tx_origin = origins.get(&meta.tx_hash).copied();
         }
         Ok(())
     }
+
+    /// the logs of `filter` in the block range, fetched in halves of the range while the node
+    /// rejects it as too wide
+    async fn get_logs(&self, from: u64, to: u64, filter: FilterBuilder) -> anyhow::Result<Vec<Log>> {
+        let mut ranges = vec![(from, to)];
+        let mut logs = Vec::new();
+        while let Some((from, to)) = ranges.pop() {
+            let range_filter = filter.clone()
+                .from_block(BlockNumber::Number(from.into()))
+                .to_block(BlockNumber::Number(to.into()))
+                .build();
+            match self.web3.eth().logs(range_filter).await {
+                Ok(range_logs) => logs.extend(range_logs),
+                Err(web3::Error::Rpc(e)) if e.code == ErrorCode::ServerError(LOG_RANGE_TOO_WIDE) && from < to => {
+                    let middle = from + (to - from) / 2;
+                    log::debug!("logs of blocks {} to {} rejected, {}, split at {}", from, to, e.message, middle);
+                    ranges.push((middle + 1, to));
+                    ranges.push((from, middle));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(logs)
+    }
+
     async fn sync_events<T>(
         &mut self,
         from: u64,
         to: u64,
         address: Vec<H160>,
-        topics: Vec<H256>
-    ) -> anyhow::Result<Vec<T>>
-    where
-        T: TryFrom<Log>,
-        T::Error: Debug,
-    {
+        topics: Vec<H256>,
+        decode: impl Fn(&Log) -> anyhow::Result<T>,
+    ) -> anyhow::Result<Vec<T>> {
         let filter = FilterBuilder::default()
             .address(address)
-            .from_block(BlockNumber::Number(from.into()))
-            .to_block(BlockNumber::Number(to.into()))
-            .topics(Some(topics), None, None, None)
-            .build();
-        let mut logs = self.web3.eth().logs(filter).await?;
-        println!("get logs {:?}",logs);
-        let is_possible_to_sort_logs = logs.iter().all(|log| log.log_index.is_some());
+            .topics(Some(topics), None, None, None);
+        let mut logs = self.get_logs(from, to, filter).await?;
+        log::debug!("get logs {:?}",logs);
+        let is_possible_to_sort_logs = logs.iter().all(|log| log.block_number.is_some() && log.log_index.is_some());
         if is_possible_to_sort_logs {
+            // log_index is only unique within a block
             logs.sort_by_key(|log| {
-                log.log_index
-                    .expect("all logs log_index should have values")
+                (log.block_number.expect("all logs block_number should have values"),
+                 log.log_index.expect("all logs log_index should have values"))
             });
         } else {
-            log::warn!("Some of the log entries does not have log_index, we rely on the provided logs order");
+            log::warn!("Some of the log entries does not have block_number or log_index, we rely on the provided logs order");
         }
 
 
-        logs.into_iter()
-            .map(|event| {
-                T::try_from(event)
-                    .map_err(|e| format_err!("Failed to parse event log from ETH: {:?}", e))
-            })
-            .collect()
+        // a log that can't be decoded is kept in failed_logs, it must not stop the sync
+        let mut events = Vec::with_capacity(logs.len());
+        let mut failed_logs = Vec::new();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        for log in logs {
+            match decode(&log) {
+                Ok(event) => events.push(event),
+                Err(e) => {
+                    log::error!("Failed to parse event log from ETH: {:?}, log {:?}", e, log);
+                    failed_logs.push(FailedLog::new(&log, format!("{:?}", e), now));
+                }
+            }
+        }
+        if !failed_logs.is_empty() {
+            db::save_failed_logs(&self.db, &failed_logs).await?;
+        }
+        Ok(events)
+    }
+
+    /// decode the failed logs an admin asked to retry, and reindex their pairs
+    pub async fn retry_failed_logs(&mut self) -> anyhow::Result<()> {
+        let failed_logs = db::get_failed_logs_to_retry(&self.db).await?;
+        if failed_logs.is_empty() {
+            return Ok(());
+        }
+        let last_synced_block = db::get_last_sync_block(&self.db).await?;
+        let sync_lock = self.sync_lock.clone();
+        for mut failed_log in failed_logs {
+            failed_log.attempts += 1;
+            let guard = sync_lock.lock().await;
+            let result = self.apply_failed_log(&failed_log, last_synced_block).await;
+            drop(guard);
+            match result {
+                Ok(()) => {
+                    log::info!("failed log {}:{} resolved", failed_log.tx_hash, failed_log.log_index);
+                    failed_log.status = "resolved".to_string();
+                }
+                Err(e) => {
+                    log::warn!("retry of failed log {}:{} failed, {:?}", failed_log.tx_hash, failed_log.log_index, e);
+                    failed_log.status = "failed".to_string();
+                    failed_log.error = format!("{:?}", e);
+                }
+            }
+            db::update_failed_log(&self.db, &failed_log).await?;
+        }
+        Ok(())
+    }
+
+    /// a pair event was skipped, so the whole pair is reindexed rather than patched
+    async fn apply_failed_log(&mut self, failed_log: &FailedLog, last_synced_block: u64) -> anyhow::Result<()> {
+        let log = failed_log.to_log()?;
+        let pair = if let Some(factory) = self.factories.iter().position(|f| f.address == log.address) {
+            let event = self.factories[factory].protocol.decode_factory_log(&log)?;
+            let pair = event.pool;
+            if self.factory_of(pair).is_none() {
+                self.save_pair_created_events(factory, vec![event]).await?;
+            }
+            pair
+        } else {
+            let factory = self.factory_of(log.address)
+                .ok_or_else(|| format_err!("log of unknown contract {:?}", log.address))?;
+            factory.protocol.decode_pool_log(&log)?.pool_address()
+        };
+        if self.factory_of(pair).is_some() {
+            self.reindex_pair(pair, last_synced_block).await?;
+        }
+        Ok(())
+    }
+
+    /// queue the pools whose events were stored before the data they now keep: the four
+    /// amounts, direction and flash flag of swaps, the block and time of every event, the
+    /// candles, the statistics and the LP token supply. Each of them is reindexed once.
+    pub async fn enqueue_backfill_jobs(&mut self) -> anyhow::Result<()> {
+        let mut pools = db::get_pools_to_backfill(&self.db).await?;
+        pools.extend(db::get_pools_missing_candles(&self.db).await?);
+        pools.extend(db::get_pools_missing_stats(&self.db).await?);
+        for factory in &self.factories {
+            if factory.protocol.lp_transfer_topic().is_some() {
+                pools.extend(db::get_pools_missing_lp_supply(&self.db, factory.protocol.name()).await?);
+            }
+        }
+        pools.sort();
+        pools.dedup();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        db::enqueue_backfill_jobs(&self.db, &pools, now).await
     }
 
-    async fn run_sync_pair_created_events(&mut self) ->anyhow::Result<()> {
+    /// reindex a batch of the queued pools, recording the outcome of each
+    pub async fn run_backfill_jobs(&mut self) -> anyhow::Result<()> {
+        let jobs = db::get_backfill_jobs(&self.db, MAX_BACKFILL_ATTEMPTS, BACKFILL_BATCH).await?;
+        if jobs.is_empty() {
+            return Ok(());
+        }
         let last_synced_block = db::get_last_sync_block(&self.db).await?;
-        let chain_block_number = self.web3.eth().block_number().await?.as_u64();
+        let sync_lock = self.sync_lock.clone();
+        for mut job in jobs {
+            job.attempts += 1;
+            let guard = sync_lock.lock().await;
+            let result = match H160::from_str(&job.pair_address) {
+                Ok(pool) if self.factory_of(pool).is_some() => self.reindex_pair(pool, last_synced_block).await,
+                Ok(pool) => Err(format_err!("events of unknown pool {:?} can't be backfilled", pool)),
+                Err(e) => Err(e.into()),
+            };
+            drop(guard);
+            match result {
+                Ok(()) => {
+                    job.status = "done".to_string();
+                    job.synced_block = Some(last_synced_block as i64);
+                    job.error = None;
+                }
+                Err(e) => {
+                    log::warn!("backfill of pool {} failed, {:?}", job.pair_address, e);
+                    job.status = "failed".to_string();
+                    job.error = Some(format!("{:?}", e));
+                }
+            }
+            job.updated_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+            db::update_backfill_job(&self.db, &job).await?;
+        }
+        Ok(())
+    }
+
+    /// the block of the factory log of `pair`, looked up on the chain for the pools stored
+    /// before it was kept
+    async fn created_block(&mut self, pair: H160, to_block: u64) -> anyhow::Result<u64> {
+        let pair_address = hex::encode(pair);
+        let pool = db::get_pool(&self.db, &pair_address).await?
+            .ok_or_else(|| format_err!("pool {:?} is not stored", pair))?;
+        if let Some(block) = pool.created_block {
+            return Ok(block as u64);
+        }
+        let factory = self.factory_of(pair).ok_or_else(|| format_err!("unknown pool {:?}", pair))?;
+        let (address, protocol) = (factory.address, factory.protocol.clone());
+        // the tokens of the pool are indexed topics of the factory logs of both protocols
+        let filter = FilterBuilder::default()
+            .address(vec![address])
+            .topics(Some(protocol.factory_topics()),
+                    Some(vec![H256::from(H160::from_str(&pool.token_x_address)?)]),
+                    Some(vec![H256::from(H160::from_str(&pool.token_y_address)?)]),
+                    None);
+        let created = self.get_logs(0, to_block, filter).await?.iter()
+            .filter_map(|log| protocol.decode_factory_log(log).ok())
+            .find(|created| created.pool == pair)
+            .ok_or_else(|| format_err!("no factory log of pool {:?}", pair))?;
+        db::set_created_block(&self.db, &pair_address, created.block_number).await?;
+        Ok(created.block_number)
+    }
+
+    /// fetch every event of the pair up to `to_block` again and replace the stored ones.
+    /// The caller holds `sync_lock`, `to_block` is the last synced block under it.
+    pub async fn reindex_pair(&mut self, pair: H160, to_block: u64) -> anyhow::Result<()> {
+        let protocol = self.factory_of(pair)
+            .ok_or_else(|| format_err!("unknown pool {:?}", pair))?
+            .protocol.clone();
+        let mut events = Vec::new();
+        let mut transfers = Vec::new();
+        let mut start_block = self.created_block(pair, to_block).await?;
+        while start_block <= to_block {
+            let end_block = cmp::min(to_block, start_block + REINDEX_STEP - 1);
+            let (range_events, range_transfers) = self.fetch_pool_events(start_block, end_block, vec![pair]).await?;
+            events.extend(range_events);
+            transfers.extend(range_transfers);
+            start_block = end_block + 1;
+        }
+        self.attach_chain_data(&mut events).await?;
+        let mut state = PoolState::default();
+        for event in &events {
+            protocol.apply(&mut state, event)?;
+        }
+        let pair_address = hex::encode(pair);
+        if protocol.name() == uniswap_v3::NAME {
+            let events = events.into_iter().filter_map(|e| match e {
+                PoolEvent::V3(e) => Some(e),
+                _ => None,
+            }).collect();
+            db::replace_v3_pool_events(&self.db, &pair_address, events, &state, &self.pricing).await?;
+        } else {
+            let events = events.into_iter().filter_map(|e| match e {
+                PoolEvent::Pair(e) => Some(e),
+                _ => None,
+            }).collect();
+            db::replace_pair_events(&self.db, &pair_address, events, &state, &self.config, &self.pricing).await?;
+            self.reload_pool_graph(std::slice::from_ref(&pair_address)).await;
+        }
+        if protocol.lp_transfer_topic().is_some() {
+            let (minted, burned) = lp_supply_changes(&transfers).remove(&pair).unwrap_or_default();
+            db::set_lp_supply(&self.db, &pair_address, minted.saturating_sub(burned)).await?;
+        }
+        log::info!("pool {:?} reindexed up to block {}", pair, to_block);
+        Ok(())
+    }
+
+    pub(crate) async fn run_sync_pair_created_events(&mut self) ->anyhow::Result<()> {
+        self.retry_token_metadata().await?;
+        self.retry_failed_logs().await?;
+        let last_synced_block = db::get_last_sync_block(&self.db).await?;
+        // the blocks a reorg may still drop are synced once they are deep enough
+        let chain_block_number = self.web3.eth().block_number().await?.as_u64()
+            .saturating_sub(self.config.confirmations);
         let sync_step = 1000u64;
         let mut start_block = last_synced_block + 1;
-        let mut end_block = start_block;
-        let pair_event_types = vec!["mint","burn","swap","sync"];
         loop {
-            end_block = cmp::min(chain_block_number,(start_block + sync_step));
+            // ranges must not overlap, a concentrated liquidity event must be applied once
+            let end_block = cmp::min(chain_block_number,start_block + sync_step - 1);
             if start_block > end_block {
                 break;
             }
-            self.sync_pair_created_events(start_block,end_block).await?;
-            for pair_event_type in &pair_event_types {
-                self.sync_pair_events(start_block, end_block, pair_event_type).await?;
-            }
-            start_block += sync_step;
-
-        }
-        if last_synced_block != chain_block_number {
-            db::upsert_last_sync_block(
-                &mut self.db,
-                LastSyncBlock { block_number: chain_block_number as i64 }
-            ).await?;
+            self.sync_range(start_block, end_block).await?;
+            start_block = end_block + 1;
         }
         Ok(())
     }
 
     pub async fn run_watcher_server(mut self) {
-        let mut handlers = Vec::new();
-        println!("run_watcher_server");
+        let mut handlers: Vec<BoxFuture<'static, ()>> = Vec::new();
+        log::debug!("run_watcher_server");
+        // prices of tokens stored before, or with anchors configured since
+        if let Err(e) = prices::refresh_prices(&self.db, &self.pricing).await {
+            log::error!("refresh prices failed {:?}", e);
+        }
+        let reconcile_interval = self.config.reconcile_interval;
+        if reconcile_interval > 0 {
+            match ChainWatcher::new_with_transport(self.config.clone(), self.db.clone(),
+                                                   self.web3.transport().clone(),
+                                                   self.token_cache.clone(), self.pool_graph.clone()).await {
+                Ok(mut watcher) => {
+                    watcher.sync_lock = self.sync_lock.clone();
+                    // a reindex reprices through the graph of the watcher
+                    watcher.pricing = self.pricing.clone();
+                    let mut reconciler = Reconciler::new(watcher);
+                    handlers.push(Box::pin(
+                        async move {
+                            let mut poll = tokio::time::interval(Duration::from_secs(reconcile_interval as u64));
+                            loop {
+                                poll.tick().await;
+                                if let Err(e) = reconciler.reconcile().await {
+                                    log::error!("reconcile error occurred {:?}", e);
+                                }
+                            }
+                        }
+                    ));
+                }
+                Err(e) => log::error!("create reconciler failed {:?}", e),
+            }
+        }
+        if let Err(e) = self.enqueue_backfill_jobs().await {
+            log::error!("enqueue backfill jobs failed {:?}", e);
+        }
         handlers.push(Box::pin(
             async move {
                 let mut tx_poll = tokio::time::interval(Duration::from_secs(1800));
                 loop {
-                    println!("loop");
+                    log::debug!("loop");
                     tx_poll.tick().await;
                     if let Err(e) = self.run_sync_pair_created_events().await {
-                        println!("run_sync_pair_created_events error occurred {:?}", e);
                         log::error!("run_sync_pair_created_events error occurred {:?}", e);
                     }
+                    // the backfill reindexes pools up to the last synced block, it runs
+                    // between the syncs
+                    if let Err(e) = self.run_backfill_jobs().await {
+                        log::error!("run_backfill_jobs error occurred {:?}", e);
+                    }
 
                 }
             }
-                .fuse(),
         ));
         futures::future::select_all(handlers).await;
     }
 }
-pub async fn run_watcher(config: BackendConfig, db: rbatis::Rbatis) -> JoinHandle<()> {
+/// set the LP amount of the Mint and Burn events from the LP token transfers from and to
+/// the zero address in their transactions. A Mint can follow a transfer of the protocol
+/// fee, so each event takes the closest transfer before it.
+fn attach_lp_amounts(transfers: &[PairTransferEvent], events: &mut [PoolEvent]) {
+    // (pool, tx) -> (log index, amount) of the mints and of the burns
+    let mut minted: HashMap<(H160, H256), Vec<(u64, U256)>> = HashMap::new();
+    let mut burned: HashMap<(H160, H256), Vec<(u64, U256)>> = HashMap::new();
+    for transfer in transfers {
+        let key = (transfer.meta.address, transfer.meta.tx_hash);
+        let amount = (transfer.meta.log_index, transfer.value);
+        // the minimum liquidity of the first mint is not the LP amount of the Mint
+        if transfer.from.is_zero() && !transfer.to.is_zero() {
+            minted.entry(key).or_default().push(amount);
+        } else if transfer.to.is_zero() && !transfer.from.is_zero() {
+            burned.entry(key).or_default().push(amount);
+        }
+    }
+    let closest_before = |transfers: &HashMap<(H160, H256), Vec<(u64, U256)>>, meta: &EventData| {
+        transfers.get(&(meta.address, meta.tx_hash))?.iter()
+            .filter(|(index, _)| *index < meta.log_index)
+            .max_by_key(|(index, _)| *index)
+            .map(|(_, amount)| *amount)
+    };
+    for event in events.iter_mut() {
+        match event {
+            PoolEvent::Pair(PairEvent::MintPairEvent(mint)) => {
+                mint.lp_amount = closest_before(&minted, &mint.meta);
+            }
+            PoolEvent::Pair(PairEvent::BurnPairEvent(burn)) => {
+                burn.lp_amount = closest_before(&burned, &burn.meta);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// the LP tokens minted and burned in each pool by `transfers`, including the protocol fee
+/// and the minimum liquidity locked at the zero address
+fn lp_supply_changes(transfers: &[PairTransferEvent]) -> HashMap<H160, (U256, U256)> {
+    let mut changes: HashMap<H160, (U256, U256)> = HashMap::new();
+    for transfer in transfers {
+        let (minted, burned) = changes.entry(transfer.meta.address).or_default();
+        if transfer.from.is_zero() {
+            *minted += transfer.value;
+        } else if transfer.to.is_zero() {
+            *burned += transfer.value;
+        }
+    }
+    changes
+}
+
+/// store new pools, with the concentrated liquidity state of those that have one
+async fn save_pools(tx: &mut RBatisTxExecutor, pools: &[(PoolInfo, Option<V3Pool>)]) -> anyhow::Result<()> {
+    for (pool, v3_pool) in pools {
+        db::save_pool(tx, pool).await?;
+        if let Some(v3_pool) = v3_pool {
+            db::save_v3_pool(tx, v3_pool).await?;
+        }
+    }
+    Ok(())
+}
+
+pub async fn run_watcher(config: BackendConfig, db: rbatis::Rbatis, token_cache: TokenCache, pool_graph: PoolGraph)
+    -> JoinHandle<()> {
     log::info!("Starting watcher!");
-    let mut watcher = ChainWatcher::new(config, db).await.unwrap();
+    let transport = WatcherTransport::from_config(&config).unwrap();
+    let watcher = ChainWatcher::new_with_transport(config, db, transport, token_cache, pool_graph).await.unwrap();
     tokio::spawn(watcher.run_watcher_server())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::db::sqlite::new_test_db;
+    use crate::watcher::transport::ReplayTransport;
+    use crate::watcher::mock_rpc::{MockChain, MockRpcServer};
+    use crate::protocol::uniswap_v2::UniswapV2;
+    use crate::db::tables::{BackfillJob, Event};
+    use crate::db::candles;
+    use crate::amm::units::mul_div;
+
+    const REPLAY_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/watcher/fixtures/uniswap_v2_replay.jsonl");
+
+    #[tokio::test]
+    async fn test_replay_sync() {
+        let db = new_test_db("replay_sync").await;
+        let config = BackendConfig {
+            contract_address: H160::from_str("0x5c69bee701ef814a2b6a3edd4b1652cb9cc5aa6f").unwrap(),
+            ..Default::default()
+        };
+        let transport = WatcherTransport::Replay(ReplayTransport::from_file(REPLAY_FIXTURE).unwrap());
+        let token_cache = TokenCache::start(db.clone(), 0).await.unwrap();
+        let mut watcher = ChainWatcher::new_with_transport(config, db.clone(), transport, token_cache,
+                                                           PoolGraph::default()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), 32);
+        let tokens = db::get_tokens(&db).await.unwrap();
+        assert_eq!(tokens.len(), 2);
+        let pools = db::get_all_store_pools(&db).await.unwrap();
+        assert_eq!(pools.len(), 1);
+        let pool = &pools[0];
+        assert_eq!(pool.pair_address, "33".repeat(20));
+        assert_eq!((pool.token_x_symbol.as_str(), pool.token_y_symbol.as_str()), ("TKA", "TKB"));
+        // reserves of the last Sync, which has the lower log index of the two
+        assert_eq!(pool.token_x_reserves.0, "1001000000000000000000");
+        assert_eq!(pool.token_y_reserves.0, "1998006001");
+        assert_eq!(pool.total_add_liq_count, 1);
+        assert_eq!(pool.total_swap_count, 1);
+        // the pool graph follows the stored reserves
+        let graph = watcher.pool_graph.read();
+        let pair = graph.pair(&pool.pair_address).unwrap();
+        assert_eq!((pair.reserve_x.to_string(), pair.reserve_y.to_string()),
+                   (pool.token_x_reserves.0.clone(), pool.token_y_reserves.0.clone()));
+    }
+
+    fn mock_chain() -> (MockChain, H160) {
+        let mut chain = MockChain::new(H160::from_low_u64_be(0xfac));
+        let token_a = chain.deploy_token("TKA", 18);
+        let token_b = chain.deploy_token("TKB", 6);
+        let pair = chain.create_pair(token_a, token_b);
+        let lp = H160::from_low_u64_be(0xaaa);
+        chain.mint(pair, lp, U256::exp10(21), U256::exp10(9) * 2);
+        chain.swap(pair, lp, H160::from_low_u64_be(0xbbb), U256::exp10(18), true);
+        chain.swap(pair, lp, lp, U256::exp10(6), false);
+        chain.burn(pair, lp, lp, U256::exp10(20), U256::exp10(8));
+        chain.mine(10);
+        (chain, pair)
+    }
+
+    async fn mock_watcher(server: &MockRpcServer, db: &rbatis::Rbatis) -> ChainWatcher {
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            ..Default::default()
+        };
+        ChainWatcher::new(config, db.clone()).await.unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_rpc_sync() {
+        let (chain, pair) = mock_chain();
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("mock_rpc_sync").await;
+        let mut watcher = mock_watcher(&server, &db).await;
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        let (head, state) = {
+            let chain = server.chain();
+            (chain.block_number, chain.pairs[&pair].clone())
+        };
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head);
+        let pools = db::get_all_store_pools(&db).await.unwrap();
+        assert_eq!(pools.len(), 1);
+        assert_eq!(pools[0].pair_address, hex::encode(pair));
+        assert_eq!(pools[0].token_x_reserves.0, state.reserve0.to_string());
+        assert_eq!(pools[0].token_y_reserves.0, state.reserve1.to_string());
+        assert_eq!(pools[0].total_swap_count, 2);
+
+        // the LP amounts come from the transfers from and to the zero address
+        let minted = (U256::exp10(21) * U256::exp10(9) * U256::from(2)).integer_sqrt();
+        let events: Vec<Event> = db.query_decode("select * from events where event_type in (1, 2) order by id", vec![])
+            .await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].lp_amount.clone().unwrap().0, (minted - 1000).to_string());
+        assert_eq!(events[1].lp_amount.clone().unwrap().0, (minted - state.total_supply).to_string());
+        assert_eq!(pools[0].lp_total_supply.clone().unwrap().0, state.total_supply.to_string());
+        let (mint_time, origin) = {
+            let chain = server.chain();
+            (chain.timestamp(events[0].block_number.unwrap() as u64), chain.tx_origin)
+        };
+        assert_eq!(events[0].block_timestamp, Some(mint_time as i64));
+        assert_eq!(events[0].tx_origin, Some(hex::encode(origin)));
+
+        // every event is in the same week, closing at the reserves after the burn
+        let weeks = candles::get_candles(&db, &hex::encode(pair), 7 * 24 * 3600, 0, i64::MAX).await.unwrap();
+        assert_eq!(weeks.len(), 1);
+        let close = mul_div(state.reserve1, candles::price_scale(), state.reserve0);
+        assert_eq!((weeks[0].close, weeks[0].swap_count), (close, 2));
+        assert!(weeks[0].low <= weeks[0].open && weeks[0].open <= weeks[0].high);
+    }
+
+    #[actix_rt::test]
+    async fn test_events_backfill() {
+        let (chain, pair) = mock_chain();
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("events_backfill").await;
+        let mut watcher = mock_watcher(&server, &db).await;
+        watcher.run_sync_pair_created_events().await.unwrap();
+        // swaps stored before the migration
+        db.exec("update events set amount0_in = null, amount1_in = null, amount0_out = null, \
+            amount1_out = null, direction = null, amount_x = 0 where event_type = 3", vec![]).await.unwrap();
+        assert_eq!(db::get_pools_to_backfill(&db).await.unwrap(), vec![hex::encode(pair)]);
+        // events and pools stored before the block, its time, the transaction sender and the
+        // LP supply were kept
+        db.exec("update events set block_number = null, log_index = null, block_timestamp = null, \
+            tx_origin = null", vec![])
+            .await.unwrap();
+        let created_block = db::get_pool(&db, &hex::encode(pair)).await.unwrap().unwrap().created_block;
+        assert!(created_block.is_some());
+        db.exec("update pool_info set lp_total_supply = null, created_block = null", vec![]).await.unwrap();
+        db.exec("delete from pool_candles", vec![]).await.unwrap();
+        db.exec("delete from pool_day_stats", vec![]).await.unwrap();
+        assert_eq!(db::get_pools_missing_candles(&db).await.unwrap(), vec![hex::encode(pair)]);
+        assert_eq!(db::get_pools_missing_stats(&db).await.unwrap(), vec![hex::encode(pair)]);
+        assert_eq!(db::get_pools_missing_lp_supply(&db, "uniswap_v2").await.unwrap(), vec![hex::encode(pair)]);
+
+        // the sync leaves the pools to the backfill jobs
+        watcher.run_sync_pair_created_events().await.unwrap();
+        assert_eq!(db::get_pools_to_backfill(&db).await.unwrap(), vec![hex::encode(pair)]);
+        watcher.enqueue_backfill_jobs().await.unwrap();
+        watcher.run_backfill_jobs().await.unwrap();
+        let head = server.chain().block_number;
+        let jobs: Vec<BackfillJob> = db.query_decode("select * from backfill_jobs", vec![]).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!((jobs[0].status.as_str(), jobs[0].attempts, jobs[0].synced_block), ("done", 1, Some(head as i64)));
+        // the pool was reindexed from the block of its factory log, looked up again
+        assert_eq!(db::get_pool(&db, &hex::encode(pair)).await.unwrap().unwrap().created_block, created_block);
+        // a pool is backfilled once
+        watcher.enqueue_backfill_jobs().await.unwrap();
+        assert!(db::get_backfill_jobs(&db, MAX_BACKFILL_ATTEMPTS, BACKFILL_BATCH).await.unwrap().is_empty());
+
+        assert!(db::get_pools_to_backfill(&db).await.unwrap().is_empty());
+        assert!(db::get_pools_missing_lp_supply(&db, "uniswap_v2").await.unwrap().is_empty());
+        assert!(db::get_pools_missing_candles(&db).await.unwrap().is_empty());
+        assert!(db::get_pools_missing_stats(&db).await.unwrap().is_empty());
+        let total_supply = server.chain().pairs[&pair].total_supply;
+        let pools = db::get_all_store_pools(&db).await.unwrap();
+        assert_eq!(pools[0].lp_total_supply.clone().unwrap().0, total_supply.to_string());
+        let swaps: Vec<Event> = db.query_decode("select * from events where event_type = 3 order by id", vec![])
+            .await.unwrap();
+        assert_eq!(swaps.len(), 2);
+        assert_eq!(swaps[0].amount0_in.clone().unwrap().0, U256::exp10(18).to_string());
+        assert_eq!(swaps[0].amount1_in.clone().unwrap().0, "0");
+        assert_eq!(swaps[0].amount_x, swaps[0].amount0_in);
+        assert_eq!(swaps[0].amount_y, swaps[0].amount1_out);
+        assert_eq!(swaps[0].to_account, Some(hex::encode(H160::from_low_u64_be(0xbbb))));
+        assert_eq!(swaps[0].direction.as_deref(), Some("x_to_y"));
+        assert_eq!(swaps[1].amount1_in.clone().unwrap().0, U256::exp10(6).to_string());
+        assert_eq!(swaps[1].direction.as_deref(), Some("y_to_x"));
+        assert!(!swaps[0].flash && !swaps[1].flash);
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_rpc_fault_does_not_advance() {
+        let (chain, _) = mock_chain();
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("mock_rpc_fault").await;
+        let mut watcher = mock_watcher(&server, &db).await;
+
+        server.chain().fail_next("eth_getLogs", -32000, "header not found");
+        assert!(watcher.run_sync_pair_created_events().await.is_err());
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), 0);
+        assert!(db::get_all_store_pools(&db).await.unwrap().is_empty());
+
+        // the next poll starts over from the last synced block
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let head = server.chain().block_number;
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head);
+        assert_eq!(db::get_all_store_pools(&db).await.unwrap().len(), 1);
+    }
+
+    fn requests(server: &MockRpcServer, method: &str) -> usize {
+        server.chain().requests.iter().filter(|m| m.as_str() == method).count()
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_rpc_errors_are_retried() {
+        let (chain, _) = mock_chain();
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("mock_rpc_retry").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            rpc_retries: 2,
+            rpc_retry_backoff_ms: 10,
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+
+        // more failures than retries fail the sync
+        for _ in 0..3 {
+            server.chain().fail_next("eth_getLogs", -32000, "header not found");
+        }
+        assert!(watcher.run_sync_pair_created_events().await.is_err());
+        assert_eq!(requests(&server, "eth_getLogs"), 3);
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), 0);
+
+        for _ in 0..2 {
+            server.chain().fail_next("eth_getLogs", -32000, "header not found");
+        }
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let head = server.chain().block_number;
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head);
+        assert_eq!(db::get_all_store_pools(&db).await.unwrap().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_rpc_timeout_is_retried() {
+        let (chain, _) = mock_chain();
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("mock_rpc_timeout").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            rpc_timeout_secs: 1,
+            rpc_retries: 1,
+            rpc_retry_backoff_ms: 10,
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+
+        server.chain().delay_next("eth_blockNumber", Duration::from_secs(3));
+        watcher.run_sync_pair_created_events().await.unwrap();
+        assert_eq!(requests(&server, "eth_blockNumber"), 2);
+        let head = server.chain().block_number;
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head);
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_rpc_log_range_is_split() {
+        let (mut chain, pair) = mock_chain();
+        chain.max_block_range = Some(4);
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("mock_rpc_split").await;
+        let mut watcher = mock_watcher(&server, &db).await;
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        let (head, state) = {
+            let chain = server.chain();
+            (chain.block_number, chain.pairs[&pair].clone())
+        };
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head);
+        let stored = db::get_pool(&db, &hex::encode(pair)).await.unwrap().unwrap();
+        assert_eq!((stored.total_add_liq_count, stored.total_swap_count, stored.total_rm_liq_count), (1, 2, 1));
+        assert_eq!(stored.token_x_reserves.0, state.reserve0.to_string());
+        assert_eq!(stored.lp_total_supply.unwrap().0, state.total_supply.to_string());
+        let events: Vec<Event> = db.query_decode("select * from events", vec![]).await.unwrap();
+        assert_eq!(events.len(), 4);
+    }
+
+    #[actix_rt::test]
+    async fn test_mock_rpc_reorg_within_confirmations() {
+        let (mut chain, pair) = mock_chain();
+        let lp = H160::from_low_u64_be(0xaaa);
+        // a swap of the fork dropped below
+        chain.swap(pair, lp, lp, U256::exp10(17), true);
+        chain.mine(1);
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("mock_rpc_reorg").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            confirmations: 5,
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let head = server.chain().block_number;
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head - 5);
+        let stored = db::get_pool(&db, &hex::encode(pair)).await.unwrap().unwrap();
+        assert_eq!(stored.total_swap_count, 2);
+
+        {
+            let mut chain = server.chain();
+            chain.reorg(2);
+            chain.swap(pair, lp, lp, U256::exp10(5), false);
+            chain.mine(10);
+        }
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let head = server.chain().block_number;
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head - 5);
+        let swaps: Vec<Event> = db.query_decode("select * from events where event_type = 3 order by block_number",
+                                                vec![]).await.unwrap();
+        assert_eq!(swaps.len(), 3);
+        assert_eq!(swaps[2].amount1_in.clone().unwrap().0, U256::exp10(5).to_string());
+        assert!(swaps.iter().all(|s| s.amount0_in.clone().unwrap().0 != U256::exp10(17).to_string()));
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_range_is_stored_once() {
+        let (mut chain, pair) = mock_chain();
+        let v3_factory = H160::from_low_u64_be(0xfac3);
+        let token_a = chain.deploy_token("TKC", 18);
+        let token_b = chain.deploy_token("TKD", 18);
+        let pool = chain.create_v3_pool(v3_factory, token_a, token_b, 500, 10);
+        let lp = H160::from_low_u64_be(0xaaa);
+        let e18 = U256::exp10(18);
+        chain.v3_initialize(pool, U256::from(2).pow(96.into()), 0);
+        chain.v3_mint(pool, lp, (-100, 100), 5000, (e18, e18));
+        // events of the second range of the sync
+        chain.mine(1000);
+        chain.swap(pair, lp, lp, U256::exp10(17), true);
+        chain.mint(pair, lp, U256::exp10(19), U256::exp10(7) * 2);
+        chain.v3_mint(pool, lp, (-200, 100), 700, (e18, e18));
+        chain.v3_burn(pool, lp, (-100, 100), 1000, (e18 / 5, e18 / 5));
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("failed_range").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            v3_factory_address: Some(v3_factory),
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+
+        // the times of the blocks of the second range are fetched after all of its logs
+        server.chain().fail_from_block("eth_getBlockByNumber", 1001, -32000, "header not found");
+        assert!(watcher.run_sync_pair_created_events().await.is_err());
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), 1000);
+        let stored = db::get_pool(&db, &hex::encode(pair)).await.unwrap().unwrap();
+        assert_eq!((stored.total_add_liq_count, stored.total_swap_count, stored.total_rm_liq_count), (1, 2, 1));
+        // the LP supply of the first range is stored with it
+        assert_ne!(stored.lp_total_supply.unwrap().0, "0");
+        assert_eq!(db::get_v3_pool_state(&db, &hex::encode(pool)).await.unwrap().liquidity, 5000);
+
+        // the next poll syncs the second range once
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let (head, state, v3_state) = {
+            let chain = server.chain();
+            (chain.block_number, chain.pairs[&pair].clone(), chain.v3_pools[&pool].clone())
+        };
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head);
+        let stored = db::get_pool(&db, &hex::encode(pair)).await.unwrap().unwrap();
+        assert_eq!((stored.total_add_liq_count, stored.total_swap_count, stored.total_rm_liq_count), (2, 3, 1));
+        assert_eq!(stored.token_x_reserves.0, state.reserve0.to_string());
+        assert_eq!(stored.token_y_reserves.0, state.reserve1.to_string());
+        assert_eq!(stored.lp_total_supply.unwrap().0, state.total_supply.to_string());
+        let stored = db::get_pool(&db, &hex::encode(pool)).await.unwrap().unwrap();
+        assert_eq!((stored.total_add_liq_count, stored.total_rm_liq_count), (2, 1));
+        let stored_state = db::get_v3_pool_state(&db, &hex::encode(pool)).await.unwrap();
+        assert_eq!(stored_state.liquidity, v3_state.liquidity());
+        assert_eq!(stored_state.liquidity_net,
+                   v3_state.liquidity_net.into_iter().filter(|(_, net)| *net != 0).collect());
+        let events: Vec<Event> = db.query_decode("select * from events", vec![]).await.unwrap();
+        assert_eq!(events.len(), 9);
+    }
+
+    #[actix_rt::test]
+    async fn test_malformed_log_does_not_stop_sync() {
+        let (mut chain, pair) = mock_chain();
+        // a Swap with one amount instead of four
+        chain.emit_raw(vec![(pair, vec![UniswapV2::embedded_topics().topic("swap"), H256::zero(), H256::zero()],
+                             vec![0u8; 32])]);
+        let token_a = chain.deploy_token("TKC", 18);
+        let token_b = chain.deploy_token("TKD", 18);
+        chain.create_pair(token_a, token_b);
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("malformed_log").await;
+        let mut watcher = mock_watcher(&server, &db).await;
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        let head = server.chain().block_number;
+        assert_eq!(db::get_last_sync_block(&db).await.unwrap(), head);
+        assert_eq!(db::get_all_store_pools(&db).await.unwrap().len(), 2);
+        let failed = db::get_failed_logs(&db, None, 10).await.unwrap();
+        assert_eq!(failed.len(), 1);
+        assert_eq!((failed[0].address.as_str(), failed[0].status.as_str()), (hex::encode(pair).as_str(), "failed"));
+
+        // retrying with the same decoder fails again
+        assert_eq!(db::request_failed_logs_retry(&db, failed[0].id).await.unwrap(), 1);
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let failed = db::get_failed_logs(&db, None, 10).await.unwrap();
+        assert_eq!((failed[0].status.as_str(), failed[0].attempts), ("failed", 2));
+
+        // after a decoder fix the log decodes, and the pair is reindexed
+        db.exec("update failed_logs set data = ?", vec![rbs::to_value!(hex::encode([1u8; 128]))]).await.unwrap();
+        db::request_failed_logs_retry(&db, None).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+        let failed = db::get_failed_logs(&db, None, 10).await.unwrap();
+        assert_eq!((failed.len(), failed[0].status.as_str()), (1, "resolved"));
+        assert!(db::get_failed_logs(&db, Some("retry".to_string()), 10).await.unwrap().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_v3_sync() {
+        let (mut chain, pair) = mock_chain();
+        let v3_factory = H160::from_low_u64_be(0xfac3);
+        let token_a = chain.deploy_token("TKC", 18);
+        let token_b = chain.deploy_token("TKD", 18);
+        let pool = chain.create_v3_pool(v3_factory, token_a, token_b, 500, 10);
+        let lp = H160::from_low_u64_be(0xaaa);
+        let e18 = U256::exp10(18);
+        chain.v3_initialize(pool, U256::from(2).pow(96.into()), 0);
+        chain.v3_mint(pool, lp, (-100, 100), 5000, (e18, e18));
+        chain.v3_mint(pool, lp, (-200, -100), 700, (0.into(), e18));
+        let server = MockRpcServer::start(chain);
+        let db = new_test_db("v3_sync").await;
+        let config = BackendConfig {
+            remote_web3_url: server.url.clone(),
+            contract_address: server.chain().factory,
+            v3_factory_address: Some(v3_factory),
+            ..Default::default()
+        };
+        let mut watcher = ChainWatcher::new(config, db.clone()).await.unwrap();
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        // the next sync applies its events to the stored state
+        {
+            let mut chain = server.chain();
+            chain.v3_swap(pool, lp, (-1000, 1100), U256::from(2).pow(95.into()), -150);
+            chain.v3_burn(pool, lp, (-100, 100), 5000, (e18 / 2, e18 / 2));
+            chain.v3_collect(pool, lp, (-100, 100), (1000, 0));
+        }
+        watcher.run_sync_pair_created_events().await.unwrap();
+
+        let pools = db::get_all_store_pools(&db).await.unwrap();
+        let protocols: HashMap<String, String> = pools.iter()
+            .map(|p| (p.pair_address.clone(), p.protocol.clone()))
+            .collect();
+        assert_eq!(protocols[&hex::encode(pair)], "uniswap_v2");
+        assert_eq!(protocols[&hex::encode(pool)], "uniswap_v3");
+        let stored = pools.iter().find(|p| p.pair_address == hex::encode(pool)).unwrap();
+        assert_eq!(stored.token_x_reserves.0, (e18 - 2000).to_string());
+        assert_eq!(stored.token_y_reserves.0, (e18 * U256::from(2) + 1100).to_string());
+        assert_eq!((stored.total_add_liq_count, stored.total_rm_liq_count, stored.total_swap_count), (2, 1, 1));
+        let v3_pools = db::get_v3_pools(&db).await.unwrap();
+        assert_eq!(v3_pools.len(), 1);
+        assert_eq!((v3_pools[0].fee, v3_pools[0].tick_spacing, v3_pools[0].tick), (500, 10, -150));
+        assert_eq!(v3_pools[0].sqrt_price_x96.0, U256::from(2).pow(95.into()).to_string());
+        assert_eq!(v3_pools[0].liquidity.0, "700");
+        let state = db::get_v3_pool_state(&db, &hex::encode(pool)).await.unwrap();
+        assert_eq!(state.liquidity_net, [(-200, 700), (-100, -700)].into_iter().collect());
+
+        // a reindex from the chain ends in the same state
+        let head = server.chain().block_number;
+        watcher.reindex_pair(pool, head).await.unwrap();
+        assert_eq!(db::get_v3_pool_state(&db, &hex::encode(pool)).await.unwrap(), state);
+    }
+}